@@ -12,6 +12,8 @@ const RESPONSE_END: &str = "!!!end!!!";
 pub struct Client {
     stream: TcpStream,
     txn_version: Option<u64>,
+    // 当前使用的数据库
+    current_db: Option<String>,
 }
 
 impl Client {
@@ -20,6 +22,7 @@ impl Client {
         Ok(Self {
             stream,
             txn_version: None,
+            current_db: None,
         })
     }
 
@@ -47,6 +50,10 @@ impl Client {
                     self.txn_version = Some(version);
                 }
             }
+            // 解析切换数据库命令 USE DATABASE db_name
+            if let Some(database_name) = res.strip_prefix("USE DATABASE ") {
+                self.current_db = Some(database_name.trim().to_string());
+            }
             println!("{}", res);
         }
         Ok(())
@@ -88,9 +95,13 @@ async fn main() -> Result<(), Box<dyn Error>> {
 
     let mut editor = DefaultEditor::new()?;
     loop {
+        let db = match &client.current_db {
+            Some(database_name) => format!("legend_db({})", database_name),
+            None => "legend_db".into(),
+        };
         let prompt = match client.txn_version {
-            Some(version) => format!("legend_db#{}> ", version),
-            None => "legend_db> ".into(),
+            Some(version) => format!("{}#{}> ", db, version),
+            None => format!("{}> ", db),
         };
         let readline = editor.readline(&prompt);
         match readline {
@@ -0,0 +1,353 @@
+// 压测和一致性校验工具
+// 使用银行转账模型：多个客户端并发在账户之间转账，所有账户的余额总和必须保持不变
+// --verify 开启一致性校验，--kill-interval-ms 开启混沌模式，周期性杀掉工作进程后重启并校验恢复后的数据
+
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use bincode::config;
+use clap::Parser;
+use legend_db::custom_error::{LegendDBError, LegendDBResult};
+use legend_db::storage::disk::DiskEngine;
+use legend_db::storage::engine::Engine;
+use legend_db::storage::mvcc::{Mvcc, MvccTransaction};
+
+// 每个账户的初始余额
+const INITIAL_BALANCE: i64 = 1000;
+// 记录已提交转账次数的key
+const TRANSFERS_KEY: &[u8] = b"bench/transfers";
+
+#[derive(Parser, Debug, Clone)]
+#[command(version, about = "legend_db bench and consistency checker", long_about = None)]
+struct Args {
+    // 数据文件路径，默认在临时目录中
+    #[arg(long)]
+    data: Option<PathBuf>,
+    // 账户数量
+    #[arg(long, default_value_t = 10)]
+    accounts: u64,
+    // 并发客户端数量
+    #[arg(long, default_value_t = 4)]
+    clients: u64,
+    // 运行时间，单位秒
+    #[arg(long, default_value_t = 10)]
+    duration: u64,
+    // 开启一致性校验
+    #[arg(long)]
+    verify: bool,
+    // 混沌模式，每隔多久杀掉一次工作进程，0表示不杀进程
+    #[arg(long, default_value_t = 0)]
+    kill_interval_ms: u64,
+    // 作为工作进程运行，由混沌模式的父进程启动
+    #[arg(long, hide = true)]
+    worker: bool,
+}
+
+// 简单的伪随机数生成器，压测不需要引入额外的依赖
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_nanos() as u64);
+        Rng((nanos ^ seed.wrapping_mul(0x9E37_79B9_7F4A_7C15)) | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        // xorshift64
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+}
+
+fn account_key(id: u64) -> Vec<u8> {
+    format!("bench/account/{:08}", id).into_bytes()
+}
+
+fn read_i64<E: Engine>(txn: &MvccTransaction<E>, key: &[u8]) -> LegendDBResult<Option<i64>> {
+    txn.get(key.to_vec())?
+        .map(|v| bincode::decode_from_slice(&v, config::standard()).map(|(n, _)| n))
+        .transpose()
+        .map_err(|e| e.into())
+}
+
+fn write_i64<E: Engine>(txn: &MvccTransaction<E>, key: &[u8], value: i64) -> LegendDBResult<()> {
+    txn.set(key.to_vec(), bincode::encode_to_vec(value, config::standard())?)
+}
+
+fn violation(msg: String) -> LegendDBError {
+    LegendDBError::Internal(format!("consistency violation: {}", msg))
+}
+
+// 初始化账户，已经初始化过的话直接跳过
+fn init_accounts<E: Engine>(mvcc: &Mvcc<E>, accounts: u64) -> LegendDBResult<()> {
+    let txn = mvcc.begin()?;
+    if read_i64(&txn, TRANSFERS_KEY)?.is_some() {
+        return txn.commit();
+    }
+    for id in 0..accounts {
+        write_i64(&txn, &account_key(id), INITIAL_BALANCE)?;
+    }
+    write_i64(&txn, TRANSFERS_KEY, 0)?;
+    txn.commit()
+}
+
+// 读取所有账户的快照，校验余额总和以及余额不能为负数，返回已提交的转账次数
+fn check_snapshot<E: Engine>(mvcc: &Mvcc<E>, accounts: u64) -> LegendDBResult<i64> {
+    let txn = mvcc.begin()?;
+    let mut total = 0;
+    for id in 0..accounts {
+        let balance = read_i64(&txn, &account_key(id))?
+            .ok_or_else(|| violation(format!("account {} is missing", id)))?;
+        if balance < 0 {
+            return Err(violation(format!("account {} has negative balance {}", id, balance)));
+        }
+        total += balance;
+    }
+    let transfers = read_i64(&txn, TRANSFERS_KEY)?
+        .ok_or_else(|| violation("transfer counter is missing".to_string()))?;
+    txn.commit()?;
+    let expected = INITIAL_BALANCE * accounts as i64;
+    if total != expected {
+        return Err(violation(format!("total balance is {}, expected {}", total, expected)));
+    }
+    Ok(transfers)
+}
+
+// 执行一次转账，返回提交后的转账次数，没有执行转账时返回None
+fn transfer<E: Engine>(mvcc: &Mvcc<E>, rng: &mut Rng, accounts: u64, verify: bool) -> LegendDBResult<Option<i64>> {
+    let from = rng.below(accounts);
+    let to = (from + 1 + rng.below(accounts - 1)) % accounts;
+    let amount = 1 + rng.below(100) as i64;
+    let txn = mvcc.begin()?;
+    let result = (|| {
+        let from_balance = read_i64(&txn, &account_key(from))?.unwrap_or_default();
+        let to_balance = read_i64(&txn, &account_key(to))?.unwrap_or_default();
+        if from_balance < amount {
+            return Ok(None);
+        }
+        write_i64(&txn, &account_key(from), from_balance - amount)?;
+        write_i64(&txn, &account_key(to), to_balance + amount)?;
+        // 读自己的写，必须能读到当前事务刚写入的值
+        if verify {
+            let read_back = read_i64(&txn, &account_key(from))?;
+            if read_back != Some(from_balance - amount) {
+                return Err(violation(format!("read your writes: account {} read {:?}, expected {}", from, read_back, from_balance - amount)));
+            }
+        }
+        let transfers = read_i64(&txn, TRANSFERS_KEY)?.unwrap_or_default() + 1;
+        write_i64(&txn, TRANSFERS_KEY, transfers)?;
+        Ok(Some(transfers))
+    })();
+    match result {
+        Ok(transfers) => {
+            txn.commit()?;
+            Ok(transfers)
+        },
+        Err(e) => {
+            txn.rollback()?;
+            Err(e)
+        }
+    }
+}
+
+// 启动客户端线程，直到 stop 被设置或者发现数据不一致
+// 每次转账提交之后调用 on_commit
+fn run_clients<E: Engine + Send + 'static>(
+    mvcc: Mvcc<E>,
+    args: &Args,
+    stop: Arc<AtomicBool>,
+    on_commit: Arc<dyn Fn(i64) + Send + Sync>,
+) -> LegendDBResult<(u64, u64)> {
+    let committed = Arc::new(AtomicU64::new(0));
+    let conflicts = Arc::new(AtomicU64::new(0));
+    let mut handles = Vec::new();
+    for client in 0..args.clients {
+        let (mvcc, stop, on_commit) = (mvcc.clone(), stop.clone(), on_commit.clone());
+        let (committed, conflicts) = (committed.clone(), conflicts.clone());
+        let (accounts, verify) = (args.accounts, args.verify);
+        handles.push(thread::spawn(move || -> LegendDBResult<()> {
+            let mut rng = Rng::new(client);
+            while !stop.load(Ordering::Relaxed) {
+                match transfer(&mvcc, &mut rng, accounts, verify) {
+                    Ok(Some(transfers)) => {
+                        committed.fetch_add(1, Ordering::Relaxed);
+                        on_commit(transfers);
+                    },
+                    Ok(None) => {},
+                    Err(LegendDBError::WriteMvccConflict { .. }) => {
+                        conflicts.fetch_add(1, Ordering::Relaxed);
+                    },
+                    Err(e) => {
+                        stop.store(true, Ordering::Relaxed);
+                        return Err(e);
+                    }
+                }
+            }
+            Ok(())
+        }));
+    }
+    // 校验线程，并发读取快照检查余额总和
+    if args.verify {
+        let (mvcc, stop, accounts) = (mvcc.clone(), stop.clone(), args.accounts);
+        handles.push(thread::spawn(move || -> LegendDBResult<()> {
+            while !stop.load(Ordering::Relaxed) {
+                if let Err(e) = check_snapshot(&mvcc, accounts) {
+                    stop.store(true, Ordering::Relaxed);
+                    return Err(e);
+                }
+                thread::sleep(Duration::from_millis(10));
+            }
+            Ok(())
+        }));
+    }
+    let mut result = Ok(());
+    for handle in handles {
+        let r = handle.join().map_err(|_| LegendDBError::Internal("bench thread panicked".to_string()))?;
+        if result.is_ok() {
+            result = r;
+        }
+    }
+    result.map(|_| (committed.load(Ordering::Relaxed), conflicts.load(Ordering::Relaxed)))
+}
+
+// 打开数据文件，回滚上次异常退出时遗留的事务
+fn open(path: &Path) -> LegendDBResult<Mvcc<DiskEngine>> {
+    let mvcc = Mvcc::new(DiskEngine::new(path.to_path_buf())?);
+    mvcc.recover()?;
+    Ok(mvcc)
+}
+
+// 在当前进程中运行，不杀进程
+fn run_local(args: &Args, path: &Path) -> LegendDBResult<()> {
+    let mvcc = open(path)?;
+    init_accounts(&mvcc, args.accounts)?;
+    let stop = Arc::new(AtomicBool::new(false));
+    let timer = {
+        let stop = stop.clone();
+        let duration = Duration::from_secs(args.duration);
+        thread::spawn(move || {
+            let start = Instant::now();
+            while !stop.load(Ordering::Relaxed) && start.elapsed() < duration {
+                thread::sleep(Duration::from_millis(50));
+            }
+            stop.store(true, Ordering::Relaxed);
+        })
+    };
+    let start = Instant::now();
+    let result = run_clients(mvcc.clone(), args, stop.clone(), Arc::new(|_| {}));
+    stop.store(true, Ordering::Relaxed);
+    let _ = timer.join();
+    let (committed, conflicts) = result?;
+    let elapsed = start.elapsed().as_secs_f64();
+    println!(
+        "committed {} transfers in {:.2}s ({:.0} txn/s), {} write conflicts",
+        committed, elapsed, committed as f64 / elapsed, conflicts
+    );
+    if args.verify {
+        let transfers = check_snapshot(&mvcc, args.accounts)?;
+        println!("verify ok: total balance preserved, {} transfers committed", transfers);
+    }
+    Ok(())
+}
+
+// 工作进程，一直转账直到被父进程杀掉，每次提交之后把转账次数输出给父进程
+fn run_worker(args: &Args, path: &Path) -> LegendDBResult<()> {
+    let mvcc = open(path)?;
+    init_accounts(&mvcc, args.accounts)?;
+    println!("ready");
+    let stop = Arc::new(AtomicBool::new(false));
+    run_clients(mvcc, args, stop, Arc::new(|transfers| println!("ack {}", transfers)))?;
+    Ok(())
+}
+
+fn spawn_worker(args: &Args, path: &Path) -> LegendDBResult<Child> {
+    Ok(Command::new(std::env::current_exe()?)
+        .arg("--worker")
+        .arg("--data").arg(path)
+        .arg("--accounts").arg(args.accounts.to_string())
+        .arg("--clients").arg(args.clients.to_string())
+        .args(if args.verify { vec!["--verify"] } else { vec![] })
+        .stdout(Stdio::piped())
+        .spawn()?)
+}
+
+// 混沌模式：周期性杀掉工作进程，重新打开数据文件校验恢复后的数据
+// 除了余额总和，还要求已经确认提交的转账在恢复后不能丢失
+fn run_chaos(args: &Args, path: &Path) -> LegendDBResult<()> {
+    let start = Instant::now();
+    let mut rng = Rng::new(args.clients);
+    let mut acked = 0;
+    let mut kills = 0;
+    while start.elapsed() < Duration::from_secs(args.duration) {
+        let mut child = spawn_worker(args, path)?;
+        let stdout = child.stdout.take().ok_or(LegendDBError::Internal("worker has no stdout".to_string()))?;
+        let max_ack = Arc::new(AtomicU64::new(acked));
+        let reader = {
+            let max_ack = max_ack.clone();
+            thread::spawn(move || {
+                for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+                    if let Some(n) = line.strip_prefix("ack ").and_then(|n| n.parse::<u64>().ok()) {
+                        max_ack.fetch_max(n, Ordering::Relaxed);
+                    }
+                }
+            })
+        };
+        // 随机等待一段时间再杀掉进程
+        thread::sleep(Duration::from_millis(args.kill_interval_ms / 2 + rng.below(args.kill_interval_ms.max(1))));
+        if let Some(status) = child.try_wait()? {
+            return Err(LegendDBError::Internal(format!("worker exited unexpectedly with {}", status)));
+        }
+        child.kill()?;
+        child.wait()?;
+        let _ = reader.join();
+        kills += 1;
+        acked = max_ack.load(Ordering::Relaxed);
+
+        // 工作进程退出后文件锁释放，重新打开并校验
+        let mvcc = open(path)?;
+        let transfers = check_snapshot(&mvcc, args.accounts)?;
+        if (transfers as u64) < acked {
+            return Err(violation(format!("lost committed transfers after restart: recovered {}, acknowledged {}", transfers, acked)));
+        }
+        println!("kill #{}: recovered {} transfers, {} acknowledged, total balance preserved", kills, transfers, acked);
+    }
+    println!("chaos verify ok: {} kills, {} transfers acknowledged", kills, acked);
+    Ok(())
+}
+
+fn main() {
+    let args = Args::parse();
+    if args.accounts < 2 {
+        eprintln!("at least 2 accounts are required");
+        std::process::exit(2);
+    }
+    let (path, temp_dir) = match &args.data {
+        Some(path) => (path.clone(), None),
+        None => {
+            let dir = tempfile::tempdir().expect("failed to create temp dir");
+            (dir.path().join("legend_db-bench"), Some(dir))
+        }
+    };
+    let result = if args.worker {
+        run_worker(&args, &path)
+    } else if args.kill_interval_ms > 0 {
+        run_chaos(&args, &path)
+    } else {
+        run_local(&args, &path)
+    };
+    drop(temp_dir);
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
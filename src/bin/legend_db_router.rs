@@ -0,0 +1,411 @@
+use futures::SinkExt;
+use tokio::net::{TcpListener, TcpStream};
+use tokio_stream::StreamExt;
+use tokio_util::codec::{Framed, LinesCodec};
+
+use std::collections::HashMap;
+use std::error::Error;
+use clap::Parser as ClapParser;
+use legend_db::sql::executor::executor::{DisplayOptions, ResultSet, ROWS_PREFIX};
+use legend_db::sql::parser::ast::{evaluate_const, Expression, FromItem, Operation, Statement};
+use legend_db::sql::parser::parser::Parser;
+use legend_db::sql::types::{Row, Value};
+
+const RESPONSE_END: &str = "!!!end!!!";
+
+type RouterResult<T> = Result<T, Box<dyn Error + Send + Sync>>;
+
+// 分片路由，按主键的哈希值把数据分布到多个 legend_db_server 上
+// 带主键等值条件的语句只发送到对应的分片，其他语句发送到所有分片再合并结果
+#[derive(ClapParser, Debug)]
+#[command(author, version, about, long_about = None)]
+pub struct Router {
+    ///监听地址
+    #[arg(short, long, default_value = "0.0.0.0:8090")]
+    listen: String,
+    ///分片地址，多个分片用逗号分隔，比如 127.0.0.1:8080,127.0.0.1:8081
+    #[arg(short, long, value_delimiter = ',', required = true)]
+    shards: Vec<String>,
+}
+
+// 一个后端分片的连接
+struct Shard {
+    lines: Framed<TcpStream, LinesCodec>,
+}
+
+impl Shard {
+    async fn connect(addr: &str) -> RouterResult<Self> {
+        let stream = TcpStream::connect(addr).await?;
+        Ok(Self { lines: Framed::new(stream, LinesCodec::new()) })
+    }
+
+    // 发送一条命令，读取直到结束标记的所有行
+    async fn execute(&mut self, cmd: &str) -> RouterResult<String> {
+        self.lines.send(cmd).await?;
+        let mut response = Vec::new();
+        while let Some(line) = self.lines.next().await {
+            let line = line?;
+            if line == RESPONSE_END {
+                return Ok(response.join("\n"));
+            }
+            response.push(line);
+        }
+        Err("shard closed the connection".into())
+    }
+}
+
+// 一个客户端连接对应的路由会话，持有到每个分片的连接
+struct RouterSession {
+    shards: Vec<Shard>,
+    // 表名 -> 主键列的位置
+    primary_keys: HashMap<String, (usize, String)>,
+}
+
+impl RouterSession {
+    async fn connect(addrs: &[String]) -> RouterResult<Self> {
+        let mut shards = Vec::new();
+        for addr in addrs {
+            shards.push(Shard::connect(addr).await?);
+        }
+        Ok(Self { shards, primary_keys: HashMap::new() })
+    }
+
+    // 主键所在的分片，对主键的 bincode 编码计算 FNV-1a 哈希
+    // 不能用 DefaultHasher，它的算法在不同的 Rust 版本之间可能变化，升级之后数据会被路由到错误的分片
+    // FNV-1a 的低位分布很差（最低位只取决于每个字节最低位的异或），取模之前用 murmur3 的 fmix64 打散
+    fn shard_of(&self, key: &Value) -> RouterResult<usize> {
+        let hash = fmix64(fnv1a(&bincode::encode_to_vec(key, bincode::config::standard())?));
+        Ok((hash % self.shards.len() as u64) as usize)
+    }
+
+    // 获取表的主键位置和列名，表结构在所有分片上都一样，从第一个分片读取
+    async fn primary_key(&mut self, table_name: &str) -> RouterResult<(usize, String)> {
+        if let Some(pk) = self.primary_keys.get(table_name) {
+            return Ok(pk.clone());
+        }
+        // CREATE TABLE t (a Integer PRIMARY KEY,
+        // b String)
+        let schema = self.shards[0].execute(&format!("SHOW TABLE {}", table_name)).await?;
+        let schema = schema.split_once('(').map(|(_, columns)| columns).ok_or(schema.clone())?;
+        let pk = schema
+            .split(",\n")
+            .enumerate()
+            .find(|(_, column)| column.contains(" PRIMARY KEY"))
+            .and_then(|(index, column)| column_name(column).map(|name| (index, name)))
+            .ok_or(format!("can not find primary key of table {}", table_name))?;
+        self.primary_keys.insert(table_name.to_string(), pk.clone());
+        Ok(pk)
+    }
+
+    async fn execute(&mut self, cmd: &str) -> RouterResult<String> {
+        // 健康检查需要所有分片都正常
+        if cmd.trim().trim_end_matches(';').eq_ignore_ascii_case("PING") {
+            return self.broadcast(cmd).await;
+        }
+        if cmd.to_uppercase().starts_with("BATCH ") {
+            return Err("the router does not support BATCH, the statements may span several shards".into());
+        }
+        let stmt = match Parser::new(cmd).parse() {
+            Ok(stmt) => stmt,
+            // SHOW TABLES 等非 SQL 命令以及语法错误，交给第一个分片处理
+            Err(_) => return self.shards[0].execute(cmd).await,
+        };
+        match stmt {
+            Statement::Insert { table_name, columns, values } => self.insert(table_name, columns, values).await,
+            Statement::Select { columns, from: FromItem::Table { name, alias }, where_clause, group_by, having, order_by, limit, offset } => {
+                if let Some(shard) = self.point_shard(&name, alias.as_deref(), where_clause.as_ref()).await? {
+                    return self.shards[shard].execute(cmd).await;
+                }
+                let has_agg = columns.iter().any(|(expr, _)| matches!(expr, Expression::Function(..)));
+                if has_agg || group_by.is_some() || having.is_some() || !order_by.is_empty() || limit.is_some() || offset.is_some() {
+                    return Err("the router only supports aggregates, ORDER BY and LIMIT on a single shard, add a primary key condition".into());
+                }
+                self.scan_all(cmd).await
+            },
+            Statement::Select { .. } => Err("the router does not support joins or subqueries across shards".into()),
+            // 修改主键之后行应该属于另一个分片，需要先删除再插入
+            Statement::Update { table_name, columns, .. } if columns.contains_key(&self.primary_key(&table_name).await?.1) => {
+                Err("the router does not support updating the primary key, delete the row and insert it again".into())
+            },
+            Statement::Update { table_name, where_clause, .. } | Statement::Delete { table_name, where_clause } => {
+                if let Some(shard) = self.point_shard(&table_name, None, where_clause.as_ref()).await? {
+                    return self.shards[shard].execute(cmd).await;
+                }
+                self.count_all(cmd).await
+            },
+            // DDL 以及会话设置需要在所有分片上执行
+            _ => self.broadcast(cmd).await,
+        }
+    }
+
+    // 按主键把插入的行拆分到各个分片
+    async fn insert(&mut self, table_name: String, columns: Option<Vec<String>>, values: Vec<Vec<Expression>>) -> RouterResult<String> {
+        let (pk_index, pk_name) = self.primary_key(&table_name).await?;
+        let pk_index = match &columns {
+            Some(columns) => columns.iter().position(|c| *c == pk_name).ok_or(format!("primary key {} is required by the router", pk_name))?,
+            None => pk_index,
+        };
+        let mut shard_rows: HashMap<usize, Vec<String>> = HashMap::new();
+        for row in values {
+            let pk = row.get(pk_index).ok_or("primary key value is missing")?;
+            let shard = self.shard_of(&evaluate_const(pk)?)?;
+            shard_rows.entry(shard).or_default().push(render_row(&row));
+        }
+        let columns = columns.map(|columns| format!(" ({})", columns.join(", "))).unwrap_or_default();
+        let mut count = 0;
+        for (shard, rows) in shard_rows {
+            let sql = format!("insert into {}{} values {};", table_name, columns, rows.join(", "));
+            count += parse_count(&self.shards[shard].execute(&sql).await?, "INSERT")?;
+        }
+        Ok(format!("INSERT {} rows", count))
+    }
+
+    // where 条件是主键等值比较时，只需要访问一个分片
+    async fn point_shard(&mut self, table_name: &str, alias: Option<&str>, where_clause: Option<&Expression>) -> RouterResult<Option<usize>> {
+        let (field, value) = match where_clause {
+            Some(Expression::Operation(Operation::Equal(l, r))) => match (l.as_ref(), r.as_ref()) {
+                (Expression::Field(field), value @ Expression::Consts(_))
+                | (value @ Expression::Consts(_), Expression::Field(field)) => (field, value),
+                _ => return Ok(None),
+            },
+            _ => return Ok(None),
+        };
+        let (_, pk_name) = self.primary_key(table_name).await?;
+        // 带表名或别名的列 t.a
+        let field = match field.split_once('.') {
+            Some((table, col)) if table == table_name || Some(table) == alias => col,
+            _ => field.as_str(),
+        };
+        if field != pk_name {
+            return Ok(None);
+        }
+        Ok(Some(self.shard_of(&evaluate_const(value)?)?))
+    }
+
+    // 在所有分片上查询，合并所有行
+    async fn scan_all(&mut self, cmd: &str) -> RouterResult<String> {
+        let (columns, rows) = self.scan_rows(cmd).await?;
+        Ok(ResultSet::Scan { columns, rows }.render(&DisplayOptions::default()))
+    }
+
+    // 通过 ROWS 请求获取每个分片结构化的查询结果，不是查询结果说明分片返回了错误
+    async fn scan_rows(&mut self, cmd: &str) -> RouterResult<(Vec<String>, Vec<Row>)> {
+        let mut columns = Vec::new();
+        let mut rows = Vec::new();
+        for shard in self.shards.iter_mut() {
+            let response = shard.execute(&format!("{}{}", ROWS_PREFIX, cmd)).await?;
+            let (shard_columns, shard_rows) = ResultSet::decode_rows(&response)?.ok_or(response)?;
+            columns = shard_columns;
+            rows.extend(shard_rows);
+        }
+        Ok((columns, rows))
+    }
+
+    // 在所有分片上执行更新或删除，累加影响的行数
+    async fn count_all(&mut self, cmd: &str) -> RouterResult<String> {
+        let mut count = 0;
+        let mut kind = "";
+        for shard in self.shards.iter_mut() {
+            let response = shard.execute(cmd).await?;
+            kind = if response.starts_with("DELETE") { "DELETE" } else { "UPDATE" };
+            count += parse_count(&response, kind)?;
+        }
+        Ok(format!("{} {} rows", kind, count))
+    }
+
+    // 在所有分片上执行，结果一致时返回一份，否则按分片分别返回
+    async fn broadcast(&mut self, cmd: &str) -> RouterResult<String> {
+        let mut responses = Vec::new();
+        for shard in self.shards.iter_mut() {
+            responses.push(shard.execute(cmd).await?);
+        }
+        if responses.iter().all(|r| *r == responses[0]) {
+            return Ok(responses.remove(0));
+        }
+        Ok(responses.iter().enumerate().map(|(i, r)| format!("shard {}: {}", i, r)).collect::<Vec<_>>().join("\n"))
+    }
+
+    pub async fn handle_request(&mut self, socket: TcpStream) -> RouterResult<()> {
+        let mut lines = Framed::new(socket, LinesCodec::new());
+        while let Some(result) = lines.next().await {
+            match result {
+                Ok(line) => {
+                    let response = self.execute(&line).await.unwrap_or_else(|e| e.to_string());
+                    lines.send(response.as_str()).await?;
+                    lines.send(RESPONSE_END).await?;
+                }
+                Err(e) => {
+                    println!("error on decoding from socket; error = {e:?}");
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// 解析 INSERT 3 rows 这类结果中的行数，不是这个格式说明分片返回了错误
+fn parse_count(response: &str, kind: &str) -> RouterResult<usize> {
+    response
+        .strip_prefix(kind)
+        .and_then(|rest| rest.trim().strip_suffix("rows"))
+        .and_then(|count| count.trim().parse().ok())
+        .ok_or_else(|| response.into())
+}
+
+// 列定义中的列名，列名可能用反引号括起来，两个反引号表示反引号本身
+fn column_name(column: &str) -> Option<String> {
+    let column = column.trim_start();
+    let Some(quoted) = column.strip_prefix('`') else {
+        return column.split_whitespace().next().map(|name| name.to_string());
+    };
+    let mut name = String::new();
+    let mut chars = quoted.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '`' if chars.next_if_eq(&'`').is_some() => name.push('`'),
+            '`' => return Some(name),
+            c => name.push(c),
+        }
+    }
+    None
+}
+
+// 插入语句中的一行，{:#} 输出可以重新解析的 SQL，比如浮点数 1.0 不会变成整数 1
+fn render_row(row: &[Expression]) -> String {
+    format!("({})", row.iter().map(|expr| format!("{:#}", expr)).collect::<Vec<_>>().join(", "))
+}
+
+// 64 位 FNV-1a 哈希，算法固定，不同的进程和版本计算的结果一致
+fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf29ce484222325, |hash, b| (hash ^ *b as u64).wrapping_mul(0x100000001b3))
+}
+
+// murmur3 的 64 位终结函数，让每一位都受所有输入位的影响
+fn fmix64(mut hash: u64) -> u64 {
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51afd7ed558ccd);
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xc4ceb9fe1a85ec53);
+    hash ^ (hash >> 33)
+}
+
+#[tokio::main]
+async fn main() -> RouterResult<()> {
+    let args = Router::parse();
+    let listener = TcpListener::bind(&args.listen).await?;
+    println!("legend_db router starts, listening on: {}, shards: {}", args.listen, args.shards.join(","));
+
+    loop {
+        match listener.accept().await {
+            Ok((socket, _)) => {
+                let shards = args.shards.clone();
+                tokio::spawn(async move {
+                    let result = match RouterSession::connect(&shards).await {
+                        Ok(mut session) => session.handle_request(socket).await,
+                        Err(e) => Err(e),
+                    };
+                    if let Err(e) = result {
+                        println!("router session error {:?}", e);
+                    }
+                });
+            }
+            Err(e) => println!("error accepting socket; error = {e:?}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use legend_db::sql::engine::engine::Engine;
+    use legend_db::sql::engine::kv::KVEngine;
+    use legend_db::sql::parser::ast::Consts;
+    use legend_db::storage::memory::MemoryEngine;
+
+    // 测试用的分片，按 legend_db_server 的协议处理路由会发送的请求
+    async fn spawn_shard() -> RouterResult<String> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?.to_string();
+        tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            let mut session = KVEngine::new(MemoryEngine::new()).session().unwrap();
+            let mut lines = Framed::new(socket, LinesCodec::new());
+            while let Some(Ok(line)) = lines.next().await {
+                let response = if let Some(sql) = line.strip_prefix(ROWS_PREFIX) {
+                    session.execute(sql).and_then(|rs| rs.encode_rows())
+                } else if let Some(table_name) = line.strip_prefix("SHOW TABLE ") {
+                    session.get_table(table_name.to_string())
+                } else {
+                    session.execute(&line).map(|rs| rs.to_string())
+                };
+                lines.send(response.unwrap_or_else(|e| e.to_string())).await.unwrap();
+                lines.send(RESPONSE_END).await.unwrap();
+            }
+        });
+        Ok(addr)
+    }
+
+    async fn router() -> RouterResult<RouterSession> {
+        let shards = vec![spawn_shard().await?, spawn_shard().await?];
+        let mut router = RouterSession::connect(&shards).await?;
+        router.execute("create table t (id int primary key, v float, s string);").await?;
+        Ok(router)
+    }
+
+    #[tokio::test]
+    async fn test_routing() -> RouterResult<()> {
+        let mut router = router().await?;
+        let values = (1..=10).map(|i| format!("({}, {}.5, 'a |{}')", i, i, i)).collect::<Vec<_>>().join(", ");
+        assert_eq!(router.execute(&format!("insert into t values {};", values)).await?, "INSERT 10 rows");
+        // 每一行都在主键对应的分片上，两个分片都有数据
+        let mut counts = vec![0; router.shards.len()];
+        for i in 1..=10 {
+            let shard = router.shard_of(&Value::Integer(i))?;
+            let response = router.shards[shard].execute(&format!("{}select id from t where id = {};", ROWS_PREFIX, i)).await?;
+            assert_eq!(ResultSet::decode_rows(&response)?.unwrap().1, vec![vec![Value::Integer(i)]]);
+            counts[shard] += 1;
+        }
+        assert!(counts.iter().all(|count| *count > 0), "{:?}", counts);
+        // 合并所有分片的结果时保留原始的值，包括含有分隔符的字符串
+        let (columns, mut rows) = router.scan_rows("select id, s from t;").await?;
+        rows.sort_by_key(|row| match row[0] { Value::Integer(i) => i, _ => unreachable!() });
+        assert_eq!(columns, vec!["id".to_string(), "s".to_string()]);
+        assert_eq!(rows, (1..=10).map(|i| vec![Value::Integer(i), Value::String(format!("a |{}", i))]).collect::<Vec<_>>());
+        assert_eq!(router.execute("delete from t where v > 5.0;").await?, "DELETE 6 rows");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_update_primary_key() -> RouterResult<()> {
+        let mut router = router().await?;
+        router.execute("insert into t values (1, 1.5, 'a');").await?;
+        // 修改主键会让行留在错误的分片上
+        for sql in ["update t set id = 2 where id = 1;", "update t set id = id + 1;"] {
+            assert!(router.execute(sql).await.is_err(), "{}", sql);
+        }
+        assert_eq!(router.execute("update t set v = 2.5 where id = 1;").await?, "UPDATE 1 rows");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_float_round_trip() -> RouterResult<()> {
+        let float = |v: f64| Expression::Consts(Consts::Float(v));
+        assert_eq!(render_row(&[float(1.0), float(-0.5), Expression::Consts(Consts::String("it's".to_string()))]), "(1.0, -0.5, 'it''s')");
+        // 整数值的浮点数写入分片之后仍然是浮点数
+        let mut router = router().await?;
+        router.execute("insert into t values (1, 1.0, 'a'), (2, -3.0, 'b');").await?;
+        let (_, mut rows) = router.scan_rows("select v from t;").await?;
+        rows.sort_by(|a, b| a[0].partial_cmp(&b[0]).unwrap());
+        assert_eq!(rows, vec![vec![Value::Float(-3.0)], vec![Value::Float(1.0)]]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_fnv1a() {
+        // 哈希值固定不变，否则已有的数据会被路由到错误的分片
+        assert_eq!(fnv1a(b""), 0xcbf29ce484222325);
+        assert_eq!(fnv1a(b"a"), 0xaf63dc4c8601ec8c);
+        assert_eq!(fmix64(0), 0);
+        assert_eq!(fmix64(1), 0xb456bcfc34c2cb2c);
+    }
+}
@@ -4,26 +4,33 @@ use tokio_stream::StreamExt;
 use tokio_util::codec::{Framed, LinesCodec};
 
 use std::{env, fs, io};
+use std::collections::HashMap;
 use std::fs::File;
 use std::io::{BufRead, Read};
 use std::path::PathBuf;
-use std::sync::{Arc, Mutex, MutexGuard};
-use legend_db::custom_error::LegendDBResult;
-use legend_db::sql::engine::engine::{Engine, Session};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+use legend_db::custom_error::{LegendDBError, LegendDBResult};
+use legend_db::paths;
+use legend_db::sql::engine::audit::AuditLog;
+use legend_db::sql::engine::engine::{Engine, Session, Transaction};
 use legend_db::sql::engine::kv::KVEngine;
+use legend_db::sql::executor::executor::ROWS_PREFIX;
 use legend_db::storage::disk::DiskEngine;
+use legend_db::storage::engine::Engine as StorageEngine;
 
-const DB_PATH: &str = "/tmp/legend_db-test/legend_db-log";
 const RESPONSE_END: &str = "!!!end!!!";
-
-const  DEFAULT_DB_FOLDER:  &str = "/var/lib/legend_db/";
-const CURRENT_DB_FILE:  &str = "/var/lib/legend_db/current";
-
-const DB_CONFIG: &str = "/etc/legend_db/legend_db.conf";
+const RECOVERING: &str = "legend_db server is recovering, please retry later";
 
 /// Possible requests our clients can send us
 enum SqlRequest {
     SQL(String),
+    // BATCH stmt1; stmt2; ... 在一个事务中执行多条语句
+    Batch(String),
+    // ROWS stmt 返回结构化的查询结果，供分片路由合并结果
+    Rows(String),
+    Ping,
     ListTables,
     TableInfo(String),
     NoDatabase
@@ -32,23 +39,34 @@ enum SqlRequest {
 impl SqlRequest {
     pub fn parse(cmd: &str) -> Self {
         let upper_cmd = cmd.to_uppercase();
+        // 健康检查，不需要选择数据库
+        if upper_cmd.trim().trim_end_matches(';') == "PING" {
+            return SqlRequest::Ping;
+        }
+        // 切换、创建、删除数据库不需要提前选择数据库
+        if upper_cmd.starts_with("USE")
+            || upper_cmd.starts_with("CREATE DATABASE")
+            || upper_cmd.starts_with("DROP DATABASE") {
+            return SqlRequest::SQL(cmd.into());
+        }
         // 判断是否选择数据库，判断
-        if fs::metadata(CURRENT_DB_FILE).is_err() {
+        let current_db_file = paths::current_database_file();
+        if fs::metadata(&current_db_file).is_err() {
             return SqlRequest::NoDatabase;
         }
-        let current_db = fs::read_to_string(CURRENT_DB_FILE);
+        let current_db = fs::read_to_string(&current_db_file);
         if current_db.is_err() {
             return SqlRequest::NoDatabase;
         }
-        if upper_cmd.starts_with("USE") {
-            let args = upper_cmd.split_ascii_whitespace().collect::<Vec<_>>();
-            if args.len() == 2 {
-                return SqlRequest::SQL(cmd.into());
-            }
-        }
         if upper_cmd == "SHOW TABLES" {
             return SqlRequest::ListTables;
         }
+        if upper_cmd.starts_with("BATCH ") {
+            return SqlRequest::Batch(cmd["BATCH ".len()..].into());
+        }
+        if upper_cmd.starts_with(ROWS_PREFIX) {
+            return SqlRequest::Rows(cmd[ROWS_PREFIX.len()..].into());
+        }
         if upper_cmd.starts_with("SHOW TABLE") {
             let args = upper_cmd.split_ascii_whitespace().collect::<Vec<_>>();
             if args.len() == 3 {
@@ -62,15 +80,50 @@ impl SqlRequest {
 
 pub struct ServerSession<E: Engine> {
     session: Session<E>,
+    // 需要记录审计日志的数据库
+    audit_databases: Arc<Vec<String>>,
+    // 已经打开的审计日志，按数据库区分
+    audit_logs: HashMap<String, Arc<AuditLog>>,
+    // 只读设置
+    read_only: Arc<ReadOnly>,
+}
+
+// 只读模式，整个服务只读，或者部分数据库只读
+#[derive(Debug, Default)]
+struct ReadOnly {
+    all: bool,
+    databases: Vec<String>,
 }
 
 impl<E: Engine + 'static> ServerSession<E> {
-    pub fn new(eng: MutexGuard<E>) -> LegendDBResult<Self> {
+    fn new(eng: &E, audit_databases: Arc<Vec<String>>, read_only: Arc<ReadOnly>) -> LegendDBResult<Self> {
+        let mut session = eng.session()?;
+        session.read_only = read_only.all;
         Ok(Self {
-            session: eng.session()?,
+            session,
+            audit_databases,
+            audit_logs: HashMap::new(),
+            read_only,
         })
     }
 
+    // 根据当前的数据库切换只读模式和审计日志，审计日志存放在数据库目录下的 {database}.audit
+    fn switch_database(&mut self) -> LegendDBResult<()> {
+        let database = fs::read_to_string(paths::current_database_file()).unwrap_or_default();
+        let database = database.trim();
+        self.session.read_only = self.read_only.all || self.read_only.databases.iter().any(|db| db == database);
+        if !self.audit_databases.iter().any(|db| db == database) {
+            self.session.audit_log = None;
+            return Ok(());
+        }
+        if !self.audit_logs.contains_key(database) {
+            let audit_log = AuditLog::open(paths::audit_file(database))?;
+            self.audit_logs.insert(database.to_string(), Arc::new(audit_log));
+        }
+        self.session.audit_log = self.audit_logs.get(database).cloned();
+        Ok(())
+    }
+
     pub async fn handle_request(&mut self, socket: TcpStream) -> LegendDBResult<()> {
         let mut lines = Framed::new(socket, LinesCodec::new());
         while let Some(result) = lines.next().await {
@@ -81,9 +134,22 @@ impl<E: Engine + 'static> ServerSession<E> {
                     
                     // 执行请求
                     let response = match req {
-                        SqlRequest::NoDatabase => todo!("No database selected"),
-                        SqlRequest::SQL(sql) => match self.session.execute(&sql) {
-                            Ok(rs) => rs.to_string(),
+                        // 每条语句的结果依次返回
+                        SqlRequest::Batch(sql) => match self.switch_database().and_then(|_| self.session.execute_batch(&sql)) {
+                            Ok(results) => {
+                                let options = self.session.settings.display_options();
+                                results.iter().map(|rs| rs.render(&options)).collect::<Vec<_>>().join("\n")
+                            },
+                            Err(e) => e.to_string(),
+                        },
+                        SqlRequest::Rows(sql) => match self.switch_database().and_then(|_| self.session.execute(&sql)).and_then(|rs| rs.encode_rows()) {
+                            Ok(response) => response,
+                            Err(e) => e.to_string(),
+                        },
+                        SqlRequest::Ping => self.session.ping().map_or_else(|e| e.to_string(), |_| "PONG".to_string()),
+                        SqlRequest::NoDatabase => "No database selected, please execute `USE database_name;` first".to_string(),
+                        SqlRequest::SQL(sql) => match self.switch_database().and_then(|_| self.session.execute(&sql)) {
+                            Ok(rs) => rs.render(&self.session.settings.display_options()),
                             Err(e) => e.to_string(),
                         },
                         SqlRequest::ListTables => self.session.get_table_names().unwrap_or_else(|e| e.to_string()),
@@ -117,10 +183,21 @@ async fn main() -> LegendDBResult<()> {
     let mut addr = String::new();
     let mut port = String::new();
     let mut endpoint = String::from("0.0.0.0:8080");
-    if fs::metadata(CURRENT_DB_FILE).is_err() {
-        panic!("no config file")
+    let mut audit_databases = Vec::new();
+    let mut write_limits = Vec::new();
+    // 后台整理存储空间的间隔秒数，0 表示不整理
+    let mut compact_interval = 3600;
+    // --read-only 启动时整个服务只读
+    let mut read_only = ReadOnly {
+        all: env::args().any(|arg| arg == "--read-only"),
+        databases: Vec::new(),
+    };
+    // 配置文件默认在 /etc/legend_db 下，Windows 在 %ProgramData%\legend_db 下，可以通过环境变量 LEGEND_DB_CONFIG 指定
+    let config_path = paths::config_file();
+    if fs::metadata(&config_path).is_err() {
+        panic!("no config file {}", config_path.display())
     }
-    let config_file = File::open(DB_CONFIG)?;
+    let config_file = File::open(&config_path)?;
     let reader = io::BufReader::new(config_file);
     for line in reader.lines() {
         match line { 
@@ -133,6 +210,47 @@ async fn main() -> LegendDBResult<()> {
                         .trim()
                         .to_string();
                 }
+                // audit_log = db1,db2 对这些数据库记录审计日志
+                if line.starts_with("audit_log") {
+                    audit_databases = line
+                        .split('=')
+                        .nth(1)
+                        .unwrap_or_default()
+                        .split(',')
+                        .map(|db| db.trim().to_string())
+                        .filter(|db| !db.is_empty())
+                        .collect();
+                }
+                // read_only = db1,db2 这些数据库只读
+                if line.starts_with("read_only") {
+                    read_only.databases = line
+                        .split('=')
+                        .nth(1)
+                        .unwrap_or_default()
+                        .split(',')
+                        .map(|db| db.trim().to_string())
+                        .filter(|db| !db.is_empty())
+                        .collect();
+                }
+                // write_limit = table:t1:1000, table:t2:500 限制表每秒写入的行数
+                if line.starts_with("write_limit") {
+                    write_limits = parse_write_limits(line.split('=').nth(1).unwrap_or_default());
+                }
+                // compact_interval = 3600 每隔多少秒在后台整理一次存储空间
+                if line.starts_with("compact_interval") {
+                    let value = line.split('=').nth(1).unwrap_or_default().trim();
+                    match value.parse() {
+                        Ok(seconds) => compact_interval = seconds,
+                        Err(_) => println!("invalid compact_interval {}, expected seconds", value),
+                    }
+                }
+                // data-dir = /var/lib/legend_db 数据目录
+                if line.starts_with("data-dir") || line.starts_with("data_dir") {
+                    let dir = line.split('=').nth(1).unwrap_or_default().trim();
+                    if !dir.is_empty() {
+                        paths::set_data_dir(dir);
+                    }
+                }
                 if line.starts_with("port") {
                     port = line.clone()
                         .split('=')
@@ -154,16 +272,106 @@ async fn main() -> LegendDBResult<()> {
     let listener = TcpListener::bind(&endpoint).await?;
     println!("legend_db server starts, listening on: {addr}:{port}");
 
-    // 初始化 DB
-    let p = PathBuf::from(DB_PATH);
-    let kvengine = KVEngine::new(DiskEngine::new(p.clone())?);
-    let shared_engine = Arc::new(Mutex::new(kvengine));
+    // 初始化 DB，日志很大时恢复比较耗时，恢复期间拒绝客户端连接
+    fs::create_dir_all(paths::data_dir())?;
+    let recovery = tokio::task::spawn_blocking(|| open_engine(env::temp_dir().join("legend_db-test").join("legend_db-log")));
+    let kvengine = reject_while_recovering(&listener, recovery).await?;
+    for (table, rows_per_second) in write_limits {
+        kvengine.set_write_limit(&table, rows_per_second)?;
+    }
+    if compact_interval > 0 {
+        tokio::spawn(compact_periodically(kvengine.clone(), Duration::from_secs(compact_interval)));
+    }
+    println!("legend_db server is ready");
+    serve(listener, kvengine, Arc::new(audit_databases), Arc::new(read_only)).await
+}
+
+// 解析写入限流的配置 table:t1:1000，格式错误的项忽略
+fn parse_write_limits(value: &str) -> Vec<(String, u64)> {
+    value.split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .filter_map(|item| {
+            let limit = item.rsplit_once(':').and_then(|(target, rows)| {
+                let rows = rows.trim().parse().ok()?;
+                match target.split_once(':')? {
+                    ("table", name) => Some((name.to_string(), rows)),
+                    _ => None,
+                }
+            });
+            if limit.is_none() {
+                println!("invalid write_limit {}, expected table:<name>:<rows>", item);
+            }
+            limit
+        })
+        .collect()
+}
 
+// 定期在后台整理存储空间，整理在阻塞线程中执行，每一步只短暂持有存储引擎的锁，期间会话可以正常读写
+async fn compact_periodically<E: StorageEngine + Send + 'static>(engine: KVEngine<E>, period: Duration) {
+    let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+    loop {
+        interval.tick().await;
+        let kv = engine.kv.clone();
+        match tokio::task::spawn_blocking(move || kv.compact()).await {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => println!("compaction failed; error = {e:?}"),
+            Err(e) => println!("compaction task failed; error = {e:?}"),
+        }
+    }
+}
+
+// 打开数据库，打印恢复进度
+fn open_engine(path: PathBuf) -> LegendDBResult<KVEngine<DiskEngine>> {
+    let start = Instant::now();
+    let engine = DiskEngine::new_with_progress(path, &mut |done, total| {
+        let percent = if total == 0 { 100.0 } else { done as f64 * 100.0 / total as f64 };
+        // 按目前的速度估算剩余时间
+        let eta = if done == 0 { 0.0 } else { start.elapsed().as_secs_f64() * (total - done) as f64 / done as f64 };
+        println!("recovering: {done}/{total} bytes ({percent:.1}%), eta {eta:.1}s");
+    })?;
+    let kvengine = KVEngine::new(engine);
+    // 回滚上次异常退出时没有提交的事务
+    let recovered = kvengine.kv.recover()?;
+    if recovered > 0 {
+        println!("rolled back {recovered} unfinished transactions");
+    }
+    // 开启第一个事务时检查存储格式，无法读取的数据库直接启动失败
+    kvengine.begin()?.commit()?;
+    Ok(kvengine)
+}
+
+// 恢复完成之前，新的连接直接返回正在恢复的错误后关闭
+async fn reject_while_recovering<T>(listener: &TcpListener, mut recovery: JoinHandle<LegendDBResult<T>>) -> LegendDBResult<T> {
+    loop {
+        tokio::select! {
+            result = &mut recovery => return result.map_err(|e| LegendDBError::Internal(e.to_string()))?,
+            Ok((socket, _)) = listener.accept() => {
+                let mut lines = Framed::new(socket, LinesCodec::new());
+                if let Err(e) = lines.send(RECOVERING).await {
+                    println!("error on sending response; error = {e:?}");
+                }
+                if let Err(e) = lines.send(RESPONSE_END).await {
+                    println!("error on sending response; error = {e:?}");
+                }
+            }
+        }
+    }
+}
+
+// 接收客户端连接，每个连接一个会话
+// KVEngine 内部通过 Arc 共享存储，直接克隆即可，不需要在外面加锁，也就不会有锁跨越 await
+async fn serve<E: StorageEngine + Send + 'static>(listener: TcpListener, engine: KVEngine<E>, audit_databases: Arc<Vec<String>>, read_only: Arc<ReadOnly>) -> LegendDBResult<()> {
     loop {
         match listener.accept().await {
             Ok((socket, _)) => {
-                let db = shared_engine.clone();
-                let mut ss = ServerSession::new(db.lock()?)?;
+                let mut ss = match ServerSession::new(&engine, audit_databases.clone(), read_only.clone()) {
+                    Ok(ss) => ss,
+                    Err(e) => {
+                        println!("failed to create session; error = {e:?}");
+                        continue;
+                    }
+                };
 
                 tokio::spawn(async move {
                     match ss.handle_request(socket).await {
@@ -178,3 +386,120 @@ async fn main() -> LegendDBResult<()> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use legend_db::sql::executor::executor::ResultSet;
+    use legend_db::storage::memory::MemoryEngine;
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_connects() -> LegendDBResult<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        tokio::spawn(serve(listener, KVEngine::new(MemoryEngine::new()), Arc::new(Vec::new()), Arc::default()));
+        // 同时建立多个连接，每个连接都能得到完整的响应
+        let mut clients = Vec::new();
+        for _ in 0..16 {
+            clients.push(tokio::spawn(async move {
+                let mut lines = Framed::new(TcpStream::connect(addr).await.unwrap(), LinesCodec::new());
+                lines.send("show tables").await.unwrap();
+                let mut response = Vec::new();
+                while let Some(line) = lines.next().await {
+                    let line = line.unwrap();
+                    if line == RESPONSE_END {
+                        break;
+                    }
+                    response.push(line);
+                }
+                response
+            }));
+        }
+        for client in clients {
+            assert!(!client.await.unwrap().is_empty());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_write_limits() {
+        assert_eq!(
+            parse_write_limits(" table:t1:1000, table:t2:500, t3:10, table:t4:x, user:bulk:500,"),
+            vec![("t1".to_string(), 1000), ("t2".to_string(), 500)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_reject_while_recovering() -> LegendDBResult<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let (done, wait) = tokio::sync::oneshot::channel::<()>();
+        let recovery = tokio::spawn(async move {
+            wait.await.unwrap();
+            Ok(KVEngine::new(MemoryEngine::new()))
+        });
+        let server = tokio::spawn(async move {
+            let engine = reject_while_recovering(&listener, recovery).await?;
+            serve(listener, engine, Arc::new(Vec::new()), Arc::default()).await
+        });
+        // 恢复期间的连接收到正在恢复的错误
+        let mut lines = Framed::new(TcpStream::connect(addr).await?, LinesCodec::new());
+        assert_eq!(lines.next().await.unwrap().unwrap(), RECOVERING);
+        assert_eq!(lines.next().await.unwrap().unwrap(), RESPONSE_END);
+        // 恢复完成之后正常处理请求
+        done.send(()).unwrap();
+        let mut lines = loop {
+            let mut lines = Framed::new(TcpStream::connect(addr).await?, LinesCodec::new());
+            lines.send("PING").await.unwrap();
+            if lines.next().await.unwrap().unwrap() == "PONG" {
+                break lines;
+            }
+        };
+        assert_eq!(lines.next().await.unwrap().unwrap(), RESPONSE_END);
+        server.abort();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_ping() -> LegendDBResult<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        tokio::spawn(serve(listener, KVEngine::new(MemoryEngine::new()), Arc::new(Vec::new()), Arc::default()));
+        let mut lines = Framed::new(TcpStream::connect(addr).await?, LinesCodec::new());
+        for cmd in ["PING", "ping;"] {
+            lines.send(cmd).await.unwrap();
+            assert_eq!(lines.next().await.unwrap().unwrap(), "PONG");
+            assert_eq!(lines.next().await.unwrap().unwrap(), RESPONSE_END);
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_compact_periodically() -> LegendDBResult<()> {
+        let p = tempfile::tempdir()?.into_path().join("legend_db-log");
+        let engine = open_engine(p.clone())?;
+        let mut s = engine.session()?;
+        s.execute("create table t (a int primary key, b string);")?;
+        s.execute("insert into t values (1, 'a'), (2, 'b');")?;
+        for _ in 0..20 {
+            s.execute("update t set b = 'c';")?;
+        }
+        let size = fs::metadata(&p)?.len();
+        let task = tokio::spawn(compact_periodically(engine.clone(), Duration::from_millis(100)));
+        // 整理之后回收已经删除和覆盖的记录占用的空间，数据不变
+        // 第一次整理完成之后马上停止，下一次整理还没有开始，删除目录时不会有整理的临时文件
+        let mut compacted = false;
+        for _ in 0..400 {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+            if fs::metadata(&p)?.len() < size {
+                compacted = true;
+                break;
+            }
+        }
+        task.abort();
+        assert!(compacted);
+        assert!(matches!(s.execute("select b from t where b = 'c';")?, ResultSet::Scan { rows, .. } if rows.len() == 2));
+        fs::remove_dir_all(p.parent().unwrap())?;
+        Ok(())
+    }
+}
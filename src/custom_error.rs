@@ -7,10 +7,6 @@ use std::sync::{Arc, PoisonError};
 use bincode::error::DecodeError;
 use bincode::error::EncodeError;
 
-// 数据库默认存储路径
-pub static DEFAULT_DB_FOLDER: &'static str = "/var/lib/legend_db/";
-pub static CURRENT_DB_FILE: &'static str = "/var/lib/legend_db/current";
-
 // 自定义Result
 pub type LegendDBResult<T> = Result<T, LegendDBError>;
 
@@ -31,20 +27,49 @@ pub enum LegendDBError {
     TryFromSliceError(String),
     #[error("parse error: {0}")]
     Parser(String),
+    // 带位置的语法错误，near 是出错位置的 Token 原文
+    #[error("syntax error at line {line}, column {column} near {}: {message}", .near.as_ref().map_or("end of input".to_string(), |near| format!("'{}'", near)))]
+    Syntax {
+        message: String,
+        // 字节偏移
+        offset: usize,
+        line: usize,
+        column: usize,
+        near: Option<String>,
+    },
     #[error("not supported")]
     NotSupported,
     #[error("internal error {0}")]
     Internal(String),
     #[error("table exists: {0}")]
     TableExist(String),
+    #[error("semantic error: {}", .0.join("; "))]
+    Semantic(Vec<String>),
     #[error("table not exists: {0}")]
     TableNotFound(String),
     #[error("decode error: {0}")]
     DecodeError(String),
     #[error("encode error: {0}")]
     EncodeError(String),
-    #[error("write mvcc conflict")]
-    WriteMvccConflict,
+    // 数据库文件被其他进程打开，holder 描述持有锁的进程
+    #[error("database file {path} is locked by {holder}")]
+    DatabaseLocked { path: String, holder: String },
+    #[error("duplicate entry {key} for key {column} in table {table}")]
+    DuplicateKey { table: String, column: String, key: String },
+    #[error("write mvcc conflict on key {key}: transaction {version} conflicts with {} transaction {conflict_version}", if *.conflict_active { "active" } else { "committed" })]
+    WriteMvccConflict {
+        // 发生冲突的key，不可打印的字节会被转义
+        key: String,
+        // 当前事务的版本号
+        version: u64,
+        // 与之冲突的事务版本号
+        conflict_version: u64,
+        // 冲突的事务是否还没有提交
+        conflict_active: bool,
+    },
+    // 写入超过了表或者用户的限流，客户端等待 retry_after_ms 毫秒之后重试
+    #[error("write rate limit exceeded for {target}, retry after {retry_after_ms} ms")]
+    WriteThrottled { target: String, retry_after_ms: u64 },
     #[error("serializer error: {0}")]
     SerializerError(String),
     #[error("deserializer error: {0}")]
@@ -1,3 +1,4 @@
 pub mod sql;
 pub mod storage;
-pub mod custom_error;
\ No newline at end of file
+pub mod custom_error;
+pub mod paths;
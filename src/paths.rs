@@ -0,0 +1,80 @@
+// 数据库使用的文件路径
+// 类 Unix 系统默认使用 /var/lib/legend_db 和 /etc/legend_db，Windows 默认使用 %ProgramData%\legend_db
+// 数据目录可以通过环境变量或者服务端配置文件中的 data-dir 指定，配置文件可以通过环境变量指定
+
+use std::env;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+// 指定数据目录的环境变量
+pub const DATA_DIR_ENV: &str = "LEGEND_DB_DATA_DIR";
+// 指定配置文件的环境变量
+pub const CONFIG_ENV: &str = "LEGEND_DB_CONFIG";
+
+static DATA_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+// 设置数据目录，需要在第一次使用数据目录之前设置，已经确定数据目录时返回 false
+pub fn set_data_dir(dir: impl Into<PathBuf>) -> bool {
+    DATA_DIR.set(dir.into()).is_ok()
+}
+
+// 数据目录，优先使用 set_data_dir 设置的目录，其次是环境变量，最后是平台默认目录
+pub fn data_dir() -> &'static Path {
+    DATA_DIR.get_or_init(|| env::var_os(DATA_DIR_ENV).map(PathBuf::from).unwrap_or_else(default_dir))
+}
+
+// 配置文件路径
+pub fn config_file() -> PathBuf {
+    match env::var_os(CONFIG_ENV) {
+        Some(path) => PathBuf::from(path),
+        None if cfg!(windows) => default_dir().join("legend_db.conf"),
+        None => PathBuf::from("/etc/legend_db/legend_db.conf"),
+    }
+}
+
+// 数据库文件 {data_dir}/{name}.db
+pub fn database_file(name: &str) -> PathBuf {
+    data_dir().join(format!("{name}.db"))
+}
+
+// 记录当前数据库的文件
+pub fn current_database_file() -> PathBuf {
+    data_dir().join("current")
+}
+
+// 审计日志 {data_dir}/{name}.audit
+pub fn audit_file(name: &str) -> PathBuf {
+    data_dir().join(format!("{name}.audit"))
+}
+
+fn default_dir() -> PathBuf {
+    if cfg!(windows) {
+        env::var_os("ProgramData")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from(r"C:\ProgramData"))
+            .join("legend_db")
+    } else {
+        PathBuf::from("/var/lib/legend_db")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_paths() {
+        // 文件都在数据目录下，使用平台的路径分隔符拼接
+        for (path, name) in [
+            (database_file("db1"), "db1.db"),
+            (current_database_file(), "current"),
+            (audit_file("db1"), "db1.audit"),
+        ] {
+            assert_eq!(path.parent(), Some(data_dir()));
+            assert_eq!(path.file_name().unwrap(), name);
+        }
+        assert_eq!(config_file().file_name().unwrap(), "legend_db.conf");
+        // 数据目录确定之后不能再修改
+        assert!(!set_data_dir("other"));
+    }
+}
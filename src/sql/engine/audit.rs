@@ -0,0 +1,111 @@
+// 审计日志，记录执行成功的 DDL 和 DML 语句
+// 日志文件只追加写入，每条语句一行，字段之间用制表符分隔
+//
+// 时间戳(毫秒)    语句类型    影响的行数    SQL
+//
+// DDL 语句没有影响的行数，记为 -
+
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::sql::executor::executor::ResultSet;
+use crate::custom_error::{LegendDBError, LegendDBResult};
+
+pub struct AuditLog {
+    file: Mutex<File>,
+}
+
+impl AuditLog {
+    // 打开审计日志文件，不存在则创建
+    pub fn open(path: impl AsRef<Path>) -> LegendDBResult<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            file: Mutex::new(file),
+        })
+    }
+
+    // 记录一条执行成功的语句，查询和会话相关的语句不记录
+    pub fn record(&self, sql: &str, result: &ResultSet) -> LegendDBResult<()> {
+        let (kind, rows) = match result {
+            ResultSet::CreateDatabase { .. } => ("CREATE DATABASE", None),
+            ResultSet::DropDatabase { .. } => ("DROP DATABASE", None),
+            ResultSet::CreateTable { .. } => ("CREATE TABLE", None),
+            ResultSet::DropTable { .. } => ("DROP TABLE", None),
+            ResultSet::AlterTable { .. } => ("ALTER TABLE", None),
+            ResultSet::Insert { count } => ("INSERT", Some(*count)),
+            ResultSet::Update { count } => ("UPDATE", Some(*count)),
+            ResultSet::Delete { count } => ("DELETE", Some(*count)),
+            ResultSet::UseDatabase { .. } | ResultSet::Scan { .. } | ResultSet::Order { .. } | ResultSet::Set { .. }
+            | ResultSet::Explain { .. } => return Ok(()),
+        };
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)
+            .map_err(|e| LegendDBError::Internal(e.to_string()))?
+            .as_millis();
+        let rows = rows.map_or("-".to_string(), |n| n.to_string());
+        // SQL 中的换行和制表符会破坏日志格式，替换为空格
+        let sql = sql.trim().replace(['\n', '\r', '\t'], " ");
+        let mut file = self.file.lock()?;
+        writeln!(file, "{}\t{}\t{}\t{}", timestamp, kind, rows, sql)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use crate::sql::engine::audit::AuditLog;
+    use crate::sql::engine::engine::Engine;
+    use crate::sql::engine::kv::KVEngine;
+    use crate::sql::executor::executor::ResultSet;
+    use crate::sql::types::Value;
+    use crate::storage::memory::MemoryEngine;
+    use crate::custom_error::LegendDBResult;
+
+    #[test]
+    fn test_audit_log() -> LegendDBResult<()> {
+        let path = tempfile::tempdir()?.into_path().join("test.audit");
+        let mut s = KVEngine::new(MemoryEngine::new()).session()?;
+        s.audit_log = Some(Arc::new(AuditLog::open(&path)?));
+        s.execute("create table t1 (a int primary key, b text);")?;
+        s.execute("insert into t1 values (1, 'a'),\n(2, 'b');")?;
+        s.execute("select * from t1;")?;
+        s.execute("set max_display_rows = 10;")?;
+        assert!(s.execute("insert into t1 values (1, 'a');").is_err());
+        s.execute("update t1 set b = 'c' where a = 1;")?;
+        s.execute("delete from t1;")?;
+
+        let content = std::fs::read_to_string(&path)?;
+        let lines = content.lines()
+            .map(|l| l.split_once('\t').map(|(_, rest)| rest.to_string()).unwrap_or_default())
+            .collect::<Vec<_>>();
+        assert_eq!(lines, vec![
+            "CREATE TABLE\t-\tcreate table t1 (a int primary key, b text);",
+            "INSERT\t2\tinsert into t1 values (1, 'a'), (2, 'b');",
+            "UPDATE\t1\tupdate t1 set b = 'c' where a = 1;",
+            "DELETE\t2\tdelete from t1;",
+        ]);
+        std::fs::remove_dir_all(path.parent().unwrap())?;
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_audit_log_failure() -> LegendDBResult<()> {
+        // 写入 /dev/full 总是失败，语句已经提交，审计日志写入失败不影响语句的结果
+        let engine = KVEngine::new(MemoryEngine::new());
+        let mut s = engine.session()?;
+        s.audit_log = Some(Arc::new(AuditLog::open("/dev/full")?));
+        s.execute("create table t1 (a int primary key);")?;
+        s.execute("insert into t1 values (1);")?;
+        s.execute_batch("insert into t1 values (2); insert into t1 values (3);")?;
+        s.execute("set dml_batch_size = 2;")?;
+        s.execute("delete from t1 where a > 2;")?;
+        match engine.session()?.execute("select * from t1;")? {
+            ResultSet::Scan { rows, .. } => assert_eq!(rows, vec![vec![Value::Integer(1)], vec![Value::Integer(2)]]),
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+}
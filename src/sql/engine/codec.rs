@@ -0,0 +1,89 @@
+// 表结构、行数据和 key 的编解码
+// 存储格式统一通过 Codec 接口读写，每个数据库记录写入数据时使用的编解码器，
+// 以后修改存储格式（比如根据表结构编码、压缩）时，新的编解码器可以声明兼容旧的格式，迁移期间新旧数据可以共存
+
+use std::fmt::Debug;
+use bincode::config;
+use crate::sql::engine::kv::{KeyPrefix, TransactionKey};
+use crate::sql::engine::row_codec;
+use crate::sql::schema::Table;
+use crate::sql::types::Row;
+use crate::custom_error::LegendDBResult;
+use crate::storage::keycode::{deserializer, serializer};
+
+pub trait Codec: Debug + Send + Sync {
+    // 编解码器的名字，记录在数据库中，格式变化时需要使用新的名字
+    fn name(&self) -> &str;
+
+    // 是否能读取指定编解码器写入的数据，默认只能读取自己写入的数据
+    fn can_read(&self, name: &str) -> bool {
+        name == self.name()
+    }
+
+    // key 的编码需要保持顺序，并且前缀的编码是 key 的编码的前缀
+    fn encode_key(&self, key: &TransactionKey) -> LegendDBResult<Vec<u8>>;
+
+    fn encode_prefix(&self, prefix: &KeyPrefix) -> LegendDBResult<Vec<u8>>;
+
+    fn decode_key(&self, data: &[u8]) -> LegendDBResult<TransactionKey>;
+
+    fn encode_table(&self, table: &Table) -> LegendDBResult<Vec<u8>>;
+
+    fn decode_table(&self, data: &[u8]) -> LegendDBResult<Table>;
+
+    fn encode_row(&self, table: &Table, row: &Row) -> LegendDBResult<Vec<u8>>;
+
+    fn decode_row(&self, table: &Table, data: &[u8]) -> LegendDBResult<Row>;
+
+    // 只解码指定位置的列，默认解码整行之后再取出需要的列
+    fn decode_columns(&self, table: &Table, data: &[u8], indexes: &[usize]) -> LegendDBResult<Row> {
+        let row = self.decode_row(table, data)?;
+        Ok(indexes.iter().map(|&i| row[i].clone()).collect())
+    }
+}
+
+// 默认的编解码器，key 使用 keycode 编码，表结构使用 bincode 编码，行数据根据表结构紧凑编码
+#[derive(Debug, Default, Clone, Copy)]
+pub struct DefaultCodec;
+
+impl DefaultCodec {
+    pub const NAME: &'static str = "legend-v1";
+}
+
+impl Codec for DefaultCodec {
+    fn name(&self) -> &str {
+        Self::NAME
+    }
+
+    fn encode_key(&self, key: &TransactionKey) -> LegendDBResult<Vec<u8>> {
+        serializer(key)
+    }
+
+    fn encode_prefix(&self, prefix: &KeyPrefix) -> LegendDBResult<Vec<u8>> {
+        serializer(prefix)
+    }
+
+    fn decode_key(&self, data: &[u8]) -> LegendDBResult<TransactionKey> {
+        deserializer(data)
+    }
+
+    fn encode_table(&self, table: &Table) -> LegendDBResult<Vec<u8>> {
+        Ok(bincode::encode_to_vec(table, config::standard())?)
+    }
+
+    fn decode_table(&self, data: &[u8]) -> LegendDBResult<Table> {
+        Ok(bincode::decode_from_slice(data, config::standard()).map(|(table, _)| table)?)
+    }
+
+    fn encode_row(&self, table: &Table, row: &Row) -> LegendDBResult<Vec<u8>> {
+        row_codec::encode_row(table, row)
+    }
+
+    fn decode_row(&self, table: &Table, data: &[u8]) -> LegendDBResult<Row> {
+        row_codec::decode_row(table, data)
+    }
+
+    fn decode_columns(&self, table: &Table, data: &[u8], indexes: &[usize]) -> LegendDBResult<Row> {
+        row_codec::decode_columns(table, data, indexes)
+    }
+}
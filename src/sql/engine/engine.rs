@@ -1,7 +1,10 @@
-use crate::sql::executor::executor::ResultSet;
-use crate::sql::parser::ast::Expression;
+use std::sync::Arc;
+use crate::sql::engine::audit::AuditLog;
+use crate::sql::executor::executor::{DisplayOptions, ResultSet, StringOutput};
+use crate::sql::parser::ast::{Consts, Expression, FromItem, Operation, OrderDirection, Statement};
 use crate::sql::parser::parser::Parser;
 use crate::sql::plan::node::Plan;
+use crate::sql::plan::resolver::Resolver;
 use crate::sql::schema::Table;
 use crate::sql::types::{Row, Value};
 use crate::custom_error::{LegendDBError, LegendDBResult};
@@ -16,8 +19,16 @@ pub trait Engine: Clone{
         Ok(Session {
             engine: self.clone(),
             transaction: None,
+            settings: SessionSettings::default(),
+            audit_log: None,
+            read_only: false,
         })
     }
+
+    // 创建多个会话，每个会话可以移动到不同的线程中使用
+    fn sessions(&self, n: usize) -> LegendDBResult<Vec<Session<Self>>> {
+        (0..n).map(|_| self.session()).collect()
+    }
 }
 
 
@@ -31,12 +42,18 @@ pub trait Transaction {
     // 回滚事务
     fn rollback(&self) -> LegendDBResult<()>;
 
+    // 设置批量导入模式，默认不支持
+    fn set_bulk_load(&mut self, _bulk_load: bool) {}
+
     // 创建数据库
     fn create_database(&self, name: &str) -> LegendDBResult<()>;
 
     // 删除数据库
     fn drop_database(&self, name: &str) -> LegendDBResult<()>;
 
+    // 判断数据库是否存在
+    fn database_exists(&self, name: &str) -> LegendDBResult<bool>;
+
     // 切换数据库
     fn use_database(&self, database_name: &str) -> LegendDBResult<()>;
 
@@ -44,7 +61,10 @@ pub trait Transaction {
     fn create_table(&mut self, table: Table) -> LegendDBResult<()>;
 
     // 删除表
-    fn drop_table(&self, name: &str) -> LegendDBResult<()>;
+    fn drop_table(&mut self, name: &str) -> LegendDBResult<()>;
+
+    // 更新表结构，表必须已经存在
+    fn update_table(&mut self, table: Table) -> LegendDBResult<()>;
 
     //创建行
     fn create_row(&mut self, table: String, row: Row) -> LegendDBResult<()>;
@@ -52,11 +72,29 @@ pub trait Transaction {
     // 更新行
     fn update_row(&mut self, table: &Table, id: &Value, row: Row) -> LegendDBResult<()>;
 
+    // 用新的表结构重写一行，不维护唯一约束的索引，修改表结构之后需要调用 rebuild_unique_keys
+    fn rewrite_row(&mut self, table: &Table, id: &Value, row: Row) -> LegendDBResult<()>;
+
+    // 重建表的唯一约束索引，已有的数据中有重复的值时报错
+    fn rebuild_unique_keys(&mut self, table: &Table) -> LegendDBResult<()>;
+
     // 删除行
     fn delete_row(&mut self, table: &Table, id: &Value) -> LegendDBResult<()>;
 
     // 扫描表
-    fn scan_table(&mut self, table_name: String, filter: Option<Vec<Expression>>) -> LegendDBResult<Vec<Row>>;
+    fn scan_table(&mut self, table_name: String, filter: Option<Expression>) -> LegendDBResult<Vec<Row>>;
+
+    // 按主键批量读取行，不存在的主键跳过，返回的行按主键排序，重复的主键只返回一次
+    fn read_rows(&mut self, table: &Table, ids: Vec<Value>) -> LegendDBResult<Vec<Row>>;
+
+    // 扫描表，只返回指定的列
+    fn scan_table_columns(&mut self, table_name: String, filter: Option<Expression>, columns: &[String]) -> LegendDBResult<Vec<Row>>;
+
+    // 统计表的行数，有行数统计时直接返回，否则扫描全表
+    fn count_rows(&mut self, table_name: &str) -> LegendDBResult<usize>;
+
+    // 检查表中的数据，包括行的编码、类型、长度和范围、检查约束以及唯一约束的索引，返回发现的问题
+    fn check_table(&mut self, table_name: &str) -> LegendDBResult<Vec<String>>;
 
     //获取表信息
     fn get_table(&self, table: String) -> LegendDBResult<Option<Table>>;
@@ -75,6 +113,147 @@ pub trait Transaction {
 pub struct Session<E: Engine> {
     pub engine: E,
     pub transaction: Option<E::Transaction>,
+    // 会话变量，通过 SET name = value 修改
+    pub settings: SessionSettings,
+    // 审计日志，为 None 时不记录
+    pub audit_log: Option<Arc<AuditLog>>,
+    // 只读模式，拒绝修改数据和表结构的语句，不能通过 SET 修改
+    pub read_only: bool,
+}
+
+// 会话变量定义
+#[derive(Debug, Clone, Default)]
+pub struct SessionSettings {
+    // 安全模式，开启后 DELETE 和 UPDATE 必须带 WHERE 条件
+    pub safe_updates: bool,
+    // 结果展示时每一列的最大宽度，0 表示不限制
+    pub max_column_width: Option<usize>,
+    // 结果展示时最多展示的行数，0 表示不限制
+    pub max_display_rows: Option<usize>,
+    // SQL模式
+    pub sql_mode: SqlMode,
+    // 批量导入模式，插入时延迟到提交时检查主键重复
+    pub bulk_load: bool,
+    // 结果展示时字符串的展示方式 escaped/hex/raw
+    pub string_output: StringOutput,
+    // 查询结果的顺序固定，排序的值相同时再按主键排序，没有 order by 的查询也按主键排序
+    pub deterministic_order: bool,
+    // 严格计数，开启后 select count(*) 也扫描全表，不使用行数统计
+    pub strict_count: bool,
+    // DELETE 和 UPDATE 分批执行，每批处理的行数，0 表示不分批
+    pub dml_batch_size: Option<usize>,
+    // 优化规则的开关，用于对比执行计划或者绕过优化器的问题
+    pub optimizer: OptimizerRules,
+}
+
+// 优化规则，默认全部开启，通过 SET enable_xxx = off 关闭
+#[derive(Debug, Clone, PartialEq)]
+pub struct OptimizerRules {
+    // 单表的过滤条件下推到扫描中执行，关闭时扫描全表之后再过滤
+    pub predicate_pushdown: bool,
+    // 扫描时只解码投影和排序用到的列，关闭时解码所有的列
+    pub column_pruning: bool,
+    // 聚合只依赖 join 一边的列时，先在这一边按 join 的列预聚合，再做 join
+    pub aggregate_pushdown: bool,
+}
+
+impl Default for OptimizerRules {
+    fn default() -> Self {
+        Self {
+            predicate_pushdown: true,
+            column_pruning: true,
+            aggregate_pushdown: true,
+        }
+    }
+}
+
+// SQL模式
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum SqlMode {
+    // 严格模式，非空列插入NULL时报错
+    #[default]
+    Strict,
+    // MySQL兼容模式，非空列插入NULL时使用列的默认值
+    MySQL,
+}
+
+impl SqlMode {
+    pub fn parse(mode: &str) -> Option<Self> {
+        match mode.to_uppercase().as_ref() {
+            "STRICT" => Some(SqlMode::Strict),
+            "MYSQL" => Some(SqlMode::MySQL),
+            _ => None,
+        }
+    }
+}
+
+impl SessionSettings {
+    // 设置会话变量
+    pub fn set(&mut self, name: &str, value: &Value) -> LegendDBResult<()> {
+        match name {
+            "safe_updates" => self.safe_updates = Self::to_bool(name, value)?,
+            "bulk_load" => self.bulk_load = Self::to_bool(name, value)?,
+            "deterministic_order" => self.deterministic_order = Self::to_bool(name, value)?,
+            "strict_count" => self.strict_count = Self::to_bool(name, value)?,
+            "max_column_width" => self.max_column_width = Self::to_limit(name, value)?,
+            "max_display_rows" => self.max_display_rows = Self::to_limit(name, value)?,
+            "dml_batch_size" => self.dml_batch_size = Self::to_limit(name, value)?,
+            "enable_predicate_pushdown" => self.optimizer.predicate_pushdown = Self::to_bool(name, value)?,
+            "enable_column_pruning" => self.optimizer.column_pruning = Self::to_bool(name, value)?,
+            "enable_aggregate_pushdown" => self.optimizer.aggregate_pushdown = Self::to_bool(name, value)?,
+            "sql_mode" => self.sql_mode = match value {
+                Value::String(mode) => SqlMode::parse(mode),
+                _ => None,
+            }.ok_or(LegendDBError::Internal(format!("invalid value {} for session variable {}", value, name)))?,
+            "string_output" => self.string_output = match value {
+                Value::String(output) => StringOutput::parse(output),
+                _ => None,
+            }.ok_or(LegendDBError::Internal(format!("invalid value {} for session variable {}", value, name)))?,
+            _ => return Err(LegendDBError::Internal(format!("unknown session variable {}", name))),
+        }
+        Ok(())
+    }
+
+    // 执行前根据会话变量校验语句
+    pub fn check(&self, stmt: &Statement) -> LegendDBResult<()> {
+        if self.safe_updates && let Statement::Delete { table_name, where_clause: None } = stmt {
+            return Err(LegendDBError::Internal(format!("safe_updates is on, DELETE from table {} requires a WHERE clause", table_name)));
+        }
+        if self.safe_updates && let Statement::Update { table_name, where_clause: None, .. } = stmt {
+            return Err(LegendDBError::Internal(format!("safe_updates is on, UPDATE of table {} requires a WHERE clause", table_name)));
+        }
+        Ok(())
+    }
+
+    // 结果集的展示选项
+    pub fn display_options(&self) -> DisplayOptions {
+        DisplayOptions {
+            max_column_width: self.max_column_width,
+            max_rows: self.max_display_rows,
+            string_output: self.string_output,
+        }
+    }
+
+    // 限制类型的变量，0 表示不限制
+    fn to_limit(name: &str, value: &Value) -> LegendDBResult<Option<usize>> {
+        match value {
+            Value::Integer(0) => Ok(None),
+            Value::Integer(n) if *n > 0 => Ok(Some(*n as usize)),
+            _ => Err(LegendDBError::Internal(format!("invalid value {} for session variable {}", value, name))),
+        }
+    }
+
+    // 布尔类型的变量支持 true/false 以及 1/0
+    fn to_bool(name: &str, value: &Value) -> LegendDBResult<bool> {
+        match value {
+            Value::Boolean(b) => Ok(*b),
+            Value::Integer(1) => Ok(true),
+            Value::Integer(0) => Ok(false),
+            Value::String(s) if s.eq_ignore_ascii_case("on") => Ok(true),
+            Value::String(s) if s.eq_ignore_ascii_case("off") => Ok(false),
+            _ => Err(LegendDBError::Internal(format!("invalid value {} for session variable {}", value, name))),
+        }
+    }
 }
 
 #[allow(unused)]
@@ -82,12 +261,25 @@ impl<E: Engine + 'static> Session<E>  {
     // 执行客户端SQL语句
     pub fn execute(&mut self, sql: &str) -> LegendDBResult<ResultSet> {
         match Parser::new(sql).parse()? {
+            // 会话变量只影响当前会话，不需要开启事务
+            Statement::Set { name, value } => Self::set_variable(&mut self.settings, name, value),
+            stmt @ (Statement::Delete { .. } | Statement::Update { .. }) if self.settings.dml_batch_size.is_some() => {
+                let batch_size = self.settings.dml_batch_size.unwrap_or_default();
+                self.execute_in_batches(sql, stmt, batch_size)
+            },
             stmt => {
+                self.check_statement(&stmt)?;
                 let mut txn = self.engine.begin()?;
-                // 构建执行计划Plan，执行sql
-                match Plan::build(stmt)?.execute(&mut txn) {
+                txn.set_bulk_load(self.settings.bulk_load);
+                // 语义检查之后构建执行计划Plan，执行sql
+                let result = Resolver::new(&txn).resolve(&stmt)
+                    .and_then(|_| Plan::build_with_settings(stmt, &self.settings))
+                    .and_then(|plan| plan.execute(&mut txn));
+                match result {
                     Ok(result) => {
                         txn.commit()?;
+                        // 提交成功之后才记录审计日志
+                        self.audit(sql, &result);
                         Ok(result)
                     }
                     Err(err) => {
@@ -99,6 +291,159 @@ impl<E: Engine + 'static> Session<E>  {
         }
     }
     
+    // 在一个事务中执行多条语句，全部成功才提交，返回每条语句的结果
+    pub fn execute_batch(&mut self, sql: &str) -> LegendDBResult<Vec<ResultSet>> {
+        let stmts = Parser::new(sql).parse_batch()?;
+        for stmt in stmts.iter() {
+            self.check_statement(stmt)?;
+        }
+        let mut txn = self.engine.begin()?;
+        txn.set_bulk_load(self.settings.bulk_load);
+        let mut results = Vec::new();
+        // 审计日志中按语句记录规范化后的 SQL
+        let mut statements = Vec::new();
+        // SET 修改的是副本，之后的语句按修改后的值执行，提交成功之后才生效，失败时会话变量保持不变
+        let mut settings = self.settings.clone();
+        for stmt in stmts {
+            statements.push(stmt.to_sql());
+            let result = match stmt {
+                Statement::Set { name, value } => Self::set_variable(&mut settings, name, value),
+                stmt => Resolver::new(&txn).resolve(&stmt)
+                    .and_then(|_| Plan::build_with_settings(stmt, &settings))
+                    .and_then(|plan| plan.execute(&mut txn)),
+            };
+            match result {
+                Ok(result) => results.push(result),
+                Err(err) => {
+                    txn.rollback()?;
+                    return Err(err);
+                }
+            }
+        }
+        txn.commit()?;
+        self.settings = settings;
+        for (stmt, result) in statements.iter().zip(results.iter()) {
+            self.audit(stmt, result);
+        }
+        Ok(results)
+    }
+
+    // 分批执行 DELETE 和 UPDATE，按主键的顺序每批处理 batch_size 行，每批在自己的事务中提交
+    // 避免一个事务的写集合过大、冲突窗口过长；中途失败时已经提交的批次不会回滚
+    fn execute_in_batches(&mut self, sql: &str, stmt: Statement, batch_size: usize) -> LegendDBResult<ResultSet> {
+        self.check_statement(&stmt)?;
+        let mut total = 0;
+        // 上一批处理的最大主键
+        let mut last = None;
+        loop {
+            let mut txn = self.engine.begin()?;
+            txn.set_bulk_load(self.settings.bulk_load);
+            match self.execute_chunk(&mut txn, &stmt, batch_size, &mut last) {
+                Ok((scanned, count)) => {
+                    txn.commit()?;
+                    total += count;
+                    if scanned < batch_size {
+                        break;
+                    }
+                },
+                Err(err) => {
+                    txn.rollback()?;
+                    return Err(err);
+                },
+            }
+        }
+        let result = match stmt {
+            Statement::Delete { .. } => ResultSet::Delete { count: total },
+            _ => ResultSet::Update { count: total },
+        };
+        self.audit(sql, &result);
+        Ok(result)
+    }
+
+    // 执行一批：先按主键顺序查出上一批之后满足条件的 batch_size 行，再对这些主键的范围执行语句
+    // 返回这一批扫描到的行数和实际修改的行数
+    fn execute_chunk(&self, txn: &mut E::Transaction, stmt: &Statement, batch_size: usize, last: &mut Option<Value>) -> LegendDBResult<(usize, usize)> {
+        let (table_name, where_clause) = match stmt {
+            Statement::Delete { table_name, where_clause } | Statement::Update { table_name, where_clause, .. } => (table_name, where_clause),
+            _ => return Err(LegendDBError::Internal("only DELETE and UPDATE can be executed in batches".to_string())),
+        };
+        Resolver::new(&*txn).resolve(stmt)?;
+        let table = txn.get_table_must(table_name.clone())?;
+        let pk = table.get_primary_key_name()?.to_string();
+        if let Statement::Update { columns, .. } = stmt && columns.contains_key(&pk) {
+            return Err(LegendDBError::Internal(format!("cannot update primary key {} of table {} in batches", pk, table_name)));
+        }
+        let and = |left: Option<Expression>, right: Expression| Some(match left {
+            Some(left) => Expression::Operation(Operation::And(Box::new(left), Box::new(right))),
+            None => right,
+        });
+        let field = || Box::new(Expression::Field(pk.clone()));
+        let mut filter = where_clause.clone();
+        if let Some(last) = last.as_ref() {
+            filter = and(filter, Expression::Operation(Operation::GreaterThan(field(), Box::new(last.to_expression()))));
+        }
+        let select = Statement::Select {
+            columns: vec![(Expression::Field(pk.clone()), None)],
+            from: FromItem::Table { name: table_name.clone(), alias: None },
+            where_clause: filter.clone(),
+            group_by: None,
+            having: None,
+            order_by: vec![(Expression::Field(pk.clone()), OrderDirection::Asc)],
+            limit: Some(Expression::Consts(Consts::Integer(batch_size as i64))),
+            offset: None,
+        };
+        let keys = match Plan::build_with_settings(select, &self.settings)?.execute(txn)? {
+            ResultSet::Scan { rows, .. } => rows.into_iter().filter_map(|row| row.into_iter().next()).collect::<Vec<_>>(),
+            _ => return Err(LegendDBError::Internal(format!("failed to scan primary keys of table {}", table_name))),
+        };
+        let (Some(first), Some(max)) = (keys.first(), keys.last()) else {
+            return Ok((0, 0));
+        };
+        let filter = and(filter, Expression::Operation(Operation::And(
+            Box::new(Expression::Operation(Operation::GreaterThanOrEqual(field(), Box::new(first.to_expression())))),
+            Box::new(Expression::Operation(Operation::LessThanOrEqual(field(), Box::new(max.to_expression())))),
+        )));
+        let chunk = match stmt.clone() {
+            Statement::Delete { table_name, .. } => Statement::Delete { table_name, where_clause: filter },
+            Statement::Update { table_name, columns, .. } => Statement::Update { table_name, columns, where_clause: filter },
+            _ => unreachable!(),
+        };
+        *last = Some(max.clone());
+        let count = match Plan::build_with_settings(chunk, &self.settings)?.execute(txn)? {
+            ResultSet::Delete { count } | ResultSet::Update { count } => count,
+            _ => 0,
+        };
+        Ok((keys.len(), count))
+    }
+
+    // 设置会话变量
+    fn set_variable(settings: &mut SessionSettings, name: String, value: Expression) -> LegendDBResult<ResultSet> {
+        let value = match value {
+            // SET safe_updates = on，标识符当作字符串处理
+            Expression::Field(ident) => Value::String(ident),
+            Expression::Consts(_) => Value::from_expression(value),
+            expr => return Err(LegendDBError::Internal(format!("invalid value {:?} for session variable {}", expr, name))),
+        };
+        settings.set(&name, &value)?;
+        Ok(ResultSet::Set { name, value })
+    }
+
+    // 记录审计日志，语句已经提交，写入失败时只打印错误，不影响语句的结果
+    fn audit(&self, sql: &str, result: &ResultSet) {
+        if let Some(audit_log) = &self.audit_log
+            && let Err(e) = audit_log.record(sql, result) {
+            println!("error on writing audit log; error = {e:?}");
+        }
+    }
+
+    // 执行之前的检查，只读模式以及会话变量的限制
+    fn check_statement(&self, stmt: &Statement) -> LegendDBResult<()> {
+        if self.read_only && stmt.is_write() {
+            return Err(LegendDBError::Internal("the server is in read-only mode, only queries are allowed".to_string()));
+        }
+        self.settings.check(stmt)
+    }
+
     // 获取表信息
     pub fn get_table(&self, table_name: String) -> LegendDBResult<String> {
         let txn = self.engine.begin()?;
@@ -114,4 +459,9 @@ impl<E: Engine + 'static> Session<E>  {
         Ok(table_names.join(",\n"))
     }
 
+    // 健康检查，开启并提交一个空事务，确认存储引擎可以正常读写
+    pub fn ping(&self) -> LegendDBResult<()> {
+        self.engine.begin()?.commit()
+    }
+
 }
\ No newline at end of file
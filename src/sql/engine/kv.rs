@@ -1,37 +1,178 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::fs;
 use std::fs::File;
-use bincode::{config, Decode, Encode};
+use std::sync::{Arc, Mutex, OnceLock};
+use bincode::{Decode, Encode};
 use serde::{Deserialize, Serialize};
-use crate::sql::engine::engine::{Engine, Session, Transaction};
-use crate::sql::parser::ast::{evaluate_expr, Expression, Operation};
+use crate::sql::engine::engine::{Engine, Session, SessionSettings, Transaction};
+use crate::sql::engine::codec::{Codec, DefaultCodec};
+use crate::sql::engine::row_codec;
+use crate::sql::engine::throttle::WriteThrottle;
+use crate::sql::parser::ast::{collect_fields, evaluate_expr, Expression, Operation};
 use crate::sql::schema::Table;
 use crate::storage;
 use crate::storage::engine::Engine as StorageEngine;
 use crate::storage::keycode::{deserializer, serializer};
 use crate::storage::mvcc::{MvccTransaction};
 use crate::sql::types::{Row, Value};
-use crate::custom_error::{LegendDBError, LegendDBResult, CURRENT_DB_FILE, DEFAULT_DB_FOLDER};
+use crate::custom_error::{LegendDBError, LegendDBResult};
+use crate::paths;
+// 存储格式的版本，格式变化时加一，并在 KVEngine::migrate_format 中迁移旧的格式
+// 1: 唯一约束写入索引
+// 2: 行数据以 0xFF 标记开头，不再和 bincode 编码的行混淆
+const FORMAT_VERSION: u64 = 2;
+
 // KV引擎定义
+// 线程安全：KVEngine 可以克隆，克隆之后共享存储、表结构缓存和行数统计，底层存储 E 满足 Send 时可以在线程间移动
+// 同一个引擎创建的多个会话可以分别在不同的线程中并发执行，每条语句在自己的事务中执行，
+// 并发修改同一行时后提交的事务返回 WriteMvccConflict，调用方回滚后重试即可；单个会话不能在多个线程中同时使用
 #[derive(Debug)]
 pub struct KVEngine<E: StorageEngine> {
     // 底层存储引擎
     pub kv: storage::mvcc::Mvcc<E>,
+    // 表结构缓存，所有会话共享
+    schema_cache: Arc<Mutex<SchemaCache>>,
+    // 表的行数统计，所有会话共享
+    row_counts: Arc<Mutex<RowCounts>>,
+    // 表结构和行数据的编解码器
+    codec: Arc<dyn Codec>,
+    // 第一次开启事务时检查数据库记录的编解码器和存储格式，检查的结果所有会话共享
+    codec_checked: Arc<OnceLock<LegendDBResult<()>>>,
+    // 写入限流，所有会话共享
+    throttle: Arc<Mutex<WriteThrottle>>,
 }
 
 impl<E: StorageEngine> Clone for KVEngine<E>  {
     fn clone(&self) -> Self {
         Self {
             kv: self.kv.clone(),
+            schema_cache: self.schema_cache.clone(),
+            row_counts: self.row_counts.clone(),
+            codec: self.codec.clone(),
+            codec_checked: self.codec_checked.clone(),
+            throttle: self.throttle.clone(),
         }
     }
 }
 
 impl<E: StorageEngine> KVEngine<E> {
     pub fn new(engine: E) -> Self {
+        Self::with_codec(engine, Arc::new(DefaultCodec))
+    }
+
+    // 使用指定的编解码器读写数据
+    pub fn with_codec(engine: E, codec: Arc<dyn Codec>) -> Self {
         Self {
             kv: storage::mvcc::Mvcc::new(engine),
+            schema_cache: Arc::new(Mutex::new(SchemaCache::default())),
+            row_counts: Arc::new(Mutex::new(RowCounts::default())),
+            codec,
+            codec_checked: Arc::new(OnceLock::new()),
+            throttle: Arc::new(Mutex::new(WriteThrottle::default())),
+        }
+    }
+
+    pub fn codec(&self) -> &dyn Codec {
+        self.codec.as_ref()
+    }
+
+    // 限制表每秒写入的行数，0 表示取消限制
+    pub fn set_write_limit(&self, table: &str, rows_per_second: u64) -> LegendDBResult<()> {
+        self.throttle.lock()?.set_limit(table, rows_per_second);
+        Ok(())
+    }
+
+    // 旧版本的 keycode 直接写入字符串的字节，没有结尾标记，表名的 key 不以 [0, 0] 结尾
+    // 这样的数据库中表名和行的 key 无法区分，不能读取也不能迁移，打开时直接报错，不做任何修改
+    // 记录了存储格式版本的数据库一定是新的编码，不需要检查
+    fn check_key_encoding(&self) -> LegendDBResult<()> {
+        let mut txn = self.kv.begin()?;
+        let legacy = txn.get(TransactionKey::FormatVersion.encode()?)?.is_none()
+            && txn.scan_prefix(KeyPrefix::Table.encode()?)?.iter().any(|result| !result.key.ends_with(&[0, 0]));
+        txn.commit()?;
+        if legacy {
+            return Err(LegendDBError::Internal(
+                "database uses the old key encoding without string terminators, export it with the previous version and import it again".to_string()
+            ));
+        }
+        Ok(())
+    }
+
+    // 检查数据库中记录的编解码器是否能被当前的编解码器读取，并记录为当前的编解码器
+    // 记录编解码器的 key 直接使用 keycode 编码，不随编解码器变化
+    fn check_codec(&self) -> LegendDBResult<()> {
+        let mut txn = self.kv.begin()?;
+        let key = TransactionKey::Codec.encode()?;
+        let stored = match txn.get(key.clone())? {
+            Some(name) => Some(String::from_utf8(name)?),
+            // 没有记录编解码器的数据库已经有表时，数据是默认的编解码器写入的，空的数据库可以使用任意编解码器
+            None if !txn.scan_prefix(KeyPrefix::Table.encode()?)?.is_empty() => Some(DefaultCodec::NAME.to_string()),
+            None => None,
+        };
+        if let Some(name) = &stored {
+            if !self.codec.can_read(name) {
+                txn.rollback()?;
+                return Err(LegendDBError::Internal(format!(
+                    "database is encoded with codec {}, which cannot be read by codec {}", name, self.codec.name()
+                )));
+            }
+            if name == self.codec.name() {
+                return txn.commit();
+            }
+        }
+        txn.set(key, self.codec.name().as_bytes().to_vec())?;
+        txn.commit()
+    }
+
+    // 检查数据库的存储格式，旧的格式在这里迁移，比当前程序新的格式不能打开
+    // 记录格式版本的 key 和编解码器一样直接使用 keycode 编码
+    fn check_format(&self) -> LegendDBResult<()> {
+        let mut txn = self.begin_transaction()?;
+        let key = TransactionKey::FormatVersion.encode()?;
+        let version = match txn.txn.get(key.clone())? {
+            Some(value) => u64::from_be_bytes(value.try_into().map_err(|_| LegendDBError::Internal("invalid storage format version".to_string()))?),
+            None => 0,
+        };
+        if version == FORMAT_VERSION {
+            return txn.commit();
+        }
+        let result = if version > FORMAT_VERSION {
+            Err(LegendDBError::Internal(format!("database storage format {} is newer than the supported format {}", version, FORMAT_VERSION)))
+        } else {
+            Self::migrate_format(&mut txn, version)
+        };
+        if let Err(e) = result.and_then(|_| txn.txn.set(key, FORMAT_VERSION.to_be_bytes().to_vec())) {
+            txn.rollback()?;
+            return Err(e);
+        }
+        txn.commit()
+    }
+
+    // 从 version 迁移到当前的存储格式
+    fn migrate_format(txn: &mut KVTransaction<E>, version: u64) -> LegendDBResult<()> {
+        for name in txn.get_table_names()? {
+            let table = txn.get_table_must(name)?;
+            // 格式 2 之前行数据以版本号 1 开头，改为新的标记，先于其他需要解码行的迁移
+            if version < 2 {
+                txn.migrate_row_marker(&table)?;
+            }
+            // 格式 1 之前没有唯一约束的索引，为已有的数据补充
+            if version < 1 {
+                txn.rebuild_unique_keys(&table)?;
+            }
         }
+        Ok(())
+    }
+
+    // 开启事务，不检查编解码器和存储格式
+    fn begin_transaction(&self) -> LegendDBResult<KVTransaction<E>> {
+        // 持有缓存锁开启事务，保证事务的快照和缓存版本、行数统计是一致的
+        let cache = self.schema_cache.lock()?;
+        let row_counts = self.row_counts.lock()?;
+        let txn = self.kv.begin()?;
+        let mut txn = KVTransaction::new(txn, self.codec.clone(), self.schema_cache.clone(), cache.version, self.row_counts.clone(), row_counts.commits);
+        txn.throttle = Some(self.throttle.clone());
+        Ok(txn)
     }
 }
 
@@ -40,33 +181,249 @@ impl<E: StorageEngine> Engine for KVEngine<E> {
     type Transaction = KVTransaction<E>;
 
     fn begin(&self) -> LegendDBResult<Self::Transaction> {
-        Ok(Self::Transaction::new(self.kv.begin()?))
+        self.codec_checked.get_or_init(|| {
+            self.check_key_encoding()
+                .and_then(|_| self.check_codec())
+                .and_then(|_| self.check_format())
+        }).clone()?;
+        self.begin_transaction()
     }
 
     fn session(&self) -> LegendDBResult<Session<Self>> {
         Ok(Session {
             engine: self.clone(),
             transaction: None,
+            settings: SessionSettings::default(),
+            audit_log: None,
+            read_only: false,
         })
     }
 
 }
 
+// 表结构缓存，避免每条语句都从存储中解码表结构
+// 每次 DDL 提交之后版本号加一并清空缓存，只有事务开始时的版本号和缓存的版本号一致时才能使用缓存
+#[derive(Debug, Default)]
+pub struct SchemaCache {
+    version: u64,
+    tables: HashMap<String, Table>,
+}
+
+// 表的行数统计，不持久化，重启之后第一次统计时扫描全表
+// 每次修改了行数的事务提交时序号加一，并记录每个表最后一次修改时的序号
+// 事务开始之后表没有被其他事务修改过时，统计的行数加上本事务的修改就是事务快照中的行数
+#[derive(Debug, Default)]
+pub struct RowCounts {
+    commits: u64,
+    // 表名 -> (行数, 最后一次修改时的序号)，行数为 None 表示还没有统计过
+    tables: HashMap<String, (Option<usize>, u64)>,
+}
+
+impl RowCounts {
+    // 事务提交时合并事务中的修改
+    fn apply(&mut self, changes: &HashMap<String, RowCountChange>) {
+        self.commits += 1;
+        for (table, change) in changes {
+            let count = match (change, self.tables.get(table)) {
+                (RowCountChange::Created(n), _) => Some(*n as usize),
+                (RowCountChange::Delta(n), Some((Some(count), _))) => Some((*count as i64 + n) as usize),
+                _ => None,
+            };
+            self.tables.insert(table.clone(), (count, self.commits));
+        }
+    }
+}
+
+// 事务中对表的行数的修改
+#[derive(Debug, Clone, Copy)]
+enum RowCountChange {
+    // 插入和删除的行数之差
+    Delta(i64),
+    // 表是本事务创建的，行数从 0 开始
+    Created(i64),
+    // 表被删除了
+    Dropped,
+}
+
+// 唯一约束索引中的一项，(key, 约束名, 约束列的值)
+type UniqueEntry = (Vec<u8>, String, Vec<Value>);
+
 // kv transaction 定义， 实际就是存储引擎中MvccTransaction的封装
 #[derive(Debug, Clone)]
 pub struct KVTransaction<E: StorageEngine> {
     pub txn: MvccTransaction<E>,
+    codec: Arc<dyn Codec>,
+    // 批量导入模式，插入时不检查主键是否已存在，延迟到提交时统一检查
+    bulk_load: bool,
+    // 批量导入模式下写入的主键，key -> (表名, 主键值)
+    deferred_keys: HashMap<Vec<u8>, (String, Value)>,
+    schema_cache: Arc<Mutex<SchemaCache>>,
+    // 事务开始时的缓存版本号
+    schema_version: u64,
+    // 当前事务是否修改过表结构，修改过的事务不使用缓存
+    schema_changed: bool,
+    row_counts: Arc<Mutex<RowCounts>>,
+    // 事务开始时行数统计的序号
+    row_count_seq: u64,
+    // 当前事务对表的行数的修改，提交时合并到行数统计中
+    row_count_changes: HashMap<String, RowCountChange>,
+    // 写入限流，第一次写入表之前检查，提交之后按写入的行数扣减
+    throttle: Option<Arc<Mutex<WriteThrottle>>>,
+    // 当前事务每个表写入的行数
+    written_rows: HashMap<String, u64>,
 }
 
 impl<E: StorageEngine> KVTransaction<E> {
-    pub fn new(txn: MvccTransaction<E>) -> Self {
-        KVTransaction { txn }
+    pub fn new(txn: MvccTransaction<E>, codec: Arc<dyn Codec>, schema_cache: Arc<Mutex<SchemaCache>>, schema_version: u64, row_counts: Arc<Mutex<RowCounts>>, row_count_seq: u64) -> Self {
+        KVTransaction {
+            txn,
+            codec,
+            bulk_load: false,
+            deferred_keys: HashMap::new(),
+            schema_cache,
+            schema_version,
+            schema_changed: false,
+            row_counts,
+            row_count_seq,
+            row_count_changes: HashMap::new(),
+            throttle: None,
+            written_rows: HashMap::new(),
+        }
+    }
+
+    // 记录写入的行数，用于提交之后的限流
+    fn add_written_rows(&mut self, table_name: &str) {
+        *self.written_rows.entry(table_name.to_string()).or_default() += 1;
+    }
+
+    // 事务第一次写入表之前检查限流，表还欠着令牌时直接失败
+    fn check_throttle(&self, table_name: &str) -> LegendDBResult<()> {
+        match &self.throttle {
+            Some(throttle) if !self.written_rows.contains_key(table_name) => throttle.lock()?.check(table_name),
+            _ => Ok(()),
+        }
+    }
+
+    // 提交之后按实际写入的行数扣减令牌
+    fn charge_throttle(&self) -> LegendDBResult<()> {
+        if let Some(throttle) = &self.throttle && !self.written_rows.is_empty() {
+            throttle.lock()?.charge(&self.written_rows);
+        }
+        Ok(())
+    }
+
+    // 记录本事务插入或者删除的行数
+    fn add_row_count(&mut self, table_name: &str, n: i64) {
+        match self.row_count_changes.entry(table_name.to_string()).or_insert(RowCountChange::Delta(0)) {
+            RowCountChange::Delta(count) | RowCountChange::Created(count) => *count += n,
+            RowCountChange::Dropped => {},
+        }
+    }
+
+    // 行在唯一约束索引中的 key，约束列的值中有 NULL 时不算重复，不写入索引
+    fn unique_entries(&self, table: &Table, row: &Row) -> LegendDBResult<Vec<UniqueEntry>> {
+        let mut entries = Vec::new();
+        for (name, positions) in table.unique_keys() {
+            let values = positions.iter().map(|&i| row[i].clone()).collect::<Vec<_>>();
+            if values.contains(&Value::Null) {
+                continue;
+            }
+            let data = bincode::encode_to_vec(&values, bincode::config::standard())?;
+            let key = self.codec.encode_key(&TransactionKey::UniqueKey(table.name.clone(), name.clone(), data))?;
+            entries.push((key, name, values));
+        }
+        Ok(entries)
+    }
+
+    // 把以旧的版本号开头的行改为新的标记，修改后仍然无法解码的行直接报错
+    fn migrate_row_marker(&mut self, table: &Table) -> LegendDBResult<()> {
+        let prefix = self.codec.encode_prefix(&KeyPrefix::Row(table.name.clone()))?;
+        for result in self.txn.scan_prefix(prefix)? {
+            if self.codec.decode_row(table, &result.value).is_ok() {
+                continue;
+            }
+            let mut value = result.value;
+            if value.first() == Some(&row_codec::LEGACY_ROW_FORMAT_VERSION) {
+                value[0] = row_codec::ROW_FORMAT_MARKER;
+            }
+            self.codec.decode_row(table, &value).map_err(|e| LegendDBError::Internal(format!(
+                "row of table {} is in an unknown format: {}", table.name, e
+            )))?;
+            self.txn.set(result.key, value)?;
+        }
+        Ok(())
+    }
+
+    // 维护唯一约束的索引，old 为 None 表示插入新行，new 为 None 表示删除行
+    // 通过点查判断值是否已经被其他行使用，并发写入相同的值时由 MVCC 的写冲突检测
+    fn update_unique_keys(&mut self, table: &Table, old: Option<&Row>, new: Option<&Row>) -> LegendDBResult<()> {
+        let old_entries = old.map(|row| self.unique_entries(table, row)).transpose()?.unwrap_or_default();
+        let new_entries = new.map(|row| self.unique_entries(table, row)).transpose()?.unwrap_or_default();
+        for (key, ..) in &old_entries {
+            if !new_entries.iter().any(|(k, ..)| k == key) {
+                self.txn.delete(key.clone())?;
+            }
+        }
+        let Some(new) = new else {
+            return Ok(());
+        };
+        let pk = table.get_primary_key(new)?;
+        let pk_changed = old.map(|row| table.get_primary_key(row)).transpose()?.is_some_and(|old_pk| old_pk != pk);
+        let pk_data = bincode::encode_to_vec(&pk, bincode::config::standard())?;
+        for (key, name, values) in new_entries {
+            let owned = old_entries.iter().any(|(k, ..)| *k == key);
+            if owned && !pk_changed {
+                continue;
+            }
+            if !owned && self.txn.get(key.clone())?.is_some_and(|v| v != pk_data) {
+                return Err(table.unique_key_error(&name, &values));
+            }
+            self.txn.set(key, pk_data.clone())?;
+        }
+        Ok(())
+    }
+
+    // 提交前检查批量导入的主键是否和事务开始前的数据重复
+    fn check_deferred_keys(&self) -> LegendDBResult<()> {
+        let keys = self.deferred_keys.keys().cloned().collect::<Vec<_>>();
+        let values = self.txn.multi_get_snapshot(keys.clone())?;
+        for (key, value) in keys.iter().zip(values) {
+            if value.is_some() {
+                let (table_name, primary_key) = &self.deferred_keys[key];
+                let table = self.get_table_must(table_name.clone())?;
+                return Err(table.duplicate_key_error(primary_key));
+            }
+        }
+        Ok(())
     }
 }
 
 impl<E: StorageEngine> Transaction for KVTransaction<E> {
     fn commit(&self) -> LegendDBResult<()> {
-        Ok(self.txn.commit()?)
+        if let Err(e) = self.check_deferred_keys() {
+            self.txn.rollback()?;
+            return Err(e);
+        }
+        if !self.schema_changed && self.row_count_changes.is_empty() {
+            self.txn.commit()?;
+            return self.charge_throttle();
+        }
+        // 修改过表结构，提交的同时让缓存失效，修改过行数，提交的同时更新行数统计，期间不能开启新的事务
+        let cache = self.schema_changed.then(|| self.schema_cache.lock()).transpose()?;
+        let row_counts = (!self.row_count_changes.is_empty()).then(|| self.row_counts.lock()).transpose()?;
+        self.txn.commit()?;
+        if let Some(mut cache) = cache {
+            cache.version += 1;
+            cache.tables.clear();
+        }
+        if let Some(mut row_counts) = row_counts {
+            row_counts.apply(&self.row_count_changes);
+        }
+        self.charge_throttle()
+    }
+
+    fn set_bulk_load(&mut self, bulk_load: bool) {
+        self.bulk_load = bulk_load;
     }
 
     fn rollback(&self) -> LegendDBResult<()> {
@@ -75,31 +432,34 @@ impl<E: StorageEngine> Transaction for KVTransaction<E> {
 
     fn create_database(&self, name: &str) -> LegendDBResult<()> {
         // 判断数据库是否存在
-        if fs::metadata(format!("{}{}.db", DEFAULT_DB_FOLDER, name)).is_ok() {
+        if fs::metadata(paths::database_file(name)).is_ok() {
             return Err(LegendDBError::Internal(format!("database {} already exists", name)));
         } else {
-            File::create(format!("{}{}.db", DEFAULT_DB_FOLDER, name))?;
+            File::create(paths::database_file(name))?;
         }
         Ok(())
     }
-    #[allow(unused)]
     fn drop_database(&self, name: &str) -> LegendDBResult<()> {
         // 判断数据库是否存在
-        if !fs::metadata(format!("{}/{}.db", DEFAULT_DB_FOLDER, name)).is_ok() {
+        if !self.database_exists(name)? {
             return Err(LegendDBError::Internal(format!("database {} not already exists", name)));
         } else {
-            fs::remove_file(format!("{}/{}.db", DEFAULT_DB_FOLDER, name))?;
+            fs::remove_file(paths::database_file(name))?;
         }
         Ok(())
     }
 
+    fn database_exists(&self, name: &str) -> LegendDBResult<bool> {
+        Ok(fs::metadata(paths::database_file(name)).is_ok())
+    }
+
     fn use_database(&self, database_name: &str) -> LegendDBResult<()> {
         // 判断数据库是否存在
-        if !fs::metadata(format!("{}/{}", DEFAULT_DB_FOLDER, database_name)).is_ok() {
+        if fs::metadata(paths::database_file(database_name)).is_err() {
             return Err(LegendDBError::Internal(format!("database {} not already exists", database_name)));
         }
         // 没有文件会创建文件，并将内容写到文件中
-        fs::write(CURRENT_DB_FILE, database_name)?;
+        fs::write(paths::current_database_file(), database_name)?;
 
         Ok(())
     }
@@ -111,110 +471,220 @@ impl<E: StorageEngine> Transaction for KVTransaction<E> {
         }
         // 判断表的有效性
         table.validate()?;
-        let key = TransactionKey::TableName(table.name.clone()).encode()?;
+        let key = self.codec.encode_key(&TransactionKey::TableName(table.name.clone()))?;
         // 简单序列化
         // let key_bytes: Vec<u8> = to_bytes::<RancorError>(&key)?.into_vec();
         // 高性能序列化
         // let mut arena = Arena::new();
         // let key_result = to_bytes_with_alloc::<_, RancorError>(&key, arena.acquire())?.into_vec();
         // let table_result = to_bytes_with_alloc::<_, RancorError>(&table, arena.acquire())?.into_vec();
-        let config = config::standard();
-        let table_result = bincode::encode_to_vec(table, config)?;
-        self.txn.set(key, table_result)?;
+        let name = table.name.clone();
+        let table_result = self.codec.encode_table(&table)?;
+        // 其他事务并发创建了同名的表，上面的检查看不到还没提交或者在本事务开始之后提交的表
+        // 写入表名时会产生写冲突，这里返回表已存在的错误
+        self.txn.set(key, table_result).map_err(|err| match err {
+            LegendDBError::WriteMvccConflict { .. } => LegendDBError::TableExist(name.clone()),
+            err => err,
+        })?;
+        self.schema_changed = true;
+        self.row_count_changes.insert(name, RowCountChange::Created(0));
+        Ok(())
+    }
+
+    fn update_table(&mut self, table: Table) -> LegendDBResult<()> {
+        self.get_table_must(table.name.clone())?;
+        let key = self.codec.encode_key(&TransactionKey::TableName(table.name.clone()))?;
+        let value = self.codec.encode_table(&table)?;
+        self.txn.set(key, value)?;
+        self.schema_changed = true;
         Ok(())
     }
 
-    #[allow(unused)]
-    fn drop_table(&self, name: &str) -> LegendDBResult<()> {
-        todo!()
+    fn drop_table(&mut self, name: &str) -> LegendDBResult<()> {
+        let table = self.get_table_must(name.to_string())?;
+        // 先删除表中所有的数据，再删除表结构
+        let prefix = self.codec.encode_prefix(&KeyPrefix::Row(table.name.clone()))?;
+        for result in self.txn.scan_prefix(prefix)? {
+            self.txn.delete(result.key)?;
+        }
+        let prefix = self.codec.encode_prefix(&KeyPrefix::Unique(table.name.clone()))?;
+        for result in self.txn.scan_prefix(prefix)? {
+            self.txn.delete(result.key)?;
+        }
+        self.row_count_changes.insert(table.name.clone(), RowCountChange::Dropped);
+        let key = self.codec.encode_key(&TransactionKey::TableName(table.name))?;
+        self.txn.delete(key)?;
+        self.schema_changed = true;
+        Ok(())
     }
 
     fn create_row(&mut self, table_name: String, row: Row) -> LegendDBResult<()> {
+        self.check_throttle(&table_name)?;
         let table = self.get_table_must(table_name.clone())?;
         // 校验行的有效性
-        for (index, column) in table.columns.iter().enumerate() {
-            match row[index].get_type() { 
-                None if column.nullable => {},
-                None => {
-                    return Err(LegendDBError::Internal(format!("column {} is null", column.name)));
-                },
-                Some(dt) if dt != column.data_type => {
-                    return Err(LegendDBError::Internal(format!("column {} type is not match", column.name)));
-                },
-                _ => {}
-            }
-        }
+        table.validate_row(&row)?;
         // 存放数据
         // 找到表中的主键作为一行数据的唯一标识
         let primary_key = table.get_primary_key(&row)?;
         // 查看主键对应的数据是否已经存在
-        let id = TransactionKey::RowKey(table_name.clone(), primary_key.clone()).encode()?;
-        if self.txn.get(id.clone())?.is_some() {
-            return Err(LegendDBError::Internal(format!("Duplicte data for primary key {:?} in table {}", primary_key.clone(), table_name.clone())));
+        let id = self.codec.encode_key(&TransactionKey::RowKey(table_name.clone(), primary_key.clone()))?;
+        if self.bulk_load {
+            // 批量导入模式下只检查本事务内是否重复，和已有数据的重复延迟到提交时检查
+            if self.deferred_keys.contains_key(&id) {
+                return Err(table.duplicate_key_error(&primary_key));
+            }
+            self.deferred_keys.insert(id.clone(), (table_name.clone(), primary_key));
+        } else if self.txn.get(id.clone())?.is_some() {
+            return Err(table.duplicate_key_error(&primary_key));
         }
-        let config = config::standard();
-        let value = bincode::encode_to_vec(row, config)?;
+        self.update_unique_keys(&table, None, Some(&row))?;
+        let value = self.codec.encode_row(&table, &row)?;
         self.txn.set(id, value)?;
+        self.add_row_count(&table_name, 1);
+        self.add_written_rows(&table_name);
         Ok(())
     }
 
     fn update_row(&mut self, table: &Table, id: &Value, row: Row) -> LegendDBResult<()> {
+        self.check_throttle(&table.name)?;
+        table.validate_row(&row)?;
+        let key = self.codec.encode_key(&TransactionKey::RowKey(table.name.clone(), id.clone()))?;
+        let old = self.txn.get(key)?.map(|value| self.codec.decode_row(table, &value)).transpose()?;
+        self.update_unique_keys(table, old.as_ref(), Some(&row))?;
+        self.rewrite_row(table, id, row)
+    }
+
+    fn rewrite_row(&mut self, table: &Table, id: &Value, row: Row) -> LegendDBResult<()> {
+        self.check_throttle(&table.name)?;
         let new_pk = table.get_primary_key(&row)?;
         // 如果更新了主键，则删除旧的数据
         if new_pk != *id {
-            let key = TransactionKey::RowKey(table.name.clone(), id.clone()).encode()?;
+            // 新的主键不能和已有的数据重复
+            let new_key = self.codec.encode_key(&TransactionKey::RowKey(table.name.clone(), new_pk.clone()))?;
+            if self.txn.get(new_key)?.is_some() {
+                return Err(table.duplicate_key_error(&new_pk));
+            }
+            let key = self.codec.encode_key(&TransactionKey::RowKey(table.name.clone(), id.clone()))?;
             self.txn.delete(key)?;
             // return Err(LegendDBError::Internal(format!("primary key is not match")));
         }
-        let key = TransactionKey::RowKey(table.name.clone(), new_pk).encode()?;
-        let value = bincode::encode_to_vec(row, config::standard())?;
+        let key = self.codec.encode_key(&TransactionKey::RowKey(table.name.clone(), new_pk))?;
+        let value = self.codec.encode_row(table, &row)?;
         self.txn.set(key, value)?;
+        self.add_written_rows(&table.name);
         Ok(())
     }
 
     fn delete_row(&mut self, table: &Table, id: &Value) -> LegendDBResult<()> {
-        let key = TransactionKey::RowKey(table.name.clone(), id.clone()).encode()?;
-        self.txn.delete(key)?;
+        self.check_throttle(&table.name)?;
+        let key = self.codec.encode_key(&TransactionKey::RowKey(table.name.clone(), id.clone()))?;
+        // 行不存在时不影响行数
+        if let Some(value) = self.txn.get(key.clone())? {
+            let old = self.codec.decode_row(table, &value)?;
+            self.update_unique_keys(table, Some(&old), None)?;
+            self.txn.delete(key)?;
+            self.add_row_count(&table.name, -1);
+            self.add_written_rows(&table.name);
+        }
         Ok(())
     }
 
 
+    fn rebuild_unique_keys(&mut self, table: &Table) -> LegendDBResult<()> {
+        let prefix = self.codec.encode_prefix(&KeyPrefix::Unique(table.name.clone()))?;
+        for result in self.txn.scan_prefix(prefix)? {
+            self.txn.delete(result.key)?;
+        }
+        if table.unique_keys().is_empty() {
+            return Ok(());
+        }
+        for row in self.scan_table(table.name.clone(), None)? {
+            self.update_unique_keys(table, None, Some(&row))?;
+        }
+        Ok(())
+    }
+
     fn get_table_names(&mut self) -> LegendDBResult<Vec<String>> {
-        let prefix = KeyPrefix::Table.encode()?;
+        let prefix = self.codec.encode_prefix(&KeyPrefix::Table)?;
         let results = self.txn.scan_prefix(prefix)?;
         let mut names = Vec::new();
         for result in results {
-            let (table, _): (Table, usize) = bincode::decode_from_slice(&result.value, config::standard())?;
+            let table = self.codec.decode_table(&result.value)?;
             names.push(table.name);
         }
         Ok(names)
     }
 
-    fn scan_table(&mut self, table_name: String, filter: Option<Vec<Expression>>) -> LegendDBResult<Vec<Row>> {
+    fn scan_table(&mut self, table_name: String, filter: Option<Expression>) -> LegendDBResult<Vec<Row>> {
         let table = self.get_table_must(table_name.clone())?;
-        let prefix = KeyPrefix::Row(table_name.clone()).encode()?;
-        let config = config::standard();
+        let prefix = self.codec.encode_prefix(&KeyPrefix::Row(table_name.clone()))?;
         let results = self.txn.scan_prefix(prefix)?;
         let mut rows = Vec::new();
         for result in results {
-            let (row, _) = bincode::decode_from_slice(&result.value, config)?;
+            let row = self.codec.decode_row(&table, &result.value)?;
             // 根据filter进行过滤
             match filter {
                 None => {
                     rows.push(row);
                 },
-                Some(ref filters) => {
+                Some(ref filter) => {
                     let table_cols = table.columns.iter().map(|c| c.name.clone()).collect::<Vec<_>>();
-                    for filter in filters {
-                        match evaluate_expr(filter, &table_cols, &row, &table_cols, &row)? {
-                            Value::Boolean(true) => {
-                                rows.push(row.clone());
-                            },
-                            Value::Null => {}
-                            Value::Boolean(false) => {}
-                            _ => {
-                                return Err(LegendDBError::Internal("filter is not match".to_string()));
-                            }
+                    match evaluate_expr(filter, &table_cols, &row, &table_cols, &row)? {
+                        Value::Boolean(true) => {
+                            rows.push(row);
+                        },
+                        Value::Null => {}
+                        Value::Boolean(false) => {}
+                        _ => {
+                            return Err(LegendDBError::Internal("filter is not match".to_string()));
+                        }
+                    }
+                }
+            }
+        }
+        Ok(rows)
+    }
+
+    fn read_rows(&mut self, table: &Table, ids: Vec<Value>) -> LegendDBResult<Vec<Row>> {
+        // 编码后的主键和扫描的顺序一致
+        let keys = ids.into_iter()
+            .map(|id| self.codec.encode_key(&TransactionKey::RowKey(table.name.clone(), id)))
+            .collect::<LegendDBResult<BTreeSet<_>>>()?;
+        let values = self.txn.multi_get(keys.into_iter().collect())?;
+        values.into_iter().flatten().map(|value| self.codec.decode_row(table, &value)).collect()
+    }
+
+    fn scan_table_columns(&mut self, table_name: String, filter: Option<Expression>, columns: &[String]) -> LegendDBResult<Vec<Row>> {
+        let table = self.get_table_must(table_name.clone())?;
+        let indexes = columns.iter().map(|c| table.get_column_index(c)).collect::<LegendDBResult<Vec<_>>>()?;
+        // 过滤条件中用到的列，只解码这些列用于过滤
+        let mut filter_cols = Vec::new();
+        if let Some(filter) = &filter {
+            collect_fields(filter, &mut filter_cols);
+        }
+        filter_cols.retain(|c| table.columns.iter().any(|col| col.name == *c));
+        let mut seen = HashSet::new();
+        filter_cols.retain(|c| seen.insert(c.clone()));
+        let filter_indexes = filter_cols.iter().map(|c| table.get_column_index(c)).collect::<LegendDBResult<Vec<_>>>()?;
+
+        let prefix = self.codec.encode_prefix(&KeyPrefix::Row(table_name.clone()))?;
+        let results = self.txn.scan_prefix(prefix)?;
+        let mut rows = Vec::new();
+        for result in results {
+            match filter {
+                None => {
+                    rows.push(self.codec.decode_columns(&table, &result.value, &indexes)?);
+                },
+                Some(ref filter) => {
+                    let filter_row = self.codec.decode_columns(&table, &result.value, &filter_indexes)?;
+                    match evaluate_expr(filter, &filter_cols, &filter_row, &filter_cols, &filter_row)? {
+                        Value::Boolean(true) => {
+                            rows.push(self.codec.decode_columns(&table, &result.value, &indexes)?);
+                        },
+                        Value::Null => {}
+                        Value::Boolean(false) => {}
+                        _ => {
+                            return Err(LegendDBError::Internal("filter is not match".to_string()));
                         }
                     }
                 }
@@ -224,20 +694,150 @@ impl<E: StorageEngine> Transaction for KVTransaction<E> {
     }
 
 
+    fn count_rows(&mut self, table_name: &str) -> LegendDBResult<usize> {
+        self.get_table_must(table_name.to_string())?;
+        let delta = match self.row_count_changes.get(table_name) {
+            Some(RowCountChange::Created(n)) => return Ok(*n as usize),
+            Some(RowCountChange::Delta(n)) => *n,
+            _ => 0,
+        };
+        // 表在事务开始之后没有被其他事务修改过时，可以使用统计的行数
+        if let Some((Some(count), seq)) = self.row_counts.lock()?.tables.get(table_name) && *seq <= self.row_count_seq {
+            return Ok((*count as i64 + delta) as usize);
+        }
+        let count = self.scan_table_columns(table_name.to_string(), None, &[])?.len();
+        // 扫描的结果去掉本事务的修改就是已提交的行数，表在事务开始之后没有被修改过时记录下来
+        let mut row_counts = self.row_counts.lock()?;
+        let seq = row_counts.tables.get(table_name).map_or(0, |(_, seq)| *seq);
+        if seq <= self.row_count_seq {
+            row_counts.tables.insert(table_name.to_string(), (Some((count as i64 - delta) as usize), seq));
+        }
+        Ok(count)
+    }
+
+    fn check_table(&mut self, table_name: &str) -> LegendDBResult<Vec<String>> {
+        let table = self.get_table_must(table_name.to_string())?;
+        let prefix = self.codec.encode_prefix(&KeyPrefix::Row(table_name.to_string()))?;
+        let mut problems = Vec::new();
+        // 根据行计算出的唯一约束索引，key 对应行的主键、约束名和约束列的值
+        let mut expected: BTreeMap<Vec<u8>, (Value, String, Vec<Value>)> = BTreeMap::new();
+        let format_values = |values: &[Value]| values.iter().map(|v| v.to_string()).collect::<Vec<_>>().join(", ");
+        let message = |e: LegendDBError| match e {
+            LegendDBError::Internal(msg) => msg,
+            e => e.to_string(),
+        };
+        for result in self.txn.scan_prefix(prefix)? {
+            // key 中存放的主键
+            let id = match self.codec.decode_key(&result.key) {
+                Ok(TransactionKey::RowKey(_, id)) => id,
+                _ => {
+                    problems.push(format!("invalid row key {}", result.key.escape_ascii()));
+                    continue;
+                }
+            };
+            let row = match self.codec.decode_row(&table, &result.value) {
+                Ok(row) => row,
+                Err(e) => {
+                    problems.push(format!("row {} cannot be decoded: {}", id, e));
+                    continue;
+                }
+            };
+            // 数据中的主键需要和 key 一致
+            let primary_key = table.get_primary_key(&row)?;
+            if primary_key != id {
+                problems.push(format!("row {} has primary key {} in its data", id, primary_key));
+            }
+            // 校验列的约束
+            for (column, value) in table.columns.iter().zip(row.iter()) {
+                match value.get_type() {
+                    None if !column.nullable => {
+                        problems.push(format!("row {} has NULL in NOT NULL column {}", id, column.name));
+                    },
+                    Some(dt) if dt != column.data_type => {
+                        problems.push(format!("row {} has {:?} value in column {} of type {:?}", id, dt, column.name, column.data_type));
+                    },
+                    _ => {}
+                }
+                // 字符串的长度和整数的范围
+                if let Err(e) = column.fit(&mut value.clone(), false) {
+                    problems.push(format!("row {}: {}", id, message(e)));
+                }
+            }
+            // 检查约束
+            if let Err(e) = table.check_row(&row) {
+                problems.push(format!("row {}: {}", id, message(e)));
+            }
+            for (key, name, values) in self.unique_entries(&table, &row)? {
+                match expected.get(&key) {
+                    Some((other, ..)) => problems.push(format!(
+                        "rows {} and {} have the same value ({}) for unique key {}", other, id, format_values(&values), name
+                    )),
+                    None => {
+                        expected.insert(key, (id.clone(), name, values));
+                    },
+                }
+            }
+        }
+        // 唯一约束索引需要和行一致，索引中存放的是行的主键
+        let prefix = self.codec.encode_prefix(&KeyPrefix::Unique(table_name.to_string()))?;
+        for result in self.txn.scan_prefix(prefix)? {
+            match expected.remove(&result.key) {
+                Some((id, name, values)) => {
+                    if result.value != bincode::encode_to_vec(&id, bincode::config::standard())? {
+                        problems.push(format!("unique key {} ({}) of row {} points to another row", name, format_values(&values), id));
+                    }
+                },
+                None => {
+                    let entry = match self.codec.decode_key(&result.key) {
+                        Ok(TransactionKey::UniqueKey(_, name, data)) => bincode::decode_from_slice::<Vec<Value>, _>(&data, bincode::config::standard())
+                            .map(|(values, _)| format!("{} ({})", name, format_values(&values)))
+                            .unwrap_or(name),
+                        _ => result.key.escape_ascii().to_string(),
+                    };
+                    problems.push(format!("unique key {} has no matching row", entry));
+                },
+            }
+        }
+        for (id, name, values) in expected.into_values() {
+            problems.push(format!("row {} is missing from unique key {} ({})", id, name, format_values(&values)));
+        }
+        Ok(problems)
+    }
+
     fn get_table(&self, table: String) -> LegendDBResult<Option<Table>> {
+        // 优先从缓存中获取
+        if !self.schema_changed {
+            let cache = self.schema_cache.lock()?;
+            if cache.version == self.schema_version && let Some(t) = cache.tables.get(&table) {
+                return Ok(Some(t.clone()));
+            }
+        }
+        let result = self.get_table_from_storage(table.clone())?;
+        // 缓存版本没有变化时才能放入缓存，否则读到的可能是旧的表结构
+        if !self.schema_changed && let Some(t) = &result {
+            let mut cache = self.schema_cache.lock()?;
+            if cache.version == self.schema_version {
+                cache.tables.insert(table, t.clone());
+            }
+        }
+        Ok(result)
+    }
+}
+
+impl<E: StorageEngine> KVTransaction<E> {
+    fn get_table_from_storage(&self, table: String) -> LegendDBResult<Option<Table>> {
         // let bytes = to_bytes::<Error>(&value).unwrap();
         // let deserialized = from_bytes::<Example, Error>(&bytes).unwrap()
-        let key = TransactionKey::TableName(table).encode()?;
-        let config = config::standard();
+        let key = self.codec.encode_key(&TransactionKey::TableName(table))?;
         // let mut arena = Arena::new();
         // let key_bytes = to_bytes_with_alloc::<_, RancorError>(&key, arena.acquire())?.into_vec();
         let value = self.txn.get(key)?;
-        Ok(value.map(|v| {
+        value.map(|v| {
             //Result<&ArchivedTable, RancorError>
             // let table_archived: &ArchivedTable = access::<ArchivedTable, RancorError>(&v)?;
             // deserialize::<Table, RancorError>(table_archived)
-            bincode::decode_from_slice(&v, config).map(|(table, _)| table)
-        }).transpose()?)
+            self.codec.decode_table(&v)
+        }).transpose()
     }
 }
 
@@ -245,6 +845,13 @@ impl<E: StorageEngine> Transaction for KVTransaction<E> {
 pub enum TransactionKey {
     TableName(String),
     RowKey(String, Value),
+    // 数据库使用的编解码器
+    Codec,
+    // 唯一约束的索引，(表名, 约束名, 约束列的值的 bincode 编码) -> 主键的 bincode 编码
+    // 通过 MVCC 写入，并发插入相同的值时会产生写冲突
+    UniqueKey(String, String, #[serde(with = "serde_bytes")] Vec<u8>),
+    // 存储格式的版本
+    FormatVersion,
 }
 
 impl TransactionKey {
@@ -256,7 +863,10 @@ impl TransactionKey {
 #[derive(Debug, Clone, Encode, Decode, Serialize, Deserialize)]
 pub enum KeyPrefix {
     Table,
-    Row(String)
+    Row(String),
+    // 变体的顺序和 TransactionKey 保持一致，前缀的编码才是 key 的编码的前缀
+    Codec,
+    Unique(String),
 }
 
 impl KeyPrefix {
@@ -271,12 +881,19 @@ impl KeyPrefix {
 
 #[cfg(test)]
 mod tests {
-    use crate::sql::engine::engine::Engine;
+    use crate::sql::engine::engine::{Engine, Session, Transaction};
     use crate::sql::executor::executor::ResultSet;
     use crate::storage::disk::DiskEngine;
-    use super::KVEngine;
+    use std::sync::Arc;
+    use super::{KVEngine, KeyPrefix, TransactionKey, FORMAT_VERSION};
+    use crate::sql::engine::codec::{Codec, DefaultCodec};
+    use crate::sql::engine::row_codec::{self, encode_row};
+    use crate::sql::schema::Table;
     use crate::storage::memory::MemoryEngine;
-    use crate::custom_error::LegendDBResult;
+    use crate::sql::types::{Row, Value};
+    use crate::sql::parser::parser::Parser;
+    use crate::sql::plan::node::Plan;
+    use crate::custom_error::{LegendDBError, LegendDBResult};
 
     #[test]
     fn test_create_table() -> LegendDBResult<()> {
@@ -290,6 +907,26 @@ mod tests {
         Ok(())
     }
     
+    #[test]
+    fn test_create_table_concurrently() -> LegendDBResult<()> {
+        let kv_engine = KVEngine::new(MemoryEngine::new());
+        let create = |txn: &mut _| -> LegendDBResult<_> {
+            Plan::build(Parser::new("create table t1 (a int primary key);").parse()?)?.execute(txn)
+        };
+        // 两个事务都看不到对方创建的表
+        let mut txn1 = kv_engine.begin()?;
+        let mut txn2 = kv_engine.begin()?;
+        create(&mut txn1)?;
+        assert!(matches!(create(&mut txn2), Err(LegendDBError::TableExist(_))));
+        txn2.rollback()?;
+        txn1.commit()?;
+        // 先提交的事务创建成功，之后开始的事务能看到这个表
+        let mut txn3 = kv_engine.begin()?;
+        assert!(matches!(create(&mut txn3), Err(LegendDBError::TableExist(_))));
+        txn3.rollback()?;
+        Ok(())
+    }
+
     #[test]
     fn test_update() -> LegendDBResult<()> {
         let kv_engine = KVEngine::new(MemoryEngine::new());
@@ -388,70 +1025,1952 @@ mod tests {
     }
 
     #[test]
-    fn test_create_database() -> LegendDBResult<()> {
-        let p = tempfile::tempdir()?.into_path().join("sqldb-log");
-        let kvengine = KVEngine::new(DiskEngine::new(p.clone())?);
-        let mut s = kvengine.session()?;
-        s.execute("create database test;")?;
+    fn test_where_condition() -> LegendDBResult<()> {
+        let kv_engine = KVEngine::new(MemoryEngine::new());
+        let mut s = kv_engine.session()?;
+        s.execute("create table t1 (a int primary key, b int, c text);")?;
+        s.execute("create table t2 (d int primary key, e int);")?;
+        s.execute("insert into t1 values (1, 10, 'x'), (2, 20, 'y'), (3, null, 'z'), (4, 40, 'x');")?;
+        s.execute("insert into t2 values (1, 100), (2, 200);")?;
+        let select_a = |s: &mut Session<KVEngine<MemoryEngine>>, sql: &str| -> LegendDBResult<Vec<Value>> {
+            match s.execute(sql)? {
+                ResultSet::Scan { rows, .. } => Ok(rows.into_iter().map(|r| r[0].clone()).collect()),
+                _ => unreachable!(),
+            }
+        };
+        let ints = |v: &[i64]| v.iter().map(|i| Value::Integer(*i)).collect::<Vec<_>>();
+        // OR 不会被当成 AND，每行最多返回一次
+        assert_eq!(select_a(&mut s, "select a from t1 where a = 1 or b = 10;")?, ints(&[1]));
+        assert_eq!(select_a(&mut s, "select a from t1 where a = 1 or c = 'x' and b > 30;")?, ints(&[1, 4]));
+        assert_eq!(select_a(&mut s, "select a from t1 where (a = 1 or c = 'x') and b > 30;")?, ints(&[4]));
+        // NULL 参与的比较结果未知，NOT 之后仍然未知
+        assert_eq!(select_a(&mut s, "select a from t1 where not b > 15;")?, ints(&[1]));
+        assert_eq!(select_a(&mut s, "select a from t1 where b > 15 or a = 3;")?, ints(&[2, 3, 4]));
+        // 比较运算符
+        assert_eq!(select_a(&mut s, "select a from t1 where b >= 20;")?, ints(&[2, 4]));
+        assert_eq!(select_a(&mut s, "select a from t1 where b <= 20 and a >= 2;")?, ints(&[2]));
+        assert_eq!(select_a(&mut s, "select a from t1 where c <> 'x';")?, ints(&[2, 3]));
+        assert_eq!(select_a(&mut s, "select a from t1 where b != 10;")?, ints(&[2, 4]));
+        assert!(s.execute("select a from t1 where c >= 1;").is_err());
+        // join 之后再过滤，条件可以引用多个表的列
+        assert_eq!(select_a(&mut s, "select * from t1 join t2 on a = d where e > 100 or c = 'x';")?, ints(&[1, 2]));
+        // update 和 delete 同样使用完整的条件
+        s.execute("update t1 set b = 0 where not (a = 1 or a = 2);")?;
+        assert_eq!(select_a(&mut s, "select a from t1 where b = 0;")?, ints(&[3, 4]));
+        s.execute("delete from t1 where a = 1 or a = 4;")?;
+        assert_eq!(select_a(&mut s, "select a from t1;")?, ints(&[2, 3]));
         Ok(())
     }
 
     #[test]
-    fn test_drop_database() -> LegendDBResult<()> {
-        let p = tempfile::tempdir()?.into_path().join("sqldb-log");
-        let kvengine = KVEngine::new(DiskEngine::new(p.clone())?);
-        let mut s = kvengine.session()?;
-        s.execute("drop database test;")?;
+    fn test_arithmetic() -> LegendDBResult<()> {
+        let kv_engine = KVEngine::new(MemoryEngine::new());
+        let mut s = kv_engine.session()?;
+        s.execute("create table t1 (a int primary key, b int default -1, c float);")?;
+        s.execute("insert into t1 values (1, 10, 1.5), (2, 2 * 10, -0.5), (3, null, 2 ^ -1);")?;
+        s.execute("insert into t1 (a) values (4);")?;
+        match s.execute("select a * 2 + 1, b % 7 as m, b / 3, c * 2, -a ^ 2 from t1;")? {
+            ResultSet::Scan { columns, rows } => {
+                assert_eq!(columns, vec!["a * 2 + 1", "m", "b / 3", "c * 2", "-a ^ 2"]);
+                assert_eq!(rows, vec![
+                    vec![Value::Integer(3), Value::Integer(3), Value::Integer(3), Value::Float(3.0), Value::Integer(-1)],
+                    vec![Value::Integer(5), Value::Integer(6), Value::Integer(6), Value::Float(-1.0), Value::Integer(-4)],
+                    vec![Value::Integer(7), Value::Null, Value::Null, Value::Float(1.0), Value::Integer(-9)],
+                    vec![Value::Integer(9), Value::Integer(-1), Value::Integer(0), Value::Null, Value::Integer(-16)],
+                ]);
+            },
+            _ => unreachable!(),
+        }
+        match s.execute("select a from t1 where a + b > 11;")? {
+            ResultSet::Scan { rows, .. } => assert_eq!(rows, vec![vec![Value::Integer(2)]]),
+            _ => unreachable!(),
+        }
+        // update 中引用原来的值
+        s.execute("update t1 set b = b + a * 100 where a < 3;")?;
+        match s.execute("select b from t1 where a < 3;")? {
+            ResultSet::Scan { rows, .. } => assert_eq!(rows, vec![vec![Value::Integer(110)], vec![Value::Integer(220)]]),
+            _ => unreachable!(),
+        }
+        // 括号中的表达式
+        match s.execute("select (a + b) * 2, a from t1 where (a = 1 or a = 4) and not (b > 100);")? {
+            ResultSet::Scan { columns, rows } => {
+                assert_eq!(columns, vec!["(a + b) * 2", "a"]);
+                assert_eq!(rows, vec![vec![Value::Integer(6), Value::Integer(4)]]);
+            },
+            _ => unreachable!(),
+        }
+        assert!(s.execute("select a / 0 from t1;").is_err());
+        assert!(s.execute("select a + 9223372036854775807 from t1;").is_err());
+        assert!(s.execute("select a + 'x' from t1;").is_err());
         Ok(())
     }
-    
+
     #[test]
-    fn test_agg() -> LegendDBResult<()> {
-        let p = tempfile::tempdir()?.into_path().join("sqldb-log");
-        let kvengine = KVEngine::new(DiskEngine::new(p.clone())?);
-        let mut s = kvengine.session()?;
-        s.execute("create table t1 (a int primary key, b text, c float);")?;
-        s.execute("insert into t1 values (1, 'a', 1.1);")?;
-        s.execute("insert into t1 values (2, 'b', 2.2);")?;
-        s.execute("insert into t1 values (3, 'c', 3.3);")?;
-        match s.execute("select min(c) as ffffff from t1;")? {
+    fn test_subquery_in_from() -> LegendDBResult<()> {
+        let kv_engine = KVEngine::new(MemoryEngine::new());
+        let mut s = kv_engine.session()?;
+        s.execute("create table t (a int primary key, b int);")?;
+        s.execute("create table u (c int primary key, d string);")?;
+        s.execute("insert into t values (1, 10), (2, 20), (3, 30);")?;
+        s.execute("insert into u values (10, 'x'), (30, 'z');")?;
+        // 外层查询使用子查询输出的列名
+        match s.execute("select x, d from (select a as x, b from t where a > 1) as sub join u on b = c;")? {
             ResultSet::Scan { columns, rows } => {
-                print!("{:?}", columns);
-                print!("{:?}", rows);
+                assert_eq!(columns, vec!["x", "d"]);
+                assert_eq!(rows, vec![vec![Value::Integer(3), Value::String("z".to_string())]]);
+            },
+            _ => unreachable!(),
+        }
+        match s.execute("select * from (select b * 2 as y from t) as sub where y > 20 order by y desc;")? {
+            ResultSet::Scan { columns, rows } => {
+                assert_eq!(columns, vec!["y"]);
+                assert_eq!(rows, vec![vec![Value::Integer(60)], vec![Value::Integer(40)]]);
+            },
+            _ => unreachable!(),
+        }
+        match s.execute("select count(a) as n from (select a from t where b < 30) as sub;")? {
+            ResultSet::Scan { rows, .. } => assert_eq!(rows, vec![vec![Value::Integer(2)]]),
+            _ => unreachable!(),
+        }
+        assert!(s.execute("select * from (select a from t);").is_err());
+        assert!(s.execute("select * from (select a from t) as t1 join t as t1 on a = a;").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_subquery_in_where() -> LegendDBResult<()> {
+        let kv_engine = KVEngine::new(MemoryEngine::new());
+        let mut s = kv_engine.session()?;
+        s.execute("create table t (a int primary key, b int);")?;
+        s.execute("create table u (c int primary key, d int);")?;
+        s.execute("insert into t values (1, 10), (2, 20), (3, 30);")?;
+        s.execute("insert into u values (10, 1), (30, null);")?;
+        let query = |s: &mut Session<KVEngine<MemoryEngine>>, sql: &str| -> LegendDBResult<Vec<Row>> {
+            match s.execute(sql)? {
+                ResultSet::Scan { rows, .. } => Ok(rows),
+                _ => unreachable!(),
             }
+        };
+        assert_eq!(query(&mut s, "select a from t where b in (select c from u);")?, vec![vec![Value::Integer(1)], vec![Value::Integer(3)]]);
+        assert_eq!(query(&mut s, "select a from t where b not in (select c from u where c < 20);")?, vec![vec![Value::Integer(2)], vec![Value::Integer(3)]]);
+        // 子查询结果中有 NULL 时 NOT IN 的结果未知
+        assert!(query(&mut s, "select a from t where a not in (select d from u);")?.is_empty());
+        assert_eq!(query(&mut s, "select a from t where exists (select c from u where c > 20) and a > 2;")?, vec![vec![Value::Integer(3)]]);
+        assert_eq!(query(&mut s, "select a from t where not exists (select c from u where c > 100);")?.len(), 3);
+        // update 和 delete 中也可以使用子查询
+        s.execute("delete from t where b in (select c from u);")?;
+        assert_eq!(query(&mut s, "select a from t;")?, vec![vec![Value::Integer(2)]]);
+        assert!(s.execute("select a from t where b in (select c, d from u);").is_err());
+        assert!(s.execute("select a from t where b not (select c from u);").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_qualified_columns() -> LegendDBResult<()> {
+        let kv_engine = KVEngine::new(MemoryEngine::new());
+        let mut s = kv_engine.session()?;
+        s.execute("create table t1 (a int primary key, b int);")?;
+        s.execute("create table t2 (a int primary key, c int);")?;
+        s.execute("insert into t1 values (1, 10), (2, 20);")?;
+        s.execute("insert into t2 values (2, 200), (3, 300);")?;
+        // 同名的列用表名区分，不重复的列不带表名
+        match s.execute("select * from t1 join t2 on t2.a = t1.a;")? {
+            ResultSet::Scan { columns, rows } => {
+                assert_eq!(columns, vec!["t1.a", "b", "t2.a", "c"]);
+                assert_eq!(rows, vec![vec![Value::Integer(2), Value::Integer(20), Value::Integer(2), Value::Integer(200)]]);
+            },
+            _ => unreachable!(),
+        }
+        match s.execute("select x.a, y.c from t1 as x left join t2 as y on x.a = y.a where x.b > 5 order by x.a desc;")? {
+            ResultSet::Scan { columns, rows } => {
+                assert_eq!(columns, vec!["a", "c"]);
+                assert_eq!(rows, vec![vec![Value::Integer(2), Value::Integer(200)], vec![Value::Integer(1), Value::Null]]);
+            },
+            _ => unreachable!(),
+        }
+        // 单表查询也可以带表名
+        match s.execute("select t1.b from t1 where t1.a = 1;")? {
+            ResultSet::Scan { rows, .. } => assert_eq!(rows, vec![vec![Value::Integer(10)]]),
             _ => unreachable!(),
         }
+        s.execute("update t1 set b = t1.b + 1 where t1.a = 1;")?;
+        // 同名的列不带表名时有歧义，表名必须是 from 中的表名或别名
+        assert!(s.execute("select a from t1 join t2 on t1.a = t2.a;").is_err());
+        assert!(s.execute("select t3.a from t1;").is_err());
+        assert!(s.execute("select t1.a from t1 as x;").is_err());
         Ok(())
     }
 
     #[test]
-    fn test_agg_group() -> LegendDBResult<()> {
-        let p = tempfile::tempdir()?.into_path().join("sqldb-log");
-        let kvengine = KVEngine::new(DiskEngine::new(p.clone())?);
-        let mut s = kvengine.session()?;
-        s.execute("create table t1 (a int primary key, b text, c float);")?;
-        s.execute("insert into t1 values (1, 'a', 1.1);")?;
-        s.execute("insert into t1 values (2, 'c', 2.2);")?;
-        s.execute("insert into t1 values (3, 'a', 3.3);")?;
-        s.execute("insert into t1 values (4, 'c', 3.3);")?;
-        match s.execute("select b, min(c) from t1 group by b;")? {
+    fn test_quoted_identifiers() -> LegendDBResult<()> {
+        let kv_engine = KVEngine::new(MemoryEngine::new());
+        let mut s = kv_engine.session()?;
+        // 关键字、空格以及大写字母都可以出现在带引号的名字中
+        s.execute("create table `select` (`order` int primary key, \"My Col\" string);")?;
+        s.execute("insert into `select` values (1, 'a'), (2, 'b');")?;
+        match s.execute("select `order`, \"My Col\" from \"select\" where `order` > 1;")? {
             ResultSet::Scan { columns, rows } => {
-                print!("{:?}", columns);
-                print!("{:?}", rows);
-            }
+                assert_eq!(columns, vec!["order", "My Col"]);
+                assert_eq!(rows, vec![vec![Value::Integer(2), Value::String("b".to_string())]]);
+            },
+            _ => unreachable!(),
+        }
+        match s.execute("select t.\"My Col\" from `select` t join `select` u on t.`order` = u.`order` where u.`order` = 1;")? {
+            ResultSet::Scan { rows, .. } => assert_eq!(rows, vec![vec![Value::String("a".to_string())]]),
             _ => unreachable!(),
         }
+        // 带引号的名字区分大小写
+        assert!(s.execute("select \"my col\" from `select`;").is_err());
         Ok(())
     }
 
     #[test]
-    fn test_where_great_group() -> LegendDBResult<()> {
-        let p = tempfile::tempdir()?.into_path().join("sqldb-log");
-        let kvengine = KVEngine::new(DiskEngine::new(p.clone())?);
-        let mut s = kvengine.session()?;
-        s.execute("create table t1 (a int primary key, b text, c float);")?;
-        s.execute("insert into t1 values (1, 'a', 1.1);")?;
-        s.execute("insert into t1 values (2, 'c', 2.2);")?;
+    fn test_string_escape() -> LegendDBResult<()> {
+        let kv_engine = KVEngine::new(MemoryEngine::new());
+        let mut s = kv_engine.session()?;
+        s.execute("create table t (a int primary key, b string);")?;
+        s.execute(r"insert into t values (1, 'it''s'), (2, 'a\'b'), (3, 'c:\\tmp');")?;
+        match s.execute("select b from t;")? {
+            ResultSet::Scan { rows, .. } => assert_eq!(rows, vec![
+                vec![Value::String("it's".to_string())],
+                vec![Value::String("a'b".to_string())],
+                vec![Value::String(r"c:\tmp".to_string())],
+            ]),
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_function_default() -> LegendDBResult<()> {
+        let kv_engine = KVEngine::new(MemoryEngine::new());
+        let mut s = kv_engine.session()?;
+        s.execute("create table t (a int primary key, created_at string default now(), ts int default unix_timestamp() + 1);")?;
+        s.execute("insert into t (a) values (1);")?;
+        match s.execute("select * from t;")? {
+            ResultSet::Scan { rows, .. } => {
+                // yyyy-mm-dd hh:mm:ss
+                assert!(matches!(&rows[0][1], Value::String(s) if s.len() == 19 && &s[4..5] == "-" && &s[13..14] == ":"));
+                assert!(matches!(rows[0][2], Value::Integer(ts) if ts > 1_700_000_000));
+            },
+            _ => unreachable!(),
+        }
+        // 未知的函数在建表时报错，插入时才求值的默认值不能填充已有的数据
+        assert!(s.execute("create table u (a int primary key, b int default foo());").is_err());
+        assert!(s.execute("alter table t add column c int default unix_timestamp();").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_semantic_errors() -> LegendDBResult<()> {
+        let kv_engine = KVEngine::new(MemoryEngine::new());
+        let mut s = kv_engine.session()?;
+        s.execute("create table t (a int primary key, b string, c bool);")?;
+        s.execute("create table u (a int primary key, d float);")?;
+        // 表中没有数据时也会检查，所有的错误一起返回
+        match s.execute("select x, b + 1 from t where a = 'x' and c;") {
+            Err(LegendDBError::Semantic(errors)) => assert_eq!(errors, vec![
+                "cannot compare INTEGER with STRING in a = 'x'".to_string(),
+                "unknown column x".to_string(),
+                "operator + cannot be applied to STRING in b + 1".to_string(),
+            ]),
+            r => panic!("unexpected result {:?}", r.map(|_| ())),
+        }
+        match s.execute("select a from t join u on t.a = u.a where v.d > 1;") {
+            Err(LegendDBError::Semantic(errors)) => assert_eq!(errors, vec![
+                "unknown table v in column v.d".to_string(),
+                "column a is ambiguous".to_string(),
+            ]),
+            r => panic!("unexpected result {:?}", r.map(|_| ())),
+        }
+        assert!(matches!(s.execute("update t set a = 'x' where b;"), Err(LegendDBError::Semantic(e)) if e.len() == 2));
+        assert!(matches!(s.execute("delete from t where b like 1;"), Err(LegendDBError::Semantic(_))));
+        assert!(matches!(s.execute("select * from missing;"), Err(LegendDBError::TableNotFound(_))));
+        // 合法的查询不受影响
+        s.execute("select t.a, d * 2 from t join u on t.a = u.a where d + t.a > 1.5 order by d desc;")?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_limit_offset() -> LegendDBResult<()> {
+        let kv_engine = KVEngine::new(MemoryEngine::new());
+        let mut s = kv_engine.session()?;
+        s.execute("create table t (a int primary key);")?;
+        s.execute("insert into t values (1), (2), (3);")?;
+        let rows = |s: &mut Session<KVEngine<MemoryEngine>>, sql: &str| -> LegendDBResult<usize> {
+            match s.execute(sql)? {
+                ResultSet::Scan { rows, .. } => Ok(rows.len()),
+                _ => unreachable!(),
+            }
+        };
+        assert_eq!(rows(&mut s, "select * from t limit 1 + 1;")?, 2);
+        assert_eq!(rows(&mut s, "select * from t limit all offset 1;")?, 2);
+        assert_eq!(rows(&mut s, "select * from t limit null;")?, 3);
+        // MySQL 的 LIMIT offset, count
+        assert_eq!(rows(&mut s, "select * from t limit 1, 1;")?, 1);
+        assert_eq!(rows(&mut s, "select * from t limit 2, 10;")?, 1);
+        assert!(s.execute("select * from t limit 1, 1 offset 1;").is_err());
+        // offset 超过行数时返回空结果
+        assert_eq!(rows(&mut s, "select * from t offset 10;")?, 0);
+        for sql in ["select * from t limit -1;", "select * from t offset -1;", "select * from t limit 1.5;", "select * from t limit a;"] {
+            assert!(s.execute(sql).is_err(), "{}", sql);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_table_constraints() -> LegendDBResult<()> {
+        let kv_engine = KVEngine::new(MemoryEngine::new());
+        let mut s = kv_engine.session()?;
+        s.execute("create table t (id int, b int, c string, constraint pk primary key (id), unique (b, c));")?;
+        assert_eq!(
+            s.get_table("t".to_string())?,
+            "CREATE TABLE t (id Integer PRIMARY KEY,\nb Integer DEFAULT NULL,\nc String DEFAULT NULL,\nCONSTRAINT pk PRIMARY KEY (id),\nCONSTRAINT t_b_c_key UNIQUE (b, c))"
+        );
+        s.execute("insert into t (id) values (1);")?;
+        assert!(s.execute("insert into t (b) values (1);").is_err());
+        for sql in [
+            "create table u (a int, b int, primary key (a, b));",
+            "create table u (a int primary key, b int, primary key (b));",
+            "create table u (a int primary key, unique (x));",
+            "create table u (a int primary key, b int, constraint k unique (a), constraint k unique (b));",
+        ] {
+            assert!(s.execute(sql).is_err(), "{}", sql);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_foreign_keys() -> LegendDBResult<()> {
+        let kv_engine = KVEngine::new(MemoryEngine::new());
+        let mut s = kv_engine.session()?;
+        s.execute("create table p (a int primary key, b string, unique (b));")?;
+        s.execute("create table c (id int primary key, pa int references p on delete cascade, pb string, parent int, \
+                   constraint c_pb foreign key (pb) references p (b) on update set null, \
+                   foreign key (parent) references c (id) on delete no action on update restrict);")?;
+        assert_eq!(
+            s.get_table("c".to_string())?,
+            "CREATE TABLE c (id Integer PRIMARY KEY,\npa Integer DEFAULT NULL,\npb String DEFAULT NULL,\nparent Integer DEFAULT NULL,\n\
+             CONSTRAINT c_pb FOREIGN KEY (pb) REFERENCES p (b) ON UPDATE SET NULL,\n\
+             CONSTRAINT c_parent_fkey FOREIGN KEY (parent) REFERENCES c (id) ON UPDATE RESTRICT,\n\
+             CONSTRAINT c_pa_fkey FOREIGN KEY (pa) REFERENCES p (a) ON DELETE CASCADE)"
+        );
+        for sql in [
+            // 引用的表不存在、列不是主键或唯一约束、类型不一致
+            "create table d (id int primary key, x int references missing);",
+            "create table d (id int primary key, x int references p (b));",
+            "create table d (id int primary key, x string references p (a));",
+            "create table d (id int primary key, x int, foreign key (x) references p (a, b));",
+            "alter table p add column x int references c;",
+        ] {
+            assert!(s.execute(sql).is_err(), "{}", sql);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_constraints() -> LegendDBResult<()> {
+        let kv_engine = KVEngine::new(MemoryEngine::new());
+        let mut s = kv_engine.session()?;
+        s.execute("create table t (a int primary key, b int check (b > 0), c int, check (c < b), check (a != 100));")?;
+        assert_eq!(
+            s.get_table("t".to_string())?,
+            "CREATE TABLE t (a Integer PRIMARY KEY,\nb Integer DEFAULT NULL,\nc Integer DEFAULT NULL,\n\
+             CONSTRAINT t_check CHECK (c < b),\nCONSTRAINT t_check1 CHECK (a != 100),\nCONSTRAINT t_b_check CHECK (b > 0))"
+        );
+        s.execute("insert into t values (1, 10, 5), (2, null, null);")?;
+        for sql in ["insert into t values (3, -1, null);", "insert into t values (4, 1, 2);", "insert into t values (100, 1, null);", "update t set b = 0 where a = 1;"] {
+            assert!(s.execute(sql).is_err(), "{}", sql);
+        }
+        s.execute("update t set c = 9 where a = 1;")?;
+        match s.execute("select b, c from t;")? {
+            ResultSet::Scan { rows, .. } => assert_eq!(rows, vec![
+                vec![Value::Integer(10), Value::Integer(9)],
+                vec![Value::Null, Value::Null],
+            ]),
+            _ => unreachable!(),
+        }
+        assert!(s.execute("create table u (a int primary key, check (x > 0));").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_interval() -> LegendDBResult<()> {
+        let kv_engine = KVEngine::new(MemoryEngine::new());
+        let mut s = kv_engine.session()?;
+        s.execute("create table jobs (id int primary key, run_at string, created string);")?;
+        s.execute("insert into jobs values (1, '2024-01-31', '2024-03-01 10:00:00'), (2, '2024-02-28 23:30:00', '2023-12-01 00:00:00');")?;
+        // 月末的日期取新月份的月末，只有日期时加整天的结果也只有日期
+        match s.execute("select id, run_at + interval '1 month', interval '1 hour' + run_at, created - interval '1 year 1 day' from jobs;")? {
+            ResultSet::Scan { rows, .. } => assert_eq!(rows, vec![
+                vec![Value::Integer(1), Value::String("2024-02-29".to_string()), Value::String("2024-01-31 01:00:00".to_string()), Value::String("2023-02-28 10:00:00".to_string())],
+                vec![Value::Integer(2), Value::String("2024-03-28 23:30:00".to_string()), Value::String("2024-02-29 00:30:00".to_string()), Value::String("2022-11-30 00:00:00".to_string())],
+            ]),
+            _ => unreachable!(),
+        }
+        match s.execute("select id from jobs where created < '2024-01-15' - interval '1 week';")? {
+            ResultSet::Scan { rows, .. } => assert_eq!(rows, vec![vec![Value::Integer(2)]]),
+            _ => unreachable!(),
+        }
+        match s.execute("select interval '1 day' * 3 + interval '90 minutes', -interval '2 months' from jobs where id = 1;")? {
+            ResultSet::Scan { rows, .. } => {
+                assert_eq!(rows[0][0].to_string(), "3 days 1 hour 30 minutes");
+                assert_eq!(rows[0][1].to_string(), "-2 months");
+            },
+            _ => unreachable!(),
+        }
+        assert!(s.execute("select interval '3 fortnights' from jobs;").is_err());
+        assert!(matches!(s.execute("select id + interval '1 day' from jobs;"), Err(LegendDBError::Semantic(_))));
+        assert!(s.execute("insert into jobs values (3, interval '1 day', '2024-01-01');").is_err());
+        match s.execute("select 'soon' + interval '1 day' from jobs;") {
+            Err(LegendDBError::Internal(msg)) => assert!(msg.contains("invalid timestamp 'soon'"), "{}", msg),
+            r => panic!("unexpected result {:?}", r.is_ok()),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_unique_column() -> LegendDBResult<()> {
+        let kv_engine = KVEngine::new(MemoryEngine::new());
+        let mut s = kv_engine.session()?;
+        s.execute("create table t (id int primary key, email string unique, a int, b int, unique (a, b));")?;
+        assert!(s.get_table("t".to_string())?.contains("email String UNIQUE"));
+        s.execute("insert into t values (1, 'x', 1, 1), (2, null, 1, 2), (3, null, null, 2);")?;
+        // 唯一列和表级 UNIQUE 约束都会检查，NULL 不算重复
+        match s.execute("insert into t values (4, 'x', 5, 5);") {
+            Err(LegendDBError::DuplicateKey { column, key, .. }) => assert_eq!((column.as_str(), key.as_str()), ("email", "'x'")),
+            r => panic!("unexpected result {:?}", r.is_ok()),
+        }
+        match s.execute("insert into t values (4, 'y', 1, 2);") {
+            Err(LegendDBError::DuplicateKey { column, key, .. }) => assert_eq!((column.as_str(), key.as_str()), ("t_a_b_key", "1, 2")),
+            r => panic!("unexpected result {:?}", r.is_ok()),
+        }
+        // 同一条语句中插入的重复值也会报错，并且整条语句不生效
+        assert!(s.execute("insert into t values (4, 'y', 4, 4), (5, 'y', 5, 5);").is_err());
+        assert!(s.execute("update t set email = 'x' where id = 2;").is_err());
+        s.execute("insert into t values (4, 'y', null, 2);")?;
+        match s.execute("select id, email from t;")? {
+            ResultSet::Scan { rows, .. } => assert_eq!(rows.len(), 4),
+            _ => unreachable!(),
+        }
+        // 已有的数据重复时不能修改为唯一列
+        assert!(s.execute("alter table t modify column b int unique;").is_err());
+        assert!(s.execute("alter table t add column c int default 0 unique;").is_err());
+        s.execute("alter table t add column d int unique;")?;
+        // 唯一列可以被外键引用
+        s.execute("create table u (id int primary key, email string references t (email));")?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_unique_index() -> LegendDBResult<()> {
+        let kv_engine = KVEngine::new(MemoryEngine::new());
+        let mut s = kv_engine.session()?;
+        s.execute("create table t (id int primary key, email string unique);")?;
+        s.execute("insert into t values (1, 'x'), (2, 'y');")?;
+        // 修改和删除之后旧的值可以被其他行使用，只修改主键时仍然占用原来的值
+        s.execute("update t set email = 'z' where id = 1;")?;
+        s.execute("insert into t values (3, 'x');")?;
+        s.execute("delete from t where id = 2;")?;
+        s.execute("update t set id = 4 where id = 3;")?;
+        s.execute("insert into t values (5, 'y');")?;
+        assert!(s.execute("insert into t values (6, 'x');").is_err());
+        s.execute("alter table t modify column email string;")?;
+        s.execute("insert into t values (6, 'x');")?;
+        assert!(s.execute("alter table t modify column email string unique;").is_err());
+        s.execute("drop table t;")?;
+        s.execute("create table t (id int primary key, email string unique);")?;
+        s.execute("insert into t values (1, 'x');")?;
+
+        // 并发插入相同的值时，唯一约束的索引产生写冲突
+        let insert = |txn: &mut _, sql: &str| -> LegendDBResult<_> { Plan::build(Parser::new(sql).parse()?)?.execute(txn) };
+        let mut txn1 = kv_engine.begin()?;
+        let mut txn2 = kv_engine.begin()?;
+        insert(&mut txn1, "insert into t values (2, 'w');")?;
+        assert!(matches!(insert(&mut txn2, "insert into t values (3, 'w');"), Err(LegendDBError::WriteMvccConflict { .. })));
+        txn2.rollback()?;
+        txn1.commit()?;
+        assert!(matches!(s.execute("insert into t values (3, 'w');"), Err(LegendDBError::DuplicateKey { .. })));
+        Ok(())
+    }
+
+    #[test]
+    fn test_unique_index_migration() -> LegendDBResult<()> {
+        let p = tempfile::tempdir()?.into_path().join("sqldb-log");
+        {
+            let kv_engine = KVEngine::new(DiskEngine::new(p.clone())?);
+            kv_engine.session()?.execute("create table t (id int primary key, email string unique);")?;
+            kv_engine.session()?.execute("insert into t values (1, 'x');")?;
+            // 模拟没有唯一约束索引的旧格式
+            let mut txn = kv_engine.kv.begin()?;
+            txn.delete(TransactionKey::FormatVersion.encode()?)?;
+            for result in txn.scan_prefix(KeyPrefix::Unique("t".to_string()).encode()?)? {
+                txn.delete(result.key)?;
+            }
+            txn.commit()?;
+        }
+        // 打开旧格式的数据库时补充索引
+        let mut s = KVEngine::new(DiskEngine::new(p.clone())?).session()?;
+        assert!(matches!(s.execute("insert into t values (2, 'x');"), Err(LegendDBError::DuplicateKey { .. })));
+        drop(s);
+        std::fs::remove_dir_all(p.parent().unwrap())?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_row_marker_migration() -> LegendDBResult<()> {
+        let p = tempfile::tempdir()?.into_path().join("sqldb-log");
+        {
+            let kv_engine = KVEngine::new(DiskEngine::new(p.clone())?);
+            kv_engine.session()?.execute("create table t (id int primary key, name string default '');")?;
+            kv_engine.session()?.execute("insert into t values (1, 'a'), (2, 'b');")?;
+            // 模拟格式 1 的数据库，行数据以版本号 1 开头
+            let mut txn = kv_engine.kv.begin()?;
+            txn.set(TransactionKey::FormatVersion.encode()?, 1u64.to_be_bytes().to_vec())?;
+            for result in txn.scan_prefix(KeyPrefix::Row("t".to_string()).encode()?)? {
+                let mut value = result.value;
+                value[0] = row_codec::LEGACY_ROW_FORMAT_VERSION;
+                txn.set(result.key, value)?;
+            }
+            txn.commit()?;
+        }
+        {
+            // 打开时迁移为新的标记
+            let kv_engine = KVEngine::new(DiskEngine::new(p.clone())?);
+            match kv_engine.session()?.execute("select name from t;")? {
+                ResultSet::Scan { rows, .. } => assert_eq!(rows, vec![vec![Value::String("a".to_string())], vec![Value::String("b".to_string())]]),
+                _ => unreachable!(),
+            }
+            let mut txn = kv_engine.kv.begin()?;
+            assert_eq!(txn.get(TransactionKey::FormatVersion.encode()?)?, Some(FORMAT_VERSION.to_be_bytes().to_vec()));
+            for result in txn.scan_prefix(KeyPrefix::Row("t".to_string()).encode()?)? {
+                assert_eq!(result.value[0], row_codec::ROW_FORMAT_MARKER);
+            }
+            // 无法识别的行
+            txn.set(TransactionKey::FormatVersion.encode()?, 1u64.to_be_bytes().to_vec())?;
+            txn.set(TransactionKey::RowKey("t".to_string(), Value::Integer(3)).encode()?, vec![1, 2, 3])?;
+            txn.commit()?;
+        }
+        let err = KVEngine::new(DiskEngine::new(p.clone())?).session()?.execute("select * from t;").unwrap_err();
+        assert!(err.to_string().contains("unknown format"), "{}", err);
+        std::fs::remove_dir_all(p.parent().unwrap())?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_legacy_key_encoding() -> LegendDBResult<()> {
+        let kv_engine = KVEngine::new(MemoryEngine::new());
+        // 旧的编码中表名没有结尾标记
+        let txn = kv_engine.kv.begin()?;
+        txn.set(vec![0, b't', b'1'], vec![1])?;
+        txn.commit()?;
+        let err = kv_engine.session()?.execute("select * from t1;").unwrap_err();
+        assert!(err.to_string().contains("old key encoding"), "{}", err);
+        // 报错时不修改数据库
+        let txn = kv_engine.kv.begin()?;
+        assert!(txn.get(TransactionKey::Codec.encode()?)?.is_none());
+        assert!(txn.get(TransactionKey::FormatVersion.encode()?)?.is_none());
+        txn.commit()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_auto_increment() -> LegendDBResult<()> {
+        let kv_engine = KVEngine::new(MemoryEngine::new());
+        let mut s = kv_engine.session()?;
+        s.execute("create table t (id int primary key auto_increment, name string);")?;
+        assert!(s.get_table("t".to_string())?.contains("id Integer PRIMARY KEY AUTO_INCREMENT"));
+        // 没有指定值或者值为 NULL 时自动生成，指定的值更大时从指定的值继续递增
+        s.execute("insert into t (name) values ('a'), ('b');")?;
+        s.execute("insert into t values (null, 'c'), (10, 'd'), (null, 'e');")?;
+        s.execute("insert into t (name) values ('f');")?;
+        match s.execute("select id, name from t;")? {
+            ResultSet::Scan { rows, .. } => assert_eq!(
+                rows.iter().map(|r| r[0].clone()).collect::<Vec<_>>(),
+                [1, 2, 3, 10, 11, 12].map(Value::Integer).to_vec()
+            ),
+            _ => unreachable!(),
+        }
+        assert!(s.execute("insert into t values (2, 'g');").is_err());
+        assert!(s.execute("create table u (id int primary key, name string auto_increment);").is_err());
+        assert!(s.execute("create table u (id int primary key auto_increment default 1);").is_err());
+        assert!(s.execute("create table u (id int primary key auto_increment, b int auto_increment);").is_err());
+        // Postgres 风格的 serial
+        s.execute("create table v (id serial primary key, name string);")?;
+        assert!(s.get_table("v".to_string())?.contains("id Integer PRIMARY KEY AUTO_INCREMENT"));
+        s.execute("insert into v (name) values ('a'), ('b');")?;
+        match s.execute("select id from v;")? {
+            ResultSet::Scan { rows, .. } => assert_eq!(rows, vec![vec![Value::Integer(1)], vec![Value::Integer(2)]]),
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_show_table_round_trip() -> LegendDBResult<()> {
+        let kv_engine = KVEngine::new(MemoryEngine::new());
+        let mut s = kv_engine.session()?;
+        s.execute("create table `Order` (`select` int primary key, `my col` string not null default 'it''s', f float default 2.0, \
+            b bool default true, ts string default now(), constraint `Key` unique (`my col`, f), check (f > 0.5));")?;
+        let ddl = s.get_table("Order".to_string())?;
+        assert_eq!(
+            ddl,
+            "CREATE TABLE `Order` (`select` Integer PRIMARY KEY,\n`my col` String NOT NULL DEFAULT 'it''s',\nf Float DEFAULT 2.0,\n\
+             b Boolean DEFAULT TRUE,\nts String DEFAULT now(),\nCONSTRAINT `Key` UNIQUE (`my col`, f),\nCONSTRAINT `Order_check` CHECK (f > 0.5))"
+        );
+        // 输出的建表语句可以重新执行，得到相同的表结构
+        let kv_engine = KVEngine::new(MemoryEngine::new());
+        let mut s = kv_engine.session()?;
+        s.execute(&format!("{};", ddl))?;
+        assert_eq!(s.get_table("Order".to_string())?, ddl);
+        Ok(())
+    }
+
+    #[test]
+    fn test_deterministic_order() -> LegendDBResult<()> {
+        let kv_engine = KVEngine::new(MemoryEngine::new());
+        let mut s = kv_engine.session()?;
+        s.execute("create table t (id int primary key, g int);")?;
+        s.execute("create table u (id int primary key, g int);")?;
+        s.execute("insert into t values (5, 1), (-3, 2), (1, 1), (-10, 2), (7, 3);")?;
+        s.execute("insert into u values (2, 1), (-2, 1), (9, 2);")?;
+        s.execute("set deterministic_order = on;")?;
+        let ids = |result: ResultSet| match result {
+            ResultSet::Scan { rows, .. } => rows.into_iter().map(|r| r[0].clone()).collect::<Vec<_>>(),
+            _ => unreachable!(),
+        };
+        // 没有 order by 时按主键排序，排序的值相同时再按主键排序
+        assert_eq!(ids(s.execute("select * from t;")?), [-10, -3, 1, 5, 7].map(Value::Integer));
+        assert_eq!(ids(s.execute("select id from t order by g desc;")?), [7, -10, -3, 1, 5].map(Value::Integer));
+        assert_eq!(ids(s.execute("select id from t order by g asc limit 2 offset 1;")?), [5, -10].map(Value::Integer));
+        match s.execute("select t.id, u.id from t join u on t.g = u.g order by t.g;")? {
+            ResultSet::Scan { rows, .. } => assert_eq!(rows, [(1, -2), (1, 2), (5, -2), (5, 2), (-10, 9), (-3, 9)]
+                .map(|(a, b)| vec![Value::Integer(a), Value::Integer(b)]).to_vec()),
+            _ => unreachable!(),
+        }
+        // 聚合的结果按整行排序
+        assert_eq!(ids(s.execute("select g, count(id) from t group by g;")?), [1, 2, 3].map(Value::Integer));
+        assert!(s.execute("set deterministic_order = maybe;").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_order_by_expression() -> LegendDBResult<()> {
+        let kv_engine = KVEngine::new(MemoryEngine::new());
+        let mut s = kv_engine.session()?;
+        s.execute("create table t (id int primary key, a int, b int);")?;
+        s.execute("insert into t values (1, 5, 1), (2, 1, 2), (3, 3, 9), (4, 2, 2);")?;
+        let ids = |result: ResultSet| match result {
+            ResultSet::Scan { rows, .. } => rows.into_iter().map(|r| r[0].clone()).collect::<Vec<_>>(),
+            _ => unreachable!(),
+        };
+        // 按表达式排序
+        assert_eq!(ids(s.execute("select id from t order by a + b desc;")?), [3, 1, 4, 2].map(Value::Integer));
+        assert_eq!(ids(s.execute("select id, a * -1 as na from t order by na;")?), [1, 3, 4, 2].map(Value::Integer));
+        // 按查询列的序号排序
+        assert_eq!(ids(s.execute("select id, a from t order by 2;")?), [2, 4, 3, 1].map(Value::Integer));
+        assert_eq!(ids(s.execute("select * from t order by 3 desc, 2;")?), [3, 2, 4, 1].map(Value::Integer));
+        assert!(s.execute("select id, a from t order by 3;").is_err());
+        assert!(s.execute("select * from t order by 0;").is_err());
+        // 没有指定方向时后面的 limit 正常解析
+        assert_eq!(ids(s.execute("select id from t order by b limit 2;")?), [1, 2].map(Value::Integer));
+        // 聚合查询按聚合函数或者序号排序
+        assert_eq!(ids(s.execute("select b, count(id) from t group by b order by count(id) desc, b;")?), [2, 1, 9].map(Value::Integer));
+        assert_eq!(ids(s.execute("select b, sum(a) as total from t group by b order by 2 desc, 1;")?), [1, 2, 9].map(Value::Integer));
+        assert!(s.execute("select b, count(id) from t group by b order by max(a);").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_order_by_stable() -> LegendDBResult<()> {
+        let kv_engine = KVEngine::new(MemoryEngine::new());
+        let mut s = kv_engine.session()?;
+        s.execute("create table t (id int primary key, b int);")?;
+        s.execute("insert into t values (5, 2), (4, 1), (3, 2), (2, 1), (1, 2);")?;
+        let ids = |result: ResultSet| match result {
+            ResultSet::Scan { rows, .. } => rows.into_iter().map(|r| r[0].clone()).collect::<Vec<_>>(),
+            _ => unreachable!(),
+        };
+        // 排序值相同的行按扫描的顺序（主键顺序）输出，降序时也一样
+        assert_eq!(ids(s.execute("select id from t order by b;")?), [2, 4, 1, 3, 5].map(Value::Integer));
+        assert_eq!(ids(s.execute("select id from t order by b desc;")?), [1, 3, 5, 2, 4].map(Value::Integer));
+        assert_eq!(ids(s.execute("select id from t order by b desc limit 2;")?), [1, 3].map(Value::Integer));
+        Ok(())
+    }
+
+    #[test]
+    fn test_count_rows() -> LegendDBResult<()> {
+        let kv_engine = KVEngine::new(MemoryEngine::new());
+        let mut s = kv_engine.session()?;
+        s.execute("create table t (a int primary key, b int);")?;
+        s.execute("insert into t values (1, null), (2, 20), (3, 30);")?;
+        let count = |s: &mut Session<_>, sql: &str| -> LegendDBResult<Value> {
+            match s.execute(sql)? {
+                ResultSet::Scan { rows, .. } => Ok(rows[0][0].clone()),
+                _ => unreachable!(),
+            }
+        };
+        // 没有过滤条件的 count(*) 使用行数统计
+        match s.execute("explain select count(*) as n from t;")? {
+            ResultSet::Explain { plan } => assert!(plan.contains("Count Rows: t"), "{}", plan),
+            _ => unreachable!(),
+        }
+        assert_eq!(count(&mut s, "select count(*) from t;")?, Value::Integer(3));
+        assert_eq!(count(&mut s, "select count(b) from t;")?, Value::Integer(2));
+        assert_eq!(count(&mut s, "select count(*) from t where a > 1;")?, Value::Integer(2));
+        s.execute("insert into t values (4, 40);")?;
+        s.execute("delete from t where a = 1;")?;
+        s.execute("update t set a = 10 where a = 2;")?;
+        assert_eq!(count(&mut s, "select count(*) from t;")?, Value::Integer(3));
+
+        // 统计的行数和事务的快照一致
+        let select = |txn: &mut _| -> LegendDBResult<_> {
+            match Plan::build(Parser::new("select count(*) from t;").parse()?)?.execute(txn)? {
+                ResultSet::Scan { rows, .. } => Ok(rows[0][0].clone()),
+                _ => unreachable!(),
+            }
+        };
+        let mut txn1 = kv_engine.begin()?;
+        let mut txn2 = kv_engine.begin()?;
+        Plan::build(Parser::new("insert into t values (5, 50), (6, 60);").parse()?)?.execute(&mut txn2)?;
+        assert_eq!(select(&mut txn2)?, Value::Integer(5));
+        txn2.commit()?;
+        assert_eq!(select(&mut txn1)?, Value::Integer(3));
+        Plan::build(Parser::new("delete from t where a = 3;").parse()?)?.execute(&mut txn1)?;
+        assert_eq!(select(&mut txn1)?, Value::Integer(2));
+        txn1.rollback()?;
+        assert_eq!(count(&mut s, "select count(*) from t;")?, Value::Integer(5));
+
+        // 严格计数时扫描全表
+        s.execute("set strict_count = on;")?;
+        match s.execute("explain select count(*) from t;")? {
+            ResultSet::Explain { plan } => assert!(plan.contains("Aggregate: count(*)"), "{}", plan),
+            _ => unreachable!(),
+        }
+        assert_eq!(count(&mut s, "select count(*) from t;")?, Value::Integer(5));
+        s.execute("set strict_count = off;")?;
+        // 删除之后重新创建的表从 0 开始
+        s.execute("drop table t;")?;
+        s.execute("create table t (a int primary key);")?;
+        assert_eq!(count(&mut s, "select count(*) from t;")?, Value::Integer(0));
+        assert!(s.execute("select sum(*) from t;").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_explain_analyze() -> LegendDBResult<()> {
+        let kv_engine = KVEngine::new(MemoryEngine::new());
+        let mut s = kv_engine.session()?;
+        s.execute("create table t (a int primary key, b int);")?;
+        s.execute("insert into t values (1, 10), (2, 20), (3, 30);")?;
+        let plan = match s.execute("explain analyze select a from t where a > 1 order by b desc;")? {
+            ResultSet::Explain { plan } => plan,
+            _ => unreachable!(),
+        };
+        // 每个节点展示实际的行数和耗时
+        assert!(plan.contains("Projection: a (actual rows=2 "), "{}", plan);
+        assert!(plan.contains("  Order: b DESC (actual rows=2 "), "{}", plan);
+        assert!(plan.contains("    Scan: t (full table scan) filter: a > 1 columns: a, b (actual rows=2 "), "{}", plan);
+        s.execute("explain analyze select count(*) from t;")?;
+        // 运行统计写到 __query_stats 表中，每个节点一行
+        match s.execute("select query, depth, operator, rows, fingerprint from __query_stats order by id;")? {
+            ResultSet::Scan { rows, .. } => {
+                assert_eq!(rows.len(), 4);
+                assert_eq!(rows[0][0], Value::String("SELECT a FROM t WHERE a > 1 ORDER BY b DESC".to_string()));
+                assert_eq!(rows[0][4], Value::String("SELECT a FROM t WHERE a > ? ORDER BY b DESC".to_string()));
+                assert_eq!(rows[2][1..4], [Value::Integer(2), Value::String("Scan: t (full table scan) filter: a > 1 columns: a, b".to_string()), Value::Integer(2)]);
+                assert_eq!(rows[3][3], Value::Integer(1));
+            },
+            _ => unreachable!(),
+        }
+        match s.execute("select operator from __query_stats where self_us >= 0 and total_us >= self_us order by self_us desc limit 1;")? {
+            ResultSet::Scan { rows, .. } => assert_eq!(rows.len(), 1),
+            _ => unreachable!(),
+        }
+        // 只读模式下不能写运行统计
+        s.read_only = true;
+        assert!(s.execute("explain analyze select * from t;").is_err());
+        assert!(s.execute("explain select * from t;").is_ok());
+        Ok(())
+    }
+
+    // 新格式的行数据前面加一个标记字节，没有标记的是默认编解码器写入的旧数据
+    #[derive(Debug)]
+    struct MarkedCodec;
+
+    impl Codec for MarkedCodec {
+        fn name(&self) -> &str {
+            "marked"
+        }
+
+        fn can_read(&self, name: &str) -> bool {
+            name == self.name() || name == DefaultCodec::NAME
+        }
+
+        fn encode_key(&self, key: &TransactionKey) -> LegendDBResult<Vec<u8>> {
+            DefaultCodec.encode_key(key)
+        }
+
+        fn encode_prefix(&self, prefix: &KeyPrefix) -> LegendDBResult<Vec<u8>> {
+            DefaultCodec.encode_prefix(prefix)
+        }
+
+        fn decode_key(&self, data: &[u8]) -> LegendDBResult<TransactionKey> {
+            DefaultCodec.decode_key(data)
+        }
+
+        fn encode_table(&self, table: &Table) -> LegendDBResult<Vec<u8>> {
+            DefaultCodec.encode_table(table)
+        }
+
+        fn decode_table(&self, data: &[u8]) -> LegendDBResult<Table> {
+            DefaultCodec.decode_table(data)
+        }
+
+        fn encode_row(&self, table: &Table, row: &Row) -> LegendDBResult<Vec<u8>> {
+            let mut output = vec![0xfe];
+            output.extend(DefaultCodec.encode_row(table, row)?);
+            Ok(output)
+        }
+
+        fn decode_row(&self, table: &Table, data: &[u8]) -> LegendDBResult<Row> {
+            match data.split_first() {
+                Some((0xfe, rest)) => DefaultCodec.decode_row(table, rest),
+                _ => DefaultCodec.decode_row(table, data),
+            }
+        }
+    }
+
+    #[test]
+    fn test_codec_migration() -> LegendDBResult<()> {
+        let p = tempfile::tempdir()?.into_path().join("sqldb-log");
+        {
+            let mut s = KVEngine::new(DiskEngine::new(p.clone())?).session()?;
+            s.execute("create table t (a int primary key, b text);")?;
+            s.execute("insert into t values (1, 'old');")?;
+        }
+        {
+            // 新的编解码器可以读取旧的数据，新旧数据共存
+            let mut s = KVEngine::with_codec(DiskEngine::new(p.clone())?, Arc::new(MarkedCodec)).session()?;
+            s.execute("insert into t values (2, 'new');")?;
+            match s.execute("select b from t order by a;")? {
+                ResultSet::Scan { rows, .. } => assert_eq!(rows, vec![
+                    vec![Value::String("old".to_string())],
+                    vec![Value::String("new".to_string())],
+                ]),
+                _ => unreachable!(),
+            }
+        }
+        // 写入过新格式的数据库不能再用默认的编解码器打开
+        let mut s = KVEngine::new(DiskEngine::new(p.clone())?).session()?;
+        match s.execute("select * from t;") {
+            Err(LegendDBError::Internal(msg)) => assert!(msg.contains("encoded with codec marked"), "{}", msg),
+            _ => unreachable!(),
+        }
+
+        std::fs::remove_dir_all(p.parent().unwrap())?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_datetime_literals() -> LegendDBResult<()> {
+        let kvengine = KVEngine::new(MemoryEngine::new());
+        let mut s = kvengine.session()?;
+        s.execute("create table t (d date primary key, t time, ts timestamp default TIMESTAMP '2024-01-01');")?;
+        s.execute("insert into t values (DATE '2024-03-01', TIME '08:30:00', TIMESTAMP '2024-03-01 08:30:00');")?;
+        s.execute("insert into t (d, t) values (DATE '2023-12-31', TIME '23:59:59');")?;
+        s.execute("insert into t values (DATE '1969-07-20', TIME '20:17:40', NULL);")?;
+
+        match s.execute("select d, t, ts from t where d > DATE '1970-01-01' order by d desc;")? {
+            ResultSet::Scan { rows, .. } => {
+                let rows = rows.iter().map(|r| r.iter().map(|v| v.to_string()).collect::<Vec<_>>()).collect::<Vec<_>>();
+                assert_eq!(rows, vec![
+                    vec!["2024-03-01", "08:30:00", "2024-03-01 08:30:00"],
+                    vec!["2023-12-31", "23:59:59", "2024-01-01 00:00:00"],
+                ]);
+            },
+            _ => unreachable!(),
+        }
+        // 1970-01-01 之前的日期是负数
+        match s.execute("select d from t where t = TIME '20:17:40';")? {
+            ResultSet::Scan { rows, .. } => assert_eq!(rows[0], vec![Value::Date(-165)]),
+            _ => unreachable!(),
+        }
+        match s.execute("select d from t where t = TIME '20:17:40' or ts >= TIMESTAMP '2024-03-01 08:30:00' order by d;")? {
+            ResultSet::Scan { rows, .. } => assert_eq!(rows.len(), 2),
+            _ => unreachable!(),
+        }
+
+        assert!(Parser::new("select * from t where d = DATE '2024-02-30';").parse().is_err());
+        assert!(Parser::new("select * from t where t = TIME '24:00:00';").parse().is_err());
+        assert!(s.execute("select * from t where d = TIME '08:30:00';").is_err());
+        assert!(s.execute("insert into t values ('yesterday', NULL, NULL);").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_datetime_arithmetic() -> LegendDBResult<()> {
+        let kvengine = KVEngine::new(MemoryEngine::new());
+        let mut s = kvengine.session()?;
+        s.execute("create table t (id int primary key, d date, t time, ts timestamp);")?;
+        // 字符串写入日期时间类型的列时按列的类型解析
+        s.execute("insert into t values (1, '2024-01-31', '23:30:00', '2024-01-31 23:30:00');")?;
+        s.execute("insert into t values (2, DATE '2024-03-01', TIME '00:15:00', DATE '2024-03-01');")?;
+        s.execute("update t set d = '2024-02-29' where id = 2;")?;
+
+        let query = |s: &mut Session<KVEngine<MemoryEngine>>, sql: &str| -> LegendDBResult<Vec<String>> {
+            match s.execute(sql)? {
+                ResultSet::Scan { rows, .. } => Ok(rows.iter().map(|r| r[0].to_string()).collect()),
+                _ => unreachable!(),
+            }
+        };
+        assert_eq!(query(&mut s, "select d + 1 from t order by id;")?, vec!["2024-02-01", "2024-03-01"]);
+        assert_eq!(query(&mut s, "select d - DATE '2024-01-01' from t order by id;")?, vec!["30", "59"]);
+        assert_eq!(query(&mut s, "select d + interval '1 month' from t order by id;")?, vec!["2024-02-29 00:00:00", "2024-03-29 00:00:00"]);
+        assert_eq!(query(&mut s, "select ts - interval '1 day 30 minutes' from t order by id;")?, vec!["2024-01-30 23:00:00", "2024-02-28 23:30:00"]);
+        assert_eq!(query(&mut s, "select t + interval '1 hour' from t order by id;")?, vec!["00:30:00", "01:15:00"]);
+        assert_eq!(query(&mut s, "select t - interval '30 minutes' from t order by id;")?, vec!["23:00:00", "23:45:00"]);
+        assert_eq!(query(&mut s, "select d + t from t order by id;")?, vec!["2024-01-31 23:30:00", "2024-02-29 00:15:00"]);
+        assert_eq!(query(&mut s, "select ts - TIMESTAMP '2024-01-31 00:00:00' from t order by id;")?, vec!["23 hours 30 minutes", "30 days"]);
+        // 和字符串、日期和时间戳之间可以比较
+        assert_eq!(query(&mut s, "select id from t where d > '2024-02-01';")?, vec!["2"]);
+        assert_eq!(query(&mut s, "select id from t where ts >= d + 1;")?, vec!["2"]);
+        assert_eq!(query(&mut s, "select id from t where ts < now() and d <= current_date();")?, vec!["1", "2"]);
+        assert_eq!(query(&mut s, "select max(d) from t;")?, vec!["2024-02-29"]);
+
+        assert!(s.execute("select d + t + t from t;").is_err());
+        assert!(s.execute("select d * 2 from t;").is_err());
+        assert!(s.execute("select id from t where d > 'yesterday';").is_err());
+        assert!(s.execute("insert into t values (3, '2024-02-30', NULL, NULL);").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_binary() -> LegendDBResult<()> {
+        let kvengine = KVEngine::new(MemoryEngine::new());
+        let mut s = kvengine.session()?;
+        s.execute("create table files (hash blob primary key, data binary, name string default null);")?;
+        s.execute("insert into files (hash, data, name) values (x'00ff', x'DEADBEEF', 'a'), (x'0001', x'', 'b'), (x'01', x'00', 'c');")?;
+        match s.execute("select hash, data from files order by hash;")? {
+            ResultSet::Scan { rows, .. } => assert_eq!(rows, vec![
+                vec![Value::Binary(vec![0, 1]), Value::Binary(vec![])],
+                vec![Value::Binary(vec![0, 255]), Value::Binary(vec![0xde, 0xad, 0xbe, 0xef])],
+                vec![Value::Binary(vec![1]), Value::Binary(vec![0])],
+            ]),
+            _ => unreachable!(),
+        }
+        match s.execute("select name, data, cast(name as blob) from files where data = x'deadbeef';")? {
+            ResultSet::Scan { rows, .. } => {
+                assert_eq!(rows, vec![vec![Value::String("a".to_string()), Value::Binary(vec![0xde, 0xad, 0xbe, 0xef]), Value::Binary(b"a".to_vec())]]);
+                assert_eq!(rows[0][1].to_string(), "\\xdeadbeef");
+            },
+            _ => unreachable!(),
+        }
+        // 二进制数据不能和字符串比较
+        assert!(s.execute("select * from files where data = 'a';").is_err());
+        assert!(s.execute("insert into files (hash, data) values (x'01', x'02');").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_throttle() -> LegendDBResult<()> {
+        let kvengine = KVEngine::new(MemoryEngine::new());
+        let mut s = kvengine.session()?;
+        s.execute("create table t (a int primary key);")?;
+        s.execute("create table u (a int primary key);")?;
+        kvengine.set_write_limit("t", 5)?;
+        // 单个事务可以超过限制，之后的写入要等欠下的行数补回来
+        s.execute("insert into t values (1), (2), (3), (4), (5), (6), (7), (8);")?;
+        match s.execute("insert into t values (9);") {
+            Err(LegendDBError::WriteThrottled { target, retry_after_ms }) => {
+                assert_eq!(target, "table t");
+                assert!(retry_after_ms > 0);
+            },
+            r => panic!("unexpected result {:?}", r),
+        }
+        match s.execute("select count(*) from t;")? {
+            ResultSet::Scan { rows, .. } => assert_eq!(rows, vec![vec![Value::Integer(8)]]),
+            _ => unreachable!(),
+        }
+        // 其他表和只读的语句不受影响
+        s.execute("insert into u values (1);")?;
+        s.execute("select * from t;")?;
+        // 失败的语句没有提交，不扣减令牌
+        kvengine.set_write_limit("u", 2)?;
+        assert!(matches!(s.execute("insert into u values (1);"), Err(LegendDBError::DuplicateKey { .. })));
+        s.execute("insert into u values (10), (11);")?;
+        assert!(matches!(s.execute("insert into u values (12);"), Err(LegendDBError::WriteThrottled { .. })));
+        kvengine.set_write_limit("u", 0)?;
+        assert!(matches!(s.execute("delete from u;")?, ResultSet::Delete { count: 3 }));
+        Ok(())
+    }
+
+    #[test]
+    fn test_dml_in_batches() -> LegendDBResult<()> {
+        let kvengine = KVEngine::new(MemoryEngine::new());
+        let mut s = kvengine.session()?;
+        s.execute("create table t (a int primary key, b int);")?;
+        s.execute("insert into t values (1, 1), (2, 2), (3, 3), (4, 4), (5, 5), (6, 6), (7, 7);")?;
+        s.execute("set dml_batch_size = 2;")?;
+        assert!(matches!(s.execute("update t set b = b + 10 where b > 1;")?, ResultSet::Update { count: 6 }));
+        assert!(matches!(s.execute("delete from t where b >= 13;")?, ResultSet::Delete { count: 5 }));
+        match s.execute("select * from t;")? {
+            ResultSet::Scan { rows, .. } => assert_eq!(rows, vec![
+                vec![Value::Integer(1), Value::Integer(1)],
+                vec![Value::Integer(2), Value::Integer(12)],
+            ]),
+            _ => unreachable!(),
+        }
+        // 分批执行不能修改主键
+        assert!(s.execute("update t set a = a + 10;").is_err());
+        s.execute("set dml_batch_size = 0;")?;
+        assert!(matches!(s.execute("update t set a = a + 10;")?, ResultSet::Update { count: 2 }));
+        Ok(())
+    }
+
+    #[test]
+    fn test_uuid() -> LegendDBResult<()> {
+        let kvengine = KVEngine::new(MemoryEngine::new());
+        let mut s = kvengine.session()?;
+        s.execute("create table t (id uuid primary key default uuid(), name string);")?;
+        // 字符串写入 UUID 列时按 UUID 解析，没有指定时生成新的 UUID
+        s.execute("insert into t values ('123E4567-E89B-42D3-A456-426614174000', 'a');")?;
+        s.execute("insert into t (name) values ('b'), ('c');")?;
+        assert!(s.execute("insert into t values ('not a uuid', 'd');").is_err());
+        let id = "123e4567-e89b-42d3-a456-426614174000";
+        match s.execute(&format!("select name, cast(id as string) from t where id = '{}';", id))? {
+            ResultSet::Scan { rows, .. } => assert_eq!(rows, vec![vec![Value::String("a".to_string()), Value::String(id.to_string())]]),
+            _ => unreachable!(),
+        }
+        match s.execute("select id from t where name != 'a' order by id;")? {
+            ResultSet::Scan { rows, .. } => {
+                assert_eq!(rows.len(), 2);
+                assert!(matches!(rows[0][0], Value::Uuid(_)));
+                assert!(rows[0][0] < rows[1][0]);
+            },
+            _ => unreachable!(),
+        }
+        s.execute(&format!("update t set name = 'x' where id = uuid '{}';", id))?;
+        match s.execute("select count(*) from t where name = 'x';")? {
+            ResultSet::Scan { rows, .. } => assert_eq!(rows, vec![vec![Value::Integer(1)]]),
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_optimizer_toggles() -> LegendDBResult<()> {
+        let kvengine = KVEngine::new(MemoryEngine::new());
+        let mut s = kvengine.session()?;
+        s.execute("create table t (a int primary key, b int, c int);")?;
+        s.execute("insert into t values (1, 10, 100), (2, 20, 200), (3, 30, 300);")?;
+        let sql = "select a from t where b > 10 order by a;";
+        let explain = |s: &mut Session<KVEngine<MemoryEngine>>| match s.execute(&format!("explain {}", sql)) {
+            Ok(ResultSet::Explain { plan }) => plan,
+            r => panic!("unexpected result {:?}", r),
+        };
+        let expected = match s.execute(sql)? {
+            ResultSet::Scan { rows, .. } => rows,
+            _ => unreachable!(),
+        };
+        assert!(explain(&mut s).contains("Scan: t (full table scan) filter: b > 10 columns: a\n"));
+        // 关闭规则之后计划不同，结果相同
+        s.execute("set enable_column_pruning = off;")?;
+        assert!(explain(&mut s).contains("Scan: t (full table scan) filter: b > 10\n"));
+        s.execute("set enable_predicate_pushdown = off;")?;
+        let plan = explain(&mut s);
+        assert!(plan.contains("Filter: b > 10\n") && plan.contains("-> Scan: t (full table scan)\n"), "{}", plan);
+        match s.execute(sql)? {
+            ResultSet::Scan { rows, .. } => assert_eq!(rows, expected),
+            _ => unreachable!(),
+        }
+        assert!(s.execute("explain delete from t where b > 10;").is_ok_and(|r| r.to_string().contains("Estimated affected rows: 2")));
+        assert!(matches!(s.execute("update t set c = 0 where b > 10;")?, ResultSet::Update { count: 2 }));
+        assert!(matches!(s.execute("delete from t where c = 0;")?, ResultSet::Delete { count: 2 }));
+        s.execute("set enable_predicate_pushdown = on;")?;
+        assert!(explain(&mut s).contains("Scan: t (full table scan) filter: b > 10\n"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_aggregate_pushdown() -> LegendDBResult<()> {
+        let kv_engine = KVEngine::new(MemoryEngine::new());
+        let mut s = kv_engine.session()?;
+        s.execute("create table c (id int primary key, name string);")?;
+        s.execute("create table o (id int primary key, cid int, amount int);")?;
+        s.execute("create table d (id int primary key, k int);")?;
+        s.execute("insert into c values (1, 'a'), (2, 'b'), (3, 'c');")?;
+        s.execute("insert into o values (1, 1, 10), (2, 1, 20), (3, 2, null), (4, 2, 5), (5, null, 7), (6, 4, 1);")?;
+        // d 中连接的值有重复，预聚合的结果会连接多次
+        s.execute("insert into d values (1, 1), (2, 1), (3, 2), (4, null);")?;
+        let sqls = [
+            "select c.id, sum(o.amount) as total, count(o.amount), count(*) as n, min(o.amount) as lo, max(o.amount) as hi, avg(o.amount) as mean \
+             from c join o on c.id = o.cid group by c.id order by 1;",
+            "select o.cid, sum(o.amount) as total, count(*) from o join c on o.cid = c.id group by o.cid order by 1;",
+            "select d.k, sum(o.amount) as total, count(o.id) as n, avg(o.amount) from d join o on o.cid = d.k group by d.k order by 1;",
+        ];
+        let rows = |s: &mut Session<KVEngine<MemoryEngine>>, sql: &str| match s.execute(sql) {
+            Ok(ResultSet::Scan { columns, rows }) => (columns, rows),
+            r => panic!("unexpected result {:?}", r),
+        };
+        for sql in sqls {
+            let plan = s.execute(&format!("explain {}", sql))?.to_string();
+            assert_eq!(plan.matches("Aggregate").count(), 2, "{}", plan);
+            let pushed = rows(&mut s, sql);
+            s.execute("set enable_aggregate_pushdown = off;")?;
+            let plan = s.execute(&format!("explain {}", sql))?.to_string();
+            assert_eq!(plan.matches("Aggregate").count(), 1, "{}", plan);
+            assert_eq!(pushed, rows(&mut s, sql), "{}", sql);
+            s.execute("set enable_aggregate_pushdown = on;")?;
+        }
+        assert_eq!(rows(&mut s, sqls[0]).1, vec![
+            vec![Value::Integer(1), Value::Float(30.0), Value::Integer(2), Value::Integer(2), Value::Integer(10), Value::Integer(20), Value::Float(15.0)],
+            vec![Value::Integer(2), Value::Float(5.0), Value::Integer(1), Value::Integer(2), Value::Integer(5), Value::Integer(5), Value::Float(5.0)],
+        ]);
+        assert_eq!(rows(&mut s, sqls[2]).1, vec![
+            vec![Value::Integer(1), Value::Float(60.0), Value::Integer(4), Value::Float(15.0)],
+            vec![Value::Integer(2), Value::Float(5.0), Value::Integer(2), Value::Float(5.0)],
+        ]);
+        // 聚合的列不带表名、分组的列不是连接的列、有过滤条件或者 having 时不下推
+        for sql in [
+            "select c.id, sum(amount) from c join o on c.id = o.cid group by c.id;",
+            "select c.name, sum(o.amount) from c join o on c.id = o.cid group by c.name;",
+            "select c.id, sum(o.amount) from c join o on c.id = o.cid where o.amount > 1 group by c.id;",
+            "select c.id, sum(o.amount) from c join o on c.id = o.cid group by c.id having sum(o.amount) > 1;",
+            "select c.id, sum(o.amount) from c left join o on c.id = o.cid group by c.id;",
+        ] {
+            let plan = s.execute(&format!("explain {}", sql))?.to_string();
+            assert_eq!(plan.matches("Aggregate").count(), 1, "{}", plan);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_index_recommendations() -> LegendDBResult<()> {
+        let kv_engine = KVEngine::new(MemoryEngine::new());
+        let mut s = kv_engine.session()?;
+        let rows = |s: &mut Session<KVEngine<MemoryEngine>>| match s.execute("show index recommendations;") {
+            Ok(ResultSet::Scan { rows, .. }) => rows,
+            r => panic!("unexpected result {:?}", r),
+        };
+        assert!(rows(&mut s).is_empty());
+        s.execute("create table t (id int primary key, a int, b string, c int);")?;
+        s.execute("create table u (id int primary key, tid int);")?;
+        s.execute("insert into t values (1, 1, 'x', 10), (2, 3, 'y', 20);")?;
+        s.execute("insert into u values (1, 1), (2, 2);")?;
+        for sql in [
+            "select * from t where a = 1 and c > 5;",
+            "select * from t where a = 2 and c > 6;",
+            "select a from t where 5 < c and 1 = a;",
+            "select b from t where b like 'x%';",
+            "select * from t join u on t.id = u.tid where t.a = 3;",
+            // 按主键查找、前缀不确定的 like 不需要索引
+            "select * from t where id = 1 and a = 1;",
+            "select * from t where b like '%x';",
+        ] {
+            s.execute(&format!("explain analyze {}", sql))?;
+        }
+        let rows = rows(&mut s);
+        // 按耗时排序，耗时不固定，这里按表和列排序后比较
+        let mut summary = rows.iter().map(|row| format!("{} ({}) queries={} executions={}", row[0], row[1], row[4], row[5])).collect::<Vec<_>>();
+        summary.sort();
+        assert_eq!(summary, [
+            "t (a, c) queries=2 executions=3",
+            "t (b) queries=1 executions=1",
+            "u (tid) queries=1 executions=1",
+        ]);
+        assert!(rows.iter().any(|row| row[2] == Value::String("CREATE INDEX idx_t_a_c ON t (a, c)".to_string())));
+        // 还不支持创建索引，推荐的语句只是建议
+        assert!(rows.iter().all(|row| row[3] == Value::String("advisory only: CREATE INDEX is not supported yet".to_string())));
+        assert!(s.execute("CREATE INDEX idx_t_a_c ON t (a, c);").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_int_width() -> LegendDBResult<()> {
+        let kv_engine = KVEngine::new(MemoryEngine::new());
+        let mut s = kv_engine.session()?;
+        assert!(s.execute("create table t0 (a int primary key, b tinyint default 300);").is_err());
+        s.execute("create table t1 (a int primary key, b tinyint, c smallint unsigned, d bigint);")?;
+        // 严格模式下超出范围的值报错
+        s.execute("insert into t1 values (1, -128, 65535, 9223372036854775807);")?;
+        assert!(s.execute("insert into t1 values (2, 300, 0, 0);").is_err());
+        assert!(s.execute("insert into t1 values (2, 0, -1, 0);").is_err());
+        assert!(s.execute("insert into t1 values (2147483648, 0, 0, 0);").is_err());
+        assert!(s.execute("update t1 set b = b - 1;").is_err());
+        // MySQL兼容模式下取范围的边界值
+        s.execute("set sql_mode = 'mysql';")?;
+        s.execute("insert into t1 values (2, 300, -1, 0);")?;
+        match s.execute("select * from t1;")? {
+            ResultSet::Scan { rows, .. } => assert_eq!(rows, vec![
+                vec![Value::Integer(1), Value::Integer(-128), Value::Integer(65535), Value::Integer(i64::MAX)],
+                vec![Value::Integer(2), Value::Integer(127), Value::Integer(0), Value::Integer(0)],
+            ]),
+            _ => unreachable!(),
+        }
+        assert!(s.get_table("t1".to_string())?.contains("c SMALLINT UNSIGNED"));
+        // 修改列的类型时已有的数据不能超出新的范围
+        assert!(s.execute("alter table t1 modify column c tinyint unsigned;").is_err());
+        s.execute("alter table t1 modify column b smallint;")?;
+        s.execute("set sql_mode = 'strict';")?;
+        s.execute("insert into t1 values (3, 300, 0, 0);")?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_concurrent_sessions() -> LegendDBResult<()> {
+        let kvengine = KVEngine::new(MemoryEngine::new());
+        let mut s = kvengine.session()?;
+        s.execute("create table t (a int primary key, b int);")?;
+        s.execute("insert into t values (0, 0);")?;
+        // 每个线程插入自己的行，同时修改同一行，冲突时重试
+        let handles = kvengine.sessions(4)?.into_iter().enumerate().map(|(i, mut s)| {
+            std::thread::spawn(move || -> LegendDBResult<()> {
+                for j in 1..=25 {
+                    s.execute(&format!("insert into t values ({}, {});", i * 100 + j, j))?;
+                    loop {
+                        match s.execute("update t set b = b + 1 where a = 0;") {
+                            Err(LegendDBError::WriteMvccConflict { .. }) => continue,
+                            result => {
+                                result?;
+                                break;
+                            }
+                        }
+                    }
+                }
+                Ok(())
+            })
+        }).collect::<Vec<_>>();
+        for handle in handles {
+            handle.join().unwrap()?;
+        }
+        match s.execute("select count(*), max(b) from t;")? {
+            ResultSet::Scan { rows, .. } => assert_eq!(rows, vec![vec![Value::Integer(101), Value::Integer(100)]]),
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_only() -> LegendDBResult<()> {
+        let kv_engine = KVEngine::new(MemoryEngine::new());
+        let mut s = kv_engine.session()?;
+        s.execute("create table t (a int primary key);")?;
+        s.execute("insert into t values (1);")?;
+        s.read_only = true;
+        // 只读模式下只能查询
+        match s.execute("select * from t;")? {
+            ResultSet::Scan { rows, .. } => assert_eq!(rows, vec![vec![Value::Integer(1)]]),
+            _ => unreachable!(),
+        }
+        s.execute("set safe_updates = on;")?;
+        for sql in ["insert into t values (2);", "update t set a = 2;", "delete from t where a = 1;", "create table u (b int primary key);", "drop table t;"] {
+            assert!(s.execute(sql).is_err());
+        }
+        s.read_only = false;
+        s.execute("insert into t values (2);")?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_execute_batch() -> LegendDBResult<()> {
+        let kv_engine = KVEngine::new(MemoryEngine::new());
+        let mut s = kv_engine.session()?;
+        let results = s.execute_batch("create table t (a int primary key, b string); insert into t values (1, 'a;b'), (2, 'c'); select b from t where a = 1;")?;
+        assert_eq!(results.len(), 3);
+        match &results[2] {
+            ResultSet::Scan { rows, .. } => assert_eq!(rows, &vec![vec![Value::String("a;b".to_string())]]),
+            _ => unreachable!(),
+        }
+        // 任意一条语句失败，整个批次回滚
+        assert!(s.execute_batch("insert into t values (3, 'd'); insert into t values (1, 'e');").is_err());
+        match s.execute("select a from t;")? {
+            ResultSet::Scan { rows, .. } => assert_eq!(rows.len(), 2),
+            _ => unreachable!(),
+        }
+        assert!(s.execute_batch("").is_err());
+        assert!(s.execute_batch("select a from t").is_err());
+
+        // 批次失败时其中的 SET 不生效，成功提交之后才生效
+        assert!(s.execute_batch("set deterministic_order = on; insert into t values (1, 'e');").is_err());
+        assert!(!s.settings.deterministic_order);
+        s.execute_batch("set deterministic_order = on; insert into t values (3, 'e');")?;
+        assert!(s.settings.deterministic_order);
+        Ok(())
+    }
+
+    #[test]
+    fn test_explain() -> LegendDBResult<()> {
+        let kv_engine = KVEngine::new(MemoryEngine::new());
+        let mut s = kv_engine.session()?;
+        s.execute("create table t (a int primary key, b int);")?;
+        s.execute("create table u (c int primary key, d int);")?;
+        s.execute("insert into t values (1, 10);")?;
+        let plan = match s.execute("explain select a, count(b) from t join u on a = c where b > 1 group by a order by a asc limit 1;")? {
+            ResultSet::Explain { plan } => plan,
+            _ => unreachable!(),
+        };
+        // 只展示执行计划，不执行查询
+        assert!(plan.contains("Nested Loop Join: inner"));
+        assert!(plan.contains("Scan: t (full table scan)"));
+        assert!(plan.contains("Aggregate: a, count(b) group by: a"));
+
+        // 修改数据的语句只展示计划和估计影响的行数，不执行
+        s.execute("insert into t values (2, 20), (3, 30);")?;
+        let explain = |s: &mut Session<KVEngine<MemoryEngine>>, sql: &str| match s.execute(sql) {
+            Ok(ResultSet::Explain { plan }) => plan,
+            r => panic!("unexpected result {:?}", r),
+        };
+        assert_eq!(
+            explain(&mut s, "explain update t set b = b + 1 where b > 10;"),
+            "Update: t set b = b + 1\n-> Scan: t (full table scan) filter: b > 10\nEstimated affected rows: 2\n"
+        );
+        assert_eq!(explain(&mut s, "explain delete from t;"), "Delete: t\n-> Scan: t (full table scan)\nEstimated affected rows: 3\n");
+        assert_eq!(explain(&mut s, "explain insert into t values (4, 40), (5, 50);"), "Insert: t (2 rows)\nEstimated affected rows: 2\n");
+        match s.execute("select count(*) from t where b = 11 or a > 3;")? {
+            ResultSet::Scan { rows, .. } => assert_eq!(rows, vec![vec![Value::Integer(0)]]),
+            _ => unreachable!(),
+        }
+        // 只读模式下也可以查看修改数据的语句的计划
+        s.read_only = true;
+        explain(&mut s, "explain delete from t where a = 1;");
+        assert!(s.execute("explain analyze delete from t;").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_update_not_null() -> LegendDBResult<()> {
+        let kv_engine = KVEngine::new(MemoryEngine::new());
+        let mut s = kv_engine.session()?;
+        s.execute("create table t (a int primary key, b int not null, c int default 0);")?;
+        s.execute("insert into t values (1, 10, 1);")?;
+        // 和插入一样，非空的列不能更新为 NULL，类型不一致时报错
+        assert!(s.execute("update t set b = null;").is_err());
+        assert!(s.execute("update t set b = 'x';").is_err());
+        s.execute("update t set c = null;")?;
+        match s.execute("select * from t;")? {
+            ResultSet::Scan { rows, .. } => assert_eq!(rows, vec![vec![Value::Integer(1), Value::Integer(10), Value::Null]]),
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_update_counter() -> LegendDBResult<()> {
+        let kv_engine = KVEngine::new(MemoryEngine::new());
+        let mut s = kv_engine.session()?;
+        s.execute("create table t1 (a int primary key, counter int default 0);")?;
+        s.execute("insert into t1 (a) values (1), (2);")?;
+        for _ in 0..3 {
+            s.execute("update t1 set counter = counter + 1 where a = 1;")?;
+        }
+        s.execute("update t1 set counter = counter + a * 10;")?;
+        match s.execute("select a, counter from t1;")? {
+            ResultSet::Scan { rows, .. } => assert_eq!(rows, vec![
+                vec![Value::Integer(1), Value::Integer(13)],
+                vec![Value::Integer(2), Value::Integer(20)],
+            ]),
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_cast() -> LegendDBResult<()> {
+        let kv_engine = KVEngine::new(MemoryEngine::new());
+        let mut s = kv_engine.session()?;
+        s.execute("create table t1 (a int primary key, b string, c float);")?;
+        s.execute("insert into t1 values (1, '12', 2.7), (2, 'true', -1.5), (3, null, 0.0);")?;
+        match s.execute("select cast(a as string), cast(c as int), cast(c as bool), cast(a as float) / 2 from t1;")? {
+            ResultSet::Scan { columns, rows } => {
+                assert_eq!(columns, vec!["CAST(a AS STRING)", "CAST(c AS INTEGER)", "CAST(c AS BOOLEAN)", "CAST(a AS FLOAT) / 2"]);
+                assert_eq!(rows, vec![
+                    vec![Value::String("1".to_string()), Value::Integer(2), Value::Boolean(true), Value::Float(0.5)],
+                    vec![Value::String("2".to_string()), Value::Integer(-1), Value::Boolean(true), Value::Float(1.0)],
+                    vec![Value::String("3".to_string()), Value::Integer(0), Value::Boolean(false), Value::Float(1.5)],
+                ]);
+            },
+            _ => unreachable!(),
+        }
+        match s.execute("select a from t1 where cast(c as int) > 1;")? {
+            ResultSet::Scan { rows, .. } => assert_eq!(rows, vec![vec![Value::Integer(1)]]),
+            _ => unreachable!(),
+        }
+        // NULL 转换之后还是 NULL
+        match s.execute("select cast(b as bool) from t1 where a > 1;")? {
+            ResultSet::Scan { rows, .. } => assert_eq!(rows, vec![vec![Value::Boolean(true)], vec![Value::Null]]),
+            _ => unreachable!(),
+        }
+        assert!(s.execute("select cast(b as int) from t1;").is_err());
+        assert!(s.execute("select cast(b as bool) from t1;").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_where_like() -> LegendDBResult<()> {
+        let kv_engine = KVEngine::new(MemoryEngine::new());
+        let mut s = kv_engine.session()?;
+        s.execute("create table t1 (a int primary key, b text);")?;
+        s.execute("create table t2 (c int primary key, d text);")?;
+        s.execute("insert into t1 values (1, 'abc'), (2, 'abd'), (3, 'xabc'), (4, null), (5, 'a_c'), (6, '数据库');")?;
+        s.execute("insert into t2 values (1, 'x'), (3, 'y');")?;
+        let select_a = |s: &mut Session<KVEngine<MemoryEngine>>, sql: &str| -> LegendDBResult<Vec<Value>> {
+            match s.execute(sql)? {
+                ResultSet::Scan { rows, .. } => Ok(rows.into_iter().map(|r| r[0].clone()).collect()),
+                _ => unreachable!(),
+            }
+        };
+        let ints = |v: &[i64]| v.iter().map(|i| Value::Integer(*i)).collect::<Vec<_>>();
+        assert_eq!(select_a(&mut s, "select a from t1 where b like 'ab%';")?, ints(&[1, 2]));
+        assert_eq!(select_a(&mut s, "select a from t1 where b like '%bc';")?, ints(&[1, 3]));
+        assert_eq!(select_a(&mut s, "select a from t1 where b like 'a_c';")?, ints(&[1, 5]));
+        assert_eq!(select_a(&mut s, "select a from t1 where b like '%a%b%c%';")?, ints(&[1, 3]));
+        assert_eq!(select_a(&mut s, "select a from t1 where b like '数_%';")?, ints(&[6]));
+        assert_eq!(select_a(&mut s, "select a from t1 where b like 'abc';")?, ints(&[1]));
+        // NULL 不匹配 LIKE 也不匹配 NOT LIKE
+        assert_eq!(select_a(&mut s, "select a from t1 where b not like 'ab%';")?, ints(&[3, 5, 6]));
+        // join 之后的过滤
+        assert_eq!(select_a(&mut s, "select * from t1 join t2 on a = c where b like '%c' and d not like 'y';")?, ints(&[1]));
+        assert!(s.execute("select a from t1 where a like 'a%';").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_table() -> LegendDBResult<()> {
+        let kv_engine = KVEngine::new(MemoryEngine::new());
+        let mut s = kv_engine.session()?;
+        s.execute("create table t1 (a int primary key, b text not null, c int);")?;
+        s.execute("insert into t1 values (1, 'x', 10), (2, 'y', 20), (3, 'z', 30), (4, 'w', 40);")?;
+        match s.execute("check table t1;")? {
+            ResultSet::Scan { rows, .. } => assert_eq!(rows, vec![
+                vec![Value::String("t1".to_string()), Value::String("note".to_string()), Value::String("checksums not checked: rows are stored without checksums".to_string())],
+                vec![Value::String("t1".to_string()), Value::String("status".to_string()), Value::String("OK".to_string())],
+            ]),
+            _ => unreachable!(),
+        }
+
+        // 直接写入损坏的数据
+        let txn = kv_engine.begin()?;
+        let table = txn.get_table_must("t1".to_string())?;
+        let key = |id: i64| TransactionKey::RowKey("t1".to_string(), Value::Integer(id)).encode();
+        txn.txn.set(key(1)?, vec![1, 2, 3])?;
+        txn.txn.set(key(2)?, encode_row(&table, &vec![Value::Integer(5), Value::String("y".to_string()), Value::Integer(20)])?)?;
+        txn.txn.set(key(3)?, encode_row(&table, &vec![Value::Integer(3), Value::Null, Value::Integer(30)])?)?;
+        txn.commit()?;
+
+        match s.execute("check table t1;")? {
+            ResultSet::Scan { columns, rows } => {
+                assert_eq!(columns, vec!["table", "msg_type", "msg_text"]);
+                let messages = rows.iter().map(|r| r[2].to_string()).collect::<Vec<_>>();
+                assert_eq!(messages.len(), 5);
+                assert!(messages[0].starts_with("row 1 cannot be decoded"));
+                assert_eq!(messages[1], "row 2 has primary key 5 in its data");
+                assert_eq!(messages[2], "row 3 has NULL in NOT NULL column b");
+                assert!(messages[3].starts_with("checksums not checked"));
+                assert_eq!(messages[4], "Corrupt");
+            },
+            _ => unreachable!(),
+        }
+        assert!(s.execute("check table t2;").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_table_constraints() -> LegendDBResult<()> {
+        let kv_engine = KVEngine::new(MemoryEngine::new());
+        let mut s = kv_engine.session()?;
+        s.execute("create table t (a int primary key, b varchar(3), c tinyint, d int unique, check (c > 0));")?;
+        s.execute("insert into t values (1, 'x', 1, 10), (2, 'y', 2, 20), (3, 'z', 3, 30), (4, 'w', 4, 40);")?;
+        let messages = |s: &mut Session<KVEngine<MemoryEngine>>| -> LegendDBResult<Vec<String>> {
+            match s.execute("check table t;")? {
+                ResultSet::Scan { rows, .. } => Ok(rows.into_iter().filter(|r| r[1] == Value::String("error".to_string())).map(|r| r[2].to_string()).collect()),
+                _ => unreachable!(),
+            }
+        };
+        assert!(messages(&mut s)?.is_empty());
+
+        // 直接写入违反长度、范围和检查约束的行，以及和索引不一致的行
+        let txn = kv_engine.begin()?;
+        let table = txn.get_table_must("t".to_string())?;
+        let key = |id: i64| TransactionKey::RowKey("t".to_string(), Value::Integer(id)).encode();
+        let row = |a: i64, b: &str, c: i64, d: i64| encode_row(&table, &vec![Value::Integer(a), Value::String(b.to_string()), Value::Integer(c), Value::Integer(d)]);
+        txn.txn.set(key(1)?, row(1, "long", 1, 10)?)?;
+        txn.txn.set(key(2)?, row(2, "y", 300, 20)?)?;
+        txn.txn.set(key(3)?, row(3, "z", -1, 30)?)?;
+        // 行 4 的 d 改成和行 1 相同，索引中 40 没有对应的行
+        txn.txn.set(key(4)?, row(4, "w", 4, 10)?)?;
+        // 行 5 没有写入索引
+        txn.txn.set(key(5)?, row(5, "v", 5, 50)?)?;
+        txn.commit()?;
+
+        let messages = messages(&mut s)?;
+        assert_eq!(messages, vec![
+            "row 1: value too long for column b, maximum length is 3",
+            "row 2: value 300 out of range for column c TINYINT",
+            "row 3: row violates check constraint t_check of table t: c > 0",
+            "rows 1 and 4 have the same value (10) for unique key d",
+            "unique key d (40) has no matching row",
+            "row 5 is missing from unique key d (50)",
+        ]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_delete_all() -> LegendDBResult<()> {
+        let kv_engine = KVEngine::new(MemoryEngine::new());
+        let mut s = kv_engine.session()?;
+        s.execute("create table t1 (a int primary key, b text default 'vv');")?;
+        s.execute("insert into t1 values (1, 'a'), (2, 'b'), (3, 'c');")?;
+        // 安全模式下不允许不带WHERE的DELETE
+        s.execute("set safe_updates = true;")?;
+        assert!(s.execute("delete from t1;").is_err());
+        s.execute("set safe_updates = 0;")?;
+        match s.execute("delete from t1;")? {
+            ResultSet::Delete { count } => assert_eq!(count, 3),
+            _ => unreachable!(),
+        }
+        match s.execute("select * from t1;")? {
+            ResultSet::Scan { rows, .. } => assert!(rows.is_empty()),
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_update_all() -> LegendDBResult<()> {
+        let kv_engine = KVEngine::new(MemoryEngine::new());
+        let mut s = kv_engine.session()?;
+        s.execute("create table t1 (a int primary key, b text default 'vv');")?;
+        s.execute("insert into t1 values (1, 'a'), (2, 'b'), (3, 'c');")?;
+        // 安全模式下不允许不带WHERE的UPDATE
+        s.execute("set safe_updates = on;")?;
+        match s.execute("update t1 set b = 'x';") {
+            Err(LegendDBError::Internal(msg)) => assert!(msg.contains("UPDATE of table t1 requires a WHERE clause"), "{}", msg),
+            _ => unreachable!(),
+        }
+        s.execute("set safe_updates = off;")?;
+        match s.execute("update t1 set b = 'x';")? {
+            ResultSet::Update { count } => assert_eq!(count, 3),
+            _ => unreachable!(),
+        }
+        match s.execute("select b from t1;")? {
+            ResultSet::Scan { rows, .. } => assert!(rows.iter().all(|row| row[0] == Value::String("x".to_string()))),
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_drop_table() -> LegendDBResult<()> {
+        let kv_engine = KVEngine::new(MemoryEngine::new());
+        let mut s = kv_engine.session()?;
+        s.execute("create table t1 (a int primary key, b text);")?;
+        s.execute("create table t10 (a int primary key);")?;
+        s.execute("insert into t1 values (1, 'a'), (2, 'b');")?;
+        s.execute("insert into t10 values (1);")?;
+        s.execute("drop table t1;")?;
+        assert!(s.execute("select * from t1;").is_err());
+        assert!(s.execute("drop table t1;").is_err());
+        s.execute("drop table if exists t1;")?;
+        // 其他表的数据不受影响
+        match s.execute("select * from t10;")? {
+            ResultSet::Scan { rows, .. } => assert_eq!(rows.len(), 1),
+            _ => unreachable!(),
+        }
+        // 重新创建同名表，之前的数据已经被清理
+        s.execute("create table t1 (a int primary key, b text);")?;
+        match s.execute("select * from t1;")? {
+            ResultSet::Scan { rows, .. } => assert!(rows.is_empty()),
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_scan_pruned_columns() -> LegendDBResult<()> {
+        let kv_engine = KVEngine::new(MemoryEngine::new());
+        let mut s = kv_engine.session()?;
+        s.execute("create table t1 (a int primary key, b text, c int, d text);")?;
+        s.execute("insert into t1 values (1, 'x', 30, 'p'), (2, null, 20, 'q'), (3, 'z', 10, null);")?;
+        // 过滤条件中的列不在投影中
+        match s.execute("select d, b as bb from t1 where c > 15 order by a;")? {
+            ResultSet::Scan { columns, rows } => {
+                assert_eq!(columns, vec!["d", "bb"]);
+                assert_eq!(rows, vec![
+                    vec![Value::String("p".to_string()), Value::String("x".to_string())],
+                    vec![Value::String("q".to_string()), Value::Null],
+                ]);
+            },
+            _ => unreachable!(),
+        }
+        // 排序的列不在投影中
+        match s.execute("select b from t1 order by c;")? {
+            ResultSet::Scan { rows, .. } => assert_eq!(rows, vec![
+                vec![Value::String("z".to_string())],
+                vec![Value::Null],
+                vec![Value::String("x".to_string())],
+            ]),
+            _ => unreachable!(),
+        }
+        assert!(s.execute("select e from t1;").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_alter_table_add_column() -> LegendDBResult<()> {
+        let kv_engine = KVEngine::new(MemoryEngine::new());
+        let mut s = kv_engine.session()?;
+        s.execute("create table t1 (a int primary key, b text);")?;
+        s.execute("insert into t1 values (1, 'a'), (2, 'b');")?;
+        s.execute("alter table t1 add column c int default 10;")?;
+        s.execute("alter table t1 add d text;")?;
+        match s.execute("select * from t1;")? {
+            ResultSet::Scan { columns, rows } => {
+                assert_eq!(columns, vec!["a", "b", "c", "d"]);
+                assert_eq!(rows[0], vec![Value::Integer(1), Value::String("a".to_string()), Value::Integer(10), Value::Null]);
+                assert_eq!(rows[1], vec![Value::Integer(2), Value::String("b".to_string()), Value::Integer(10), Value::Null]);
+            },
+            _ => unreachable!(),
+        }
+        s.execute("insert into t1 values (3, 'c', 30, 'x');")?;
+        // 列已经存在
+        assert!(s.execute("alter table t1 add column c int;").is_err());
+        // 非空的表不能新增没有默认值的非空列
+        assert!(s.execute("alter table t1 add column e int not null;").is_err());
+        // 不能新增主键列
+        assert!(s.execute("alter table t1 add column f int primary key;").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_alter_table_drop_modify_column() -> LegendDBResult<()> {
+        let kv_engine = KVEngine::new(MemoryEngine::new());
+        let mut s = kv_engine.session()?;
+        s.execute("create table t1 (a int primary key, b text, c int, d text);")?;
+        s.execute("insert into t1 values (1, 'a', 10, '1.5'), (2, 'b', null, 'x');")?;
+        s.execute("alter table t1 drop column b;")?;
+        // 不能删除主键列
+        assert!(s.execute("alter table t1 drop column a;").is_err());
+        assert!(s.execute("alter table t1 drop column b;").is_err());
+
+        // int 转换为 float
+        s.execute("alter table t1 modify column c float;")?;
+        // 已有NULL值，不能修改为非空
+        assert!(s.execute("alter table t1 modify column c float not null;").is_err());
+        // 'x' 无法转换为 float
+        assert!(s.execute("alter table t1 modify column d float;").is_err());
+        s.execute("update t1 set d = '2.5' where a = 2;")?;
+        s.execute("alter table t1 modify column d float default 0.0;")?;
+        // 主键列可以修改类型
+        s.execute("alter table t1 modify column a text;")?;
+        match s.execute("select * from t1;")? {
+            ResultSet::Scan { columns, rows } => {
+                assert_eq!(columns, vec!["a", "c", "d"]);
+                assert_eq!(rows[0], vec![Value::String("1".to_string()), Value::Float(10.0), Value::Float(1.5)]);
+                assert_eq!(rows[1], vec![Value::String("2".to_string()), Value::Null, Value::Float(2.5)]);
+            },
+            _ => unreachable!(),
+        }
+        s.execute("insert into t1 (a) values ('3');")?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_schema_cache() -> LegendDBResult<()> {
+        let kv_engine = KVEngine::new(MemoryEngine::new());
+        let mut s = kv_engine.session()?;
+        s.execute("create table t1 (a int primary key, b text);")?;
+        assert_eq!(kv_engine.schema_cache.lock()?.version, 1);
+        s.execute("insert into t1 values (1, 'a');")?;
+        assert!(kv_engine.schema_cache.lock()?.tables.contains_key("t1"));
+
+        // 修改表结构的事务开始前开启的事务，仍然读取旧的表结构
+        let old_txn = kv_engine.begin()?;
+        s.execute("alter table t1 add column c int default 1;")?;
+        {
+            let cache = kv_engine.schema_cache.lock()?;
+            assert_eq!(cache.version, 2);
+            assert!(cache.tables.is_empty());
+        }
+        assert_eq!(old_txn.get_table_must("t1".to_string())?.columns.len(), 2);
+        // 旧的事务不会把旧的表结构放入缓存
+        assert!(kv_engine.schema_cache.lock()?.tables.is_empty());
+        old_txn.commit()?;
+
+        match s.execute("select * from t1;")? {
+            ResultSet::Scan { columns, .. } => assert_eq!(columns, vec!["a", "b", "c"]),
+            _ => unreachable!(),
+        }
+        assert_eq!(kv_engine.schema_cache.lock()?.tables["t1"].columns.len(), 3);
+        Ok(())
+    }
+
+    #[test]
+    fn test_insert_null_as_default() -> LegendDBResult<()> {
+        let kv_engine = KVEngine::new(MemoryEngine::new());
+        let mut s = kv_engine.session()?;
+        s.execute("create table t1 (a int primary key, b int not null default 10);")?;
+        // 严格模式下非空列不能插入NULL
+        assert!(s.execute("insert into t1 values (1, null);").is_err());
+        // MySQL兼容模式下使用默认值
+        s.execute("set sql_mode = 'mysql';")?;
+        s.execute("insert into t1 values (1, null);")?;
+        s.execute("insert into t1 (b, a) values (null, 2);")?;
+        match s.execute("select * from t1;")? {
+            ResultSet::Scan { rows, .. } => {
+                assert_eq!(rows, vec![
+                    vec![Value::Integer(1), Value::Integer(10)],
+                    vec![Value::Integer(2), Value::Integer(10)],
+                ]);
+            }
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_string_max_length() -> LegendDBResult<()> {
+        let kv_engine = KVEngine::new(MemoryEngine::new());
+        let mut s = kv_engine.session()?;
+        assert!(s.execute("create table t0 (a int primary key, b varchar(2) default 'abc');").is_err());
+        s.execute("create table t1 (a int primary key, b varchar(3), c char(2));")?;
+        // 严格模式下超过长度的字符串报错，长度按字符计算
+        s.execute("insert into t1 values (1, '中文字', 'ab');")?;
+        assert!(s.execute("insert into t1 values (2, 'abcd', 'ab');").is_err());
+        assert!(s.execute("update t1 set c = 'abc';").is_err());
+        // MySQL兼容模式下截断
+        s.execute("set sql_mode = 'mysql';")?;
+        s.execute("insert into t1 values (2, 'abcd', 'xy');")?;
+        s.execute("update t1 set c = 'xyz' where a = 2;")?;
+        match s.execute("select * from t1;")? {
+            ResultSet::Scan { rows, .. } => assert_eq!(rows, vec![
+                vec![Value::Integer(1), Value::String("中文字".to_string()), Value::String("ab".to_string())],
+                vec![Value::Integer(2), Value::String("abc".to_string()), Value::String("xy".to_string())],
+            ]),
+            _ => unreachable!(),
+        }
+        // 修改列的长度时已有的数据不能超过新的长度
+        assert!(s.execute("alter table t1 modify column b varchar(2);").is_err());
+        s.execute("alter table t1 modify column b varchar(10);")?;
+        s.execute("set sql_mode = 'strict';")?;
+        s.execute("insert into t1 values (3, 'abcdefghij', 'ab');")?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_duplicate_key() -> LegendDBResult<()> {
+        let kv_engine = KVEngine::new(MemoryEngine::new());
+        let mut s = kv_engine.session()?;
+        s.execute("create table t1 (a text primary key, b int);")?;
+        s.execute("insert into t1 values ('x', 1), ('y', 2);")?;
+        match s.execute("insert into t1 values ('x', 3);") {
+            Err(LegendDBError::DuplicateKey { table, column, key }) => {
+                assert_eq!(table, "t1");
+                assert_eq!(column, "a");
+                assert_eq!(key, "'x'");
+            }
+            r => panic!("unexpected result {:?}", r),
+        }
+        // 更新主键为已存在的值
+        assert!(matches!(s.execute("update t1 set a = 'y' where b = 1;"), Err(LegendDBError::DuplicateKey { .. })));
+        Ok(())
+    }
+
+    #[test]
+    fn test_bulk_load() -> LegendDBResult<()> {
+        let kv_engine = KVEngine::new(MemoryEngine::new());
+        let mut s = kv_engine.session()?;
+        s.execute("create table t1 (a int primary key, b int);")?;
+        s.execute("insert into t1 values (1, 1);")?;
+        s.execute("set bulk_load = on;")?;
+        s.execute("insert into t1 values (2, 2), (3, 3);")?;
+        // 同一个事务内重复
+        assert!(matches!(s.execute("insert into t1 values (4, 4), (4, 5);"), Err(LegendDBError::DuplicateKey { .. })));
+        // 和已有数据重复，提交时检查并回滚
+        assert!(matches!(s.execute("insert into t1 values (5, 5), (1, 6);"), Err(LegendDBError::DuplicateKey { .. })));
+        match s.execute("select * from t1;")? {
+            ResultSet::Scan { rows, .. } => {
+                assert_eq!(rows, vec![
+                    vec![Value::Integer(1), Value::Integer(1)],
+                    vec![Value::Integer(2), Value::Integer(2)],
+                    vec![Value::Integer(3), Value::Integer(3)],
+                ]);
+            }
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_primary_key_lookup() -> LegendDBResult<()> {
+        let kv_engine = KVEngine::new(MemoryEngine::new());
+        let mut s = kv_engine.session()?;
+        s.execute("create table t1 (id int primary key, tid int);")?;
+        s.execute("create table t2 (id int primary key, v text);")?;
+        s.execute("insert into t1 values (1, 20), (2, 40), (3, NULL), (4, 20);")?;
+        s.execute("insert into t2 values (10, 'a'), (20, 'b'), (30, 'c'), (40, 'd'), (50, 'e');")?;
+        let rows = |s: &mut Session<KVEngine<MemoryEngine>>, sql: &str| -> LegendDBResult<Vec<Vec<Value>>> {
+            match s.execute(sql)? {
+                ResultSet::Scan { rows, .. } => Ok(rows),
+                _ => unreachable!(),
+            }
+        };
+        let plan = |s: &mut Session<KVEngine<MemoryEngine>>, sql: &str| -> LegendDBResult<String> {
+            match s.execute(&format!("explain analyze {}", sql))? {
+                ResultSet::Explain { plan } => Ok(plan),
+                _ => unreachable!(),
+            }
+        };
+        let int = |i: i64| Value::Integer(i);
+        let text = |s: &str| Value::String(s.to_string());
+
+        // 主键 IN 子查询按主键批量读取，重复的值和 NULL 不影响结果
+        let sql = "select id, v from t2 where id in (select tid from t1);";
+        assert_eq!(rows(&mut s, sql)?, vec![vec![int(20), text("b")], vec![int(40), text("d")]]);
+        // 右边按左边的值读取，左连接没有匹配的行仍然输出
+        let sql = "select t1.id, t2.v from t1 left join t2 on t1.tid = t2.id;";
+        assert_eq!(rows(&mut s, sql)?, vec![
+            vec![int(1), text("b")],
+            vec![int(2), text("d")],
+            vec![int(3), Value::Null],
+            vec![int(4), text("b")],
+        ]);
+        let sql = "select t1.id, t2.v from t1 join t2 on t2.id = t1.tid;";
+        assert_eq!(rows(&mut s, sql)?.len(), 3);
+        assert!(plan(&mut s, sql)?.lines().any(|line| line.trim_start().starts_with("Scan: t2") && line.contains("(actual rows=2 ")));
+        // 右边的扫描条件仍然生效
+        assert_eq!(rows(&mut s, "select t1.id from t1 join (select * from t2 where v = 'd') t on t1.tid = t.id;")?, vec![vec![int(2)]]);
+        // 不是主键的列、类型不同的值仍然扫描全表
+        assert_eq!(rows(&mut s, "select id from t2 where v in (select v from t2 where id = 30);")?, vec![vec![int(30)]]);
+        assert_eq!(rows(&mut s, "select id from t2 where id in (select tid + 0.0 from t1 where id = 1);")?, vec![vec![int(20)]]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_left_join_empty_right() -> LegendDBResult<()> {
+        let kv_engine = KVEngine::new(MemoryEngine::new());
+        let mut s = kv_engine.session()?;
+        s.execute("create table t1 (a int primary key);")?;
+        s.execute("create table t2 (b int primary key);")?;
+        s.execute("insert into t1 values (1), (2);")?;
+        match s.execute("select * from t1 left join t2 on a = b;")? {
+            ResultSet::Scan { columns, rows } => {
+                assert_eq!(columns, vec!["a".to_string(), "b".to_string()]);
+                assert_eq!(rows.len(), 2);
+                assert!(rows.iter().all(|row| row[1] == Value::Null));
+            }
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_full_and_using_join() -> LegendDBResult<()> {
+        let kv_engine = KVEngine::new(MemoryEngine::new());
+        let mut s = kv_engine.session()?;
+        s.execute("create table t1 (id int primary key, a int, x int);")?;
+        s.execute("create table t2 (id int primary key, a int, y int);")?;
+        s.execute("insert into t1 values (1, 10, 100), (2, 20, 200), (3, NULL, 300);")?;
+        s.execute("insert into t2 values (2, 20, 2000), (3, 30, 3000), (4, NULL, 4000);")?;
+        let ids = |s: &mut Session<KVEngine<MemoryEngine>>, sql: &str| -> LegendDBResult<Vec<Vec<Value>>> {
+            match s.execute(sql)? {
+                ResultSet::Scan { rows, .. } => Ok(rows),
+                _ => unreachable!(),
+            }
+        };
+        let int = |i: i64| Value::Integer(i);
+
+        // 全连接输出两边没有匹配的行
+        assert_eq!(ids(&mut s, "select t1.id, t2.id from t1 full outer join t2 on t1.a = t2.a;")?, vec![
+            vec![int(1), Value::Null],
+            vec![int(2), int(2)],
+            vec![int(3), Value::Null],
+            vec![Value::Null, int(3)],
+            vec![Value::Null, int(4)],
+        ]);
+        assert_eq!(ids(&mut s, "select t1.id, t2.id from t1 join t2 using (id);")?, vec![vec![int(2), int(2)], vec![int(3), int(3)]]);
+        assert_eq!(ids(&mut s, "select t1.id, t2.id from t1 right outer join t2 using (id, a);")?, vec![
+            vec![int(2), int(2)],
+            vec![Value::Null, int(3)],
+            vec![Value::Null, int(4)],
+        ]);
+        // NATURAL JOIN 使用所有同名的列 id 和 a，NULL 不相等
+        assert_eq!(ids(&mut s, "select t1.x, t2.y from t1 natural join t2;")?, vec![vec![int(200), int(2000)]]);
+        assert_eq!(ids(&mut s, "select t1.id, t2.id from t1 natural full join t2;")?.len(), 5);
+
+        match s.execute("select * from t1 join t2 using (x);") {
+            Err(LegendDBError::Semantic(errors)) => assert_eq!(errors, vec!["column x in USING does not exist on the right side of the join"]),
+            _ => unreachable!(),
+        }
+        assert!(s.execute("select * from t1 natural cross join t2;").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_create_database() -> LegendDBResult<()> {
+        let p = tempfile::tempdir()?.into_path().join("sqldb-log");
+        let kvengine = KVEngine::new(DiskEngine::new(p.clone())?);
+        let mut s = kvengine.session()?;
+        s.execute("create database test;")?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_drop_database() -> LegendDBResult<()> {
+        let p = tempfile::tempdir()?.into_path().join("sqldb-log");
+        let kvengine = KVEngine::new(DiskEngine::new(p.clone())?);
+        let mut s = kvengine.session()?;
+        s.execute("drop database test;")?;
+        Ok(())
+    }
+    
+    #[test]
+    fn test_agg() -> LegendDBResult<()> {
+        let p = tempfile::tempdir()?.into_path().join("sqldb-log");
+        let kvengine = KVEngine::new(DiskEngine::new(p.clone())?);
+        let mut s = kvengine.session()?;
+        s.execute("create table t1 (a int primary key, b text, c float);")?;
+        s.execute("insert into t1 values (1, 'a', 1.1);")?;
+        s.execute("insert into t1 values (2, 'b', 2.2);")?;
+        s.execute("insert into t1 values (3, 'c', 3.3);")?;
+        match s.execute("select min(c) as ffffff from t1;")? {
+            ResultSet::Scan { columns, rows } => {
+                print!("{:?}", columns);
+                print!("{:?}", rows);
+            }
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_agg_group() -> LegendDBResult<()> {
+        let p = tempfile::tempdir()?.into_path().join("sqldb-log");
+        let kvengine = KVEngine::new(DiskEngine::new(p.clone())?);
+        let mut s = kvengine.session()?;
+        s.execute("create table t1 (a int primary key, b text, c float);")?;
+        s.execute("insert into t1 values (1, 'a', 1.1);")?;
+        s.execute("insert into t1 values (2, 'c', 2.2);")?;
+        s.execute("insert into t1 values (3, 'a', 3.3);")?;
+        s.execute("insert into t1 values (4, 'c', 3.3);")?;
+        match s.execute("select b, min(c) from t1 group by b;")? {
+            ResultSet::Scan { columns, rows } => {
+                print!("{:?}", columns);
+                print!("{:?}", rows);
+            }
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_where_great_group() -> LegendDBResult<()> {
+        let p = tempfile::tempdir()?.into_path().join("sqldb-log");
+        let kvengine = KVEngine::new(DiskEngine::new(p.clone())?);
+        let mut s = kvengine.session()?;
+        s.execute("create table t1 (a int primary key, b text, c float);")?;
+        s.execute("insert into t1 values (1, 'a', 1.1);")?;
+        s.execute("insert into t1 values (2, 'c', 2.2);")?;
         s.execute("insert into t1 values (3, 'a', 3.3);")?;
         s.execute("insert into t1 values (4, 'c', 3.3);")?;
         // match s.execute("select * from t1 where a > 2;")? {
@@ -462,7 +2981,7 @@ mod tests {
         //     _ => unreachable!(),
         // }
 
-        match s.execute("select b, sum(c) from t1 having sum > 5; ")? {
+        match s.execute("select b, sum(c) from t1 group by b having sum > 5; ")? {
             ResultSet::Scan { columns, rows } => {
                 println!("{:?}", columns);
                 println!("{:?}", rows);
@@ -471,4 +2990,35 @@ mod tests {
         }
         Ok(())
     }
+
+    #[test]
+    fn test_having_aggregate() -> LegendDBResult<()> {
+        let kv_engine = KVEngine::new(MemoryEngine::new());
+        let mut s = kv_engine.session()?;
+        s.execute("create table t1 (a int primary key, b text, c int);")?;
+        s.execute("insert into t1 values (1, 'a', 1), (2, 'c', 2), (3, 'a', 3), (4, 'c', 4), (5, 'b', 9);")?;
+        // having 中直接使用聚合函数，包括查询列中没有的聚合函数
+        match s.execute("select b, sum(c) from t1 group by b having sum(c) > 5 and count(a) > 1;")? {
+            ResultSet::Scan { columns, rows } => {
+                assert_eq!(columns, vec!["b", "sum"]);
+                assert_eq!(rows, vec![vec![Value::String("c".to_string()), Value::Float(6.0)]]);
+            },
+            _ => unreachable!(),
+        }
+        match s.execute("select b, count(a) as n from t1 group by b having max(c) - min(c) >= 2 or n = 1 order by b;")? {
+            ResultSet::Scan { rows, .. } => assert_eq!(rows, vec![
+                vec![Value::String("a".to_string()), Value::Integer(2)],
+                vec![Value::String("b".to_string()), Value::Integer(1)],
+                vec![Value::String("c".to_string()), Value::Integer(2)],
+            ]),
+            _ => unreachable!(),
+        }
+        // 没有分组时对整个表过滤
+        match s.execute("select count(a) from t1 having sum(c) > 100;")? {
+            ResultSet::Scan { rows, .. } => assert!(rows.is_empty()),
+            _ => unreachable!(),
+        }
+        assert!(s.execute("select b, sum(c) from t1 group by b having foo(c) > 1;").is_err());
+        Ok(())
+    }
 }
\ No newline at end of file
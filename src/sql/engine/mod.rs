@@ -1,3 +1,7 @@
 #[allow(unused)]
 pub mod kv;
-pub mod engine;
\ No newline at end of file
+pub mod engine;
+pub mod row_codec;
+pub mod codec;
+pub mod audit;
+pub mod throttle;
\ No newline at end of file
@@ -0,0 +1,319 @@
+// 行数据编码，根据表结构进行紧凑的编码
+// 相比直接用 bincode 编码 Vec<Value>，不需要为每个值存放类型标记，定长的列也可以直接按位置读取
+//
+// +------------+------------+-------------------+-------------------+-------------------+
+// | 标记(1)     | 列数(2)     | NULL 位图          | 定长槽位            | 变长数据             |
+// +------------+------------+-------------------+-------------------+-------------------+
+//
+// 标记：固定为 0xFF，bincode 的变长整数不会以 0xFF 开头，和旧的 bincode 编码的行不会混淆
+// NULL 位图：每一列占一个bit，为1表示这一列的值是NULL
+// 定长槽位：每一列都有一个槽位，NULL 值的槽位填0，槽位的位置只和表结构有关
+//     布尔类型占1个字节，整数、浮点数、日期和时间占8个字节，UUID 占16个字节
+//     字符串和二进制数据在槽位中存放它在变长数据中的结束位置，占4个字节，开始位置是前一个变长列的结束位置
+// 变长数据：所有字符串和二进制数据的内容依次存放
+//
+// 列数记录了写入这一行时的表结构，新增的列总是追加在最后，
+// 所以列数少于表结构的行，缺少的列直接使用默认值，新增列时不需要重写已有的数据
+
+use crate::sql::schema::Table;
+use crate::sql::types::{DataType, Row, Value};
+use crate::custom_error::{LegendDBError, LegendDBResult};
+
+// 行数据开头的标记
+pub const ROW_FORMAT_MARKER: u8 = 0xFF;
+// 存储格式 2 之前行数据以版本号 1 开头，和只有一列的 bincode 编码的行相同，打开数据库时迁移为新的标记
+pub const LEGACY_ROW_FORMAT_VERSION: u8 = 1;
+// 标记和列数
+const ROW_HEADER_SIZE: usize = 3;
+
+// 每种类型在定长槽位中占用的字节数
+fn slot_width(data_type: &DataType) -> LegendDBResult<usize> {
+    match data_type {
+        DataType::Boolean => Ok(1),
+        DataType::String | DataType::Binary => Ok(4),
+        DataType::Integer | DataType::Float => Ok(8),
+        DataType::Date | DataType::Time | DataType::DateTime => Ok(8),
+        DataType::Uuid => Ok(16),
+        dt => Err(LegendDBError::EncodeError(format!("unsupported data type {:?} in row codec", dt))),
+    }
+}
+
+// 根据表结构编码一行数据
+pub fn encode_row(table: &Table, row: &Row) -> LegendDBResult<Vec<u8>> {
+    if row.len() != table.columns.len() {
+        return Err(LegendDBError::EncodeError(format!(
+            "row has {} values, but table {} has {} columns", row.len(), table.name, table.columns.len()
+        )));
+    }
+    let bitmap_size = row.len().div_ceil(8);
+    let slots_size = table.columns.iter().map(|c| slot_width(&c.data_type)).sum::<LegendDBResult<usize>>()?;
+    let mut output = Vec::with_capacity(ROW_HEADER_SIZE + bitmap_size + slots_size);
+    output.push(ROW_FORMAT_MARKER);
+    output.extend((row.len() as u16).to_be_bytes());
+    output.resize(ROW_HEADER_SIZE + bitmap_size, 0);
+
+    let mut var_data: Vec<u8> = Vec::new();
+    for (i, (column, value)) in table.columns.iter().zip(row.iter()).enumerate() {
+        // 值的类型必须和列的类型一致，类型转换在写入之前完成，这里不做隐式转换
+        if value.get_type().is_some_and(|dt| dt != column.data_type) {
+            return Err(LegendDBError::EncodeError(format!(
+                "value {} cannot be stored in column {} of type {:?}", value, column.name, column.data_type
+            )));
+        }
+        match value {
+            Value::Null => {
+                output[ROW_HEADER_SIZE + i / 8] |= 1 << (i % 8);
+                output.resize(output.len() + slot_width(&column.data_type)?, 0);
+            },
+            Value::Boolean(b) => output.push(*b as u8),
+            Value::Integer(v) | Value::Date(v) | Value::Time(v) | Value::DateTime(v) => output.extend(v.to_be_bytes()),
+            Value::Float(v) => output.extend(v.to_be_bytes()),
+            Value::Uuid(u) => output.extend(u),
+            Value::String(s) => {
+                var_data.extend(s.as_bytes());
+                output.extend((var_data.len() as u32).to_be_bytes());
+            },
+            Value::Binary(b) => {
+                var_data.extend(b);
+                output.extend((var_data.len() as u32).to_be_bytes());
+            },
+            Value::Interval(_) => return Err(LegendDBError::EncodeError(format!(
+                "value {} cannot be stored in column {}", value, column.name
+            ))),
+        }
+    }
+    output.extend(var_data);
+    Ok(output)
+}
+
+// 根据表结构解码一行数据
+pub fn decode_row(table: &Table, data: &[u8]) -> LegendDBResult<Row> {
+    let reader = RowReader::new(table, data)?;
+    // 顺序解码时记录上一个变长列的结束位置，不需要每次向前查找
+    let mut string_start = 0;
+    let mut row = Vec::with_capacity(table.columns.len());
+    for i in 0..table.columns.len() {
+        let value = reader.value_from(i, string_start)?;
+        if let Value::String(_) | Value::Binary(_) = value {
+            string_start = reader.read_u32(reader.slot_offsets[i]) as usize;
+        }
+        row.push(value);
+    }
+    Ok(row)
+}
+
+// 只解码指定位置的列，不需要的列直接跳过
+pub fn decode_columns(table: &Table, data: &[u8], indexes: &[usize]) -> LegendDBResult<Row> {
+    let reader = RowReader::new(table, data)?;
+    indexes.iter().map(|&i| reader.value_from(i, reader.string_start(i))).collect()
+}
+
+// 按列读取编码后的行数据
+struct RowReader<'a> {
+    table: &'a Table,
+    data: &'a [u8],
+    // 每一列的槽位在数据中的起始位置，只包含写入时已经存在的列
+    slot_offsets: Vec<usize>,
+    // 变长数据的起始位置
+    var_offset: usize,
+}
+
+impl<'a> RowReader<'a> {
+    fn new(table: &'a Table, data: &'a [u8]) -> LegendDBResult<Self> {
+        if data.len() < ROW_HEADER_SIZE || data[0] != ROW_FORMAT_MARKER {
+            return Err(LegendDBError::DecodeError(format!("invalid row format of table {}", table.name)));
+        }
+        let columns = u16::from_be_bytes([data[1], data[2]]) as usize;
+        // 表结构中的列只会增加，除非重写过所有数据
+        if columns > table.columns.len() {
+            return Err(LegendDBError::DecodeError(format!(
+                "row has {} columns, but table {} has {} columns", columns, table.name, table.columns.len()
+            )));
+        }
+        let mut offset = ROW_HEADER_SIZE + columns.div_ceil(8);
+        let mut slot_offsets = Vec::with_capacity(columns);
+        for column in table.columns[..columns].iter() {
+            slot_offsets.push(offset);
+            offset += slot_width(&column.data_type)?;
+        }
+        if data.len() < offset {
+            return Err(LegendDBError::DecodeError(format!("row of table {} is truncated", table.name)));
+        }
+        Ok(Self { table, data, slot_offsets, var_offset: offset })
+    }
+
+    fn is_null(&self, index: usize) -> bool {
+        self.data[ROW_HEADER_SIZE + index / 8] & (1 << (index % 8)) != 0
+    }
+
+    fn read_u32(&self, offset: usize) -> u32 {
+        u32::from_be_bytes(self.data[offset..offset + 4].try_into().unwrap_or_default())
+    }
+
+    fn read_u64(&self, offset: usize) -> [u8; 8] {
+        self.data[offset..offset + 8].try_into().unwrap_or_default()
+    }
+
+    // 变长数据的开始位置是前面最近一个非NULL变长列的结束位置
+    fn string_start(&self, index: usize) -> usize {
+        (0..index.min(self.slot_offsets.len()))
+            .rev()
+            .find(|&i| matches!(self.table.columns[i].data_type, DataType::String | DataType::Binary) && !self.is_null(i))
+            .map_or(0, |i| self.read_u32(self.slot_offsets[i]) as usize)
+    }
+
+    // 读取第 index 列的值，string_start 是字符串类型的列在变长数据中的开始位置
+    fn value_from(&self, index: usize, string_start: usize) -> LegendDBResult<Value> {
+        // 写入之后新增的列
+        if index >= self.slot_offsets.len() {
+            let column = &self.table.columns[index];
+            return column.default_value.clone().ok_or(LegendDBError::DecodeError(format!(
+                "column {} of table {} is missing and has no default value", column.name, self.table.name
+            )));
+        }
+        if self.is_null(index) {
+            return Ok(Value::Null);
+        }
+        let offset = self.slot_offsets[index];
+        Ok(match self.table.columns[index].data_type {
+            DataType::Boolean => Value::Boolean(self.data[offset] != 0),
+            DataType::Integer => Value::Integer(i64::from_be_bytes(self.read_u64(offset))),
+            DataType::Float => Value::Float(f64::from_be_bytes(self.read_u64(offset))),
+            DataType::Date => Value::Date(i64::from_be_bytes(self.read_u64(offset))),
+            DataType::Time => Value::Time(i64::from_be_bytes(self.read_u64(offset))),
+            DataType::DateTime => Value::DateTime(i64::from_be_bytes(self.read_u64(offset))),
+            DataType::Uuid => Value::Uuid(self.data[offset..offset + 16].try_into().unwrap_or_default()),
+            DataType::String | DataType::Binary => {
+                let start = self.var_offset + string_start;
+                let end = self.var_offset + self.read_u32(offset) as usize;
+                let bytes = self.data.get(start..end).ok_or(LegendDBError::DecodeError(format!(
+                    "row of table {} is truncated", self.table.name
+                )))?;
+                match self.table.columns[index].data_type {
+                    DataType::Binary => Value::Binary(bytes.to_vec()),
+                    _ => Value::String(String::from_utf8(bytes.to_vec())?),
+                }
+            },
+            ref dt => return Err(LegendDBError::DecodeError(format!("unsupported data type {:?} in row codec", dt))),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bincode::config;
+    use crate::sql::engine::row_codec::{decode_columns, decode_row, encode_row};
+    use crate::sql::schema::{Column, Table};
+    use crate::sql::types::{DataType, Value};
+    use crate::custom_error::LegendDBResult;
+
+    fn column(name: &str, data_type: DataType) -> Column {
+        Column {
+            name: name.to_string(),
+            data_type,
+            max_length: None,
+            int_width: None,
+            nullable: true,
+            default_value: Some(Value::Null),
+            default_expr: None,
+            is_primary_key: false,
+            unique: false,
+            auto_increment: false,
+        }
+    }
+
+    #[test]
+    fn test_row_codec() -> LegendDBResult<()> {
+        let table = Table {
+            name: "t1".to_string(),
+            columns: vec![
+                column("a", DataType::Integer),
+                column("b", DataType::String),
+                column("c", DataType::Boolean),
+                column("d", DataType::Float),
+                column("e", DataType::String),
+                column("f", DataType::String),
+            ],
+            constraints: vec![],
+        };
+        let row = vec![
+            Value::Integer(-1),
+            Value::String("名字".to_string()),
+            Value::Boolean(true),
+            Value::Null,
+            Value::String("".to_string()),
+            Value::String("abc".to_string()),
+        ];
+        let encoded = encode_row(&table, &row)?;
+        assert_eq!(decode_row(&table, &encoded)?, row);
+        // 标记 + 列数 + 位图 + 槽位 + 变长数据
+        assert_eq!(encoded.len(), 3 + 1 + (8 + 4 + 1 + 8 + 4 + 4) + 9);
+
+        // 类型和列的类型不一致时报错，不做隐式转换
+        let row2 = vec![Value::Null, Value::Null, Value::Null, Value::Integer(2), Value::Null, Value::Null];
+        assert!(encode_row(&table, &row2).is_err());
+        let row3 = vec![Value::String("x".to_string()), Value::Null, Value::Null, Value::Null, Value::Null, Value::Null];
+        assert!(encode_row(&table, &row3).is_err());
+        // 只解码部分列
+        assert_eq!(
+            decode_columns(&table, &encoded, &[5, 0, 4, 3])?,
+            vec![Value::String("abc".to_string()), Value::Integer(-1), Value::String("".to_string()), Value::Null]
+        );
+        // 数据被截断
+        assert!(decode_row(&table, &encoded[..encoded.len() - 1]).is_err());
+        // 标记不是合法的 bincode 开头，只有一列的 bincode 编码的行也不会被当作新的格式
+        assert!(bincode::decode_from_slice::<Vec<Value>, _>(&encoded, config::standard()).is_err());
+        let legacy = bincode::encode_to_vec(vec![Value::Integer(1)], config::standard())?;
+        assert_eq!(legacy[0], 1);
+        assert!(decode_row(&Table { columns: table.columns[..1].to_vec(), ..table.clone() }, &legacy).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_row_codec_added_columns() -> LegendDBResult<()> {
+        let mut table = Table {
+            name: "t1".to_string(),
+            columns: vec![column("a", DataType::Integer), column("b", DataType::String)],
+            constraints: vec![],
+        };
+        let encoded = encode_row(&table, &vec![Value::Integer(1), Value::String("abc".to_string())])?;
+        // 新增的列使用默认值
+        table.columns.push(Column { default_value: Some(Value::Integer(10)), ..column("c", DataType::Integer) });
+        table.columns.push(column("d", DataType::String));
+        assert_eq!(
+            decode_row(&table, &encoded)?,
+            vec![Value::Integer(1), Value::String("abc".to_string()), Value::Integer(10), Value::Null]
+        );
+        assert_eq!(decode_columns(&table, &encoded, &[3, 1, 2])?, vec![Value::Null, Value::String("abc".to_string()), Value::Integer(10)]);
+        // 新增的列没有默认值
+        table.columns.push(Column { nullable: false, default_value: None, ..column("e", DataType::Integer) });
+        assert!(decode_row(&table, &encoded).is_err());
+        // 列数多于表结构
+        table.columns.truncate(1);
+        assert!(decode_row(&table, &encoded).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_row_codec_wide_table() -> LegendDBResult<()> {
+        // 宽表中每个值都省去了类型标记，浮点数和较大的整数比 bincode 更紧凑
+        let table = Table {
+            name: "t1".to_string(),
+            columns: (0..30).map(|i| match i % 3 {
+                0 => column(&format!("c{}", i), DataType::Integer),
+                1 => column(&format!("c{}", i), DataType::Float),
+                _ => column(&format!("c{}", i), DataType::Boolean),
+            }).collect(),
+            constraints: vec![],
+        };
+        let row = (0..30).map(|i| match i % 3 {
+            0 => Value::Integer(1_700_000_000_000 + i),
+            1 => Value::Float(i as f64 * 1.5),
+            _ => Value::Boolean(i % 2 == 0),
+        }).collect::<Vec<_>>();
+        let encoded = encode_row(&table, &row)?;
+        assert_eq!(decode_row(&table, &encoded)?, row);
+        assert!(encoded.len() < bincode::encode_to_vec(&row, config::standard())?.len());
+        Ok(())
+    }
+}
@@ -0,0 +1,128 @@
+// 写入限流，避免一个批量任务占满单块磁盘上的日志
+// 按表限制每秒写入的行数，每个表有一个令牌桶，令牌按配置的速率补充，最多积累一秒的量
+// 事务第一次写入一个表之前检查令牌，还欠着令牌时直接返回 WriteThrottled，不用等到写完所有的行才失败
+// 事务提交之后按实际写入的行数扣减令牌，令牌可以扣成负数，所以单个大事务也能提交，之后的写入要等欠下的令牌补回来
+// 被限流的语句所在的事务回滚，由客户端等待之后重试，服务端不排队
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use crate::custom_error::{LegendDBError, LegendDBResult};
+
+#[derive(Debug)]
+struct Bucket {
+    // 每秒补充的令牌数，也是令牌的上限
+    rate: u64,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate as f64).min(self.rate as f64);
+        self.updated = now;
+    }
+
+    // 令牌补充到至少一个需要等待的时间
+    fn wait(&self) -> Duration {
+        Duration::from_secs_f64((1.0 - self.tokens).max(0.0) / self.rate as f64)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct WriteThrottle {
+    // 表名对应的令牌桶
+    buckets: HashMap<String, Bucket>,
+}
+
+impl WriteThrottle {
+    // 设置每秒写入的行数，0 表示取消限制
+    pub fn set_limit(&mut self, table: &str, rows_per_second: u64) {
+        if rows_per_second == 0 {
+            self.buckets.remove(table);
+            return;
+        }
+        let now = Instant::now();
+        let bucket = self.buckets.entry(table.to_string()).or_insert(Bucket {
+            rate: rows_per_second,
+            tokens: rows_per_second as f64,
+            updated: now,
+        });
+        bucket.refill(now);
+        bucket.rate = rows_per_second;
+        bucket.tokens = bucket.tokens.min(rows_per_second as f64);
+    }
+
+    pub fn limit(&self, table: &str) -> Option<u64> {
+        self.buckets.get(table).map(|b| b.rate)
+    }
+
+    // 写入表之前检查表的令牌，至少有一个令牌时才能写入
+    pub fn check(&mut self, table: &str) -> LegendDBResult<()> {
+        self.check_at(table, Instant::now())
+    }
+
+    fn check_at(&mut self, table: &str, now: Instant) -> LegendDBResult<()> {
+        let Some(bucket) = self.buckets.get_mut(table) else {
+            return Ok(());
+        };
+        bucket.refill(now);
+        if bucket.tokens < 1.0 {
+            return Err(LegendDBError::WriteThrottled {
+                target: format!("table {}", table),
+                retry_after_ms: bucket.wait().as_millis() as u64 + 1,
+            });
+        }
+        Ok(())
+    }
+
+    // 提交之后按事务写入的行数扣减表的令牌，rows 是每个表写入的行数
+    pub fn charge(&mut self, rows: &HashMap<String, u64>) {
+        self.charge_at(rows, Instant::now())
+    }
+
+    fn charge_at(&mut self, rows: &HashMap<String, u64>, now: Instant) {
+        for (table, n) in rows {
+            if let Some(bucket) = self.buckets.get_mut(table) {
+                bucket.refill(now);
+                bucket.tokens -= *n as f64;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::time::{Duration, Instant};
+    use crate::sql::engine::throttle::WriteThrottle;
+    use crate::custom_error::{LegendDBError, LegendDBResult};
+
+    #[test]
+    fn test_write_throttle() -> LegendDBResult<()> {
+        let mut throttle = WriteThrottle::default();
+        throttle.set_limit("t", 10);
+        let rows = |n: u64| HashMap::from([("t".to_string(), n)]);
+        let start = Instant::now();
+        // 大事务可以一次用完并欠下令牌，欠着令牌时写入之前的检查就会失败
+        throttle.check_at("t", start)?;
+        throttle.charge_at(&rows(25), start);
+        match throttle.check_at("t", start) {
+            Err(LegendDBError::WriteThrottled { target, retry_after_ms }) => {
+                assert_eq!(target, "table t");
+                assert!((1600..=1610).contains(&retry_after_ms), "{}", retry_after_ms);
+            },
+            r => panic!("unexpected result {:?}", r),
+        }
+        // 欠下的令牌补回来之后可以继续写入
+        throttle.check_at("t", start + Duration::from_millis(1700))?;
+        // 没有限制的表不受影响
+        throttle.charge_at(&HashMap::from([("u".to_string(), 150)]), start);
+        assert!(throttle.check_at("u", start).is_ok());
+        // 限制改为 0 时取消限制
+        throttle.set_limit("t", 0);
+        assert_eq!(throttle.limit("t"), None);
+        assert!(throttle.check_at("t", start).is_ok());
+        Ok(())
+    }
+}
@@ -2,23 +2,25 @@ use std::collections::HashMap;
 use crate::custom_error::{LegendDBError, LegendDBResult};
 use crate::sql::engine::engine::Transaction;
 use crate::sql::executor::executor::{Executor, ResultSet};
-use crate::sql::parser::ast::Expression;
+use crate::sql::parser::ast::{column_position, evaluate_expr, Expression};
 use crate::sql::types::Value;
 use crate::sql::types::Value::Null;
 
 pub struct AggregateExecutor<T: Transaction> {
     source: Box<dyn Executor<T>>,
     expressions: Vec<(Expression, Option<String>)>,
-    group_by: Option<Expression>
+    group_by: Option<Expression>,
+    having: Option<Expression>,
 }
 
 impl<T: Transaction> AggregateExecutor<T> {
-    pub fn new(source: Box<dyn Executor<T>>, expressions: Vec<(Expression, Option<String>)>, group_by: Option<Expression>) -> Box<Self> {
+    pub fn new(source: Box<dyn Executor<T>>, expressions: Vec<(Expression, Option<String>)>, group_by: Option<Expression>, having: Option<Expression>) -> Box<Self> {
         Box::new(
             Self {
                 source,
                 expressions,
                 group_by,
+                having,
             }
         )
     }
@@ -28,22 +30,23 @@ impl<T: Transaction> Executor<T> for AggregateExecutor<T> {
     fn execute(self: Box<Self>, txn: &mut T) -> LegendDBResult<ResultSet> {
         if let ResultSet::Scan { columns, rows } = self.source.execute(txn)? {
             let mut new_row = Vec::new();
-            let mut new_col = Vec::new();
+            // min(a)            -> min
+            // min(a) as min_val -> min_val
+            // 有别名就取别名，没有别名就取函数名或列名
+            let new_col = self.expressions.iter().map(|(expr, alias)| match (expr, alias) {
+                (_, Some(alias)) => alias.clone(),
+                (Expression::Function(func_name, _), None) => func_name.clone(),
+                (expr, None) => expr.to_string(),
+            }).collect::<Vec<_>>();
             // 计算聚合函数 如果是分组的计算，
-            let mut agg_calculation = |col_val: Option<&Value>, row: &Vec<Vec<Value>>| -> LegendDBResult<Vec<Value>> {
+            let agg_calculation = |col_val: Option<&Value>, row: &Vec<Vec<Value>>| -> LegendDBResult<Vec<Value>> {
                 let mut new_row = Vec::new();
                 // 此处也需要使用借用类型
-                for (expr, alias) in &self.expressions {
+                for (expr, _) in &self.expressions {
                     match expr {
                         Expression::Function(func_name, col_name) => {
                             let calculator = <dyn Calculator>::build(&func_name)?;
                             let value = calculator.calculate(&col_name, &columns, row)?;
-                            // min(a)            -> min
-                            // min(a) as min_val -> min_val
-                            // 有别名就取别名，没有别名就取函数名
-                            if new_col.len() < self.expressions.len() {
-                                new_col.push(if let Some(alias) = alias { alias.clone() } else { func_name.clone() })
-                            }
                             new_row.push(value);
                         },
                         // group by的列
@@ -53,9 +56,6 @@ impl<T: Transaction> Executor<T> for AggregateExecutor<T> {
                                     return Err(LegendDBError::Internal(format!("{} must appear in the GROUP BY clause or aggregate function", col)))
                                 }
                             }
-                            if new_col.len() < self.expressions.len() {
-                                new_col.push(if let Some(alias) = alias { alias.clone() } else { col.clone() });
-                            }
                             // 此处col_val在Expression::Field(col)的match情况中，使用了就回收了，而前面是有所有权的，所以这儿可以使用借用类型
                             match col_val {
                                 None => new_row.push(Null),
@@ -69,6 +69,30 @@ impl<T: Transaction> Executor<T> for AggregateExecutor<T> {
                 }
                 return Ok(new_row)
             };
+            // having 中的聚合函数替换为对应的列，比如 sum(c) > 5 -> "sum(c)" > 5
+            // 这些列在聚合时额外计算，只用于 having 判断
+            let mut having_aggs = Vec::new();
+            let having = self.having.clone().map(|having| having.transform(&mut |expr| Ok(match expr {
+                Expression::Function(func_name, col_name) => {
+                    let name = Expression::Function(func_name.clone(), col_name.clone()).to_string();
+                    if !having_aggs.iter().any(|(n, _, _)| *n == name) {
+                        having_aggs.push((name.clone(), func_name, col_name));
+                    }
+                    Expression::Field(name)
+                },
+                expr => expr,
+            }))).transpose()?;
+            // 分组是否满足 having 条件
+            let having_match = |agg_row: &Vec<Value>, group: &Vec<Vec<Value>>| -> LegendDBResult<bool> {
+                let Some(having) = &having else { return Ok(true) };
+                let mut having_col = new_col.clone();
+                let mut having_row = agg_row.clone();
+                for (name, func_name, col_name) in &having_aggs {
+                    having_col.push(name.clone());
+                    having_row.push(<dyn Calculator>::build(func_name)?.calculate(col_name, &columns, group)?);
+                }
+                Ok(evaluate_expr(having, &having_col, &having_row, &having_col, &having_row)? == Value::Boolean(true))
+            };
             // 判断是否有group_by
             // select c2, min(c1), max(c3) from t group by c2;
             // c1 c2 c3
@@ -100,11 +124,15 @@ impl<T: Transaction> Executor<T> for AggregateExecutor<T> {
                 }
                 for (key, value) in agg_map {
                     let row = agg_calculation(Some(key), &value)?;
-                    new_row.push(row);
+                    if having_match(&row, &value)? {
+                        new_row.push(row);
+                    }
                 }
              } else {
                 let row = agg_calculation(None, &rows)?;
-                new_row.push(row);
+                if having_match(&row, &rows)? {
+                    new_row.push(row);
+                }
             }
 
             return Ok(ResultSet::Scan {
@@ -116,6 +144,28 @@ impl<T: Transaction> Executor<T> for AggregateExecutor<T> {
     }
 }
 
+// 统计表的行数，select count(*) from t
+pub struct CountRowsExecutor {
+    table_name: String,
+    column: String,
+}
+
+impl CountRowsExecutor {
+    pub fn new(table_name: String, column: String) -> Box<Self> {
+        Box::new(Self { table_name, column })
+    }
+}
+
+impl<T: Transaction> Executor<T> for CountRowsExecutor {
+    fn execute(self: Box<Self>, txn: &mut T) -> LegendDBResult<ResultSet> {
+        let count = txn.count_rows(&self.table_name)?;
+        Ok(ResultSet::Scan {
+            columns: vec![self.column],
+            rows: vec![vec![Value::Integer(count as i64)]],
+        })
+    }
+}
+
 pub trait Calculator {
     fn calculate(&self, col_name: &str, col: &Vec<String>, row: &Vec<Vec<Value>>) -> LegendDBResult<Value>;
 }
@@ -140,12 +190,7 @@ pub struct Min;
 pub struct Max;
 
 fn get_position(col: &Vec<String>, col_name: &str) -> LegendDBResult<usize> {
-    Ok(match col.iter().position(|x| x == col_name) {
-        Some(pos) => pos,
-        None => {
-            return Err(LegendDBError::Internal(format!("Column {} not found", col_name)))
-        }
-    })
+    column_position(col, col_name)
 }
 
 impl Count {
@@ -155,6 +200,10 @@ impl Count {
 }
 impl Calculator for Count {
     fn calculate(&self, col_name: &str, col: &Vec<String>, row: &Vec<Vec<Value>>) -> LegendDBResult<Value> {
+        // count(*) 包括所有列都是 NULL 的行
+        if col_name == "*" {
+            return Ok(Value::Integer(row.len() as i64));
+        }
         let position = get_position(col, col_name)?;
         // a  b     c
         // 1  X     3.1
@@ -230,20 +279,8 @@ impl Calculator for Sum {
         let mut sum = None;
         for row in row.iter() {
             match row[position] {
-                Value::Integer(i) => {
-                    if sum.is_none() {
-                        sum = Some(0.0);
-                    } else {
-                        sum = Some(sum.unwrap() + i as f64);
-                    }
-                },
-                Value::Float(f) => {
-                    if sum.is_none() {
-                        sum = Some(0.0);
-                    } else {
-                        sum = Some(sum.unwrap() + f);
-                    }
-                },
+                Value::Integer(i) => sum = Some(sum.unwrap_or(0.0) + i as f64),
+                Value::Float(f) => sum = Some(sum.unwrap_or(0.0) + f),
                 Null => {},
                 _ => {
                     return Err(LegendDBError::Internal(format!("Column {} is not number", col_name)))
@@ -26,12 +26,14 @@ impl<T: Transaction> Executor<T> for CreateDataBaseExecutor {
 
 pub struct DropDataBaseExecutor {
     pub database_name: String,
+    pub if_exists: bool,
 }
 
 impl DropDataBaseExecutor {
-    pub fn new(database_name: String) -> Box<Self> {
+    pub fn new(database_name: String, if_exists: bool) -> Box<Self> {
         Box::new(Self {
-            database_name
+            database_name,
+            if_exists,
         }
     )
     }
@@ -39,6 +41,12 @@ impl DropDataBaseExecutor {
 
 impl<T: Transaction> Executor<T> for DropDataBaseExecutor {
     fn execute(self: Box<Self>, txn: &mut T) -> LegendDBResult<ResultSet> {
+        // drop database if exists，数据库不存在时直接返回
+        if self.if_exists && !txn.database_exists(&self.database_name)? {
+            return Ok(ResultSet::DropDatabase {
+                database_name: self.database_name.clone(),
+            });
+        }
         txn.drop_database(&*self.database_name.clone())?;
         Ok(ResultSet::DropDatabase {
             database_name: self.database_name.clone(),
@@ -1,41 +1,88 @@
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
 use crate::sql::engine::engine::Transaction;
 use crate::sql::executor::databases::{CreateDataBaseExecutor, DropDataBaseExecutor, UseDatabaseExecutor};
 use crate::sql::executor::delete::DeleteExecutor;
 use crate::sql::executor::insert::InsertExecutor;
 use crate::sql::executor::join::NestLoopJoinExecutor;
-use crate::sql::executor::query::{FilterExecutor, LimitExecutor, OffsetExecutor, OrderExecutor, ProjectionExecutor, ScanExecutor};
-use crate::sql::executor::schema::{CreateTableExecutor, DropTableExecutor};
+use crate::sql::executor::query::{ExplainExecutor, FilterExecutor, LimitExecutor, OffsetExecutor, OrderExecutor, ProjectionExecutor, ScanExecutor};
+use crate::sql::executor::schema::{AlterTableExecutor, CheckTableExecutor, CreateTableExecutor, DropTableExecutor};
 use crate::sql::executor::update::UpdateExecutor;
-use crate::sql::plan::node::Node;
-use crate::sql::types::Row;
-use crate::custom_error::LegendDBResult;
-use crate::sql::executor::agg::AggregateExecutor;
+use crate::sql::parser::ast::Expression;
+use crate::sql::plan::node::{Node, Plan};
+use crate::sql::types::{format_hex, parse_hex, Row, Value};
+use crate::custom_error::{LegendDBError, LegendDBResult};
+use crate::sql::executor::agg::{AggregateExecutor, CountRowsExecutor};
+use crate::sql::executor::profile::{ExplainAnalyzeExecutor, IndexRecommendationsExecutor, ProfileExecutor, Profiler};
 
 // 抽象执行器定义
 pub trait Executor<T: Transaction> {
     fn execute(self: Box<Self<>>, txn: &mut T) -> LegendDBResult<ResultSet>;
+
+    // 只需要 column 的值在 keys 中的行，可以按主键批量读取
+    // 不能按主键读取的执行器返回所有的行，调用方仍然需要自己判断每一行是否满足条件
+    fn execute_lookup(self: Box<Self<>>, txn: &mut T, _column: &str, _keys: Vec<Value>) -> LegendDBResult<ResultSet> {
+        self.execute(txn)
+    }
+}
+
+// 执行条件中的子查询，替换为子查询的结果
+// 目前只支持不引用外层列的子查询，每个子查询只执行一次
+pub fn materialize_subqueries<T: Transaction + 'static>(expr: Expression, txn: &mut T) -> LegendDBResult<Expression> {
+    expr.transform(&mut |expr| match expr {
+        Expression::Subquery(query) => match Plan::build(*query)?.execute(txn)? {
+            ResultSet::Scan { rows, .. } => Ok(Expression::Materialized(rows)),
+            _ => Err(LegendDBError::Internal("subquery must be a query".to_string())),
+        },
+        expr => Ok(expr),
+    })
 }
 
 
 impl<T: Transaction + 'static> dyn Executor<T> {
+    // 根据执行计划节点构建执行器
     pub fn build(node: Node) -> Box<dyn Executor<T>> {
-        match node {
+        Self::build_node(node, None, 0)
+    }
+
+    // 构建执行器，每个节点都记录执行的行数和耗时
+    pub fn build_profiled(node: Node, profiler: &Profiler) -> Box<dyn Executor<T>> {
+        Self::build_node(node, Some(profiler), 0)
+    }
+
+    // 新增节点时必须在此处显式处理，不使用通配分支，遗漏的节点在编译时报错
+    fn build_node(node: Node, profiler: Option<&Profiler>, depth: usize) -> Box<dyn Executor<T>> {
+        let build = |node: Node| Self::build_node(node, profiler, depth + 1);
+        // 按先序记录节点，和执行计划的展示顺序一致
+        let profile = profiler.map(|profiler| (profiler.clone(), profiler.add(depth, &node)));
+        let executor: Box<dyn Executor<T>> = match node {
             Node::CreateTable {schema } => CreateTableExecutor::new(schema),
-            Node::Insert {table_name, columns, values} => InsertExecutor::new(table_name, columns, values),
-            Node::Scan {table_name, filter} => ScanExecutor::new(table_name, filter),
-            Node::Update {table_name, source, columns } => UpdateExecutor::new(table_name, Self::build(*source), columns),
-            Node::Delete {table_name, source} => DeleteExecutor::new(table_name, Self::build(*source)),
+            Node::Insert {table_name, columns, values, null_as_default, truncate} => InsertExecutor::new(table_name, columns, values, null_as_default, truncate),
+            Node::Scan {table_name, filter, columns} => ScanExecutor::new(table_name, filter, columns),
+            Node::Update {table_name, source, columns, truncate} => UpdateExecutor::new(table_name, build(*source), columns, truncate),
+            Node::Delete {table_name, source} => DeleteExecutor::new(table_name, build(*source)),
             Node::CreateDatabase {database_name} => CreateDataBaseExecutor::new(database_name),
-            Node::DropDatabase {database_name} => DropDataBaseExecutor::new(database_name),
-            Node::DropTable {table_name} => DropTableExecutor::new(table_name),
-            Node::OrderBy {source, order_by} => OrderExecutor::new(Self::build(*source), order_by),
-            Node::Limit {source, limit} => LimitExecutor::new(Self::build(*source), limit),
-            Node::Offset {source, offset} => OffsetExecutor::new(Self::build(*source), offset),
-            Node::Projection {source, columns} => ProjectionExecutor::new(Self::build(*source), columns),
-            Node::Aggregate {source, expr, group_by} => AggregateExecutor::new(Self::build(*source), expr, group_by),
-            Node::Filter {source, predicate} => FilterExecutor::new(Self::build(*source), predicate),
-            Node::NestedLoopJoin {left, right, predicate, outer} => NestLoopJoinExecutor::new(Self::build(*left), Self::build(*right), predicate, outer),
+            Node::DropDatabase {database_name, if_exists} => DropDataBaseExecutor::new(database_name, if_exists),
             Node::UseDatabase {database_name} => UseDatabaseExecutor::new(database_name),
+            Node::DropTable {table_name, if_exists} => DropTableExecutor::new(table_name, if_exists),
+            Node::AlterTable {table_name, operation} => AlterTableExecutor::new(table_name, operation),
+            Node::CheckTable {table_name} => CheckTableExecutor::new(table_name),
+            Node::IndexRecommendations => IndexRecommendationsExecutor::new(),
+            Node::Explain {source} => ExplainExecutor::new(*source),
+            Node::ExplainAnalyze {source, query, fingerprint} => ExplainAnalyzeExecutor::new(*source, query, fingerprint),
+            Node::OrderBy {source, order_by, tiebreak} => OrderExecutor::new(build(*source), order_by, tiebreak),
+            Node::Limit {source, limit} => LimitExecutor::new(build(*source), limit),
+            Node::Offset {source, offset} => OffsetExecutor::new(build(*source), offset),
+            Node::Projection {source, columns} => ProjectionExecutor::new(build(*source), columns),
+            Node::Aggregate {source, expr, group_by, having} => AggregateExecutor::new(build(*source), expr, group_by, having),
+            Node::CountRows {table_name, column} => CountRowsExecutor::new(table_name, column),
+            Node::Filter {source, predicate} => FilterExecutor::new(build(*source), predicate),
+            Node::NestedLoopJoin {left, right, predicate, join_type, using, left_alias, right_alias} => {
+                NestLoopJoinExecutor::new(build(*left), build(*right), predicate, join_type, using, (left_alias, right_alias))
+            },
+        };
+        match profile {
+            Some((profiler, index)) => ProfileExecutor::new(executor, profiler, index),
+            None => executor,
         }
     }
 }
@@ -59,6 +106,9 @@ pub enum ResultSet {
     DropTable {
         table_name: String
     },
+    AlterTable {
+        table_name: String
+    },
     Insert {
         count: usize
     },
@@ -76,23 +126,125 @@ pub enum ResultSet {
         columns: Vec<String>,
         rows: Vec<Row>
     },
+    Set {
+        name: String,
+        value: Value
+    },
+    Explain {
+        plan: String
+    },
+}
+
+// 结果集展示选项
+#[derive(Debug, Clone, Default)]
+pub struct DisplayOptions {
+    // 每一列最大的显示宽度，超出的部分截断，None表示不限制
+    pub max_column_width: Option<usize>,
+    // 最多展示的行数，超出的行不会被渲染，None表示不限制
+    pub max_rows: Option<usize>,
+    // 字符串的展示方式
+    pub string_output: StringOutput,
+}
+
+// 字符串的展示方式，避免控制字符破坏终端显示和按行传输的协议
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum StringOutput {
+    // 控制字符转义展示，比如 \n、\x1b，其他字符原样展示
+    #[default]
+    Escaped,
+    // 按 UTF-8 编码的十六进制展示，比如 0x616263
+    Hex,
+    // 原样展示
+    Raw,
 }
 
+impl StringOutput {
+    pub fn parse(output: &str) -> Option<Self> {
+        match output.to_uppercase().as_ref() {
+            "ESCAPED" => Some(StringOutput::Escaped),
+            "HEX" => Some(StringOutput::Hex),
+            "RAW" => Some(StringOutput::Raw),
+            _ => None,
+        }
+    }
+
+    // 按展示方式转换值
+    fn format(&self, value: &Value) -> String {
+        let Value::String(s) = value else { return value.to_string() };
+        match self {
+            StringOutput::Raw => s.clone(),
+            StringOutput::Hex => format!("0x{}", s.bytes().map(|b| format!("{:02x}", b)).collect::<String>()),
+            StringOutput::Escaped => s.chars().map(|c| match c {
+                '\n' => "\\n".to_string(),
+                '\r' => "\\r".to_string(),
+                '\t' => "\\t".to_string(),
+                c if c.is_control() && (c as u32) < 0x100 => format!("\\x{:02x}", c as u32),
+                c if c.is_control() => format!("\\u{{{:x}}}", c as u32),
+                c => c.to_string(),
+            }).collect(),
+        }
+    }
+}
+
+// 结构化查询结果的前缀，后面是列名和所有行的 bincode 编码的十六进制表示
+pub const ROWS_PREFIX: &str = "ROWS ";
+
 impl ResultSet {
     pub fn to_string(&self) -> String {
+        self.render(&DisplayOptions::default())
+    }
+
+    // 结构化的结果，查询结果编码成一行，分片路由等程序不需要解析渲染之后的表格，其他结果和 render 一样
+    pub fn encode_rows(&self) -> LegendDBResult<String> {
+        match self {
+            ResultSet::Scan { columns, rows } => {
+                let data = bincode::encode_to_vec((columns, rows), bincode::config::standard())?;
+                Ok(format!("{}{}", ROWS_PREFIX, format_hex(&data)))
+            },
+            rs => Ok(rs.to_string()),
+        }
+    }
+
+    // 解析 encode_rows 编码的查询结果，不是查询结果时返回 None
+    pub fn decode_rows(response: &str) -> LegendDBResult<Option<(Vec<String>, Vec<Row>)>> {
+        let Some(hex) = response.strip_prefix(ROWS_PREFIX) else {
+            return Ok(None);
+        };
+        let data = parse_hex(hex).ok_or_else(|| LegendDBError::Internal(format!("invalid rows response {}", response)))?;
+        let (result, _) = bincode::decode_from_slice(&data, bincode::config::standard())?;
+        Ok(Some(result))
+    }
+
+    // 根据展示选项渲染结果集
+    pub fn render(&self, options: &DisplayOptions) -> String {
         match self {
+            ResultSet::CreateDatabase { database_name } => format!("CREATE DATABASE {}", database_name),
+            ResultSet::DropDatabase { database_name } => format!("DROP DATABASE {}", database_name),
+            ResultSet::UseDatabase { database_name } => format!("USE DATABASE {}", database_name),
             ResultSet::CreateTable { table_name } => format!("CREATE TABLE {}", table_name),
             ResultSet::DropTable { table_name } => format!("DROP TABLE {}", table_name),
+            ResultSet::AlterTable { table_name } => format!("ALTER TABLE {}", table_name),
             ResultSet::Insert { count } => format!("INSERT {} rows", count),
             ResultSet::Scan { columns, rows } => {
                 let rows_len = rows.len();
+                // 只渲染需要展示的行，避免大表生成巨大的字符串
+                let shown = options.max_rows.map_or(rows_len, |max_rows| max_rows.min(rows_len));
+                // 先将所有值转换为字符串，并按最大宽度截断
+                let columns = columns
+                    .iter()
+                    .map(|c| truncate_width(c, options.max_column_width))
+                    .collect::<Vec<_>>();
+                let rows = rows[..shown]
+                    .iter()
+                    .map(|row| row.iter().map(|v| truncate_width(&options.string_output.format(v), options.max_column_width)).collect::<Vec<_>>())
+                    .collect::<Vec<_>>();
 
-                // 找到每一列最大的长度
-                let mut max_len = columns.iter().map(|c| c.len()).collect::<Vec<_>>();
-                for one_row in rows {
+                // 找到每一列最大的显示宽度，中文等宽字符占两个位置
+                let mut max_len = columns.iter().map(|c| c.width()).collect::<Vec<_>>();
+                for one_row in rows.iter() {
                     for (i, v) in one_row.iter().enumerate() {
-                        if v.to_string().len() > max_len[i] {
-                            max_len[i] = v.to_string().len();
+                        if i < max_len.len() && v.width() > max_len[i] {
+                            max_len[i] = v.width();
                         }
                     }
                 }
@@ -101,14 +253,14 @@ impl ResultSet {
                 let columns = columns
                     .iter()
                     .zip(max_len.iter())
-                    .map(|(col, &len)| format!("{:width$}", col, width = len))
+                    .map(|(col, &len)| pad_width(col, len))
                     .collect::<Vec<_>>()
                     .join(" |");
 
                 // 展示分隔符
                 let sep = max_len
                     .iter()
-                    .map(|v| format!("{}", "-".repeat(*v + 1)))
+                    .map(|v| "-".repeat(*v + 1))
                     .collect::<Vec<_>>()
                     .join("+");
 
@@ -118,22 +270,130 @@ impl ResultSet {
                     .map(|row| {
                         row.iter()
                             .zip(max_len.iter())
-                            .map(|(v, &len)| format!("{:width$}", v.to_string(), width = len))
+                            .map(|(v, &len)| pad_width(v, len))
                             .collect::<Vec<_>>()
                             .join(" |")
                     })
-                    .collect::<Vec<_>>()
-                    .join("\n");
+                    .collect::<Vec<_>>();
 
-                format!("{}\n{}\n{}\n({} rows)", columns, sep, rows, rows_len)
+                let footer = if shown < rows_len {
+                    format!("({} rows, only the first {} rows are displayed)", rows_len, shown)
+                } else {
+                    format!("({} rows)", rows_len)
+                };
+                if rows.is_empty() {
+                    format!("{}\n{}\n{}", columns, sep, footer)
+                } else {
+                    format!("{}\n{}\n{}\n{}", columns, sep, rows.join("\n"), footer)
+                }
             }
             ResultSet::Update { count } => format!("UPDATE {} rows", count),
             ResultSet::Delete { count } => format!("DELETE {} rows", count),
+            ResultSet::Set { name, value } => format!("SET {} = {}", name, value),
             // ResultSet::Begin { version } => format!("TRANSACTION {} BEGIN", version),
             // ResultSet::Commit { version } => format!("TRANSACTION {} COMMIT", version),
             // ResultSet::Rollback { version } => format!("TRANSACTION {} ROLLBACK", version),
-            // ResultSet::Explain { plan } => plan.to_string(),
+            ResultSet::Explain { plan } => plan.trim_end().to_string(),
             _ => {"".to_string()}
         }
     }
-}
\ No newline at end of file
+}
+
+// 按显示宽度截断字符串，超出部分用...表示
+fn truncate_width(s: &str, max_width: Option<usize>) -> String {
+    match max_width {
+        Some(max_width) if s.width() > max_width => {
+            let ellipsis = if max_width > 3 { "..." } else { "" };
+            let limit = max_width - ellipsis.len();
+            let mut width = 0;
+            let mut result = String::new();
+            for c in s.chars() {
+                let w = c.width().unwrap_or(0);
+                if width + w > limit {
+                    break;
+                }
+                width += w;
+                result.push(c);
+            }
+            result.push_str(ellipsis);
+            result
+        }
+        _ => s.to_string(),
+    }
+}
+
+// 按显示宽度补齐空格，format!的宽度是按字符数计算的，中文会错位
+fn pad_width(s: &str, width: usize) -> String {
+    let mut result = s.to_string();
+    result.push_str(&" ".repeat(width.saturating_sub(s.width())));
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sql::executor::executor::{DisplayOptions, ResultSet, StringOutput};
+    use crate::sql::types::Value;
+
+    #[test]
+    fn test_encode_rows() -> crate::custom_error::LegendDBResult<()> {
+        let columns = vec!["a".to_string(), "b |c".to_string()];
+        let rows = vec![vec![Value::Float(1.0), Value::String("x |y\n".to_string())], vec![Value::Null, Value::Integer(-2)]];
+        let encoded = ResultSet::Scan { columns: columns.clone(), rows: rows.clone() }.encode_rows()?;
+        assert_eq!(ResultSet::decode_rows(&encoded)?, Some((columns, rows)));
+        // 其他结果和渲染的文本一样
+        assert_eq!(ResultSet::Insert { count: 2 }.encode_rows()?, "INSERT 2 rows");
+        assert_eq!(ResultSet::decode_rows("INSERT 2 rows")?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_render_utf8() {
+        let rs = ResultSet::Scan {
+            columns: vec!["a".to_string(), "名字".to_string()],
+            rows: vec![
+                vec![Value::Integer(1), Value::String("张三".to_string())],
+                vec![Value::Integer(22), Value::Null],
+            ],
+        };
+        assert_eq!(
+            rs.to_string(),
+            "a  |名字\n---+-----\n1  |张三\n22 |NULL\n(2 rows)"
+        );
+    }
+
+    #[test]
+    fn test_render_truncate() {
+        let rs = ResultSet::Scan {
+            columns: vec!["name".to_string()],
+            rows: vec![vec![Value::String("abcdefghij".to_string())]],
+        };
+        let options = DisplayOptions { max_column_width: Some(6), ..Default::default() };
+        assert_eq!(rs.render(&options), "name  \n-------\nabc...\n(1 rows)");
+    }
+
+    #[test]
+    fn test_render_max_rows() {
+        let rs = ResultSet::Scan {
+            columns: vec!["a".to_string()],
+            rows: (1..=100).map(|i| vec![Value::Integer(i)]).collect(),
+        };
+        let options = DisplayOptions { max_rows: Some(2), ..Default::default() };
+        assert_eq!(
+            rs.render(&options),
+            "a\n--\n1\n2\n(100 rows, only the first 2 rows are displayed)"
+        );
+    }
+
+    #[test]
+    fn test_render_string_output() {
+        let rs = ResultSet::Scan {
+            columns: vec!["s".to_string()],
+            rows: vec![vec![Value::String("a\tb\n\u{1b}[31m".to_string())]],
+        };
+        // 第三行是数据
+        let render = |string_output| rs.render(&DisplayOptions { string_output, ..Default::default() }).lines().nth(2).unwrap().trim_end().to_string();
+        assert_eq!(render(StringOutput::Escaped), "a\\tb\\n\\x1b[31m");
+        assert_eq!(render(StringOutput::Hex), "0x6109620a1b5b33316d");
+        assert_eq!(render(StringOutput::Raw), "a\tb");
+    }
+}
@@ -1,7 +1,7 @@
 use std::collections::HashMap;
 use crate::sql::engine::engine::Transaction;
 use crate::sql::executor::executor::{Executor, ResultSet};
-use crate::sql::parser::ast::Expression;
+use crate::sql::parser::ast::{evaluate_const, Expression};
 use crate::sql::schema::Table;
 use crate::sql::types::{Row, Value};
 use crate::sql::types::DataType::Null;
@@ -11,14 +11,20 @@ pub struct InsertExecutor {
     table_name: String,
     columns: Vec<String>,
     values: Vec<Vec<Expression>>,
+    // 非空列插入NULL时是否使用默认值
+    null_as_default: bool,
+    // 超过列的最大长度的字符串是否截断
+    truncate: bool,
 }
 
 impl InsertExecutor {
-    pub fn new(table_name: String, columns: Vec<String>, values: Vec<Vec<Expression>>) -> Box<Self> {
+    pub fn new(table_name: String, columns: Vec<String>, values: Vec<Vec<Expression>>, null_as_default: bool, truncate: bool) -> Box<Self> {
         Box::new(Self {
             table_name,
             columns,
             values,
+            null_as_default,
+            truncate,
         })
     }
 }
@@ -33,8 +39,11 @@ fn pad_row(table: &Table, row: &Row) -> LegendDBResult<Row> {
     // skip 跳过前面的n个元素，就是跳过values的长度
     let mut results = row.clone();
     for column in table.columns.iter().skip(row.len()) {
-        if let Some(default_value) = &column.default_value {
-            results.push(default_value.clone());
+        // 自增列先填 NULL，之后再生成值
+        if column.auto_increment {
+            results.push(Value::Null);
+        } else if let Some(default_value) = column.get_default()? {
+            results.push(default_value);
         } else {
             return Err(LegendDBError::Internal("Missing default value".to_string()));
         }
@@ -46,7 +55,7 @@ fn pad_row(table: &Table, row: &Row) -> LegendDBResult<Row> {
 //   a           b       c   d
 // default     default   2   1
 
-fn make_row(table: &Table, columns: &Vec<String>, values: &Row) -> LegendDBResult<Row> {
+fn make_row(table: &Table, columns: &[String], values: &Row) -> LegendDBResult<Row> {
     // 判断columns和values的长度是否一致
     if columns.len() != values.len() {
         return Err(LegendDBError::Internal("Column and value length mismatch".to_string()))
@@ -54,18 +63,64 @@ fn make_row(table: &Table, columns: &Vec<String>, values: &Row) -> LegendDBResul
     // 创建一个HashMap，用于存储指定的列名和值
     let mut inputs = HashMap::new();
     for (index, col_name) in columns.iter().enumerate() {
+        // 指定的列必须存在于表中
+        table.get_column_index(col_name)?;
         inputs.insert(col_name, values[index].clone());
     }
+    // HashMap是无序的，需要按照表中列的顺序组装数据
+    let mut results = Vec::with_capacity(table.columns.len());
     for col in table.columns.iter() {
-        if !columns.contains(&col.name) {
-            if let Some(default_value) = &col.default_value {
-                inputs.insert(&col.name, default_value.clone());
-            } else {
-                return Err(LegendDBError::Internal(format!("Missing default value for column {}", col.name)));
+        match inputs.remove(&col.name) {
+            Some(value) => results.push(value),
+            None if col.auto_increment => results.push(Value::Null),
+            None => match col.get_default()? {
+                Some(default_value) => results.push(default_value),
+                None => return Err(LegendDBError::Internal(format!("Missing default value for column {}", col.name))),
             }
         }
     }
-    Ok(inputs.values().cloned().collect::<Vec<_>>())
+    Ok(results)
+}
+
+// 自增列的值为 NULL 时生成下一个值，next 是下一个可用的值，第一次使用时根据表中已有的最大值计算
+// 指定了比 next 更大的值时，之后从这个值继续递增
+fn fill_auto_increment<T: Transaction>(txn: &mut T, table: &Table, row: &mut Row, next: &mut Option<i64>) -> LegendDBResult<()> {
+    let Some(index) = table.columns.iter().position(|c| c.auto_increment) else {
+        return Ok(());
+    };
+    let column = &table.columns[index];
+    let current = match *next {
+        Some(current) => current,
+        None => {
+            let rows = txn.scan_table_columns(table.name.clone(), None, std::slice::from_ref(&column.name))?;
+            let max = rows.iter().filter_map(|r| match r[0] {
+                Value::Integer(i) => Some(i),
+                _ => None,
+            }).max().unwrap_or(0);
+            max.checked_add(1).ok_or(LegendDBError::Internal(format!("AUTO_INCREMENT column {} of table {} is out of range", column.name, table.name)))?
+        },
+    };
+    let value = match row[index] {
+        Value::Null => current,
+        Value::Integer(i) => i,
+        _ => return Ok(()),
+    };
+    row[index] = Value::Integer(value);
+    *next = Some(value.checked_add(1).map_or(current, |v| v.max(current)));
+    Ok(())
+}
+
+// 非空列插入了NULL，且列有默认值时，使用默认值替换（MySQL兼容模式）
+fn fill_null_with_default(table: &Table, row: &mut Row) -> LegendDBResult<()> {
+    for (index, col) in table.columns.iter().enumerate() {
+        if col.nullable || row[index] != Value::Null {
+            continue;
+        }
+        if let Some(default_value) = col.get_default()? {
+            row[index] = default_value;
+        }
+    }
+    Ok(())
 }
 
 impl<T: Transaction> Executor<T> for InsertExecutor {
@@ -74,16 +129,22 @@ impl<T: Transaction> Executor<T> for InsertExecutor {
         let mut count = 0;
         //先取出表中的信息
         let table = txn.get_table_must(self.table_name.clone())?;
+        let mut next_id = None;
         // 将表达式转换为值
         for exprs in self.values {
-            let row = exprs.into_iter().map(|expr| {Value::from_expression(expr)}).collect::<Vec<_>>();
+            let row = exprs.iter().map(evaluate_const).collect::<LegendDBResult<Vec<_>>>()?;
             // 如果没有指定插入的列
-            let insert_row = if self.columns.is_empty() {
+            let mut insert_row = if self.columns.is_empty() {
                 pad_row(&table, &row)?
             } else {
                 // 指定了插入的列，需要对value信息进行整理
                 make_row(&table, &self.columns, &row)?
             };
+            fill_auto_increment(txn, &table, &mut insert_row, &mut next_id)?;
+            table.coerce_row(&mut insert_row);
+            if self.null_as_default {
+                fill_null_with_default(&table, &mut insert_row)?;
+            }
             // 检查列类型是否匹配
             for (index, col) in table.columns.iter().enumerate() {
                 // 如果列允许为空，则跳过
@@ -93,13 +154,15 @@ impl<T: Transaction> Executor<T> for InsertExecutor {
                 let row_data_type = insert_row[index].get_type().unwrap_or_else(|| Null);
                 // 如果列不允许为空，则检查值是否为空
                 if !col.nullable && row_data_type == Null {
-                    return Err(LegendDBError::Internal(format!("Column {} cannot be null", col.name)));
+                    return Err(LegendDBError::Internal(format!("Column {} of table {} is NOT NULL, cannot insert NULL", col.name, table.name)));
                 }
                 // 类型不匹配则报错
                 if col.data_type != row_data_type {
                     return Err(LegendDBError::Internal(format!("Column type mismatch: {}", col.name)));
                 }
             }
+            table.fit_row(&mut insert_row, self.truncate)?;
+            table.check_row(&insert_row)?;
             // 将整理后的值插入到表中
             txn.create_row(self.table_name.clone(), insert_row)?;
             count += 1;
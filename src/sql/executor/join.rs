@@ -1,72 +1,169 @@
 use crate::sql::engine::engine::Transaction;
 use crate::sql::executor::executor::{Executor, ResultSet};
-use crate::sql::parser::ast::{evaluate_expr, Expression};
-use crate::sql::types::Value;
+use std::cmp::Ordering;
+use crate::sql::parser::ast::{column_position, evaluate_expr, split_qualified, Expression, JoinType, JoinUsing, Operation};
+use crate::sql::types::{Row, Value};
 use crate::custom_error::{LegendDBError, LegendDBResult};
 
 pub struct NestLoopJoinExecutor<T: Transaction> {
     left: Box<dyn Executor<T>>,
     right: Box<dyn Executor<T>>,
     predicate: Option<Expression>,
-    router: bool
+    join_type: JoinType,
+    using: Option<JoinUsing>,
+    aliases: (Option<String>, Option<String>),
 }
 
 impl<T: Transaction>  NestLoopJoinExecutor<T> {
-    pub fn new(left: Box<dyn Executor<T>>, right: Box<dyn Executor<T>>, predicate: Option<Expression>, router: bool) -> Box<Self> {
+    pub fn new(left: Box<dyn Executor<T>>, right: Box<dyn Executor<T>>, predicate: Option<Expression>, join_type: JoinType, using: Option<JoinUsing>, aliases: (Option<String>, Option<String>)) -> Box<Self> {
         Box::new(
             Self {
                 left,
                 right,
                 predicate,
-                router,
+                join_type,
+                using,
+                aliases,
         }
     )
     }
 }
 
+// 行是否满足连接条件，USING 的列两边都不是 NULL 并且相等
+fn join_matches(predicate: &Option<Expression>, columns: &Vec<String>, row: &Row, using: &[(usize, usize)]) -> LegendDBResult<bool> {
+    for (l, r) in using {
+        if row[*l].partial_cmp(&row[*r]) != Some(Ordering::Equal) || row[*l] == Value::Null {
+            return Ok(false);
+        }
+    }
+    // 如果有条件，则进行条件判断
+    let Some(predicate) = predicate else { return Ok(true) };
+    // 条件中的列可以来自两边的表，在拼接后的行上计算
+    match evaluate_expr(predicate, columns, row, columns, row)? {
+        Value::Boolean(b) => Ok(b),
+        Value::Null => Ok(false),
+        _ => Err(LegendDBError::Internal("Unexpected Expression".into())),
+    }
+}
+
+// 内连接和左连接的条件是左边的列等于右边带表名的列时，右边只需要读取左边出现过的值
+// 返回左边的列在左边输出中的位置和右边的列名
+fn lookup_column(predicate: &Option<Expression>, join_type: &JoinType, right_alias: &Option<String>, lcols: &[String]) -> Option<(usize, String)> {
+    if !matches!(join_type, JoinType::Inner | JoinType::Left) {
+        return None;
+    }
+    let Some(Expression::Operation(Operation::Equal(l, r))) = predicate else {
+        return None;
+    };
+    let (Expression::Field(l), Expression::Field(r)) = (l.as_ref(), r.as_ref()) else {
+        return None;
+    };
+    let right_alias = right_alias.as_deref()?;
+    let right_column = |name: &str| split_qualified(name).filter(|(table, _)| *table == right_alias).map(|(_, col)| col.to_string());
+    match (right_column(l), right_column(r)) {
+        (None, Some(col)) => Some((column_position(lcols, l).ok()?, col)),
+        (Some(col), None) => Some((column_position(lcols, r).ok()?, col)),
+        _ => None,
+    }
+}
+
+// 去掉列名中的表名 t1.a -> a
+fn unqualified(column: &str) -> &str {
+    column.split_once('.').map_or(column, |(_, name)| name)
+}
+
+// USING 和 NATURAL JOIN 连接的列在两边的位置，右边的位置是在拼接后的行中的位置
+// 每一列在两边都必须正好出现一次，NATURAL JOIN 没有同名的列时等同于 cross join
+fn using_positions(using: &JoinUsing, lcols: &[String], rcols: &[String]) -> LegendDBResult<Vec<(usize, usize)>> {
+    let names = match using {
+        JoinUsing::Columns(names) => names.clone(),
+        JoinUsing::Natural => {
+            let mut names = Vec::new();
+            for col in lcols.iter().map(|c| unqualified(c)) {
+                if rcols.iter().any(|c| unqualified(c) == col) && !names.iter().any(|n| n == col) {
+                    names.push(col.to_string());
+                }
+            }
+            names
+        },
+    };
+    let position = |cols: &[String], name: &str, side: &str| {
+        let mut matched = cols.iter().enumerate().filter(|(_, c)| unqualified(c) == name).map(|(i, _)| i);
+        match (matched.next(), matched.next()) {
+            (Some(i), None) => Ok(i),
+            (Some(_), Some(_)) => Err(LegendDBError::Internal(format!("column {} in join is ambiguous on the {} side", name, side))),
+            _ => Err(LegendDBError::Internal(format!("column {} in USING does not exist on the {} side", name, side))),
+        }
+    };
+    names.iter()
+        .map(|name| Ok((position(lcols, name, "left")?, lcols.len() + position(rcols, name, "right")?)))
+        .collect()
+}
+
+// 列名加上表名 a -> t1.a，已经带有表名的列替换为新的表名
+fn qualify_columns(columns: Vec<String>, alias: &Option<String>) -> Vec<String> {
+    let Some(alias) = alias else { return columns };
+    columns.into_iter().map(|col| {
+        let name = col.split_once('.').map_or(col.as_str(), |(_, name)| name);
+        format!("{}.{}", alias, name)
+    }).collect()
+}
+
 impl<T: Transaction> Executor<T> for NestLoopJoinExecutor<T> {
     fn execute(self: Box<NestLoopJoinExecutor<T>>, txn: &mut T) -> LegendDBResult<ResultSet> {
         // 先执行左边的查询
         if let ResultSet::Scan { columns: lcols, rows: lrows } = self.left.execute(txn)? {
             let mut new_rows = Vec::new();
-            let mut new_columns = lcols.clone();
-            // 获取右边的查询
-            if let ResultSet::Scan { columns: rcols, rows: rrows } = self.right.execute(txn)? {
-                new_columns.extend(rcols.clone());
+            let mut new_columns = qualify_columns(lcols, &self.aliases.0);
+            // 获取右边的查询，能按左边的值读取时只读取需要的行，比如右边的主键
+            let right = match lookup_column(&self.predicate, &self.join_type, &self.aliases.1, &new_columns) {
+                Some((index, column)) => {
+                    let keys = lrows.iter().map(|row| row[index].clone()).collect();
+                    self.right.execute_lookup(txn, &column, keys)?
+                },
+                None => self.right.execute(txn)?,
+            };
+            if let ResultSet::Scan { columns: rcols, rows: rrows } = right {
+                let rcols_len = rcols.len();
+                let using = match &self.using {
+                    Some(using) => using_positions(using, &new_columns, &rcols)?,
+                    None => Vec::new(),
+                };
+                new_columns.extend(qualify_columns(rcols, &self.aliases.1));
+                let outer = matches!(self.join_type, JoinType::Left | JoinType::Right | JoinType::Full);
+                // 全连接需要记录右表中匹配过的行
+                let mut right_matched = vec![false; rrows.len()];
                for lrow in &lrows {
                    let mut matched = false;
-                   for rrow in &rrows {
+                   for (i, rrow) in rrows.iter().enumerate() {
                        let mut row = lrow.clone();
-                       // 如果有条件，则进行条件判断，如果满足条件，则加入到结果集中
-                       if let Some(predicate) = &self.predicate {
-                           // 左表中的一列与右表的一列进行比较
-                           match evaluate_expr(predicate, &lcols, lrow, &rcols, rrow)? {
-                               Value::Boolean(true) => {
-                                   // 满足条件，则加入到结果集中
-                                   row.extend(rrow.clone());
-                                   new_rows.push(row);
-                                   matched = true;
-                               },
-                               Value::Boolean(false) => {},
-                               Value::Null => {},
-                               _ => {
-                                   return Err(LegendDBError::Internal("Unexpected Expression".into()));
-                               }
-                           }
-                       } else {
-                           row.extend(rrow.clone());
-                           new_rows.push(row.clone());
+                       row.extend(rrow.clone());
+                       // 满足条件，则加入到结果集中
+                       if join_matches(&self.predicate, &new_columns, &row, &using)? {
+                           new_rows.push(row);
+                           matched = true;
+                           right_matched[i] = true;
                        }
                    }
-                   if self.router && !matched {
+                   if outer && !matched {
                        // 如果是outer模式，则只返回一条记录， 其他的需要填充空
                        let mut row = lrow.clone();
-                       for _ in 0..rrows[0].len() {
+                       // 右表可能没有数据，按右表的列数填充，避免越界
+                       for _ in 0..rcols_len {
                            row.push(Value::Null);
                        }
                        new_rows.push(row);
                    }
                }
+                // 全连接还要输出右表中没有匹配的行，左表的列填充空
+                if self.join_type == JoinType::Full {
+                    let lcols_len = new_columns.len() - rcols_len;
+                    for (rrow, _) in rrows.iter().zip(right_matched).filter(|(_, matched)| !matched) {
+                        let mut row = vec![Value::Null; lcols_len];
+                        row.extend(rrow.clone());
+                        new_rows.push(row);
+                    }
+                }
                 return Ok(ResultSet::Scan {
                     columns: new_columns,
                     rows: new_rows,
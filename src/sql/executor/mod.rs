@@ -7,5 +7,6 @@ pub mod delete;
 pub mod databases;
 pub mod join;
 pub mod agg;
+pub mod profile;
 
 
@@ -0,0 +1,384 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashSet};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+use crate::custom_error::LegendDBResult;
+use crate::sql::engine::engine::Transaction;
+use crate::sql::executor::executor::{Executor, ResultSet};
+use crate::sql::parser::ast::{collect_fields, quote_ident, split_qualified, Consts, Expression, FromItem, Operation, Statement};
+use crate::sql::parser::parser::Parser;
+use crate::sql::plan::node::Node;
+use crate::sql::schema::{Column, Table};
+use crate::sql::types::{DataType, Value};
+
+// explain analyze 的运行统计写到这个表中，可以直接用 SQL 查询
+// select query, operator, self_us from __query_stats order by self_us desc limit 10;
+// 只有常量不同的语句指纹相同，可以按指纹分组：select fingerprint, sum(self_us) from __query_stats group by fingerprint;
+pub const QUERY_STATS_TABLE: &str = "__query_stats";
+
+// 一个执行节点的运行统计
+#[derive(Debug, Clone)]
+pub struct OperatorStats {
+    // 节点在执行计划中的层级，根节点为 0
+    pub depth: usize,
+    // 执行计划中展示的节点信息
+    pub operator: String,
+    // 输出的行数
+    pub rows: usize,
+    // 包括子节点在内的耗时
+    pub total: Duration,
+}
+
+// 收集执行节点的运行统计，节点按先序排列
+#[derive(Debug, Clone, Default)]
+pub struct Profiler(Rc<RefCell<Vec<OperatorStats>>>);
+
+impl Profiler {
+    // 记录一个节点，返回节点的编号
+    pub fn add(&self, depth: usize, node: &Node) -> usize {
+        // 节点展示的第一行就是节点自己的信息，其余是子节点
+        let operator = node.to_string().lines().next().unwrap_or_default().trim().to_string();
+        let mut stats = self.0.borrow_mut();
+        stats.push(OperatorStats { depth, operator, rows: 0, total: Duration::ZERO });
+        stats.len() - 1
+    }
+
+    pub fn stats(&self) -> Vec<OperatorStats> {
+        self.0.borrow().clone()
+    }
+}
+
+// 节点自己的耗时，也就是去掉直接子节点的耗时
+pub fn self_time(stats: &[OperatorStats], index: usize) -> Duration {
+    let depth = stats[index].depth;
+    let children = stats[index + 1..].iter()
+        .take_while(|s| s.depth > depth)
+        .filter(|s| s.depth == depth + 1)
+        .map(|s| s.total)
+        .sum::<Duration>();
+    stats[index].total.saturating_sub(children)
+}
+
+// 记录执行的行数和耗时
+pub struct ProfileExecutor<T: Transaction> {
+    source: Box<dyn Executor<T>>,
+    profiler: Profiler,
+    index: usize,
+}
+
+impl<T: Transaction> ProfileExecutor<T> {
+    pub fn new(source: Box<dyn Executor<T>>, profiler: Profiler, index: usize) -> Box<Self> {
+        Box::new(Self { source, profiler, index })
+    }
+}
+
+// 记录节点的耗时和输出的行数
+fn record(profiler: &Profiler, index: usize, start: Instant, result: &ResultSet) {
+    let mut stats = profiler.0.borrow_mut();
+    stats[index].total = start.elapsed();
+    stats[index].rows = match result {
+        ResultSet::Scan { rows, .. } => rows.len(),
+        _ => 0,
+    };
+}
+
+impl<T: Transaction> Executor<T> for ProfileExecutor<T> {
+    fn execute(self: Box<Self>, txn: &mut T) -> LegendDBResult<ResultSet> {
+        let start = Instant::now();
+        let result = self.source.execute(txn)?;
+        record(&self.profiler, self.index, start, &result);
+        Ok(result)
+    }
+
+    fn execute_lookup(self: Box<Self>, txn: &mut T, column: &str, keys: Vec<Value>) -> LegendDBResult<ResultSet> {
+        let start = Instant::now();
+        let result = self.source.execute_lookup(txn, column, keys)?;
+        record(&self.profiler, self.index, start, &result);
+        Ok(result)
+    }
+}
+
+// 执行查询，展示每个节点的运行统计，并写到 __query_stats 表中
+pub struct ExplainAnalyzeExecutor {
+    source: Node,
+    query: String,
+    fingerprint: String,
+}
+
+impl ExplainAnalyzeExecutor {
+    pub fn new(source: Node, query: String, fingerprint: String) -> Box<Self> {
+        Box::new(Self { source, query, fingerprint })
+    }
+}
+
+impl<T: Transaction + 'static> Executor<T> for ExplainAnalyzeExecutor {
+    fn execute(self: Box<Self>, txn: &mut T) -> LegendDBResult<ResultSet> {
+        let profiler = Profiler::default();
+        <dyn Executor<T>>::build_profiled(self.source, &profiler).execute(txn)?;
+        let stats = profiler.stats();
+        let mut plan = String::new();
+        let mut values = Vec::new();
+        for (i, s) in stats.iter().enumerate() {
+            let self_us = self_time(&stats, i).as_micros() as i64;
+            plan.push_str(&format!(
+                "{}{} (actual rows={} total={:.3}ms self={:.3}ms)\n",
+                "  ".repeat(s.depth), s.operator, s.rows, s.total.as_secs_f64() * 1000.0, self_us as f64 / 1000.0
+            ));
+            values.push(vec![
+                Consts::String(self.query.clone()),
+                Consts::String(self.fingerprint.clone()),
+                Consts::Integer(s.depth as i64),
+                Consts::String(s.operator.clone()),
+                Consts::Integer(s.rows as i64),
+                Consts::Integer(s.total.as_micros() as i64),
+                Consts::Integer(self_us),
+            ].into_iter().map(Expression::from).collect());
+        }
+        match txn.get_table(QUERY_STATS_TABLE.to_string())? {
+            None => txn.create_table(query_stats_table())?,
+            // 之前版本创建的表没有指纹列，追加在最后，已有的行读取为 NULL
+            Some(mut table) if !table.columns.iter().any(|c| c.name == "fingerprint") => {
+                table.columns.push(fingerprint_column());
+                txn.update_table(table)?;
+            },
+            Some(_) => {},
+        }
+        let insert = Node::Insert {
+            table_name: QUERY_STATS_TABLE.to_string(),
+            columns: ["query", "fingerprint", "depth", "operator", "rows", "total_us", "self_us"].map(String::from).to_vec(),
+            values,
+            null_as_default: false,
+            truncate: false,
+        };
+        <dyn Executor<T>>::build(insert).execute(txn)?;
+        Ok(ResultSet::Explain { plan })
+    }
+}
+
+// 根据 __query_stats 中记录的查询推荐索引
+// 每次执行取根节点的一行，重新解析查询，收集 where 和 join 条件中每个表的等值列和范围列
+// 推荐的索引先放等值列，再放一个范围列，按查询的总耗时排序
+// 目前还不支持创建索引，输出的 CREATE INDEX 语句只是建议，在 note 列中说明
+pub struct IndexRecommendationsExecutor;
+
+const INDEX_RECOMMENDATION_NOTE: &str = "advisory only: CREATE INDEX is not supported yet";
+
+impl IndexRecommendationsExecutor {
+    pub fn new() -> Box<Self> {
+        Box::new(Self)
+    }
+}
+
+// 一个推荐的索引在记录的查询中出现的情况
+#[derive(Default)]
+struct Recommendation {
+    fingerprints: HashSet<String>,
+    executions: i64,
+    total_us: i64,
+}
+
+impl<T: Transaction> Executor<T> for IndexRecommendationsExecutor {
+    fn execute(self: Box<Self>, txn: &mut T) -> LegendDBResult<ResultSet> {
+        let columns = ["table_name", "columns", "statement", "note", "queries", "executions", "total_us"].map(String::from).to_vec();
+        let Some(stats) = txn.get_table(QUERY_STATS_TABLE.to_string())? else {
+            return Ok(ResultSet::Scan { columns, rows: Vec::new() });
+        };
+        let (query, depth, total) = (stats.get_column_index("query")?, stats.get_column_index("depth")?, stats.get_column_index("total_us")?);
+        // 之前版本创建的表没有指纹列，按查询分组
+        let fingerprint = stats.get_column_index("fingerprint").ok();
+        let mut recommendations: BTreeMap<(String, Vec<String>), Recommendation> = BTreeMap::new();
+        for row in txn.scan_table(QUERY_STATS_TABLE.to_string(), None)? {
+            let (Value::String(sql), Value::Integer(0)) = (&row[query], &row[depth]) else {
+                continue;
+            };
+            let Ok(stmt) = Parser::new(&format!("{};", sql)).parse() else {
+                continue;
+            };
+            let key = match fingerprint.map(|i| &row[i]) {
+                Some(Value::String(fingerprint)) => fingerprint.clone(),
+                _ => sql.clone(),
+            };
+            let total_us = match row[total] {
+                Value::Integer(us) => us,
+                _ => 0,
+            };
+            for (table, index_columns) in candidate_indexes(txn, &stmt)? {
+                let recommendation = recommendations.entry((table, index_columns)).or_default();
+                recommendation.fingerprints.insert(key.clone());
+                recommendation.executions += 1;
+                recommendation.total_us += total_us;
+            }
+        }
+        let mut recommendations = recommendations.into_iter().collect::<Vec<_>>();
+        recommendations.sort_by(|(_, a), (_, b)| b.total_us.cmp(&a.total_us).then(b.executions.cmp(&a.executions)));
+        let rows = recommendations.into_iter().map(|((table, index_columns), r)| {
+            let statement = format!(
+                "CREATE INDEX {} ON {} ({})",
+                quote_ident(&format!("idx_{}_{}", table, index_columns.join("_"))),
+                quote_ident(&table),
+                index_columns.iter().map(|c| quote_ident(c)).collect::<Vec<_>>().join(", ")
+            );
+            vec![
+                Value::String(table),
+                Value::String(index_columns.join(", ")),
+                Value::String(statement),
+                Value::String(INDEX_RECOMMENDATION_NOTE.to_string()),
+                Value::Integer(r.fingerprints.len() as i64),
+                Value::Integer(r.executions),
+                Value::Integer(r.total_us),
+            ]
+        }).collect();
+        Ok(ResultSet::Scan { columns, rows })
+    }
+}
+
+// 一条语句中每个表可以使用的索引，等值列在前，最多一个范围列在后
+// 主键已经是存储的 key，按主键等值查找或者只有主键的范围时跳过这个表
+fn candidate_indexes<T: Transaction>(txn: &T, stmt: &Statement) -> LegendDBResult<Vec<(String, Vec<String>)>> {
+    // from 中的表以及别名，和 where、join 的条件
+    let mut tables = Vec::new();
+    let mut conditions = Vec::new();
+    match stmt {
+        Statement::Select { from, where_clause, .. } => {
+            collect_tables(from, &mut tables, &mut conditions);
+            conditions.extend(where_clause.clone());
+        },
+        Statement::Update { table_name, where_clause, .. } | Statement::Delete { table_name, where_clause } => {
+            tables.push((table_name.clone(), table_name.clone()));
+            conditions.extend(where_clause.clone());
+        },
+        _ => {},
+    }
+    let mut schemas = Vec::new();
+    for (name, alias) in tables {
+        if let Some(table) = txn.get_table(name)? {
+            schemas.push((alias, table));
+        }
+    }
+    // 列所属的表，不带表名的列必须只在一个表中出现
+    let resolve = |name: &str| -> Option<(usize, String)> {
+        let (table, col) = match split_qualified(name) {
+            Some((table, col)) => (Some(table), col),
+            None => (None, name),
+        };
+        let mut matched = schemas.iter().enumerate()
+            .filter(|(_, (alias, schema))| table.is_none_or(|t| t == alias) && schema.get_column_index(col).is_ok());
+        match (matched.next(), matched.next()) {
+            (Some((i, _)), None) => Some((i, col.to_string())),
+            _ => None,
+        }
+    };
+    let mut predicates = Vec::new();
+    for condition in conditions {
+        split_conjunction(condition, &mut predicates);
+    }
+    let mut equals: Vec<Vec<String>> = vec![Vec::new(); schemas.len()];
+    let mut ranges: Vec<Vec<String>> = vec![Vec::new(); schemas.len()];
+    for predicate in predicates {
+        let Expression::Operation(op) = predicate else { continue };
+        let (l, r, equal) = match op {
+            Operation::Equal(l, r) | Operation::In(l, r) => (l, r, true),
+            Operation::GreaterThan(l, r) | Operation::LessThan(l, r)
+            | Operation::GreaterThanOrEqual(l, r) | Operation::LessThanOrEqual(l, r) => (l, r, false),
+            // 前缀匹配可以按范围查找
+            Operation::Like(l, r) if matches!(r.as_ref(), Expression::Consts(Consts::String(p)) if !p.starts_with(['%', '_'])) => (l, r, false),
+            _ => continue,
+        };
+        let mut sides = Vec::new();
+        for (a, b) in [(&l, &r), (&r, &l)] {
+            let mut fields = Vec::new();
+            collect_fields(b, &mut fields);
+            // 另一边是常量，或者是另一个表的列（连接条件）
+            if let Expression::Field(name) = a.as_ref()
+                && let Some((i, col)) = resolve(name)
+                && fields.iter().all(|f| resolve(f).is_some_and(|(j, _)| j != i)) {
+                sides.push((i, col));
+            }
+        }
+        for (i, col) in sides {
+            let list = if equal { &mut equals[i] } else { &mut ranges[i] };
+            if !list.contains(&col) {
+                list.push(col);
+            }
+        }
+    }
+    let mut candidates = Vec::new();
+    for (i, (_, schema)) in schemas.iter().enumerate() {
+        let mut index_columns = equals[i].clone();
+        if let Some(col) = ranges[i].iter().find(|c| !index_columns.contains(c)) {
+            index_columns.push(col.clone());
+        }
+        let Some(first) = index_columns.first() else { continue };
+        if schema.get_primary_key_name().is_ok_and(|pk| pk == first || equals[i].iter().any(|c| c == pk)) {
+            continue;
+        }
+        candidates.push((schema.name.clone(), index_columns));
+    }
+    Ok(candidates)
+}
+
+// from 中的单表和 join 的条件，子查询跳过
+fn collect_tables(from: &FromItem, tables: &mut Vec<(String, String)>, conditions: &mut Vec<Expression>) {
+    match from {
+        FromItem::Table { name, alias } => tables.push((name.clone(), alias.clone().unwrap_or(name.clone()))),
+        FromItem::SubQuery { .. } => {},
+        FromItem::Join { left, right, predicate, .. } => {
+            collect_tables(left, tables, conditions);
+            collect_tables(right, tables, conditions);
+            conditions.extend(predicate.clone());
+        },
+    }
+}
+
+// 按 and 拆分条件
+fn split_conjunction(expr: Expression, predicates: &mut Vec<Expression>) {
+    match expr {
+        Expression::Operation(Operation::And(l, r)) => {
+            split_conjunction(*l, predicates);
+            split_conjunction(*r, predicates);
+        },
+        expr => predicates.push(expr),
+    }
+}
+
+fn stats_column(name: &str, data_type: DataType) -> Column {
+    Column {
+        name: name.to_string(),
+        data_type,
+        max_length: None,
+        int_width: None,
+        nullable: false,
+        default_value: None,
+        default_expr: None,
+        is_primary_key: false,
+        unique: false,
+        auto_increment: false,
+    }
+}
+
+// 语句的指纹，后来新增的列，允许为 NULL
+fn fingerprint_column() -> Column {
+    Column { nullable: true, default_value: Some(Value::Null), ..stats_column("fingerprint", DataType::String) }
+}
+
+// __query_stats 的表结构，每个执行节点一行
+fn query_stats_table() -> Table {
+    Table {
+        name: QUERY_STATS_TABLE.to_string(),
+        columns: vec![
+            Column { is_primary_key: true, auto_increment: true, ..stats_column("id", DataType::Integer) },
+            // 执行的时间，UTC yyyy-mm-dd hh:mm:ss
+            Column { default_expr: Some(Expression::Function("now".to_string(), String::new())), ..stats_column("executed_at", DataType::String) },
+            stats_column("query", DataType::String),
+            stats_column("depth", DataType::Integer),
+            stats_column("operator", DataType::String),
+            stats_column("rows", DataType::Integer),
+            // 包括子节点在内的耗时，单位微秒
+            stats_column("total_us", DataType::Integer),
+            // 节点自己的耗时，单位微秒
+            stats_column("self_us", DataType::Integer),
+            fingerprint_column(),
+        ],
+        constraints: vec![],
+    }
+}
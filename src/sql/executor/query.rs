@@ -1,50 +1,124 @@
 use std::cmp::Ordering;
-use std::collections::HashMap;
 use crate::sql::engine::engine::Transaction;
-use crate::sql::executor::executor::{Executor, ResultSet};
-use crate::sql::parser::ast::{evaluate_expr, Expression, OrderDirection};
+use crate::sql::executor::executor::{materialize_subqueries, Executor, ResultSet};
+use crate::sql::parser::ast::{column_position, evaluate_expr, Consts, Expression, Operation, OrderDirection};
 use crate::custom_error::{LegendDBError, LegendDBResult};
-use crate::sql::types::Value;
+use crate::sql::plan::node::Node;
+use crate::sql::schema::Table;
+use crate::sql::types::{DataType, Value};
 
 pub struct ScanExecutor {
     table_name: String,
-    filter: Option<Vec<Expression>>
+    filter: Option<Expression>,
+    columns: Option<Vec<String>>,
 }
 
 impl ScanExecutor {
-    pub fn new(table_name: String, filter: Option<Vec<Expression>>) -> Box<Self> {
+    pub fn new(table_name: String, filter: Option<Expression>, columns: Option<Vec<String>>) -> Box<Self> {
         Box::new(Self {
             table_name,
-            filter
+            filter,
+            columns,
         })
     }
 }
 
-impl<T: Transaction> Executor<T> for ScanExecutor {
-    fn execute(self: Box<Self>, txn: &mut T) -> LegendDBResult<ResultSet> {
+// 可以按主键读取的值，列不是主键或者有值的类型和主键不同时返回 None，NULL 不会和任何行相等
+// 浮点数的 0.0 和 -0.0 相等但编码不同，不按主键读取
+fn lookup_keys(table: &Table, column: &str, keys: Vec<Value>) -> Option<Vec<Value>> {
+    let pk = table.columns.iter().find(|c| c.is_primary_key && c.name == column)?;
+    if pk.data_type == DataType::Float {
+        return None;
+    }
+    let keys = keys.into_iter().filter(|key| *key != Value::Null).collect::<Vec<_>>();
+    keys.iter().all(|key| key.get_type().as_ref() == Some(&pk.data_type)).then_some(keys)
+}
+
+impl ScanExecutor {
+    // keys 不为 None 时只读取 column 的值在 keys 中的行
+    fn scan<T: Transaction + 'static>(self, txn: &mut T, lookup: Option<(&str, Vec<Value>)>) -> LegendDBResult<ResultSet> {
         let table = txn.get_table_must(self.table_name.clone())?;
-        let rows = txn.scan_table(self.table_name.clone(), self.filter)?;
-        Ok(ResultSet::Scan { 
-            columns: table.columns.into_iter().map(|c| c.name).collect(), 
-            rows
-        }
-        )
+        let filter = self.filter.map(|filter| materialize_subqueries(filter, txn)).transpose()?;
+        // 主键 IN 子查询时按主键批量读取，比如 id in (select ...)
+        let lookup = lookup.or_else(|| match &filter {
+            Some(Expression::Operation(Operation::In(left, right))) => match (left.as_ref(), right.as_ref()) {
+                (Expression::Field(column), Expression::Materialized(rows)) => rows.iter()
+                    .map(|row| match row.as_slice() {
+                        [value] => Some(value.clone()),
+                        _ => None,
+                    })
+                    .collect::<Option<Vec<_>>>()
+                    .map(|keys| (column.as_str(), keys)),
+                _ => None,
+            },
+            _ => None,
+        });
+        let keys = lookup.and_then(|(column, keys)| lookup_keys(&table, column, keys));
+        // 只返回需要的列，其余的列不解码
+        let (columns, rows) = match (keys, self.columns) {
+            (Some(keys), columns) => {
+                let table_cols = table.columns.iter().map(|c| c.name.clone()).collect::<Vec<_>>();
+                let mut rows = Vec::new();
+                for row in txn.read_rows(&table, keys)? {
+                    let matched = match &filter {
+                        None => true,
+                        Some(filter) => match evaluate_expr(filter, &table_cols, &row, &table_cols, &row)? {
+                            Value::Boolean(b) => b,
+                            Value::Null => false,
+                            _ => return Err(LegendDBError::Internal("filter is not match".to_string())),
+                        },
+                    };
+                    if matched {
+                        rows.push(row);
+                    }
+                }
+                match columns {
+                    Some(columns) => {
+                        let indexes = columns.iter().map(|c| table.get_column_index(c)).collect::<LegendDBResult<Vec<_>>>()?;
+                        let rows = rows.into_iter().map(|row| indexes.iter().map(|&i| row[i].clone()).collect()).collect();
+                        (columns, rows)
+                    },
+                    None => (table_cols, rows),
+                }
+            },
+            (None, Some(columns)) => {
+                let rows = txn.scan_table_columns(self.table_name, filter, &columns)?;
+                (columns, rows)
+            },
+            (None, None) => {
+                let rows = txn.scan_table(self.table_name.clone(), filter)?;
+                (table.columns.iter().map(|c| c.name.clone()).collect(), rows)
+            },
+        };
+        Ok(ResultSet::Scan { columns, rows })
     }
 }
 
+impl<T: Transaction + 'static> Executor<T> for ScanExecutor {
+    fn execute(self: Box<Self>, txn: &mut T) -> LegendDBResult<ResultSet> {
+        (*self).scan(txn, None)
+    }
 
-// 排序
+    fn execute_lookup(self: Box<Self>, txn: &mut T, column: &str, keys: Vec<Value>) -> LegendDBResult<ResultSet> {
+        (*self).scan(txn, Some((column, keys)))
+    }
+}
+
+
+// 排序，排序值相同的行保持输入的顺序
 pub struct OrderExecutor<T: Transaction> {
     source: Box<dyn Executor<T>>,
-    order_by: Vec<(String, OrderDirection)>,
+    order_by: Vec<(Expression, OrderDirection)>,
+    tiebreak: Option<Vec<(String, Option<String>)>>,
 }
 
 impl<T: Transaction> OrderExecutor<T> {
-    pub(crate) fn new(source: Box<dyn Executor<T>>, order_by: Vec<(String, OrderDirection)>) -> Box<Self> {
+    pub(crate) fn new(source: Box<dyn Executor<T>>, order_by: Vec<(Expression, OrderDirection)>, tiebreak: Option<Vec<(String, Option<String>)>>) -> Box<Self> {
         Box::new(
             Self {
                 source,
                 order_by,
+                tiebreak,
             }
         )
     }
@@ -53,31 +127,54 @@ impl<T: Transaction> OrderExecutor<T> {
 impl<T: Transaction> Executor<T> for OrderExecutor<T> {
     fn execute(self: Box<Self>, txn: &mut T) -> LegendDBResult<ResultSet> {
         match self.source.execute(txn)? { 
-            ResultSet::Scan { columns, mut rows} => {
-                // order by 后面的顺序可能跟 columns顺序不一致，所以需要找到列表中的列对应的位置
-                let mut order_col_index = HashMap::new();
-                for (i, (col_name, _)) in self.order_by.iter().enumerate() {
-                    // 从columns中找到对应的position
-                    match columns.iter().position(|c| c == col_name) {
-                        Some(pos) => {
-                            order_col_index.insert(i, pos);
-                        },
-                        None => return Err(LegendDBError::Internal(format!("Column {} not found in table", col_name)))
+            ResultSet::Scan { columns, rows} => {
+                // select * 的序号对应结果中的列，比如 order by 2 -> 第二列
+                let order_by = self.order_by.iter().map(|(expr, direction)| Ok((match expr {
+                    Expression::Consts(Consts::Integer(n)) => {
+                        let col = usize::try_from(*n).ok()
+                            .and_then(|n| n.checked_sub(1))
+                            .and_then(|i| columns.get(i))
+                            .ok_or(LegendDBError::Internal(format!("ORDER BY position {} is not in select list", n)))?;
+                        Expression::Field(col.clone())
+                    },
+                    expr => expr.clone(),
+                }, direction.clone()))).collect::<LegendDBResult<Vec<_>>>()?;
+                // 先计算每一行的排序值，再按排序值排序
+                let mut rows = rows.into_iter().map(|row| {
+                    let keys = order_by.iter()
+                        .map(|(expr, _)| evaluate_expr(expr, &columns, &row, &columns, &row))
+                        .collect::<LegendDBResult<Vec<_>>>()?;
+                    Ok((keys, row))
+                }).collect::<LegendDBResult<Vec<_>>>()?;
+                // 值相同时比较的列，先比较主键，再比较整行，被裁剪掉的主键跳过
+                let mut tiebreak = Vec::new();
+                if let Some(tables) = &self.tiebreak {
+                    for (table, prefix) in tables {
+                        let pk = txn.get_table_must(table.clone())?.get_primary_key_name()?.to_string();
+                        let pk = prefix.as_ref().map_or(pk.clone(), |prefix| format!("{}.{}", prefix, pk));
+                        tiebreak.extend(columns.iter().position(|c| *c == pk));
                     }
+                    tiebreak.extend(0..columns.len());
                 }
-                rows.sort_by(|col1, col2| {
-                    for (i, (_, direction)) in self.order_by.iter().enumerate() {
-                        let col_index = order_col_index.get(&i).unwrap();
-                        let x = &col1[*col_index];
-                        let y = &col2[*col_index];
-                        match x.partial_cmp(y) {
+                // sort_by 是稳定排序，排序值相同的行保持下层扫描输出的顺序，降序时也不会反转
+                // 之后加入并行扫描、top-k 时也要保证这一点，不能换成 sort_unstable_by
+                rows.sort_by(|(keys1, col1), (keys2, col2)| {
+                    for (i, (_, direction)) in order_by.iter().enumerate() {
+                        match keys1[i].partial_cmp(&keys2[i]) {
                             Some(Ordering::Equal) => {},
                             Some(o) => return if *direction == OrderDirection::Asc { o } else { o.reverse() },
                             None => {}
                         }
                     }
+                    for &index in tiebreak.iter() {
+                        match col1[index].partial_cmp(&col2[index]) {
+                            Some(Ordering::Equal) | None => {},
+                            Some(o) => return o,
+                        }
+                    }
                     Ordering::Equal
                 });
+                let rows = rows.into_iter().map(|(_, row)| row).collect();
                 Ok(ResultSet::Scan { columns, rows })
             },
             _ => Err(LegendDBError::Internal("Unexpected result set".into()))
@@ -149,7 +246,7 @@ impl<T: Transaction> Executor<T> for OffsetExecutor<T> {
                 // 返回迭代器：
                 // drain 方法返回一个 Drain 迭代器，允许你遍历被移除的元素。
                 // 直接修改 rows 向量，移除元素后，rows 的长度会减少， 由于是原地操作，性能较高，不需要额外的内存分配。
-                rows.drain(..self.offset);
+                rows.drain(..self.offset.min(rows.len()));
                 // 等效于 rows.iter().skip(self.offset).collect(); 但是不会改变原始向量， 而是返回一个新的向量。
                 // 需要额外的内存分配来存储结果， 性能相对 drain 较低
                 Ok(ResultSet::Scan { columns, rows })
@@ -180,23 +277,19 @@ impl<T: Transaction> Executor<T> for ProjectionExecutor<T> {
     fn execute(self: Box<Self>, txn: &mut T) -> LegendDBResult<ResultSet> {
         match self.source.execute(txn)? {
             ResultSet::Scan { columns, rows} => {
-                let mut selected_columns = Vec::new();
                 let mut new_columns = Vec::new();
-                for (col, alias) in self.columns {
+                for (col, alias) in self.columns.iter() {
                     if let Expression::Field(col_name) = col {
-                        let pos = match columns.iter().position(|c| *c == col_name) {
-                            Some(pos) => pos,
-                            None => return Err(LegendDBError::Internal(format!("Column {} not found in table", col_name)))
-                        };
-                        selected_columns.push(pos);
-                        new_columns.push(if alias.is_some() { alias.clone().unwrap() } else { col_name });
+                        column_position(&columns, col_name)?;
                     }
+                    // 没有别名时，列名就是表达式本身
+                    new_columns.push(alias.clone().unwrap_or_else(|| col.to_string()));
                 }
                 let mut new_row = Vec::new();
                 for row in rows.into_iter() {
                     let mut new_columns = Vec::new();
-                    for i in selected_columns.iter() {
-                        new_columns.push(row[*i].clone())
+                    for (expr, _) in self.columns.iter() {
+                        new_columns.push(evaluate_expr(expr, &columns, &row, &columns, &row)?);
                     }
                     new_row.push(new_columns);
                 }
@@ -223,13 +316,14 @@ impl<T: Transaction> FilterExecutor<T> {
     }
 }
 
-impl<T: Transaction> Executor<T> for FilterExecutor<T> {
+impl<T: Transaction + 'static> Executor<T> for FilterExecutor<T> {
     fn execute(self: Box<Self>, txn: &mut T) -> LegendDBResult<ResultSet> {
         match self.source.execute(txn)? { 
             ResultSet::Scan {columns, rows} => {
+                let predicate = materialize_subqueries(self.predicate, txn)?;
                 let mut new_rows = Vec::new();
                 for row in rows {
-                    match evaluate_expr(&self.predicate, &columns, &row, &columns, &row)? { 
+                    match evaluate_expr(&predicate, &columns, &row, &columns, &row)? { 
                         Value::Null => {},
                         Value::Boolean(true) => {
                             new_rows.push(row);
@@ -247,4 +341,34 @@ impl<T: Transaction> Executor<T> for FilterExecutor<T> {
             }
         }
     }
-}
\ No newline at end of file
+}
+// 展示执行计划，不执行
+// 修改数据的语句额外展示估计影响的行数，也就是当前满足条件的行数，执行时可能因为并发的修改而不同
+pub struct ExplainExecutor {
+    source: Node,
+}
+
+impl ExplainExecutor {
+    pub fn new(source: Node) -> Box<Self> {
+        Box::new(Self { source })
+    }
+}
+
+impl<T: Transaction + 'static> Executor<T> for ExplainExecutor {
+    fn execute(self: Box<Self>, txn: &mut T) -> LegendDBResult<ResultSet> {
+        let mut plan = self.source.to_string();
+        let affected = match self.source {
+            Node::Insert { values, .. } => Some(values.len()),
+            // 只执行扫描和过滤，不执行修改，扫描同样带上当前用户的行级安全策略
+            Node::Update { source, .. } | Node::Delete { source, .. } => match <dyn Executor<T>>::build(*source).execute(txn)? {
+                ResultSet::Scan { rows, .. } => Some(rows.len()),
+                _ => None,
+            },
+            _ => None,
+        };
+        if let Some(affected) = affected {
+            plan.push_str(&format!("Estimated affected rows: {}\n", affected));
+        }
+        Ok(ResultSet::Explain { plan })
+    }
+}
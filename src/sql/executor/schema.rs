@@ -1,7 +1,10 @@
 use crate::sql::engine::engine::Transaction;
 use crate::sql::executor::executor::{Executor, ResultSet};
+use crate::sql::parser::ast::ConstraintKind;
+use crate::sql::plan::node::AlterTableOperation;
 use crate::sql::schema::Table;
-use crate::custom_error::LegendDBResult;
+use crate::sql::types::Value;
+use crate::custom_error::{LegendDBError, LegendDBResult};
 
 pub struct CreateTableExecutor {
     schema: Table,
@@ -17,30 +20,209 @@ impl CreateTableExecutor {
 }
 
 impl<T: Transaction> Executor<T> for CreateTableExecutor {
-    fn execute(self: Box<Self>, txn: &mut T) -> LegendDBResult<ResultSet> {
+    fn execute(mut self: Box<Self>, txn: &mut T) -> LegendDBResult<ResultSet> {
         let table_name = self.schema.name.clone();
+        resolve_foreign_keys(txn, &mut self.schema)?;
         txn.create_table(self.schema)?;
         Ok(ResultSet::CreateTable {table_name})
     }
 }
 
+// 外键引用的表和列必须存在，并且是主键或者唯一约束，没有指定引用的列时引用主键
+// 引用自身的外键使用正在创建的表结构
+fn resolve_foreign_keys<T: Transaction>(txn: &T, schema: &mut Table) -> LegendDBResult<()> {
+    let table = schema.clone();
+    for constraint in schema.constraints.iter_mut() {
+        let ConstraintKind::ForeignKey(fk) = &mut constraint.kind else { continue };
+        let ref_table = match fk.ref_table == table.name {
+            true => table.clone(),
+            false => txn.get_table_must(fk.ref_table.clone())?,
+        };
+        let primary_key = vec![ref_table.get_primary_key_name()?.to_string()];
+        if fk.ref_columns.is_empty() {
+            fk.ref_columns = primary_key.clone();
+        }
+        if fk.ref_columns.len() != fk.columns.len() {
+            return Err(LegendDBError::Internal(format!(
+                "foreign key {} has {} columns but references {} columns", constraint.name, fk.columns.len(), fk.ref_columns.len()
+            )));
+        }
+        let is_key = fk.ref_columns == primary_key || ref_table.unique_keys().iter()
+            .any(|(_, positions)| positions.iter().map(|&i| &ref_table.columns[i].name).eq(fk.ref_columns.iter()));
+        if !is_key {
+            return Err(LegendDBError::Internal(format!(
+                "columns ({}) of table {} referenced by foreign key {} must be a primary key or unique", fk.ref_columns.join(", "), ref_table.name, constraint.name
+            )));
+        }
+        for (col, ref_col) in fk.columns.iter().zip(fk.ref_columns.iter()) {
+            let column = &table.columns[table.get_column_index(col)?];
+            let ref_column = &ref_table.columns[ref_table.get_column_index(ref_col)?];
+            if column.data_type != ref_column.data_type {
+                return Err(LegendDBError::Internal(format!(
+                    "foreign key {} column {} of type {} cannot reference {}.{} of type {}",
+                    constraint.name, col, column.data_type, ref_table.name, ref_col, ref_column.data_type
+                )));
+            }
+        }
+    }
+    Ok(())
+}
+
 pub struct DropTableExecutor {
     table_name: String,
+    if_exists: bool,
 }
 
 impl DropTableExecutor {
-    pub fn new(table_name: String) -> Box<Self> {
+    pub fn new(table_name: String, if_exists: bool) -> Box<Self> {
         Box::new(Self {
             table_name,
+            if_exists,
         })
     }
 }
 
 impl<T: Transaction> Executor<T> for DropTableExecutor {
     fn execute(self: Box<Self>, txn: &mut T) -> LegendDBResult<ResultSet> {
+        // drop table if exists，表不存在时直接返回
+        if self.if_exists && txn.get_table(self.table_name.clone())?.is_none() {
+            return Ok(ResultSet::DropTable {
+                table_name: self.table_name,
+            });
+        }
         txn.drop_table(&self.table_name)?;
         Ok(ResultSet::DropTable {
             table_name: self.table_name,
         })
     }
-}
\ No newline at end of file
+}
+
+pub struct AlterTableExecutor {
+    table_name: String,
+    operation: AlterTableOperation,
+}
+
+impl AlterTableExecutor {
+    pub fn new(table_name: String, operation: AlterTableOperation) -> Box<Self> {
+        Box::new(Self {
+            table_name,
+            operation,
+        })
+    }
+}
+
+impl<T: Transaction> Executor<T> for AlterTableExecutor {
+    fn execute(self: Box<Self>, txn: &mut T) -> LegendDBResult<ResultSet> {
+        let mut table = txn.get_table_must(self.table_name.clone())?;
+        match self.operation {
+            // 新增的列追加在最后，已有的数据在读取时使用默认值填充，不需要重写
+            AlterTableOperation::AddColumn(column) => {
+                if table.columns.iter().any(|c| c.name == column.name) {
+                    return Err(LegendDBError::Internal(format!("column {} already exists in table {}", column.name, table.name)));
+                }
+                if column.default_value.is_none() && !txn.scan_table(self.table_name.clone(), None)?.is_empty() {
+                    // 插入时才求值的默认值不能用来填充已有的数据
+                    if let Some(expr) = &column.default_expr {
+                        return Err(LegendDBError::Internal(format!("default {} of column {} is evaluated on insert, cannot be added to non-empty table {}", expr, column.name, table.name)));
+                    }
+                    return Err(LegendDBError::Internal(format!("column {} is NOT NULL and has no default value, cannot be added to non-empty table {}", column.name, table.name)));
+                }
+                let unique = column.unique;
+                table.columns.push(column);
+                table.validate()?;
+                txn.update_table(table.clone())?;
+                // 已有的数据使用相同的默认值，多于一行时会违反唯一约束
+                if unique {
+                    txn.rebuild_unique_keys(&table)?;
+                }
+            },
+            // 删除和修改列之后，需要用新的表结构重写已有的数据
+            AlterTableOperation::DropColumn(column_name) => {
+                let rows = txn.scan_table(self.table_name.clone(), None)?;
+                let index = table.get_column_index(&column_name)?;
+                if table.columns[index].is_primary_key {
+                    return Err(LegendDBError::Internal(format!("cannot drop primary key column {} of table {}", column_name, table.name)));
+                }
+                table.columns.remove(index);
+                table.validate()?;
+                txn.update_table(table.clone())?;
+                for mut row in rows {
+                    let id = table.get_primary_key(&row)?;
+                    row.remove(index);
+                    txn.rewrite_row(&table, &id, row)?;
+                }
+                txn.rebuild_unique_keys(&table)?;
+            },
+            AlterTableOperation::ModifyColumn(mut column) => {
+                let index = table.get_column_index(&column.name)?;
+                let old_column = &table.columns[index];
+                // 主键不能通过 modify 转移到其他列上
+                if column.is_primary_key && !old_column.is_primary_key {
+                    return Err(LegendDBError::Internal(format!("cannot change primary key of table {} to column {}", table.name, column.name)));
+                }
+                if old_column.is_primary_key {
+                    column.is_primary_key = true;
+                    column.nullable = false;
+                    if column.default_value == Some(Value::Null) {
+                        column.default_value = None;
+                    }
+                }
+                // 先校验所有的数据都能转换为新的类型，再修改表结构
+                let rows = txn.scan_table(self.table_name.clone(), None)?;
+                let mut new_rows = Vec::with_capacity(rows.len());
+                for mut row in rows {
+                    let id = table.get_primary_key(&row)?;
+                    let mut value = row[index].cast(&column.data_type).ok_or(LegendDBError::Internal(format!(
+                        "value {} of column {} cannot be converted to {:?}", row[index], column.name, column.data_type
+                    )))?;
+                    column.fit(&mut value, false)?;
+                    if value == Value::Null && !column.nullable {
+                        return Err(LegendDBError::Internal(format!("column {} contains NULL values, cannot be NOT NULL", column.name)));
+                    }
+                    row[index] = value;
+                    new_rows.push((id, row));
+                }
+                table.columns[index] = column;
+                table.validate()?;
+                txn.update_table(table.clone())?;
+                for (id, row) in new_rows {
+                    txn.rewrite_row(&table, &id, row)?;
+                }
+                txn.rebuild_unique_keys(&table)?;
+            },
+        }
+        Ok(ResultSet::AlterTable {
+            table_name: self.table_name,
+        })
+    }
+}
+
+pub struct CheckTableExecutor {
+    table_name: String,
+}
+
+impl CheckTableExecutor {
+    pub fn new(table_name: String) -> Box<Self> {
+        Box::new(Self {
+            table_name,
+        })
+    }
+}
+
+impl<T: Transaction> Executor<T> for CheckTableExecutor {
+    fn execute(self: Box<Self>, txn: &mut T) -> LegendDBResult<ResultSet> {
+        let problems = txn.check_table(&self.table_name)?;
+        // 每个问题一行，最后一行是检查的结果
+        let status = if problems.is_empty() { "OK" } else { "Corrupt" };
+        let mut rows = problems.into_iter()
+            .map(|p| vec![Value::String(self.table_name.clone()), Value::String("error".to_string()), Value::String(p)])
+            .collect::<Vec<_>>();
+        // 行数据没有校验和，无法发现值被改成另一个合法的值，这一项没有检查
+        rows.push(vec![Value::String(self.table_name.clone()), Value::String("note".to_string()), Value::String("checksums not checked: rows are stored without checksums".to_string())]);
+        rows.push(vec![Value::String(self.table_name.clone()), Value::String("status".to_string()), Value::String(status.to_string())]);
+        Ok(ResultSet::Scan {
+            columns: vec!["table".to_string(), "msg_type".to_string(), "msg_text".to_string()],
+            rows,
+        })
+    }
+}
@@ -1,22 +1,24 @@
 use std::collections::BTreeMap;
 use crate::sql::engine::engine::Transaction;
 use crate::sql::executor::executor::{Executor, ResultSet};
-use crate::sql::parser::ast::Expression;
-use crate::sql::types::Value;
+use crate::sql::parser::ast::{evaluate_expr, Expression};
 use crate::custom_error::{LegendDBError, LegendDBResult};
 
 pub struct UpdateExecutor<T: Transaction> {
     table_name: String,
     source: Box<dyn Executor<T>>,
     columns: BTreeMap<String, Expression>,
+    // 超过列的最大长度的字符串是否截断
+    truncate: bool,
 }
 
 impl<T: Transaction> UpdateExecutor<T> {
-    pub(crate) fn new(table_name: String, source: Box<dyn Executor<T>>, columns: BTreeMap<String, Expression>) -> Box<Self> {
+    pub(crate) fn new(table_name: String, source: Box<dyn Executor<T>>, columns: BTreeMap<String, Expression>, truncate: bool) -> Box<Self> {
         Box::new(Self {
             table_name,
             source,
             columns,
+            truncate,
         })
     }
 }
@@ -34,10 +36,13 @@ impl<T: Transaction> Executor<T> for UpdateExecutor<T> {
                     let pk = table.get_primary_key(&row)?;
                     for (index, col) in columns.iter().enumerate() {
                         if let Some(expr) = self.columns.get(col) {
-                            // 更新列的值
-                            new_row[index] = Value::from_expression(expr.clone());
+                            // 更新列的值，表达式中可以引用原来的列
+                            new_row[index] = evaluate_expr(expr, &columns, &row, &columns, &row)?;
                         }
                     }
+                    table.coerce_row(&mut new_row);
+                    table.fit_row(&mut new_row, self.truncate)?;
+                    table.check_row(&new_row)?;
                     // 执行更新操作
                     // 如果有主键更新，则删除原来的数据，新增一条新的数据
                     // 否则就根据table_name + primary key ==>更新数据
@@ -1,85 +1,499 @@
+use std::cmp::Ordering;
 use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::time::{SystemTime, UNIX_EPOCH};
+use bincode::{Decode, Encode};
+use serde::{Deserialize, Serialize};
 use crate::custom_error::{LegendDBError, LegendDBResult};
-use crate::sql::types::{DataType, Value};
+use crate::sql::parser::lexer::{Keyword, Lexer, Token};
+use crate::sql::types::{format_hex, DataType, IntWidth, Row, Value};
+use crate::sql::types::datetime::{self, Interval};
+use crate::sql::types::uuid;
 
-#[derive(Debug, PartialEq)]
+#[derive(Serialize, Deserialize, Encode, Decode, Debug, PartialEq, Clone)]
 pub enum Statement {
-    CreateTable { name: String, columns: Vec<Column> },
+    CreateTable { name: String, columns: Vec<Column>, constraints: Vec<TableConstraint> },
     CreateDatabase { database_name: String },
     Insert { table_name: String, columns: Option<Vec<String>>, values: Vec<Vec<Expression>> },
-    Update { table_name: String, columns: BTreeMap<String, Expression>, where_clause: Option<Vec<Expression>> },
-    Delete { table_name: String, where_clause: Option<Vec<Expression>> },
+    Update { table_name: String, columns: BTreeMap<String, Expression>, where_clause: Option<Expression> },
+    Delete { table_name: String, where_clause: Option<Expression> },
     // 别名可有可无
     Select { 
         columns: Vec<(Expression, Option<String>)>,
         from: FromItem,
-        where_clause: Option<Vec<Expression>>,
+        where_clause: Option<Expression>,
         group_by: Option<Expression>,
         having: Option<Expression>,
-        order_by: Vec<(String, OrderDirection)>,
+        order_by: Vec<(Expression, OrderDirection)>,
         limit: Option<Expression>,
         offset: Option<Expression>
     },
-    DropTable { table_name: String },
-    DropDatabase { database_name: String },
+    DropTable { table_name: String, if_exists: bool },
+    DropDatabase { database_name: String, if_exists: bool },
     UseDatabase { database_name: String },
+    // 修改表结构
+    AlterTable { table_name: String, operation: AlterTableOperation },
+    // 检查表数据的完整性
+    CheckTable { table_name: String },
+    // 展示执行计划
+    // analyze 时执行查询并记录每个节点的运行统计
+    Explain { statement: Box<Statement>, analyze: bool },
+    // 设置会话变量 SET name = value
+    Set { name: String, value: Expression },
+    // 根据 __query_stats 中记录的查询推荐索引
+    ShowIndexRecommendations,
     // ShowDatabases {},
     // ShowTables { },
 }
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Serialize, Deserialize, Encode, Decode, Debug, PartialEq, Clone)]
 pub enum FromItem {
     Table { name: String, alias: Option<String> },
-    // SubQuery { query: Box<Statement> },
+    // 子查询，必须有别名
+    SubQuery { query: Box<Statement>, alias: String },
     Join {
         left: Box<FromItem>,
         right: Box<FromItem>,
         join_type: JoinType,
         predicate: Option<Expression>,
+        // 按同名的列连接，和 predicate 不会同时出现
+        using: Option<JoinUsing>,
     },
 }
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Serialize, Deserialize, Encode, Decode, Debug, PartialEq, Clone)]
 pub enum JoinType {
     Cross,
     Inner,
     Left,
     Right,
+    Full,
 }
 
-#[derive(Debug, PartialEq)]
+// 按同名的列连接，USING (a, b) 指定列名，NATURAL JOIN 使用两边所有同名的列
+// 两边的列在执行时按照列名匹配，输出中两边的列都会保留
+#[derive(Serialize, Deserialize, Encode, Decode, Debug, PartialEq, Clone)]
+pub enum JoinUsing {
+    Columns(Vec<String>),
+    Natural,
+}
+
+#[derive(Serialize, Deserialize, Encode, Decode, Debug, PartialEq, Clone)]
 pub enum OrderDirection {
     Asc,
     Desc,
 }
 
-#[derive(Debug, PartialEq)]
+// alter table 支持的操作
+#[derive(Serialize, Deserialize, Encode, Decode, Debug, PartialEq, Clone)]
+pub enum AlterTableOperation {
+    AddColumn(Column),
+    DropColumn(String),
+    ModifyColumn(Column),
+}
+
+#[derive(Serialize, Deserialize, Encode, Decode, Debug, PartialEq, Clone)]
 pub struct Column {
     pub name: String,
     pub data_type: DataType,
+    // 字符串的最大长度 varchar(255)，按字符计算
+    pub max_length: Option<usize>,
+    // 整数的宽度 tinyint、bigint unsigned 等
+    pub int_width: Option<IntWidth>,
     pub nullable: Option<bool>,
     pub default: Option<Expression>,
     pub is_primary_key: bool,
     pub auto_increment: bool,
     pub unique: bool,
+    // 列级的外键 references t (a)
+    pub references: Option<ForeignKey>,
+    // 列级的检查约束 check (c > 0)
+    pub check: Option<Expression>,
+}
+
+// 表级约束 [CONSTRAINT name] PRIMARY KEY (a, b) | UNIQUE (a)
+#[derive(Serialize, Deserialize, Encode, Decode, Debug, PartialEq, Clone)]
+pub struct TableConstraint {
+    pub name: Option<String>,
+    pub kind: ConstraintKind,
+}
+
+#[derive(Serialize, Deserialize, Encode, Decode, Debug, PartialEq, Clone)]
+pub enum ConstraintKind {
+    PrimaryKey(Vec<String>),
+    Unique(Vec<String>),
+    ForeignKey(ForeignKey),
+    // 插入和更新的行需要满足的条件，结果为 NULL 时也算满足
+    Check(Expression),
+}
+
+// 外键 (columns) references ref_table (ref_columns)，没有指定引用的列时引用主键
+#[derive(Serialize, Deserialize, Encode, Decode, Debug, PartialEq, Clone)]
+pub struct ForeignKey {
+    pub columns: Vec<String>,
+    pub ref_table: String,
+    pub ref_columns: Vec<String>,
+    pub on_delete: ReferentialAction,
+    pub on_update: ReferentialAction,
+}
+
+// 被引用的行删除或者更新时的处理方式
+#[derive(Serialize, Deserialize, Encode, Decode, Debug, PartialEq, Clone, Copy)]
+pub enum ReferentialAction {
+    NoAction,
+    Restrict,
+    Cascade,
+    SetNull,
+    SetDefault,
+}
+
+impl Display for ReferentialAction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", match self {
+            ReferentialAction::NoAction => "NO ACTION",
+            ReferentialAction::Restrict => "RESTRICT",
+            ReferentialAction::Cascade => "CASCADE",
+            ReferentialAction::SetNull => "SET NULL",
+            ReferentialAction::SetDefault => "SET DEFAULT",
+        })
+    }
+}
+
+fn ident_list(columns: &[String]) -> String {
+    columns.iter().map(|c| quote_ident(c)).collect::<Vec<_>>().join(", ")
+}
+
+// 只展示 references 部分，列级和表级的外键共用
+impl Display for ForeignKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "REFERENCES {}", quote_ident(&self.ref_table))?;
+        if !self.ref_columns.is_empty() {
+            write!(f, " ({})", ident_list(&self.ref_columns))?;
+        }
+        if self.on_delete != ReferentialAction::NoAction {
+            write!(f, " ON DELETE {}", self.on_delete)?;
+        }
+        if self.on_update != ReferentialAction::NoAction {
+            write!(f, " ON UPDATE {}", self.on_update)?;
+        }
+        Ok(())
+    }
 }
 
-// join 的表达式，只有一种等于的情况
-#[derive(Debug, PartialEq, Clone)]
+impl Display for ConstraintKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConstraintKind::PrimaryKey(columns) => write!(f, "PRIMARY KEY ({})", ident_list(columns)),
+            ConstraintKind::Unique(columns) => write!(f, "UNIQUE ({})", ident_list(columns)),
+            ConstraintKind::ForeignKey(fk) => write!(f, "FOREIGN KEY ({}) {}", ident_list(&fk.columns), fk),
+            ConstraintKind::Check(expr) => write!(f, "CHECK ({:#})", expr),
+        }
+    }
+}
+
+// 比较和逻辑运算
+#[derive(Serialize, Deserialize, Encode, Decode, Debug, PartialEq, Clone)]
 pub enum Operation {
     Equal(Box<Expression>, Box<Expression>),
     NotEqual(Box<Expression>, Box<Expression>),
     GreaterThan(Box<Expression>, Box<Expression>),
     LessThan(Box<Expression>, Box<Expression>),
+    GreaterThanOrEqual(Box<Expression>, Box<Expression>),
+    LessThanOrEqual(Box<Expression>, Box<Expression>),
+    // 模式匹配，% 匹配任意多个字符，_ 匹配一个字符
+    Like(Box<Expression>, Box<Expression>),
+    // 算术运算
+    Add(Box<Expression>, Box<Expression>),
+    Subtract(Box<Expression>, Box<Expression>),
+    Multiply(Box<Expression>, Box<Expression>),
+    Divide(Box<Expression>, Box<Expression>),
+    Modulo(Box<Expression>, Box<Expression>),
+    Exponentiate(Box<Expression>, Box<Expression>),
+    Negate(Box<Expression>),
+    And(Box<Expression>, Box<Expression>),
+    Or(Box<Expression>, Box<Expression>),
+    Not(Box<Expression>),
+    // a IN (select ...)
+    In(Box<Expression>, Box<Expression>),
+    // EXISTS (select ...)
+    Exists(Box<Expression>),
 }
 
 // 表达式
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Serialize, Deserialize, Encode, Decode, Debug, PartialEq, Clone)]
 pub enum Expression {
     Field(String),
     Consts(Consts),
     Operation(Operation),
-    Function(String, String)
+    Function(String, String),
+    // 类型转换 CAST(expr AS type)
+    Cast(Box<Expression>, DataType),
+    // 子查询，执行前替换为 Materialized
+    Subquery(Box<Statement>),
+    // 子查询执行后的结果
+    Materialized(Vec<Row>),
+}
+
+impl Statement {
+    // 是否会修改数据、表结构或者数据库
+    pub fn is_write(&self) -> bool {
+        match self {
+            // explain analyze 会把运行统计写到 __query_stats 表中
+            Statement::Explain { analyze, .. } => *analyze,
+            Statement::Select { .. } | Statement::CheckTable { .. }
+            | Statement::UseDatabase { .. } | Statement::Set { .. } | Statement::ShowIndexRecommendations => false,
+            Statement::CreateTable { .. } | Statement::CreateDatabase { .. } | Statement::Insert { .. }
+            | Statement::Update { .. } | Statement::Delete { .. } | Statement::DropTable { .. }
+            | Statement::DropDatabase { .. } | Statement::AlterTable { .. } => true,
+        }
+    }
+
+    // 把语句渲染成规范的 SQL，关键字大写，标识符按需加引号，不带结尾的分号
+    // 重新解析得到的语句与原语句相同
+    pub fn to_sql(&self) -> String {
+        match self {
+            Statement::CreateTable { name, columns, constraints } => {
+                let constraints = constraints.iter().map(|c| match &c.name {
+                    Some(name) => format!("CONSTRAINT {} {}", quote_ident(name), c.kind),
+                    None => c.kind.to_string(),
+                });
+                format!(
+                    "CREATE TABLE {} ({})",
+                    quote_ident(name),
+                    columns.iter().map(Column::to_sql).chain(constraints).collect::<Vec<_>>().join(", ")
+                )
+            },
+            Statement::CreateDatabase { database_name } => format!("CREATE DATABASE {}", quote_ident(database_name)),
+            Statement::Insert { table_name, columns, values } => {
+                let mut sql = format!("INSERT INTO {}", quote_ident(table_name));
+                if let Some(columns) = columns {
+                    sql.push_str(&format!(" ({})", columns.iter().map(|c| quote_ident(c)).collect::<Vec<_>>().join(", ")));
+                }
+                let rows = values
+                    .iter()
+                    .map(|row| format!("({})", row.iter().map(|v| format!("{:#}", v)).collect::<Vec<_>>().join(", ")))
+                    .collect::<Vec<_>>();
+                sql.push_str(&format!(" VALUES {}", rows.join(", ")));
+                sql
+            },
+            Statement::Update { table_name, columns, where_clause } => {
+                let sets = columns
+                    .iter()
+                    .map(|(col, expr)| format!("{} = {:#}", quote_ident(col), expr))
+                    .collect::<Vec<_>>();
+                let mut sql = format!("UPDATE {} SET {}", quote_ident(table_name), sets.join(", "));
+                if let Some(expr) = where_clause {
+                    sql.push_str(&format!(" WHERE {:#}", expr));
+                }
+                sql
+            },
+            Statement::Delete { table_name, where_clause } => {
+                let mut sql = format!("DELETE FROM {}", quote_ident(table_name));
+                if let Some(expr) = where_clause {
+                    sql.push_str(&format!(" WHERE {:#}", expr));
+                }
+                sql
+            },
+            Statement::Select { columns, from, where_clause, group_by, having, order_by, limit, offset } => {
+                let mut sql = String::from("SELECT ");
+                if columns.is_empty() {
+                    sql.push('*');
+                } else {
+                    let columns = columns
+                        .iter()
+                        .map(|(expr, alias)| match alias {
+                            Some(alias) => format!("{:#} AS {}", expr, quote_ident(alias)),
+                            None => format!("{:#}", expr),
+                        })
+                        .collect::<Vec<_>>();
+                    sql.push_str(&columns.join(", "));
+                }
+                sql.push_str(&format!(" FROM {}", from.to_sql()));
+                if let Some(expr) = where_clause {
+                    sql.push_str(&format!(" WHERE {:#}", expr));
+                }
+                if let Some(expr) = group_by {
+                    sql.push_str(&format!(" GROUP BY {:#}", expr));
+                }
+                if let Some(expr) = having {
+                    sql.push_str(&format!(" HAVING {:#}", expr));
+                }
+                if !order_by.is_empty() {
+                    let order_by = order_by
+                        .iter()
+                        .map(|(expr, direction)| format!("{:#} {}", expr, match direction {
+                            OrderDirection::Asc => "ASC",
+                            OrderDirection::Desc => "DESC",
+                        }))
+                        .collect::<Vec<_>>();
+                    sql.push_str(&format!(" ORDER BY {}", order_by.join(", ")));
+                }
+                if let Some(expr) = limit {
+                    sql.push_str(&format!(" LIMIT {:#}", expr));
+                }
+                if let Some(expr) = offset {
+                    sql.push_str(&format!(" OFFSET {:#}", expr));
+                }
+                sql
+            },
+            Statement::DropTable { table_name, if_exists } => {
+                format!("DROP TABLE {}{}", if *if_exists { "IF EXISTS " } else { "" }, quote_ident(table_name))
+            },
+            Statement::DropDatabase { database_name, if_exists } => {
+                format!("DROP DATABASE {}{}", if *if_exists { "IF EXISTS " } else { "" }, quote_ident(database_name))
+            },
+            Statement::UseDatabase { database_name } => format!("USE {}", quote_ident(database_name)),
+            Statement::AlterTable { table_name, operation } => {
+                let table_name = quote_ident(table_name);
+                match operation {
+                    AlterTableOperation::AddColumn(column) => format!("ALTER TABLE {} ADD COLUMN {}", table_name, column.to_sql()),
+                    AlterTableOperation::DropColumn(name) => format!("ALTER TABLE {} DROP COLUMN {}", table_name, quote_ident(name)),
+                    AlterTableOperation::ModifyColumn(column) => format!("ALTER TABLE {} MODIFY COLUMN {}", table_name, column.to_sql()),
+                }
+            },
+            Statement::CheckTable { table_name } => format!("CHECK TABLE {}", quote_ident(table_name)),
+            Statement::Explain { statement, analyze: false } => format!("EXPLAIN {}", statement.to_sql()),
+            Statement::Explain { statement, analyze: true } => format!("EXPLAIN ANALYZE {}", statement.to_sql()),
+            Statement::Set { name, value } => format!("SET {} = {:#}", quote_ident(name), value),
+            Statement::ShowIndexRecommendations => "SHOW INDEX RECOMMENDATIONS".to_string(),
+        }
+    }
+
+    // 语句的指纹：在规范的 SQL 上把常量替换为 ?，连续的常量和多行 VALUES 合并为一项
+    // 只有常量不同的语句指纹相同，用于按语句的形状分组统计
+    pub fn fingerprint(&self) -> String {
+        let sql = self.to_sql();
+        let mut lexer = Lexer::new(&sql);
+        // 每一项记录原文、是否是一个值的结尾（用来区分负号和减号）以及前面是否有空白
+        let mut parts: Vec<(&str, bool, bool)> = Vec::new();
+        let mut end = 0;
+        while let Some(Ok(token)) = lexer.next() {
+            let start = lexer.token_position().offset;
+            let text = lexer.token_text().unwrap_or_default();
+            let space = start > end;
+            end = start + text.len();
+            match token {
+                Token::Number(_) | Token::String(_) | Token::Binary(_) | Token::Keyword(Keyword::True | Keyword::False | Keyword::Null) => {
+                    let len = parts.len();
+                    let mut space = space;
+                    if len > 0 && parts[len - 1].0 == "-" && (len == 1 || !parts[len - 2].1) {
+                        space = parts.pop().is_some_and(|(_, _, space)| space);
+                    }
+                    // ?, ? -> ?
+                    let len = parts.len();
+                    if len >= 2 && parts[len - 1].0 == "," && parts[len - 2].0 == "?" {
+                        parts.pop();
+                    } else {
+                        parts.push(("?", true, space));
+                    }
+                },
+                Token::RightParen => {
+                    parts.push((text, true, space));
+                    // (?), (?) -> (?)
+                    let tail = parts.iter().rev().take(7).map(|(s, _, _)| *s).collect::<Vec<_>>();
+                    if tail == [")", "?", "(", ",", ")", "?", "("] {
+                        parts.truncate(parts.len() - 4);
+                    }
+                },
+                Token::Identifier(_) => parts.push((text, true, space)),
+                _ => parts.push((text, false, space)),
+            }
+        }
+        let mut fingerprint = String::new();
+        for (i, (text, _, space)) in parts.iter().enumerate() {
+            if i > 0 && *space {
+                fingerprint.push(' ');
+            }
+            fingerprint.push_str(text);
+        }
+        fingerprint
+    }
+}
+
+impl FromItem {
+    fn to_sql(&self) -> String {
+        match self {
+            FromItem::Table { name, alias: Some(alias) } => format!("{} AS {}", quote_ident(name), quote_ident(alias)),
+            FromItem::Table { name, alias: None } => quote_ident(name),
+            FromItem::SubQuery { query, alias } => format!("({}) AS {}", query.to_sql(), quote_ident(alias)),
+            FromItem::Join { left, right, join_type, predicate, using } => {
+                let join = match join_type {
+                    JoinType::Cross => "CROSS JOIN",
+                    JoinType::Inner => "JOIN",
+                    JoinType::Left => "LEFT JOIN",
+                    JoinType::Right => "RIGHT JOIN",
+                    JoinType::Full => "FULL JOIN",
+                };
+                let natural = if using == &Some(JoinUsing::Natural) { "NATURAL " } else { "" };
+                let mut sql = format!("{} {}{} {}", left.to_sql(), natural, join, right.to_sql());
+                if let Some(JoinUsing::Columns(columns)) = using {
+                    sql.push_str(&format!(" USING ({})", columns.iter().map(|c| quote_ident(c)).collect::<Vec<_>>().join(", ")));
+                }
+                // 解析右连接时交换了条件的左右两边，这里换回来
+                if let Some(Expression::Operation(Operation::Equal(l, r))) = predicate {
+                    let (l, r) = if *join_type == JoinType::Right { (r, l) } else { (l, r) };
+                    sql.push_str(&format!(" ON {:#} = {:#}", l, r));
+                }
+                sql
+            },
+        }
+    }
+}
+
+impl Column {
+    fn to_sql(&self) -> String {
+        let mut sql = match self.int_width {
+            Some(int_width) => format!("{} {}", quote_ident(&self.name), int_width),
+            None => format!("{} {}", quote_ident(&self.name), self.data_type),
+        };
+        if let Some(max_length) = self.max_length {
+            sql.push_str(&format!("({})", max_length));
+        }
+        if self.is_primary_key {
+            sql.push_str(" PRIMARY KEY");
+        }
+        match self.nullable {
+            Some(true) => sql.push_str(" NULL"),
+            Some(false) => sql.push_str(" NOT NULL"),
+            None => {},
+        }
+        if self.auto_increment {
+            sql.push_str(" AUTO_INCREMENT");
+        }
+        if self.unique {
+            sql.push_str(" UNIQUE");
+        }
+        if let Some(default) = &self.default {
+            sql.push_str(&format!(" DEFAULT {:#}", default));
+        }
+        if let Some(fk) = &self.references {
+            sql.push_str(&format!(" {}", fk));
+        }
+        if let Some(check) = &self.check {
+            sql.push_str(&format!(" CHECK ({:#})", check));
+        }
+        sql
+    }
+}
+
+// 渲染 SQL 时的标识符，关键字、大写字母以及其他特殊字符需要用反引号括起来
+pub fn quote_ident(name: &str) -> String {
+    let plain = name.starts_with(|c: char| c.is_ascii_lowercase() || c == '_')
+        && name.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+        && Keyword::from_str(name).is_none();
+    if plain {
+        name.to_string()
+    } else {
+        format!("`{}`", name.replace('`', "``"))
+    }
+}
+
+// 带表名的列名分别加引号 t1.a，其他情况整体加引号
+fn quote_column(name: &str) -> String {
+    match split_qualified(name) {
+        Some((table, col)) => format!("{}.{}", quote_ident(table), quote_ident(col)),
+        None => quote_ident(name),
+    }
 }
 
 impl From<Consts> for Expression {
@@ -88,22 +502,390 @@ impl From<Consts> for Expression {
     }
 }
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Serialize, Deserialize, Encode, Decode, Debug, PartialEq, Clone)]
 pub enum Consts {
     Null,
     String(String),
     Integer(i64),
     Float(f64),
     Boolean(bool),
+    Interval(Interval),
+    // DATE '2024-01-01'，1970-01-01 之后的天数
+    Date(i64),
+    // TIME '12:30:00'，一天中的秒数
+    Time(i64),
+    // TIMESTAMP '2024-01-01 12:30:00'，unix 时间戳
+    DateTime(i64),
+    // 二进制数据 x'deadbeef'
+    Binary(Vec<u8>),
+    // UUID '123e4567-e89b-42d3-a456-426614174000'
+    Uuid([u8; 16]),
+}
+
+// 展示表达式，{:#} 输出可以重新解析的 SQL：标识符按需加引号，子查询展开
+impl Display for Expression {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let sql = f.alternate();
+        match self {
+            Expression::Field(name) if sql => write!(f, "{}", quote_column(name)),
+            Expression::Field(name) => write!(f, "{}", name),
+            Expression::Consts(Consts::Null) => write!(f, "NULL"),
+            // 反斜杠和单引号需要转义，展示结果可以重新解析
+            Expression::Consts(Consts::String(s)) => write!(f, "'{}'", s.replace('\\', "\\\\").replace('\'', "''")),
+            Expression::Consts(Consts::Integer(i)) => write!(f, "{}", i),
+            // 整数值的浮点数需要保留小数点，否则会被解析成整数
+            Expression::Consts(Consts::Float(v)) if sql && v.is_finite() && v.fract() == 0.0 => write!(f, "{:.1}", v),
+            Expression::Consts(Consts::Float(v)) => write!(f, "{}", v),
+            Expression::Consts(Consts::Boolean(b)) => write!(f, "{}", if *b { "TRUE" } else { "FALSE" }),
+            Expression::Consts(Consts::Interval(i)) => write!(f, "INTERVAL '{}'", i),
+            Expression::Consts(Consts::Date(d)) => write!(f, "DATE '{}'", datetime::format_date(*d)),
+            Expression::Consts(Consts::Time(t)) => write!(f, "TIME '{}'", datetime::format_time(*t)),
+            Expression::Consts(Consts::DateTime(t)) => write!(f, "TIMESTAMP '{}'", datetime::format_timestamp(*t)),
+            Expression::Consts(Consts::Binary(b)) => write!(f, "X'{}'", format_hex(b)),
+            Expression::Consts(Consts::Uuid(u)) => write!(f, "UUID '{}'", uuid::format(u)),
+            Expression::Function(name, col) if sql && col.is_empty() => write!(f, "{}()", quote_ident(name)),
+            Expression::Function(name, col) if sql && col == "*" => write!(f, "{}(*)", quote_ident(name)),
+            Expression::Function(name, col) if sql => write!(f, "{}({})", quote_ident(name), quote_column(col)),
+            Expression::Function(name, col) => write!(f, "{}({})", name, col),
+            Expression::Operation(op) if sql => write!(f, "{:#}", op),
+            Expression::Operation(op) => write!(f, "{}", op),
+            Expression::Cast(expr, data_type) if sql => write!(f, "CAST({:#} AS {})", expr, data_type),
+            Expression::Cast(expr, data_type) => write!(f, "CAST({} AS {})", expr, data_type),
+            Expression::Subquery(query) if sql => write!(f, "({})", query.to_sql()),
+            Expression::Subquery(_) => write!(f, "(subquery)"),
+            Expression::Materialized(rows) => write!(f, "({} rows)", rows.len()),
+        }
+    }
+}
+
+impl Expression {
+    // 自底向上改写表达式，先改写子表达式，再对当前表达式调用 f
+    pub fn transform<F: FnMut(Expression) -> LegendDBResult<Expression>>(self, f: &mut F) -> LegendDBResult<Expression> {
+        let mut child = |expr: Box<Expression>| -> LegendDBResult<Box<Expression>> { Ok(Box::new(expr.transform(f)?)) };
+        let expr = match self {
+            Expression::Operation(op) => Expression::Operation(match op {
+                Operation::Equal(l, r) => Operation::Equal(child(l)?, child(r)?),
+                Operation::NotEqual(l, r) => Operation::NotEqual(child(l)?, child(r)?),
+                Operation::GreaterThan(l, r) => Operation::GreaterThan(child(l)?, child(r)?),
+                Operation::LessThan(l, r) => Operation::LessThan(child(l)?, child(r)?),
+                Operation::GreaterThanOrEqual(l, r) => Operation::GreaterThanOrEqual(child(l)?, child(r)?),
+                Operation::LessThanOrEqual(l, r) => Operation::LessThanOrEqual(child(l)?, child(r)?),
+                Operation::Like(l, r) => Operation::Like(child(l)?, child(r)?),
+                Operation::Add(l, r) => Operation::Add(child(l)?, child(r)?),
+                Operation::Subtract(l, r) => Operation::Subtract(child(l)?, child(r)?),
+                Operation::Multiply(l, r) => Operation::Multiply(child(l)?, child(r)?),
+                Operation::Divide(l, r) => Operation::Divide(child(l)?, child(r)?),
+                Operation::Modulo(l, r) => Operation::Modulo(child(l)?, child(r)?),
+                Operation::Exponentiate(l, r) => Operation::Exponentiate(child(l)?, child(r)?),
+                Operation::And(l, r) => Operation::And(child(l)?, child(r)?),
+                Operation::Or(l, r) => Operation::Or(child(l)?, child(r)?),
+                Operation::In(l, r) => Operation::In(child(l)?, child(r)?),
+                Operation::Not(expr) => Operation::Not(child(expr)?),
+                Operation::Negate(expr) => Operation::Negate(child(expr)?),
+                Operation::Exists(expr) => Operation::Exists(child(expr)?),
+            }),
+            Expression::Cast(expr, data_type) => Expression::Cast(child(expr)?, data_type),
+            expr => expr,
+        };
+        f(expr)
+    }
+}
+
+impl Operation {
+    // 运算符的优先级，用于展示时决定是否需要加括号
+    fn precedence(&self) -> u8 {
+        match self {
+            Operation::Or(..) => 1,
+            Operation::And(..) => 2,
+            Operation::Not(..) => 3,
+            Operation::Equal(..) | Operation::NotEqual(..) | Operation::GreaterThan(..) | Operation::LessThan(..)
+            | Operation::GreaterThanOrEqual(..) | Operation::LessThanOrEqual(..) | Operation::Like(..)
+            | Operation::In(..) => 4,
+            Operation::Add(..) | Operation::Subtract(..) => 5,
+            Operation::Multiply(..) | Operation::Divide(..) | Operation::Modulo(..) => 6,
+            Operation::Negate(..) => 7,
+            Operation::Exponentiate(..) => 8,
+            Operation::Exists(..) => 9,
+        }
+    }
+}
+
+impl Display for Operation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        // 子表达式的优先级更低时需要加括号，右结合的乘方左边优先级相同时也要加括号
+        let sql = f.alternate();
+        let show = |expr: &dyn Display| if sql { format!("{:#}", expr) } else { expr.to_string() };
+        let operand = |expr: &Expression, min_precedence: u8| match expr {
+            Expression::Operation(op) if op.precedence() < min_precedence => format!("({})", show(op)),
+            expr => show(expr),
+        };
+        let p = self.precedence();
+        match self {
+            Operation::Not(expr) => write!(f, "NOT {}", operand(expr, p)),
+            Operation::Negate(expr) => write!(f, "-{}", operand(expr, p)),
+            Operation::Exists(expr) => write!(f, "EXISTS {}", show(expr.as_ref())),
+            Operation::Exponentiate(l, r) => write!(f, "{} ^ {}", operand(l, p + 1), operand(r, p)),
+            Operation::Equal(l, r) | Operation::NotEqual(l, r) | Operation::GreaterThan(l, r) | Operation::LessThan(l, r)
+            | Operation::GreaterThanOrEqual(l, r) | Operation::LessThanOrEqual(l, r) | Operation::Like(l, r)
+            | Operation::In(l, r) | Operation::Add(l, r) | Operation::Subtract(l, r) | Operation::Multiply(l, r) | Operation::Divide(l, r)
+            | Operation::Modulo(l, r) | Operation::And(l, r) | Operation::Or(l, r) => {
+                let symbol = match self {
+                    Operation::Equal(..) => "=",
+                    Operation::NotEqual(..) => "!=",
+                    Operation::GreaterThan(..) => ">",
+                    Operation::LessThan(..) => "<",
+                    Operation::GreaterThanOrEqual(..) => ">=",
+                    Operation::LessThanOrEqual(..) => "<=",
+                    Operation::Like(..) => "LIKE",
+                    Operation::In(..) => "IN",
+                    Operation::Add(..) => "+",
+                    Operation::Subtract(..) => "-",
+                    Operation::Multiply(..) => "*",
+                    Operation::Divide(..) => "/",
+                    Operation::Modulo(..) => "%",
+                    Operation::And(..) => "AND",
+                    _ => "OR",
+                };
+                write!(f, "{} {} {}", operand(l, p), symbol, operand(r, p + 1))
+            },
+        }
+    }
+}
+
+// 收集表达式中引用到的列
+pub fn collect_fields(expr: &Expression, fields: &mut Vec<String>) {
+    match expr {
+        Expression::Field(col) => fields.push(col.clone()),
+        Expression::Operation(Operation::Equal(left, right))
+        | Expression::Operation(Operation::NotEqual(left, right))
+        | Expression::Operation(Operation::GreaterThan(left, right))
+        | Expression::Operation(Operation::LessThan(left, right))
+        | Expression::Operation(Operation::GreaterThanOrEqual(left, right))
+        | Expression::Operation(Operation::LessThanOrEqual(left, right))
+        | Expression::Operation(Operation::Like(left, right))
+        | Expression::Operation(Operation::In(left, right))
+        | Expression::Operation(Operation::Add(left, right))
+        | Expression::Operation(Operation::Subtract(left, right))
+        | Expression::Operation(Operation::Multiply(left, right))
+        | Expression::Operation(Operation::Divide(left, right))
+        | Expression::Operation(Operation::Modulo(left, right))
+        | Expression::Operation(Operation::Exponentiate(left, right))
+        | Expression::Operation(Operation::And(left, right))
+        | Expression::Operation(Operation::Or(left, right)) => {
+            collect_fields(left, fields);
+            collect_fields(right, fields);
+        },
+        Expression::Operation(Operation::Not(expr))
+        | Expression::Operation(Operation::Negate(expr))
+        | Expression::Operation(Operation::Exists(expr))
+        | Expression::Cast(expr, _) => collect_fields(expr, fields),
+        // 子查询中的列属于子查询自己的表
+        Expression::Consts(_) | Expression::Function(_, _) | Expression::Subquery(_) | Expression::Materialized(_) => {},
+    }
+}
+
+// 比较两个值的大小，有 NULL 时结果未知，返回 None
+fn compare_values(left: Value, right: Value) -> LegendDBResult<Option<Ordering>> {
+    // 字符串和日期时间、UUID 比较时，按对方的类型解析字符串，比如 d > '2024-01-01'
+    let (left, right) = match (&left, &right) {
+        (Value::String(_), Value::Date(_) | Value::Time(_) | Value::DateTime(_) | Value::Uuid(_)) => (temporal_string(left, &right)?, right),
+        (Value::Date(_) | Value::Time(_) | Value::DateTime(_) | Value::Uuid(_), Value::String(_)) => {
+            let right = temporal_string(right, &left)?;
+            (left, right)
+        },
+        _ => (left, right),
+    };
+    match (&left, &right) {
+        (Value::Null, _) | (_, Value::Null) => Ok(None),
+        _ => left.partial_cmp(&right).map(Some).ok_or(LegendDBError::Internal(format!(
+            "can not compare expression {:?} and {:?}", left, right
+        ))),
+    }
+}
+
+// 字符串转换为和 other 相同的日期时间或者 UUID 类型
+fn temporal_string(value: Value, other: &Value) -> LegendDBResult<Value> {
+    let data_type = other.get_type().ok_or(LegendDBError::Internal(format!("can not compare {:?} and {:?}", value, other)))?;
+    value.cast(&data_type).ok_or(LegendDBError::Internal(format!("invalid {} value {}", data_type, value)))
+}
+
+// LIKE 模式匹配，% 匹配任意多个字符，_ 匹配一个字符
+// 遇到 % 时记录回溯的位置，后面匹配失败时让 % 多匹配一个字符再重试
+fn like_match(text: &str, pattern: &str) -> bool {
+    let text = text.chars().collect::<Vec<_>>();
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let (mut t, mut p) = (0, 0);
+    let mut backtrack = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('%') => {
+                p += 1;
+                backtrack = Some((p, t));
+            },
+            Some(&c) if c == '_' || c == text[t] => {
+                t += 1;
+                p += 1;
+            },
+            _ => match backtrack {
+                Some((bp, bt)) => {
+                    p = bp;
+                    t = bt + 1;
+                    backtrack = Some((bp, bt + 1));
+                },
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '%')
+}
+
+// 计算常量表达式，比如 insert 的值
+pub fn evaluate_const(expression: &Expression) -> LegendDBResult<Value> {
+    evaluate_expr(expression, &Vec::new(), &Vec::new(), &Vec::new(), &Vec::new())
+}
+
+// 算术运算，有 NULL 时结果为 NULL
+// 两个整数运算的结果还是整数，溢出时报错；有浮点数参与时结果为浮点数
+fn evaluate_arithmetic(op: &Operation, left: Value, right: Value) -> LegendDBResult<Value> {
+    let overflow = || LegendDBError::Internal(format!("value out of range in {}", Expression::Operation(op.clone())));
+    let (l, r) = match (left, right) {
+        (Value::Null, _) | (_, Value::Null) => return Ok(Value::Null),
+        // 日期加减天数，日期加上一天中的时间得到时间戳，两个日期相减得到相差的天数，两个时间戳相减得到时间间隔
+        (Value::Date(d), Value::Integer(n)) if matches!(op, Operation::Add(..) | Operation::Subtract(..)) => {
+            let n = if matches!(op, Operation::Subtract(..)) { n.checked_neg().ok_or_else(overflow)? } else { n };
+            let seconds = n.checked_mul(datetime::SECONDS_PER_DAY).ok_or_else(overflow)?;
+            return datetime::shift_datetime(d * datetime::SECONDS_PER_DAY, Interval { months: 0, seconds })
+                .map(|secs| Value::Date(secs / datetime::SECONDS_PER_DAY))
+                .ok_or_else(overflow);
+        },
+        (Value::Date(d), Value::Time(t)) | (Value::Time(t), Value::Date(d)) if matches!(op, Operation::Add(..)) => {
+            return Ok(Value::DateTime(d * datetime::SECONDS_PER_DAY + t));
+        },
+        (Value::Date(l), Value::Date(r)) if matches!(op, Operation::Subtract(..)) => return Ok(Value::Integer(l - r)),
+        (Value::DateTime(l), Value::DateTime(r)) if matches!(op, Operation::Subtract(..)) => {
+            return l.checked_sub(r).map(|seconds| Value::Interval(Interval { months: 0, seconds })).ok_or_else(overflow);
+        },
+        // 日期、时间戳加减时间间隔的结果是时间戳；一天中的时间加减时间间隔时只计算秒数，超过一天时从零点重新开始
+        (Value::Date(d), Value::Interval(i)) if matches!(op, Operation::Add(..) | Operation::Subtract(..)) => {
+            return evaluate_arithmetic(op, Value::DateTime(d * datetime::SECONDS_PER_DAY), Value::Interval(i));
+        },
+        (Value::DateTime(t), Value::Interval(i)) if matches!(op, Operation::Add(..) | Operation::Subtract(..)) => {
+            let i = if matches!(op, Operation::Subtract(..)) { i.checked_neg().ok_or_else(overflow)? } else { i };
+            return datetime::shift_datetime(t, i).map(Value::DateTime).ok_or_else(overflow);
+        },
+        (Value::Time(t), Value::Interval(i)) if matches!(op, Operation::Add(..) | Operation::Subtract(..)) => {
+            let seconds = i.seconds.rem_euclid(datetime::SECONDS_PER_DAY);
+            let seconds = if matches!(op, Operation::Subtract(..)) { datetime::SECONDS_PER_DAY - seconds } else { seconds };
+            return Ok(Value::Time((t + seconds) % datetime::SECONDS_PER_DAY));
+        },
+        (Value::Integer(n), Value::Date(d)) if matches!(op, Operation::Add(..)) => {
+            return evaluate_arithmetic(op, Value::Date(d), Value::Integer(n));
+        },
+        (Value::Interval(i), value @ (Value::Date(_) | Value::DateTime(_) | Value::Time(_))) if matches!(op, Operation::Add(..)) => {
+            return evaluate_arithmetic(op, value, Value::Interval(i));
+        },
+        // 时间加减时间间隔，时间间隔之间加减，时间间隔乘以整数
+        (Value::String(ts), Value::Interval(i)) if matches!(op, Operation::Add(..) | Operation::Subtract(..)) => {
+            let i = if matches!(op, Operation::Subtract(..)) { i.checked_neg().ok_or_else(overflow)? } else { i };
+            return datetime::add_interval(&ts, i).map(Value::String);
+        },
+        (Value::Interval(i), Value::String(ts)) if matches!(op, Operation::Add(..)) => {
+            return evaluate_arithmetic(op, Value::String(ts), Value::Interval(i));
+        },
+        (Value::Interval(l), Value::Interval(r)) if matches!(op, Operation::Add(..) | Operation::Subtract(..)) => {
+            let r = if matches!(op, Operation::Subtract(..)) { r.checked_neg().ok_or_else(overflow)? } else { r };
+            return l.checked_add(r).map(Value::Interval).ok_or_else(overflow);
+        },
+        (Value::Interval(i), Value::Integer(n)) | (Value::Integer(n), Value::Interval(i)) if matches!(op, Operation::Multiply(..)) => {
+            return i.checked_mul(n).map(Value::Interval).ok_or_else(overflow);
+        },
+        (Value::Integer(l), Value::Integer(r)) => {
+            let result = match op {
+                Operation::Divide(..) | Operation::Modulo(..) if r == 0 => {
+                    return Err(LegendDBError::Internal("division by zero".to_string()));
+                },
+                Operation::Add(..) => l.checked_add(r),
+                Operation::Subtract(..) => l.checked_sub(r),
+                Operation::Multiply(..) => l.checked_mul(r),
+                Operation::Divide(..) => l.checked_div(r),
+                Operation::Modulo(..) => l.checked_rem(r),
+                // 负数的指数结果是小数
+                _ => match u32::try_from(r) {
+                    Ok(r) => l.checked_pow(r),
+                    Err(_) => return Ok(Value::Float((l as f64).powf(r as f64))),
+                },
+            };
+            return result.map(Value::Integer).ok_or(LegendDBError::Internal(format!(
+                "integer overflow in {}", Expression::Operation(op.clone())
+            )));
+        },
+        (Value::Integer(l), Value::Float(r)) => (l as f64, r),
+        (Value::Float(l), Value::Integer(r)) => (l, r as f64),
+        (Value::Float(l), Value::Float(r)) => (l, r),
+        (left, right) => return Err(LegendDBError::Internal(format!(
+            "can not apply arithmetic to {:?} and {:?}", left, right
+        ))),
+    };
+    Ok(Value::Float(match op {
+        Operation::Divide(..) | Operation::Modulo(..) if r == 0.0 => {
+            return Err(LegendDBError::Internal("division by zero".to_string()));
+        },
+        Operation::Add(..) => l + r,
+        Operation::Subtract(..) => l - r,
+        Operation::Multiply(..) => l * r,
+        Operation::Divide(..) => l / r,
+        Operation::Modulo(..) => l % r,
+        _ => l.powf(r),
+    }))
+}
+
+// 拆分带表名的列名 t1.a -> (t1, a)，表名必须是合法的标识符
+pub fn split_qualified(name: &str) -> Option<(&str, &str)> {
+    let (table, col) = name.split_once('.')?;
+    let is_ident = |s: &str| s.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    (is_ident(table) && is_ident(col)).then_some((table, col))
+}
+
+// 查找列的位置，不带表名的列可以匹配 join 输出中带表名的列 a -> t1.a
+pub fn column_position(columns: &[String], name: &str) -> LegendDBResult<usize> {
+    if let Some(pos) = columns.iter().position(|c| c == name) {
+        return Ok(pos);
+    }
+    let mut matched = columns.iter().enumerate()
+        .filter(|(_, c)| split_qualified(name).is_none() && c.split_once('.').is_some_and(|(_, col)| col == name))
+        .map(|(pos, _)| pos);
+    match (matched.next(), matched.next()) {
+        (Some(pos), None) => Ok(pos),
+        (Some(_), Some(_)) => Err(LegendDBError::Internal(format!("Column {} is ambiguous", name))),
+        _ => Err(LegendDBError::Internal(format!("Column {} not found", name))),
+    }
+}
+
+// 没有参数的函数，比如列默认值中的 now()
+fn evaluate_function(name: &str) -> LegendDBResult<Value> {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|e| LegendDBError::Internal(e.to_string()))?
+        .as_secs() as i64;
+    match name {
+        "unix_timestamp" => Ok(Value::Integer(secs)),
+        // 当前的 UTC 时间 yyyy-mm-dd hh:mm:ss
+        "now" | "current_timestamp" => Ok(Value::String(datetime::format_timestamp(secs))),
+        // 当前的 UTC 日期和时间
+        "current_date" => Ok(Value::Date(secs.div_euclid(datetime::SECONDS_PER_DAY))),
+        "current_time" => Ok(Value::Time(secs.rem_euclid(datetime::SECONDS_PER_DAY))),
+        // 随机的 UUID
+        "uuid" => Ok(Value::Uuid(uuid::generate())),
+        _ => Err(LegendDBError::Internal(format!("unknown function {}()", name))),
+    }
 }
 
 pub fn evaluate_expr(expression: &Expression, left_col: &Vec<String>, left_row: &Vec<Value>, right_col: &Vec<String>, right_row: &Vec<Value>) -> LegendDBResult<Value> {
     match expression {
+        Expression::Function(name, arg) if arg.is_empty() => evaluate_function(name),
         // 查询哪些列
-        Expression::Field(col_name) => {
-            let pos = left_col.iter().position(|x| *x == *col_name).ok_or(LegendDBError::Internal(format!("Column {} not found", col_name)));
-            Ok(left_row[pos?].clone())
-        },
+        Expression::Field(col_name) => Ok(left_row[column_position(left_col, col_name)?].clone()),
         // 常量
         Expression::Consts(consts) => Ok(match consts {
             Consts::Null => Value::Null,
@@ -111,6 +893,12 @@ pub fn evaluate_expr(expression: &Expression, left_col: &Vec<String>, left_row:
             Consts::Integer(i) => Value::Integer(*i),
             Consts::Float(f) => Value::Float(*f),
             Consts::Boolean(b) => Value::Boolean(*b),
+            Consts::Interval(i) => Value::Interval(*i),
+            Consts::Date(d) => Value::Date(*d),
+            Consts::Time(t) => Value::Time(*t),
+            Consts::DateTime(t) => Value::DateTime(*t),
+            Consts::Binary(b) => Value::Binary(b.clone()),
+            Consts::Uuid(u) => Value::Uuid(*u),
         }),
         // 操作符
         Expression::Operation(Operation::Equal(left, right)) => {
@@ -125,7 +913,8 @@ pub fn evaluate_expr(expression: &Expression, left_col: &Vec<String>, left_row:
                 (Value::String(left_val), Value::String(right_val)) => Ok(Value::Boolean(left_val == right_val)),
                 (Value::Null, _) => Ok(Value::Null),
                 (_, Value::Null) => Ok(Value::Null),
-                (left, right) => Err(LegendDBError::Internal(format!("can not compare expression {:?} and {:?}", left, right))),
+                // 其他相同类型的值，比如日期和时间
+                (left, right) => Ok(compare_values(left, right)?.map_or(Value::Null, |o| Value::Boolean(o.is_eq()))),
             }
         },
         Expression::Operation(Operation::NotEqual(left, right)) => {
@@ -140,7 +929,8 @@ pub fn evaluate_expr(expression: &Expression, left_col: &Vec<String>, left_row:
                 (Value::String(left_val), Value::String(right_val)) => Ok(Value::Boolean(left_val != right_val)),
                 (Value::Null, _) => Ok(Value::Null),
                 (_, Value::Null) => Ok(Value::Null),
-                (left, right) => Err(LegendDBError::Internal(format!("can not compare expression {:?} and {:?}", left, right))),
+                // 其他相同类型的值，比如日期和时间
+                (left, right) => Ok(compare_values(left, right)?.map_or(Value::Null, |o| Value::Boolean(o.is_ne()))),
             }
         },
         Expression::Operation(Operation::GreaterThan(left, right)) => {
@@ -155,7 +945,8 @@ pub fn evaluate_expr(expression: &Expression, left_col: &Vec<String>, left_row:
                 (Value::String(left_val), Value::String(right_val)) => Ok(Value::Boolean(left_val > right_val)),
                 (Value::Null, _) => Ok(Value::Null),
                 (_, Value::Null) => Ok(Value::Null),
-                (left, right) => Err(LegendDBError::Internal(format!("can not compare expression {:?} and {:?}", left, right))),
+                // 其他相同类型的值，比如日期和时间
+                (left, right) => Ok(compare_values(left, right)?.map_or(Value::Null, |o| Value::Boolean(o.is_gt()))),
             }
         },
         Expression::Operation(Operation::LessThan(left, right)) => {
@@ -170,9 +961,103 @@ pub fn evaluate_expr(expression: &Expression, left_col: &Vec<String>, left_row:
                 (Value::String(left_val), Value::String(right_val)) => Ok(Value::Boolean(left_val < right_val)),
                 (Value::Null, _) => Ok(Value::Null),
                 (_, Value::Null) => Ok(Value::Null),
-                (left, right) => Err(LegendDBError::Internal(format!("can not compare expression {:?} and {:?}", left, right))),
+                // 其他相同类型的值，比如日期和时间
+                (left, right) => Ok(compare_values(left, right)?.map_or(Value::Null, |o| Value::Boolean(o.is_lt()))),
+            }
+        },
+        Expression::Operation(Operation::GreaterThanOrEqual(left, right)) => {
+            let left_val = evaluate_expr(left, left_col, left_row, right_col, right_row)?;
+            let right_val = evaluate_expr(right, right_col, right_row, left_col, left_row)?;
+            Ok(compare_values(left_val, right_val)?.map_or(Value::Null, |o| Value::Boolean(o.is_ge())))
+        },
+        Expression::Operation(Operation::LessThanOrEqual(left, right)) => {
+            let left_val = evaluate_expr(left, left_col, left_row, right_col, right_row)?;
+            let right_val = evaluate_expr(right, right_col, right_row, left_col, left_row)?;
+            Ok(compare_values(left_val, right_val)?.map_or(Value::Null, |o| Value::Boolean(o.is_le())))
+        },
+        Expression::Operation(Operation::Like(left, right)) => {
+            let left_val = evaluate_expr(left, left_col, left_row, right_col, right_row)?;
+            let right_val = evaluate_expr(right, right_col, right_row, left_col, left_row)?;
+            match (left_val, right_val) {
+                (Value::String(text), Value::String(pattern)) => Ok(Value::Boolean(like_match(&text, &pattern))),
+                (Value::Null, _) | (_, Value::Null) => Ok(Value::Null),
+                (left, right) => Err(LegendDBError::Internal(format!("can not apply LIKE to {:?} and {:?}", left, right))),
             }
         },
+        // 算术运算
+        Expression::Operation(op @ (Operation::Add(left, right)
+            | Operation::Subtract(left, right)
+            | Operation::Multiply(left, right)
+            | Operation::Divide(left, right)
+            | Operation::Modulo(left, right)
+            | Operation::Exponentiate(left, right))) => {
+            let left_val = evaluate_expr(left, left_col, left_row, right_col, right_row)?;
+            let right_val = evaluate_expr(right, left_col, left_row, right_col, right_row)?;
+            evaluate_arithmetic(op, left_val, right_val)
+        },
+        Expression::Operation(Operation::Negate(expr)) => {
+            match evaluate_expr(expr, left_col, left_row, right_col, right_row)? {
+                Value::Integer(i) => i.checked_neg().map(Value::Integer).ok_or(LegendDBError::Internal(format!("integer overflow in -{}", i))),
+                Value::Float(f) => Ok(Value::Float(-f)),
+                Value::Interval(i) => i.checked_neg().map(Value::Interval).ok_or(LegendDBError::Internal(format!("interval overflow in -{}", i))),
+                Value::Null => Ok(Value::Null),
+                value => Err(LegendDBError::Internal(format!("can not negate {:?}", value))),
+            }
+        },
+        // 逻辑运算遵循三值逻辑，NULL 表示未知
+        Expression::Operation(Operation::And(left, right)) => {
+            let left_val = evaluate_expr(left, left_col, left_row, right_col, right_row)?;
+            let right_val = evaluate_expr(right, left_col, left_row, right_col, right_row)?;
+            match (left_val, right_val) {
+                (Value::Boolean(false), _) | (_, Value::Boolean(false)) => Ok(Value::Boolean(false)),
+                (Value::Boolean(true), Value::Boolean(true)) => Ok(Value::Boolean(true)),
+                (Value::Boolean(_) | Value::Null, Value::Boolean(_) | Value::Null) => Ok(Value::Null),
+                (left, right) => Err(LegendDBError::Internal(format!("can not apply AND to {:?} and {:?}", left, right))),
+            }
+        },
+        Expression::Operation(Operation::Or(left, right)) => {
+            let left_val = evaluate_expr(left, left_col, left_row, right_col, right_row)?;
+            let right_val = evaluate_expr(right, left_col, left_row, right_col, right_row)?;
+            match (left_val, right_val) {
+                (Value::Boolean(true), _) | (_, Value::Boolean(true)) => Ok(Value::Boolean(true)),
+                (Value::Boolean(false), Value::Boolean(false)) => Ok(Value::Boolean(false)),
+                (Value::Boolean(_) | Value::Null, Value::Boolean(_) | Value::Null) => Ok(Value::Null),
+                (left, right) => Err(LegendDBError::Internal(format!("can not apply OR to {:?} and {:?}", left, right))),
+            }
+        },
+        Expression::Operation(Operation::Not(expr)) => {
+            match evaluate_expr(expr, left_col, left_row, right_col, right_row)? {
+                Value::Boolean(b) => Ok(Value::Boolean(!b)),
+                Value::Null => Ok(Value::Null),
+                value => Err(LegendDBError::Internal(format!("can not apply NOT to {:?}", value))),
+            }
+        },
+        Expression::Cast(expr, data_type) => {
+            evaluate_expr(expr, left_col, left_row, right_col, right_row)?.cast_explicit(data_type)
+        },
+        // 子查询中有匹配的值时为 true，没有匹配但有 NULL 时结果未知
+        Expression::Operation(Operation::In(left, right)) => {
+            let value = evaluate_expr(left, left_col, left_row, right_col, right_row)?;
+            let Expression::Materialized(rows) = right.as_ref() else {
+                return Err(LegendDBError::Internal(format!("subquery {} is not executed", right)));
+            };
+            let mut result = Value::Boolean(false);
+            for row in rows {
+                let [item] = row.as_slice() else {
+                    return Err(LegendDBError::Internal("subquery in IN must return exactly one column".to_string()));
+                };
+                match compare_values(value.clone(), item.clone())? {
+                    Some(Ordering::Equal) => return Ok(Value::Boolean(true)),
+                    Some(_) => {},
+                    None => result = Value::Null,
+                }
+            }
+            Ok(result)
+        },
+        Expression::Operation(Operation::Exists(expr)) => match expr.as_ref() {
+            Expression::Materialized(rows) => Ok(Value::Boolean(!rows.is_empty())),
+            expr => Err(LegendDBError::Internal(format!("subquery {} is not executed", expr))),
+        },
         _ => Err(LegendDBError::Internal("Unexpected expression".into()))
     }
 }
\ No newline at end of file
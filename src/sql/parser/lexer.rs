@@ -13,11 +13,16 @@ pub enum Keyword {
     Database,
     Int,
     Integer,
+    TinyInt,
+    SmallInt,
+    BigInt,
+    Unsigned,
     Boolean,
     Bool,
     String,
     Text,
     Varchar,
+    Char,
     Float,
     Double,
     Select,
@@ -49,6 +54,7 @@ pub enum Keyword {
     Desc,
     Limit,
     Offset,
+    All,
     As,
     Cross,
     Join,
@@ -57,7 +63,32 @@ pub enum Keyword {
     On,
     Use,
     Group,
-    Having
+    Having,
+    Add,
+    Column,
+    Modify,
+    Check,
+    Like,
+    Cast,
+    In,
+    Explain,
+    Analyze,
+    Constraint,
+    Unique,
+    Interval,
+    Date,
+    Time,
+    Timestamp,
+    Datetime,
+    Full,
+    Outer,
+    Natural,
+    Using,
+    Binary,
+    Blob,
+    Foreign,
+    References,
+    AutoIncrement,
 }
 
 impl Keyword {
@@ -68,11 +99,16 @@ impl Keyword {
             "TABLE" => Some(Keyword::Table),
             "INT" => Some(Keyword::Int),
             "INTEGER" => Some(Keyword::Integer),
+            "TINYINT" => Some(Keyword::TinyInt),
+            "SMALLINT" => Some(Keyword::SmallInt),
+            "BIGINT" => Some(Keyword::BigInt),
+            "UNSIGNED" => Some(Keyword::Unsigned),
             "BOOLEAN" => Some(Keyword::Boolean),
             "BOOL" => Some(Keyword::Bool),
             "STRING" => Some(Keyword::String),
             "TEXT" => Some(Keyword::Text),
             "VARCHAR" => Some(Keyword::Varchar),
+            "CHAR" => Some(Keyword::Char),
             "DOUBLE" => Some(Keyword::Double),
             "FLOAT" => Some(Keyword::Float),
             "SELECT" => Some(Keyword::Select),
@@ -104,15 +140,41 @@ impl Keyword {
             "DESC" => Some(Keyword::Desc),
             "LIMIT" => Some(Keyword::Limit),
             "OFFSET" => Some(Keyword::Offset),
+            "CONSTRAINT" => Some(Keyword::Constraint),
+            "UNIQUE" => Some(Keyword::Unique),
+            "INTERVAL" => Some(Keyword::Interval),
+            "DATE" => Some(Keyword::Date),
+            "TIME" => Some(Keyword::Time),
+            "TIMESTAMP" => Some(Keyword::Timestamp),
+            "DATETIME" => Some(Keyword::Datetime),
+            "FOREIGN" => Some(Keyword::Foreign),
+            "REFERENCES" => Some(Keyword::References),
+            "AUTO_INCREMENT" => Some(Keyword::AutoIncrement),
+            "ALL" => Some(Keyword::All),
             "AS" => Some(Keyword::As),
             "CROSS" => Some(Keyword::Cross),
             "JOIN" => Some(Keyword::Join),
             "LEFT" => Some(Keyword::Left),
             "RIGHT" => Some(Keyword::Right),
             "ON" => Some(Keyword::On),
+            "FULL" => Some(Keyword::Full),
+            "OUTER" => Some(Keyword::Outer),
+            "NATURAL" => Some(Keyword::Natural),
+            "USING" => Some(Keyword::Using),
+            "BINARY" => Some(Keyword::Binary),
+            "BLOB" => Some(Keyword::Blob),
             "USE" => Some(Keyword::Use),
             "GROUP" => Some(Keyword::Group),
             "HAVING" => Some(Keyword::Having),
+            "ADD" => Some(Keyword::Add),
+            "COLUMN" => Some(Keyword::Column),
+            "MODIFY" => Some(Keyword::Modify),
+            "CHECK" => Some(Keyword::Check),
+            "LIKE" => Some(Keyword::Like),
+            "CAST" => Some(Keyword::Cast),
+            "IN" => Some(Keyword::In),
+            "EXPLAIN" => Some(Keyword::Explain),
+            "ANALYZE" => Some(Keyword::Analyze),
             _ => None,
         }
     }
@@ -124,11 +186,16 @@ impl Keyword {
             Keyword::Table => "TABLE",
             Keyword::Int => "INT",
             Keyword::Integer => "INTEGER",
+            Keyword::TinyInt => "TINYINT",
+            Keyword::SmallInt => "SMALLINT",
+            Keyword::BigInt => "BIGINT",
+            Keyword::Unsigned => "UNSIGNED",
             Keyword::Boolean => "BOOLEAN",
             Keyword::Bool => "BOOL",
             Keyword::String => "STRING",
             Keyword::Text => "TEXT",
             Keyword::Varchar => "VARCHAR",
+            Keyword::Char => "CHAR",
             Keyword::Float => "FLOAT",
             Keyword::Double => "DOUBLE",
             Keyword::Select => "SELECT",
@@ -160,15 +227,41 @@ impl Keyword {
             Keyword::Desc => "DESC",
             Keyword::Limit => "LIMIT",
             Keyword::Offset => "OFFSET",
+            Keyword::Constraint => "CONSTRAINT",
+            Keyword::Unique => "UNIQUE",
+            Keyword::Interval => "INTERVAL",
+            Keyword::Date => "DATE",
+            Keyword::Time => "TIME",
+            Keyword::Timestamp => "TIMESTAMP",
+            Keyword::Datetime => "DATETIME",
+            Keyword::Foreign => "FOREIGN",
+            Keyword::References => "REFERENCES",
+            Keyword::AutoIncrement => "AUTO_INCREMENT",
+            Keyword::All => "ALL",
             Keyword::As => "AS",
             Keyword::Cross => "CROSS",
             Keyword::Join => "JOIN",
             Keyword::Left => "LEFT",
             Keyword::Right => "RIGHT",
             Keyword::On => "ON",
+            Keyword::Full => "FULL",
+            Keyword::Outer => "OUTER",
+            Keyword::Natural => "NATURAL",
+            Keyword::Using => "USING",
+            Keyword::Binary => "BINARY",
+            Keyword::Blob => "BLOB",
             Keyword::Use => "USE",
             Keyword::Group => "GROUP",
             Keyword::Having => "HAVING",
+            Keyword::Add => "ADD",
+            Keyword::Column => "COLUMN",
+            Keyword::Modify => "MODIFY",
+            Keyword::Check => "CHECK",
+            Keyword::Like => "LIKE",
+            Keyword::Cast => "CAST",
+            Keyword::In => "IN",
+            Keyword::Explain => "EXPLAIN",
+            Keyword::Analyze => "ANALYZE",
         }
     }
 }
@@ -189,6 +282,8 @@ pub enum Token {
     Number(String),
     // 字符串
     String(String),
+    // 二进制数据 x'deadbeef'，保存十六进制的数字
+    Binary(String),
     // 左括号
     LeftParen,
     // 右括号
@@ -217,6 +312,10 @@ pub enum Token {
     Asterisk,
     // 除号
     Slash,
+    // 取模
+    Percent,
+    // 乘方
+    Caret,
     // 冒号
     Colon,
     // 等号
@@ -225,8 +324,11 @@ pub enum Token {
     GreaterThan,
     // 小于号
     LessThan,
-    // 等于号
-    // 不等于号
+    // 大于等于号
+    GreaterThanOrEqual,
+    // 小于等于号
+    LessThanOrEqual,
+    // 不等于号，!= 或者 <>
     NotEqual,
     // 空白
     Whitespace,
@@ -239,6 +341,7 @@ impl Display for Token {
             Token::Identifier(ident) => ident,
             Token::Number(num) => num,
             Token::String(string) => string,
+            Token::Binary(hex) => hex,
             Token::LeftParen => "(",
             Token::RightParen => ")",
             Token::LeftBracket => "[",
@@ -253,10 +356,14 @@ impl Display for Token {
             Token::Minus => "-",
             Token::Asterisk => "*",
             Token::Slash => "/",
+            Token::Percent => "%",
+            Token::Caret => "^",
             Token::Colon => ":",
             Token::Equal => "=",
             Token::GreaterThan => ">",
             Token::LessThan => "<",
+            Token::GreaterThanOrEqual => ">=",
+            Token::LessThanOrEqual => "<=",
             Token::NotEqual => "!=",
             Token::Whitespace => " ",
         })
@@ -273,7 +380,7 @@ impl Display for Token {
 //     - BOOLEAN(BOOL): true | false
 //     - FLOAT(DOUBLE)
 //     - INTEGER(INT)
-//     - STRING(TEXT, VARCHAR)
+//     - STRING(TEXT, VARCHAR, CHAR) [ ( length ) ]
 //
 //    where column_constraint is:
 //    [ NOT NULL | NULL | DEFAULT expr ]
@@ -287,8 +394,27 @@ impl Display for Token {
 // -------------------------------------
 // SELECT * FROM table_name;
 pub struct Lexer<'a> {
+    sql: &'a str,
     iter: Peekable<Chars<'a>>,
     prev_token: Option<Token>,
+    // 下一个字符的位置
+    position: Position,
+    // 最近扫描的 Token 的开始位置，用于语法错误的提示
+    token_start: Position,
+}
+
+// SQL 中的位置，offset 是字节偏移，行号和列号从 1 开始，列号按字符计算
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Position {
+    pub offset: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Default for Position {
+    fn default() -> Self {
+        Position { offset: 0, line: 1, column: 1 }
+    }
 }
 
 impl<'a> Iterator for Lexer<'a> {
@@ -297,7 +423,7 @@ impl<'a> Iterator for Lexer<'a> {
     fn next(&mut self) -> Option<Self::Item> {
         match self.scan() {
             Ok(Some(token)) => {Some(Ok(token))},
-            Ok(None) => {self.iter.peek().map(|_| Err(LegendDBError::NotSupported))},
+            Ok(None) => {self.iter.peek().map(|c| Err(LegendDBError::Parser(format!("[Lexer] Unexpected character {}", c))))},
             Err(e) => {Some(Err(e))},
         }
     }
@@ -307,9 +433,40 @@ impl<'a> Lexer<'a> {
 
     pub fn new(sql: &'a str) -> Lexer<'a> {
         Lexer {
+            sql,
             iter: sql.chars().peekable(),
             prev_token: None,
+            position: Position::default(),
+            token_start: Position::default(),
+        }
+    }
+
+    // 最近扫描的 Token 的开始位置
+    pub fn token_position(&self) -> Position {
+        self.token_start
+    }
+
+    // 最近扫描的 Token 的原文，还没有扫描出 Token 时是下一个字符，已经到结尾时为 None
+    pub fn token_text(&self) -> Option<&'a str> {
+        let text = &self.sql[self.token_start.offset..self.position.offset];
+        if !text.is_empty() {
+            return Some(text);
         }
+        let rest = &self.sql[self.token_start.offset..];
+        rest.chars().next().map(|c| &rest[..c.len_utf8()])
+    }
+
+    // 读取下一个字符并更新位置
+    fn bump(&mut self) -> Option<char> {
+        let c = self.iter.next()?;
+        self.position.offset += c.len_utf8();
+        if c == '\n' {
+            self.position.line += 1;
+            self.position.column = 1;
+        } else {
+            self.position.column += 1;
+        }
+        Some(c)
     }
 
     /// 消除空白字符
@@ -321,7 +478,7 @@ impl<'a> Lexer<'a> {
 
     fn next_if<F: Fn(char) -> bool>(&mut self, predicate: F) -> Option<char> {
         self.iter.peek().filter(|&c| predicate(*c))?;
-        self.iter.next()
+        self.bump()
     }
 
     /// 判断当前字符是否满足条件，如果是空白字符则跳到下一个字符
@@ -335,7 +492,7 @@ impl<'a> Lexer<'a> {
     /// 判断当前字符是否满足条件，只有Token类型才跳转到下一个，并返回Token类型
     fn next_if_token<F: Fn(char) -> Option<Token>>(&mut self, predicate: F) -> Option<Token> {
         let token = self.iter.peek().and_then(|c| {predicate(*c)})?;
-        self.iter.next();
+        self.bump();
         Some(token)
     }
 
@@ -343,13 +500,19 @@ impl<'a> Lexer<'a> {
     pub fn scan(&mut self) -> LegendDBResult<Option<Token>> {
         //清除字符串中空白部分
         self.skip_whitespace();
+        self.token_start = self.position;
+        // x 后面紧跟单引号是二进制数据
+        let mut lookahead = self.iter.clone();
+        let binary = matches!(lookahead.next(), Some('x' | 'X')) && lookahead.next() == Some('\'');
         // 根据第一个字符判断
         match self.iter.peek() {
             Some('\'') => self.scan_string(), // 扫描字符串
+            Some('`') | Some('"') => self.scan_quoted_identifier(), // 扫描带引号的表名、列名
+            Some(_) if binary => self.scan_binary(),
             // is_ascii_digit 判断是否是数字
-            Some(c) if c.is_ascii_digit() => Ok(self.scan_number()), // 扫描数字
-            // is_alphabetic 判断是否是字母
-            Some(c) if c.is_alphabetic() => Ok(self.scan_identifier()), // 扫描ident 类型
+            Some(c) if c.is_ascii_digit() => self.scan_number(), // 扫描数字
+            // is_alphabetic 判断是否是字母，标识符也可以以下划线开头
+            Some(c) if c.is_alphabetic() || *c == '_' => Ok(self.scan_identifier()), // 扫描ident 类型
             Some(_) => Ok(self.scan_symbol()),
             None => Ok(None),
         }.map(|token| {
@@ -361,37 +524,89 @@ impl<'a> Lexer<'a> {
     }
 
     /// 扫描字符串是否是单引号
+    /// 连续两个单引号表示单引号本身 'it''s'，也支持反斜杠转义 'a\'b' 'line\n'
     fn scan_string(&mut self) -> LegendDBResult<Option<Token>> {
         // 扫描字符串结束
-        if self.next_if(|c| c == '\'' || c == '\"').is_none() {
+        if self.next_if(|c| c == '\'').is_none() {
             return Ok(None);
         }
         // 扫描字符串
         let mut value = String::new();
         // 扫描字符串
         loop {
-            match self.iter.next() {
-                Some('\'') => break,
+            match self.bump() {
+                Some('\'') => match self.next_if(|c| c == '\'') {
+                    Some(c) => value.push(c),
+                    None => break,
+                },
+                Some('\\') => match self.bump() {
+                    Some('n') => value.push('\n'),
+                    Some('r') => value.push('\r'),
+                    Some('t') => value.push('\t'),
+                    Some('0') => value.push('\0'),
+                    // 其他字符转义后就是字符本身，比如 \' \\
+                    Some(c) => value.push(c),
+                    None => return Err(LegendDBError::Parser("[Lexer] Unterminated string".to_string())),
+                },
                 Some(c) => value.push(c),
-                None => return Err(LegendDBError::NotSupported)
+                None => return Err(LegendDBError::Parser("[Lexer] Unterminated string".to_string())),
             }
         }
         Ok(Some(Token::String(value)))
     }
 
+    // 扫描二进制数据 x'deadbeef'，引号中必须是偶数个十六进制数字
+    fn scan_binary(&mut self) -> LegendDBResult<Option<Token>> {
+        self.bump();
+        let Some(Token::String(hex)) = self.scan_string()? else {
+            return Ok(None);
+        };
+        if !hex.len().is_multiple_of(2) || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(LegendDBError::Parser(format!("[Lexer] Invalid binary literal x'{}'", hex)));
+        }
+        Ok(Some(Token::Binary(hex.to_lowercase())))
+    }
+
     /// 扫描数字
-    fn scan_number(&mut self) -> Option<Token> {
+    /// 支持十六进制 0xFF，以及科学计数法 1e10、1.5E-3
+    fn scan_number(&mut self) -> LegendDBResult<Option<Token>> {
         // 先扫描一部分
-        let mut num = self.next_while(|c| c.is_ascii_digit())?;
-        // 如果中间存在小数点，说明是浮点数
-        if let Some(sep) = self.next_if(|c| c == '.') {
-            num.push(sep);
-            // 扫描小数点之后的部分
-            while let Some(c) = self.next_if(|c| c.is_ascii_digit()) {
-                num.push(c);
+        let Some(mut num) = self.next_while(|c| c.is_ascii_digit()) else {
+            return Ok(None);
+        };
+        // 0x 开头的是十六进制整数
+        if num == "0" && let Some(x) = self.next_if(|c| c == 'x' || c == 'X') {
+            num.push(x);
+            match self.next_while(|c| c.is_ascii_hexdigit()) {
+                Some(digits) => num.push_str(&digits),
+                None => return Err(LegendDBError::Parser(format!("[Lexer] Invalid hexadecimal literal {}", num))),
+            }
+        } else {
+            // 如果中间存在小数点，说明是浮点数
+            if let Some(sep) = self.next_if(|c| c == '.') {
+                num.push(sep);
+                // 扫描小数点之后的部分
+                while let Some(c) = self.next_if(|c| c.is_ascii_digit()) {
+                    num.push(c);
+                }
+            }
+            // 指数部分，e 后面可以带正负号，并且必须有数字
+            if let Some(e) = self.next_if(|c| c == 'e' || c == 'E') {
+                num.push(e);
+                if let Some(sign) = self.next_if(|c| c == '+' || c == '-') {
+                    num.push(sign);
+                }
+                match self.next_while(|c| c.is_ascii_digit()) {
+                    Some(digits) => num.push_str(&digits),
+                    None => return Err(LegendDBError::Parser(format!("[Lexer] Invalid numeric literal {}", num))),
+                }
             }
         }
-        Some(Token::Number(num))
+        // 数字后面紧跟字母，比如 0xZZ、12abc，不是合法的数字
+        if let Some(&c) = self.iter.peek() && (c.is_alphanumeric() || c == '_') {
+            return Err(LegendDBError::Parser(format!("[Lexer] Invalid numeric literal {}{}", num, c)));
+        }
+        Ok(Some(Token::Number(num)))
     }
 
     // 扫描identifier类型，比如表名，字段名
@@ -405,12 +620,33 @@ impl<'a> Lexer<'a> {
         Some(Keyword::from_str(&value).map_or(Token::Identifier(value.to_lowercase()), Token::Keyword))
     }
 
+    // 扫描反引号或双引号括起来的表名、列名，比如 `order`、"My Column"
+    // 引号中可以包含空格和关键字，并且保留大小写，连续两个引号表示引号本身
+    fn scan_quoted_identifier(&mut self) -> LegendDBResult<Option<Token>> {
+        let Some(quote) = self.next_if(|c| c == '`' || c == '"') else { return Ok(None) };
+        let mut value = String::new();
+        loop {
+            match self.bump() {
+                Some(c) if c == quote => match self.next_if(|c| c == quote) {
+                    Some(c) => value.push(c),
+                    None => break,
+                },
+                Some(c) => value.push(c),
+                None => return Err(LegendDBError::Parser(format!("[Lexer] Unterminated quoted identifier {}{}", quote, value))),
+            }
+        }
+        if value.is_empty() {
+            return Err(LegendDBError::Parser("[Lexer] Empty quoted identifier".to_string()));
+        }
+        Ok(Some(Token::Identifier(value)))
+    }
+
     //扫描符号
     fn scan_symbol(&mut self) -> Option<Token> {
         // cannot borrow `*self` as mutable because it is also borrowed as immutable [E0502] mutable borrow occurs here
         // Rust 不允许在同一作用域内同时存在不可变借用和可变借用，  self.prev_token（不可变借用）和 self.next_if_token（可变借用），提前获取上一个Token，不然会报不可变
         let prev_token = self.prev_token.clone();
-        self.next_if_token(|c| match c {
+        let token = self.next_if_token(|c| match c {
             '*' => {
                 if prev_token == Some(Token::Keyword(Keyword::Select)) {
                     Some(Token::Star)
@@ -421,6 +657,8 @@ impl<'a> Lexer<'a> {
             '+' => Some(Token::Plus),
             '-' => Some(Token::Minus),
             '/' => Some(Token::Slash),
+            '%' => Some(Token::Percent),
+            '^' => Some(Token::Caret),
             ':' => Some(Token::Colon),
             '=' => Some(Token::Equal),
             '>' => Some(Token::GreaterThan),
@@ -436,7 +674,16 @@ impl<'a> Lexer<'a> {
             '{' => Some(Token::LeftBrace),
             '}' => Some(Token::RightBrace),
             _ => None,
-        })
+        })?;
+        // 两个字符组成的比较运算符
+        match token {
+            Token::GreaterThan if self.next_if(|c| c == '=').is_some() => Some(Token::GreaterThanOrEqual),
+            Token::LessThan if self.next_if(|c| c == '=').is_some() => Some(Token::LessThanOrEqual),
+            Token::LessThan if self.next_if(|c| c == '>').is_some() => Some(Token::NotEqual),
+            // 单独的 ! 不是合法的符号
+            Token::NotEqual => self.next_if(|c| c == '=').map(|_| Token::NotEqual),
+            token => Some(token),
+        }
     }
 }
 
@@ -449,6 +696,7 @@ mod tests {
         sql::parser::lexer::{Keyword, Token},
     };
     use crate::custom_error::LegendDBResult;
+    use crate::sql::parser::ast::{Consts, Expression};
 
     #[test]
     fn test_lexer_create_table() -> LegendDBResult<()> {
@@ -589,6 +837,66 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_lexer_quoted_identifier() -> LegendDBResult<()> {
+        let tokens = Lexer::new("select `order`, \"My Col\", `a``b` from \"select\";")
+            .collect::<LegendDBResult<Vec<_>>>()?;
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Keyword(Keyword::Select),
+                Token::Identifier("order".to_string()),
+                Token::Comma,
+                Token::Identifier("My Col".to_string()),
+                Token::Comma,
+                Token::Identifier("a`b".to_string()),
+                Token::Keyword(Keyword::From),
+                Token::Identifier("select".to_string()),
+                Token::Semicolon,
+            ]
+        );
+        assert!(Lexer::new("select `a from t;").collect::<LegendDBResult<Vec<_>>>().is_err());
+        assert!(Lexer::new("select `` from t;").collect::<LegendDBResult<Vec<_>>>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_lexer_string_escape() -> LegendDBResult<()> {
+        let tokens = Lexer::new(r"'it''s' 'a\'b' 'x\ny\\' ''''")
+            .collect::<LegendDBResult<Vec<_>>>()?;
+        assert_eq!(
+            tokens,
+            vec![
+                Token::String("it's".to_string()),
+                Token::String("a'b".to_string()),
+                Token::String("x\ny\\".to_string()),
+                Token::String("'".to_string()),
+            ]
+        );
+        assert!(Lexer::new(r"'abc\'").collect::<LegendDBResult<Vec<_>>>().is_err());
+        // 展示的字符串常量可以重新解析
+        let display = Expression::Consts(Consts::String(r"it's\".to_string())).to_string();
+        assert_eq!(Lexer::new(&display).collect::<LegendDBResult<Vec<_>>>()?, vec![Token::String(r"it's\".to_string())]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_lexer_binary() -> LegendDBResult<()> {
+        let tokens = Lexer::new("x'DEADbeef' X'' x 'ab'").collect::<LegendDBResult<Vec<_>>>()?;
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Binary("deadbeef".to_string()),
+                Token::Binary("".to_string()),
+                Token::Identifier("x".to_string()),
+                Token::String("ab".to_string()),
+            ]
+        );
+        assert!(Lexer::new("x'abc'").collect::<LegendDBResult<Vec<_>>>().is_err());
+        assert!(Lexer::new("x'zz'").collect::<LegendDBResult<Vec<_>>>().is_err());
+        Ok(())
+    }
+
     #[test]
     fn test_lexer_update() -> LegendDBResult<()> {
         let tokens1 = Lexer::new("update tb1 set a = 1, b = 2 where c=2 and d=4;")
@@ -597,6 +905,26 @@ mod tests {
         println!("{:?}", tokens1.clone());
         Ok(())
     }
+    #[test]
+    fn test_lexer_comparison() -> LegendDBResult<()> {
+        let tokens = Lexer::new("a>=1 b <= 2 c<>3 d != 4 e>5 f<6;")
+            .peekable()
+            .collect::<LegendDBResult<Vec<_>>>()?;
+        let operators = tokens.into_iter()
+            .filter(|t| !matches!(t, Token::Identifier(_) | Token::Number(_) | Token::Semicolon))
+            .collect::<Vec<_>>();
+        assert_eq!(operators, vec![
+            Token::GreaterThanOrEqual,
+            Token::LessThanOrEqual,
+            Token::NotEqual,
+            Token::NotEqual,
+            Token::GreaterThan,
+            Token::LessThan,
+        ]);
+        assert!(Lexer::new("a ! 1;").collect::<LegendDBResult<Vec<_>>>().is_err());
+        Ok(())
+    }
+
     #[test]
     fn test_lexer_delete() -> LegendDBResult<()> {
         let tokens1 = Lexer::new("delete tb1 where c=2;")
@@ -1,25 +1,82 @@
 use std::collections::BTreeMap;
-use std::iter::Peekable;
-use crate::sql::parser::ast::{Column, Consts, Expression, FromItem, JoinType, Operation, OrderDirection, Statement};
+use crate::sql::parser::ast::{AlterTableOperation, Column, ConstraintKind, Consts, Expression, ForeignKey, FromItem, JoinType, JoinUsing, Operation, OrderDirection, ReferentialAction, Statement, TableConstraint};
 use crate::sql::parser::ast::Statement::Select;
-use crate::sql::parser::lexer::{Keyword, Lexer, Token};
-use crate::sql::types::DataType;
+use crate::sql::parser::lexer::{Keyword, Lexer, Position, Token};
+use crate::sql::types::{parse_hex, DataType, IntWidth};
+use crate::sql::types::datetime::{self, Interval};
+use crate::sql::types::uuid;
 use crate::custom_error::{LegendDBError, LegendDBResult};
 
+// 中缀运算符的结合性，比较运算不能连续使用
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Associativity {
+    Left,
+    Right,
+    None,
+}
+
 pub struct Parser<'a> {
-    lexer: Peekable<Lexer<'a>>
+    lexer: Lexer<'a>,
+    // 预读的 Token，None 表示还没有预读
+    peeked: Option<LegendDBResult<Option<Token>>>,
+    // 预读的 Token 的位置和原文
+    peeked_at: TokenAt<'a>,
+    // 最后读取的 Token 的位置和原文，读取了后面的 Token 之后才发现的错误用它定位
+    consumed_at: TokenAt<'a>,
 }
 
+// Token 的位置和原文，原文为 None 表示已经到结尾
+type TokenAt<'a> = (Position, Option<&'a str>);
+
 
 impl<'a> Parser<'a> {
+    const NOT_PRECEDENCE: u8 = 3;
+    const COMPARISON_PRECEDENCE: u8 = 4;
+    const ADDITIVE_PRECEDENCE: u8 = 5;
+    const EXPONENT_PRECEDENCE: u8 = 8;
+
     pub fn new(input: &'a str) -> Self {
         Parser {
-            lexer: Lexer::new(input).peekable()
+            lexer: Lexer::new(input),
+            peeked: None,
+            peeked_at: (Position::default(), None),
+            consumed_at: (Position::default(), None),
         }
     }
 
     // 解析，获取到抽象语法树
+    // 语法错误带上出错的位置，也就是最后读取的 Token 的位置
     pub fn parse(&mut self) -> LegendDBResult<Statement> {
+        self.parse_single().map_err(|e| self.locate(e))
+    }
+
+    // 解析多条语句，每条语句都以分号结束
+    pub fn parse_batch(&mut self) -> LegendDBResult<Vec<Statement>> {
+        self.parse_statements().map_err(|e| self.locate(e))
+    }
+
+    fn locate(&self, err: LegendDBError) -> LegendDBError {
+        let LegendDBError::Parser(message) = err else { return err };
+        Self::syntax_error((self.lexer.token_position(), self.lexer.token_text()), message)
+    }
+
+    // 在指定的 Token 处创建语法错误，已经带位置的错误 locate 不会再修改
+    fn syntax_error((position, near): TokenAt, message: String) -> LegendDBError {
+        LegendDBError::Syntax {
+            message,
+            offset: position.offset,
+            line: position.line,
+            column: position.column,
+            near: near.map(str::to_string),
+        }
+    }
+
+    // 在最后读取的 Token 处创建语法错误
+    fn error_at_consumed(&self, message: String) -> LegendDBError {
+        Self::syntax_error(self.consumed_at, message)
+    }
+
+    fn parse_single(&mut self) -> LegendDBResult<Statement> {
         let stmt = self.parse_statement()?;
         // 期望sql语句结束存在分号
         self.next_expect(Token::Semicolon)?;
@@ -30,6 +87,18 @@ impl<'a> Parser<'a> {
         Ok(stmt)
     }
 
+    fn parse_statements(&mut self) -> LegendDBResult<Vec<Statement>> {
+        let mut stmts = Vec::new();
+        while self.custom_peek()?.is_some() {
+            stmts.push(self.parse_statement()?);
+            self.next_expect(Token::Semicolon)?;
+        }
+        if stmts.is_empty() {
+            return Err(LegendDBError::Parser("[Parser] Empty batch".to_string()));
+        }
+        Ok(stmts)
+    }
+
     fn parse_statement(&mut self) -> LegendDBResult<Statement> {
         // 查看第一个token类型
         match self.custom_peek()? {
@@ -40,30 +109,114 @@ impl<'a> Parser<'a> {
             Some(Token::Keyword(Keyword::Update)) => self.parse_update(),
             Some(Token::Keyword(Keyword::Delete)) => self.parse_delete(),
             Some(Token::Keyword(Keyword::Drop)) => self.parse_drop(),
+            Some(Token::Keyword(Keyword::Set)) => self.parse_set(),
+            Some(Token::Keyword(Keyword::Alter)) => self.parse_alter(),
+            Some(Token::Keyword(Keyword::Check)) => self.parse_check(),
+            Some(Token::Keyword(Keyword::Explain)) => self.parse_explain(),
+            Some(Token::Keyword(Keyword::Show)) => self.parse_show(),
             Some(token) => Err(LegendDBError::Parser(format!("[Parser] Unexpected token: {:?}", token))),
             None => Err(LegendDBError::Parser("[Parser] Unexpected end of input".to_string())),
         }
     }
 
+    // 解析drop table/database [if exists] name
     fn parse_drop(&mut self) -> LegendDBResult<Statement> {
         self.next_expect(Token::Keyword(Keyword::Drop))?;
         match self.custom_next()? {
             Token::Keyword(Keyword::Database) => {
+                let if_exists = self.parse_if_exists()?;
                 let database_name = self.next_ident()?;
                 Ok(Statement::DropDatabase {
                     database_name,
+                    if_exists,
                 })
             }
             Token::Keyword(Keyword::Table) => {
+                let if_exists = self.parse_if_exists()?;
                 let table_name =self.next_ident()?;
                 Ok(Statement::DropTable {
                     table_name,
+                    if_exists,
                 })
             },
             _ => Err(LegendDBError::Parser("[Parser] Unexpected token".to_string())),
         }
     }
-    
+
+    // 解析alter table
+    // alter table name add [column] column_def
+    // alter table name drop [column] column_name
+    // alter table name modify [column] column_def
+    fn parse_alter(&mut self) -> LegendDBResult<Statement> {
+        self.next_expect(Token::Keyword(Keyword::Alter))?;
+        self.next_expect(Token::Keyword(Keyword::Table))?;
+        let table_name = self.next_ident()?;
+        let operation = match self.custom_next()? {
+            Token::Keyword(Keyword::Add) => {
+                self.next_if_token(Token::Keyword(Keyword::Column));
+                AlterTableOperation::AddColumn(self.parse_ddl_column()?)
+            },
+            Token::Keyword(Keyword::Drop) => {
+                self.next_if_token(Token::Keyword(Keyword::Column));
+                AlterTableOperation::DropColumn(self.next_ident()?)
+            },
+            Token::Keyword(Keyword::Modify) => {
+                self.next_if_token(Token::Keyword(Keyword::Column));
+                AlterTableOperation::ModifyColumn(self.parse_ddl_column()?)
+            },
+            token => return Err(LegendDBError::Parser(format!("[Parser] Unexpected token: {:?}", token))),
+        };
+        Ok(Statement::AlterTable {
+            table_name,
+            operation,
+        })
+    }
+
+    // 解析show index recommendations，index 和 recommendations 不是关键字
+    fn parse_show(&mut self) -> LegendDBResult<Statement> {
+        self.next_expect(Token::Keyword(Keyword::Show))?;
+        for word in ["index", "recommendations"] {
+            match self.custom_next()? {
+                Token::Identifier(ident) if ident == word => {},
+                token => return Err(LegendDBError::Parser(format!("[Parser] Unexpected token: {:?}", token))),
+            }
+        }
+        Ok(Statement::ShowIndexRecommendations)
+    }
+
+    // 解析check table name
+    fn parse_check(&mut self) -> LegendDBResult<Statement> {
+        self.next_expect(Token::Keyword(Keyword::Check))?;
+        self.next_expect(Token::Keyword(Keyword::Table))?;
+        Ok(Statement::CheckTable {
+            table_name: self.next_ident()?,
+        })
+    }
+
+    // 解析 explain [analyze]，explain 支持查询和修改数据的语句，explain analyze 会执行语句，只支持查询
+    fn parse_explain(&mut self) -> LegendDBResult<Statement> {
+        self.next_expect(Token::Keyword(Keyword::Explain))?;
+        let analyze = self.next_if_token(Token::Keyword(Keyword::Analyze)).is_some();
+        let statement = match self.custom_peek()? {
+            Some(Token::Keyword(Keyword::Select)) => self.parse_select()?,
+            Some(Token::Keyword(Keyword::Insert)) if !analyze => self.parse_insert()?,
+            Some(Token::Keyword(Keyword::Update)) if !analyze => self.parse_update()?,
+            Some(Token::Keyword(Keyword::Delete)) if !analyze => self.parse_delete()?,
+            token if analyze => return Err(LegendDBError::Parser(format!("[Parser] EXPLAIN ANALYZE only supports SELECT, got {:?}", token))),
+            token => return Err(LegendDBError::Parser(format!("[Parser] EXPLAIN only supports SELECT, INSERT, UPDATE and DELETE, got {:?}", token))),
+        };
+        Ok(Statement::Explain { statement: Box::new(statement), analyze })
+    }
+
+    // 解析可选的 if exists
+    fn parse_if_exists(&mut self) -> LegendDBResult<bool> {
+        if self.next_if_token(Token::Keyword(Keyword::If)).is_none() {
+            return Ok(false);
+        }
+        self.next_expect(Token::Keyword(Keyword::Exists))?;
+        Ok(true)
+    }
+
     // 解析delete
     fn parse_delete(&mut self) -> LegendDBResult<Statement> {
         self.next_expect(Token::Keyword(Keyword::Delete))?;
@@ -76,6 +229,22 @@ impl<'a> Parser<'a> {
         })
     }
 
+    // 解析set，设置会话变量
+    fn parse_set(&mut self) -> LegendDBResult<Statement> {
+        self.next_expect(Token::Keyword(Keyword::Set))?;
+        let name = self.next_ident()?;
+        self.next_expect(Token::Equal)?;
+        // ON 是关键字，SET name = ON 需要单独处理
+        let value = match self.next_if_token(Token::Keyword(Keyword::On)) {
+            Some(_) => Consts::String("on".to_string()).into(),
+            None => self.parse_expression()?,
+        };
+        Ok(Statement::Set {
+            name,
+            value,
+        })
+    }
+
     // 解析use
     fn parse_use(&mut self) -> LegendDBResult<Statement> {
         self.next_expect(Token::Keyword(Keyword::Use))?;
@@ -95,11 +264,12 @@ impl<'a> Parser<'a> {
         let mut columns = BTreeMap::new();
         loop {
             let column_name = self.next_ident()?;
+            let column_at = self.consumed_at;
             self.next_expect(Token::Equal)?;
             let expr = self.parse_expression()?;
-            // 判断是否重复
+            // 判断是否重复，错误的位置是重复的列名
             if columns.contains_key(&column_name) {
-                return Err(LegendDBError::Parser(format!("[Parser] Duplicate column {} for update", column_name)));
+                return Err(Self::syntax_error(column_at, format!("[Parser] Duplicate column {} for update", column_name)));
             }
             columns.insert(column_name, expr);
             // 如果没有逗号则跳出循环
@@ -118,28 +288,33 @@ impl<'a> Parser<'a> {
     // 解析select语句，暂时只支持select * from
     fn parse_select(&mut self) -> LegendDBResult<Statement> {
         // 解析select
-        Ok(Select {
-            columns: self.parse_select_columns()?,
-            from: self.parse_from()?,
-            where_clause: self.parse_where_clause()?,
-            group_by: self.parse_group_by()?,
-            having: self.parse_having()?,
-            order_by: self.parse_order_by()?,
-            limit: {
-                if self.next_if_token(Token::Keyword(Keyword::Limit)).is_some() {
-                    Some(self.parse_expression()?)
-                } else {
-                    None
-                }
-            },
-            offset: {
-                if self.next_if_token(Token::Keyword(Keyword::Offset)).is_some() {
-                    Some(self.parse_expression()?)
-                } else {
-                    None
-                }
-            },
-        })
+        let columns = self.parse_select_columns()?;
+        let from = self.parse_from()?;
+        let where_clause = self.parse_where_clause()?;
+        let group_by = self.parse_group_by()?;
+        let having = self.parse_having()?;
+        let order_by = self.parse_order_by()?;
+        let (limit, offset) = self.parse_limit()?;
+        Ok(Select { columns, from, where_clause, group_by, having, order_by, limit, offset })
+    }
+
+    // 解析 LIMIT count [OFFSET offset]，返回 (limit, offset)
+    // LIMIT ALL 表示不限制行数，也支持 MySQL 的 LIMIT offset, count
+    fn parse_limit(&mut self) -> LegendDBResult<(Option<Expression>, Option<Expression>)> {
+        let mut limit = None;
+        if self.next_if_token(Token::Keyword(Keyword::Limit)).is_some()
+            && self.next_if_token(Token::Keyword(Keyword::All)).is_none() {
+            let count = self.parse_expression()?;
+            if self.next_if_token(Token::Comma).is_some() {
+                return Ok((Some(self.parse_expression()?), Some(count)));
+            }
+            limit = Some(count);
+        }
+        let offset = match self.next_if_token(Token::Keyword(Keyword::Offset)) {
+            Some(_) => Some(self.parse_expression()?),
+            None => None,
+        };
+        Ok((limit, offset))
     }
 
     // 解析insert into
@@ -216,10 +391,16 @@ impl<'a> Parser<'a> {
         // 表名之后是一个括号，里面是字段
         self.next_expect(Token::LeftParen)?;
 
-        // 解析列信息
+        // 解析列信息以及表级约束
         let mut columns = vec![];
+        let mut constraints = vec![];
         loop {
-            columns.push(self.parse_ddl_column()?);
+            match self.custom_peek()? {
+                Some(Token::Keyword(Keyword::Constraint | Keyword::Primary | Keyword::Unique | Keyword::Foreign | Keyword::Check)) => {
+                    constraints.push(self.parse_table_constraint()?);
+                },
+                _ => columns.push(self.parse_ddl_column()?),
+            }
             // 如果后面没有逗号，列解析完成，跳出
             if self.next_if_token(Token::Comma).is_none() {
                 break;
@@ -229,26 +410,152 @@ impl<'a> Parser<'a> {
         Ok(Statement::CreateTable {
             name: table_name,
             columns,
+            constraints,
         })
 
     }
 
-    fn parse_ddl_column(&mut self) -> LegendDBResult<Column> {
-        let mut column = Column {
-            name: self.next_ident()?,
-            data_type: match self.custom_next()? {
-                Token::Keyword(Keyword::Int) | Token::Keyword(Keyword::Integer) => DataType::Integer,
-                Token::Keyword(Keyword::Boolean) | Token::Keyword(Keyword::Bool) => DataType::Boolean,
-                Token::Keyword(Keyword::Float) | Token::Keyword(Keyword::Double) => DataType::Float,
-                Token::Keyword(Keyword::String) | Token::Keyword(Keyword::Varchar) | Token::Keyword(Keyword::Text) => DataType::String,
+    // 解析表级约束 [constraint name] primary key (a, b) | unique (a)
+    fn parse_table_constraint(&mut self) -> LegendDBResult<TableConstraint> {
+        let name = match self.next_if_token(Token::Keyword(Keyword::Constraint)) {
+            Some(_) => Some(self.next_ident()?),
+            None => None,
+        };
+        let kind = match self.custom_next()? {
+            Token::Keyword(Keyword::Primary) => {
+                self.next_expect(Token::Keyword(Keyword::Key))?;
+                ConstraintKind::PrimaryKey(self.parse_ident_list()?)
+            },
+            Token::Keyword(Keyword::Unique) => ConstraintKind::Unique(self.parse_ident_list()?),
+            Token::Keyword(Keyword::Foreign) => {
+                self.next_expect(Token::Keyword(Keyword::Key))?;
+                let columns = self.parse_ident_list()?;
+                self.next_expect(Token::Keyword(Keyword::References))?;
+                ConstraintKind::ForeignKey(self.parse_references(columns)?)
+            },
+            Token::Keyword(Keyword::Check) => ConstraintKind::Check(self.parse_check_condition()?),
+            token => return Err(LegendDBError::Parser(format!("[Parser] Unexpected token: {:?}", token))),
+        };
+        Ok(TableConstraint { name, kind })
+    }
+
+    // 解析 check 之后括号中的条件
+    fn parse_check_condition(&mut self) -> LegendDBResult<Expression> {
+        self.next_expect(Token::LeftParen)?;
+        let condition = self.parse_condition()?;
+        self.next_expect(Token::RightParen)?;
+        Ok(condition)
+    }
+
+    // 解析 references 之后的部分 table [(a, b)] [on delete action] [on update action]
+    fn parse_references(&mut self, columns: Vec<String>) -> LegendDBResult<ForeignKey> {
+        let ref_table = self.next_ident()?;
+        let ref_columns = match self.custom_peek()? {
+            Some(Token::LeftParen) => self.parse_ident_list()?,
+            _ => vec![],
+        };
+        let mut fk = ForeignKey {
+            columns,
+            ref_table,
+            ref_columns,
+            on_delete: ReferentialAction::NoAction,
+            on_update: ReferentialAction::NoAction,
+        };
+        while self.next_if_token(Token::Keyword(Keyword::On)).is_some() {
+            match self.custom_next()? {
+                Token::Keyword(Keyword::Delete) => fk.on_delete = self.parse_referential_action()?,
+                Token::Keyword(Keyword::Update) => fk.on_update = self.parse_referential_action()?,
+                token => return Err(LegendDBError::Parser(format!("[Parser] Unexpected token: {:?}", token))),
+            }
+        }
+        Ok(fk)
+    }
+
+    // cascade、restrict、no action 不是保留字，按标识符解析
+    fn parse_referential_action(&mut self) -> LegendDBResult<ReferentialAction> {
+        Ok(match self.custom_next()? {
+            Token::Keyword(Keyword::Set) => match self.custom_next()? {
+                Token::Keyword(Keyword::Null) => ReferentialAction::SetNull,
+                Token::Keyword(Keyword::Default) => ReferentialAction::SetDefault,
+                token => return Err(LegendDBError::Parser(format!("[Parser] Unexpected token: {:?}", token))),
+            },
+            Token::Identifier(ident) if ident == "cascade" => ReferentialAction::Cascade,
+            Token::Identifier(ident) if ident == "restrict" => ReferentialAction::Restrict,
+            Token::Identifier(ident) if ident == "no" => match self.custom_next()? {
+                Token::Identifier(ident) if ident == "action" => ReferentialAction::NoAction,
                 token => return Err(LegendDBError::Parser(format!("[Parser] Unexpected token: {:?}", token))),
             },
+            token => return Err(LegendDBError::Parser(format!("[Parser] Unexpected token: {:?}", token))),
+        })
+    }
+
+    // 解析括号中的列名列表 (a, b)
+    fn parse_ident_list(&mut self) -> LegendDBResult<Vec<String>> {
+        self.next_expect(Token::LeftParen)?;
+        let mut idents = vec![];
+        loop {
+            idents.push(self.next_ident()?);
+            match self.custom_next()? {
+                Token::RightParen => break,
+                Token::Comma => {}
+                token => return Err(LegendDBError::Parser(format!("[Parser] Unexpected token: {:?}", token)))
+            }
+        }
+        Ok(idents)
+    }
+
+    // 解析数据类型
+    fn parse_data_type(&mut self) -> LegendDBResult<DataType> {
+        Ok(match self.custom_next()? {
+            Token::Keyword(Keyword::Int) | Token::Keyword(Keyword::Integer) | Token::Keyword(Keyword::TinyInt)
+            | Token::Keyword(Keyword::SmallInt) | Token::Keyword(Keyword::BigInt) => DataType::Integer,
+            Token::Keyword(Keyword::Boolean) | Token::Keyword(Keyword::Bool) => DataType::Boolean,
+            Token::Keyword(Keyword::Float) | Token::Keyword(Keyword::Double) => DataType::Float,
+            Token::Keyword(Keyword::String) | Token::Keyword(Keyword::Varchar) | Token::Keyword(Keyword::Text) | Token::Keyword(Keyword::Char) => DataType::String,
+            Token::Keyword(Keyword::Date) => DataType::Date,
+            Token::Keyword(Keyword::Time) => DataType::Time,
+            Token::Keyword(Keyword::Timestamp) | Token::Keyword(Keyword::Datetime) => DataType::DateTime,
+            Token::Keyword(Keyword::Binary) | Token::Keyword(Keyword::Blob) => DataType::Binary,
+            // uuid 不是关键字，可以继续作为列名
+            Token::Identifier(ident) if ident == "uuid" => DataType::Uuid,
+            token => return Err(LegendDBError::Parser(format!("[Parser] Unexpected token: {:?}", token))),
+        })
+    }
+
+    fn parse_ddl_column(&mut self) -> LegendDBResult<Column> {
+        let name = self.next_ident()?;
+        // serial 等同于 int auto_increment，兼容 Postgres 的写法，serial 不是关键字
+        let serial = self.next_if_token(Token::Identifier("serial".to_string())).is_some();
+        // 整数类型记录宽度，写入时检查范围
+        let bytes = match self.custom_peek()? {
+            _ if serial => Some(4),
+            Some(Token::Keyword(Keyword::TinyInt)) => Some(1),
+            Some(Token::Keyword(Keyword::SmallInt)) => Some(2),
+            Some(Token::Keyword(Keyword::Int)) | Some(Token::Keyword(Keyword::Integer)) => Some(4),
+            Some(Token::Keyword(Keyword::BigInt)) => Some(8),
+            _ => None,
+        };
+        let mut column = Column {
+            name,
+            data_type: if serial { DataType::Integer } else { self.parse_data_type()? },
+            max_length: None,
+            int_width: None,
             nullable: None,
             default: None,
             is_primary_key: false,
-            auto_increment: false,
+            auto_increment: serial,
             unique: false,
+            references: None,
+            check: None,
         };
+        if column.data_type == DataType::String && self.next_if_token(Token::LeftParen).is_some() {
+            column.max_length = Some(self.parse_max_length()?);
+            self.next_expect(Token::RightParen)?;
+        }
+        if let Some(bytes) = bytes {
+            let unsigned = self.next_if_token(Token::Keyword(Keyword::Unsigned)).is_some();
+            column.int_width = Some(IntWidth { bytes, unsigned });
+        }
         // 解析列的默认值，以及是否可以为空
         while let Some(Token::Keyword(keyword)) = self.next_if_keyword() {
             match keyword {
@@ -262,107 +569,253 @@ impl<'a> Parser<'a> {
                     self.next_expect(Token::Keyword(Keyword::Key))?;
                     column.is_primary_key = true;
                 },
+                Keyword::Unique => column.unique = true,
+                Keyword::AutoIncrement => column.auto_increment = true,
+                Keyword::References => column.references = Some(self.parse_references(vec![column.name.clone()])?),
+                Keyword::Check => column.check = Some(self.parse_check_condition()?),
                 k => return Err(LegendDBError::Parser(format!("[Parser] Unexpected keyword {:?}", k))),
             }
         }
         Ok(column)
     }
 
-    // 解析字段
-    // fn parse_field(&mut self) -> LegendDBResult<String> {
-    //     let field_name = self.next_ident()?;
-    //     self.next_expect(Token::Dot)?;
-    //     let table_name = self.next_ident()?;
-    //     Ok(Field {
-    //         field_name,
-    //         table_name,
-    //     })
-    // }
-
-    // 解析operation expression
-    fn parser_having_expression(&mut self) -> LegendDBResult<Expression> {
-        let left = self.parse_expression()?;
-        Ok(match self.custom_next()? {
-            Token::Equal => Expression::Operation(Operation::Equal(
-                Box::new(left),
-                Box::new(self.parse_expression()?),
-            )),
-            Token::GreaterThan => Expression::Operation(Operation::GreaterThan(
-                Box::new(left),
-                Box::new(self.parse_expression()?),
-            )),
-            Token::LessThan => Expression::Operation(Operation::LessThan(
-                Box::new(left),
-                Box::new(self.parse_expression()?),
-            )),
-            Token::NotEqual => Expression::Operation(Operation::NotEqual(
-                Box::new(left),
-                Box::new(self.parse_expression()?),
-            )),
-            _ => return Err(LegendDBError::Internal("Unexpected token".into())),
-        })
+    // 字符串类型的长度 varchar(255)，必须是正整数
+    fn parse_max_length(&mut self) -> LegendDBResult<usize> {
+        match self.custom_next()? {
+            Token::Number(n) => match n.parse::<usize>() {
+                Ok(length) if length > 0 => Ok(length),
+                _ => Err(self.error_at_consumed(format!("[Parser] invalid string length {}", n))),
+            },
+            token => Err(LegendDBError::Parser(format!("[Parser] Unexpected token: {:?}", token))),
+        }
     }
-    fn parse_operation_expression(&mut self) -> LegendDBResult<Option<Vec<Expression>>> {
-        let mut conditions = Vec::new();
-        loop {
-            let left = self.parse_expression()?;
-            
-            let op = self.custom_next()?;
-            match op {
-                Token::Equal => {
-                    let right = self.parse_expression()?;
-                    conditions.push(Expression::Operation(Operation::Equal(Box::new(left), Box::new(right))));
-                },
-                Token::NotEqual => {
-                    let right = self.parse_expression()?;
-                    conditions.push(Expression::Operation(Operation::NotEqual(Box::new(left), Box::new(right))));
-                },
-                Token::GreaterThan => {
-                    let right = self.parse_expression()?;
-                    conditions.push(Expression::Operation(Operation::GreaterThan(Box::new(left), Box::new(right))));
-                },
-                Token::LessThan => {
-                    let right = self.parse_expression()?;
-                    conditions.push(Expression::Operation(Operation::LessThan(Box::new(left), Box::new(right))));
-                },
-                _ => return Err(LegendDBError::NotSupported)
-            }
-            if self.next_if_token(Token::Keyword(Keyword::And)).is_none() && self.next_if_token(Token::Keyword(Keyword::Or)).is_none(){
+
+    // 解析列名，列名前面可以带表名或别名 t1.a
+    fn parse_column_name(&mut self, name: String) -> LegendDBResult<String> {
+        if self.next_if_token(Token::Dot).is_some() {
+            return Ok(format!("{}.{}", name, self.next_ident()?));
+        }
+        Ok(name)
+    }
+
+    // 解析条件表达式，包括逻辑运算、比较运算和算术运算
+    fn parse_condition(&mut self) -> LegendDBResult<Expression> {
+        self.parse_expression_with(0)
+    }
+
+    // 括号中的子查询 (select ...)
+    fn parse_subquery(&mut self) -> LegendDBResult<Expression> {
+        self.next_expect(Token::LeftParen)?;
+        let query = self.parse_select()?;
+        self.next_expect(Token::RightParen)?;
+        Ok(Expression::Subquery(Box::new(query)))
+    }
+
+    // 解析算术表达式，不包括比较运算和逻辑运算，比如插入的值、查询的列、排序的表达式
+    fn parse_expression(&mut self) -> LegendDBResult<Expression> {
+        self.parse_expression_with(Self::ADDITIVE_PRECEDENCE)
+    }
+
+    // 按优先级解析表达式（Pratt 解析），只解析优先级不低于 min_precedence 的中缀运算符，优先级更低的留给外层
+    fn parse_expression_with(&mut self, min_precedence: u8) -> LegendDBResult<Expression> {
+        let mut left = self.parse_prefix_expression()?;
+        // 比较运算不能连续使用，a = b = c 中第二个 = 留给外层报错
+        let mut compared = false;
+        while let Some(token) = self.custom_peek()? {
+            let Some((precedence, associativity)) = Self::infix_precedence(&token) else {
+                break;
+            };
+            if precedence < min_precedence || (compared && associativity == Associativity::None) {
                 break;
             }
+            self.custom_next()?;
+            // 左结合的运算符，右边只能是优先级更高的运算
+            let next_precedence = match associativity {
+                Associativity::Right => precedence,
+                _ => precedence + 1,
+            };
+            left = self.parse_infix_expression(token, left, next_precedence)?;
+            compared = associativity == Associativity::None;
         }
-        Ok(Some(conditions))
+        Ok(left)
+    }
 
+    // 中缀运算符的优先级和结合性，新增运算符时在这里加上优先级，在 parse_infix_expression 中构建运算
+    // 优先级从低到高依次为 OR、AND、NOT、比较运算、加减、乘除取模、正负号、乘方
+    fn infix_precedence(token: &Token) -> Option<(u8, Associativity)> {
+        Some(match token {
+            Token::Keyword(Keyword::Or) => (1, Associativity::Left),
+            Token::Keyword(Keyword::And) => (2, Associativity::Left),
+            // NOT 作为中缀运算符时只能是 NOT LIKE、NOT IN
+            Token::Equal | Token::NotEqual | Token::GreaterThan | Token::LessThan
+            | Token::GreaterThanOrEqual | Token::LessThanOrEqual
+            | Token::Keyword(Keyword::Like | Keyword::In | Keyword::Not) => (Self::COMPARISON_PRECEDENCE, Associativity::None),
+            Token::Plus | Token::Minus => (Self::ADDITIVE_PRECEDENCE, Associativity::Left),
+            Token::Asterisk | Token::Slash | Token::Percent => (6, Associativity::Left),
+            Token::Caret => (Self::EXPONENT_PRECEDENCE, Associativity::Right),
+            _ => return None,
+        })
     }
-    // 解析表达式
-    fn parse_expression(&mut self) -> LegendDBResult<Expression> {
+
+    // 运算符已经读取，解析右边的表达式并构建运算
+    fn parse_infix_expression(&mut self, token: Token, left: Expression, precedence: u8) -> LegendDBResult<Expression> {
+        let left = Box::new(left);
+        let operation = match token {
+            // a not like b、a not in (select ...)
+            Token::Keyword(Keyword::Not) => {
+                let token = match self.custom_next()? {
+                    token @ Token::Keyword(Keyword::Like | Keyword::In) => token,
+                    _ => return Err(LegendDBError::Parser("[Parser] Expected LIKE or IN after NOT".to_string())),
+                };
+                let expr = self.parse_infix_expression(token, *left, precedence)?;
+                return Ok(Expression::Operation(Operation::Not(Box::new(expr))));
+            },
+            // a in (select ...)
+            Token::Keyword(Keyword::In) => Operation::In(left, Box::new(self.parse_subquery()?)),
+            token => {
+                let right = Box::new(self.parse_expression_with(precedence)?);
+                match token {
+                    Token::Keyword(Keyword::Or) => Operation::Or(left, right),
+                    Token::Keyword(Keyword::And) => Operation::And(left, right),
+                    Token::Keyword(Keyword::Like) => Operation::Like(left, right),
+                    Token::Equal => Operation::Equal(left, right),
+                    Token::NotEqual => Operation::NotEqual(left, right),
+                    Token::GreaterThan => Operation::GreaterThan(left, right),
+                    Token::LessThan => Operation::LessThan(left, right),
+                    Token::GreaterThanOrEqual => Operation::GreaterThanOrEqual(left, right),
+                    Token::LessThanOrEqual => Operation::LessThanOrEqual(left, right),
+                    Token::Plus => Operation::Add(left, right),
+                    Token::Minus => Operation::Subtract(left, right),
+                    Token::Asterisk => Operation::Multiply(left, right),
+                    Token::Slash => Operation::Divide(left, right),
+                    Token::Percent => Operation::Modulo(left, right),
+                    Token::Caret => Operation::Exponentiate(left, right),
+                    t => return Err(LegendDBError::Parser(format!("[Parser] Unexpected operator {}", t))),
+                }
+            },
+        };
+        Ok(Expression::Operation(operation))
+    }
+
+    // 前缀运算符 NOT 和正负号，正负号的优先级低于乘方，-2 ^ 2 = -4
+    fn parse_prefix_expression(&mut self) -> LegendDBResult<Expression> {
+        if self.next_if_token(Token::Keyword(Keyword::Not)).is_some() {
+            return Ok(Expression::Operation(Operation::Not(Box::new(self.parse_expression_with(Self::NOT_PRECEDENCE)?))));
+        }
+        if self.next_if_token(Token::Minus).is_some() {
+            // i64 的最小值去掉负号之后超出了范围，需要和负号一起解析
+            if self.next_if_token(Token::Number(i64::MIN.unsigned_abs().to_string())).is_some() {
+                return Ok(Consts::Integer(i64::MIN).into());
+            }
+            return Ok(Expression::Operation(Operation::Negate(Box::new(self.parse_expression_with(Self::EXPONENT_PRECEDENCE)?))));
+        }
+        if self.next_if_token(Token::Plus).is_some() {
+            return self.parse_expression_with(Self::EXPONENT_PRECEDENCE);
+        }
+        self.parse_atom_expression()
+    }
+
+    // 解析列名、函数、常量以及括号中的表达式
+    fn parse_atom_expression(&mut self) -> LegendDBResult<Expression> {
         Ok(match self.custom_next()? {
+            // 括号中可以是算术运算，也可以是条件，比如 (a + b) * c 和 (a = 1 or b = 2)
+            Token::LeftParen => {
+                let expr = self.parse_condition()?;
+                self.next_expect(Token::RightParen)?;
+                expr
+            },
+            // EXISTS (select ...)
+            Token::Keyword(Keyword::Exists) => Expression::Operation(Operation::Exists(Box::new(self.parse_subquery()?))),
+            // CAST(expr AS type)
+            Token::Keyword(Keyword::Cast) => {
+                self.next_expect(Token::LeftParen)?;
+                let expr = self.parse_expression()?;
+                self.next_expect(Token::Keyword(Keyword::As))?;
+                let data_type = self.parse_data_type()?;
+                self.next_expect(Token::RightParen)?;
+                Expression::Cast(Box::new(expr), data_type)
+            },
+            // UUID '123e4567-e89b-42d3-a456-426614174000'
+            Token::Identifier(ident) if ident == "uuid" && matches!(self.custom_peek()?, Some(Token::String(_))) => {
+                let Token::String(s) = self.custom_next()? else { unreachable!() };
+                Consts::Uuid(uuid::parse(&s).ok_or_else(|| self.error_at_consumed(format!(
+                    "[Parser] invalid UUID literal '{}', expected xxxxxxxx-xxxx-xxxx-xxxx-xxxxxxxxxxxx", s
+                )))?).into()
+            },
             Token::Identifier(ident) => {
                 // 解析函数
                 if self.next_if_token(Token::LeftParen).is_some() {
+                    // 没有参数的函数 now()
+                    if self.next_if_token(Token::RightParen).is_some() {
+                        return Ok(Expression::Function(ident, String::new()));
+                    }
+                    // count(*) 统计所有的行
+                    if self.next_if_token(Token::Asterisk).is_some() {
+                        if !ident.eq_ignore_ascii_case("count") {
+                            return Err(LegendDBError::Parser(format!("[Parser] Unexpected * in function {}", ident)));
+                        }
+                        self.next_expect(Token::RightParen)?;
+                        return Ok(Expression::Function(ident, "*".to_string()));
+                    }
                     // 取出列名
                     let col_name = self.next_ident()?;
+                    let col_name = self.parse_column_name(col_name)?;
                     self.next_expect(Token::RightParen)?;
                     Expression::Function(ident.clone(), col_name)
                     // 解析函数
                 } else {
                     // 解析列名
-                    Expression::Field(ident)
+                    Expression::Field(self.parse_column_name(ident)?)
                 }
             },
             Token::Number(n) => {
-                if n.chars().all(|c| c.is_ascii_digit()) {
-                    // 整数
-                    Consts::Integer(n.parse()?).into()
+                if let Some(hex) = n.strip_prefix("0x").or_else(|| n.strip_prefix("0X")) {
+                    // 十六进制整数，超出 i64 范围时报错
+                    Consts::Integer(i64::from_str_radix(hex, 16).map_err(|_| self.error_at_consumed(format!(
+                        "[Parser] numeric literal {} is out of range for INTEGER ({} to {})", n, i64::MIN, i64::MAX
+                    )))?).into()
+                } else if n.chars().all(|c| c.is_ascii_digit()) {
+                    // 整数，超出 i64 范围时报错
+                    Consts::Integer(n.parse().map_err(|_| self.error_at_consumed(format!(
+                        "[Parser] numeric literal {} is out of range for INTEGER ({} to {})", n, i64::MIN, i64::MAX
+                    )))?).into()
                 } else {
-                    // 浮点数
-                    Consts::Float(n.parse()?).into()
+                    // 浮点数，包括科学计数法 1e10、1.5E-3
+                    let f: f64 = n.parse()?;
+                    if !f.is_finite() {
+                        return Err(self.error_at_consumed(format!("[Parser] numeric literal {} is out of range for FLOAT", n)));
+                    }
+                    Consts::Float(f).into()
                 }
             }
             Token::String(s) => Consts::String(s).into(),
+            Token::Binary(hex) => Consts::Binary(parse_hex(&hex)
+                .ok_or_else(|| self.error_at_consumed(format!("[Parser] invalid binary literal x'{}'", hex)))?).into(),
             Token::Keyword(Keyword::True) => Consts::Boolean(true).into(),
             Token::Keyword(Keyword::False) => Consts::Boolean(false).into(),
             Token::Keyword(Keyword::Null) => Consts::Null.into(),
+            // 时间间隔 INTERVAL '1 day'
+            Token::Keyword(Keyword::Interval) => match self.custom_next()? {
+                Token::String(s) => Consts::Interval(Interval::parse(&s).ok_or_else(|| self.error_at_consumed(format!(
+                    "[Parser] invalid interval literal '{}', expected something like '1 day' or '2 hours 30 minutes'", s
+                )))?).into(),
+                t => return Err(LegendDBError::Parser(format!("[Parser] Expected interval string after INTERVAL, got {}", t))),
+            },
+            // 日期和时间 DATE '2024-01-01'、TIME '12:30:00'、TIMESTAMP '2024-01-01 12:30:00'
+            Token::Keyword(keyword @ (Keyword::Date | Keyword::Time | Keyword::Timestamp | Keyword::Datetime)) => {
+                let s = match self.custom_next()? {
+                    Token::String(s) => s,
+                    t => return Err(LegendDBError::Parser(format!("[Parser] Expected string after {}, got {}", keyword.to_str(), t))),
+                };
+                let (consts, format) = match keyword {
+                    Keyword::Date => (datetime::parse_date(&s).map(Consts::Date), "yyyy-mm-dd"),
+                    Keyword::Time => (datetime::parse_time(&s).map(Consts::Time), "hh:mm:ss"),
+                    _ => (datetime::parse_datetime(&s).map(Consts::DateTime), "yyyy-mm-dd [hh:mm:ss]"),
+                };
+                consts.ok_or_else(|| self.error_at_consumed(format!(
+                    "[Parser] invalid {} literal '{}', expected {}", keyword.to_str(), s, format
+                )))?.into()
+            },
             t => {
                 return Err(LegendDBError::Parser(format!(
                     "[Parser] Unexpected expression token {}",
@@ -373,33 +826,30 @@ impl<'a> Parser<'a> {
     }
     
     // 解析where子句
-    fn parse_where_clause(&mut self) -> LegendDBResult<Option<Vec<Expression>>> {
+    fn parse_where_clause(&mut self) -> LegendDBResult<Option<Expression>> {
         if self.next_if_token(Token::Keyword(Keyword::Where)).is_none() {
             return Ok(None);
         }
-        Ok(self.parse_operation_expression()?)
+        Ok(Some(self.parse_condition()?))
     }
     
-    // 解析order by排序
-    fn parse_order_by(&mut self) -> LegendDBResult<Vec<(String, OrderDirection)>> {
+    // 解析order by排序，排序的可以是表达式，也可以是查询列的序号 order by a + b desc, 2
+    fn parse_order_by(&mut self) -> LegendDBResult<Vec<(Expression, OrderDirection)>> {
         if self.next_if_token(Token::Keyword(Keyword::Order)).is_none() {
             return Ok(vec![]);
         }
         self.next_expect(Token::Keyword(Keyword::By))?;
-        let mut order_conditions: Vec<(String, OrderDirection)> = Vec::new();
+        let mut order_conditions: Vec<(Expression, OrderDirection)> = Vec::new();
         loop {
-            let column_name = self.next_ident()?;
-            // let order_keyword = match self.next_if(|x| matches!(x, Token::Keyword(Keyword::Asc) | Token::Keyword(Keyword::Desc))) {
-            //     Some(Token::Keyword(Keyword::Asc)) => {OrderDirection::Asc}
-            //     Some(Token::Keyword(Keyword::Desc)) => {OrderDirection::Desc}
-            //     _ => {OrderDirection::Asc}
-            // };
-            let order = match self.next_if_keyword() {
-                Some(Token::Keyword(Keyword::Asc)) => OrderDirection::Asc,
-                Some(Token::Keyword(Keyword::Desc)) => OrderDirection::Desc,
-                _ => OrderDirection::Asc,
+            let expr = self.parse_expression()?;
+            // 只消费 ASC/DESC，后面的 LIMIT 等关键字留给外层解析
+            let order = if self.next_if_token(Token::Keyword(Keyword::Desc)).is_some() {
+                OrderDirection::Desc
+            } else {
+                self.next_if_token(Token::Keyword(Keyword::Asc));
+                OrderDirection::Asc
             };
-            order_conditions.push((column_name, order));
+            order_conditions.push((expr, order));
             if self.next_if_token(Token::Comma).is_none() {
                 break;
             }
@@ -417,12 +867,7 @@ impl<'a> Parser<'a> {
         loop {
             let expr = self.parse_expression()?;
             // 是否有别名
-            let alias = match self.next_if_token(Token::Keyword(Keyword::As)) {
-                Some(_) => {
-                    Some(self.next_ident()?)
-                },
-                None => None
-            };
+            let alias = self.parse_alias()?;
             columns.push((expr, alias));
             if self.next_if_token(Token::Comma).is_none() {
                 break;
@@ -437,12 +882,21 @@ impl<'a> Parser<'a> {
         // 第一个表名
         let mut first_item = self.parse_from_table()?;
         // 是否有join
-        while let Some(join_type) = self.parser_from_join()?{
+        while let Some((join_type, natural)) = self.parser_from_join()?{
             let left = Box::new(first_item.clone());
             let right = Box::new(self.parse_from_table()?);
+            // NATURAL JOIN 使用所有同名的列，其他的 join 可以用 USING (a, b) 指定同名的列
+            let using = if natural {
+                Some(JoinUsing::Natural)
+            } else if join_type != JoinType::Cross && self.next_if_token(Token::Keyword(Keyword::Using)).is_some() {
+                Some(JoinUsing::Columns(self.parse_ident_list()?))
+            } else {
+                None
+            };
             // 解析 join类型
             let predicate = match join_type {
                 JoinType::Cross => None,
+                _ if using.is_some() => None,
                 _ => {
                     self.next_expect(Token::Keyword(Keyword::On))?;
                     let left_expr = self.parse_expression()?;
@@ -467,28 +921,44 @@ impl<'a> Parser<'a> {
                 right,
                 join_type,
                 predicate,
+                using,
             }
         }
         Ok(first_item)
     }
 
     fn parse_from_table(&mut self) -> LegendDBResult<FromItem> {
+        // 子查询 (select ...) as alias
+        if self.next_if_token(Token::LeftParen).is_some() {
+            let query = self.parse_select()?;
+            self.next_expect(Token::RightParen)?;
+            let alias = self.parse_alias()?
+                .ok_or(LegendDBError::Parser("[Parser] subquery in FROM must have an alias".to_string()))?;
+            return Ok(FromItem::SubQuery { query: Box::new(query), alias });
+        }
+        // 解析表名
+        let name = self.next_ident()?;
         // 判断是否有别名
-        let alias = match self.next_if_token(Token::Keyword(Keyword::As)) {
-            Some(_) => {
-                Some(self.next_ident()?)
-            },
-            None => None
-        };
-        // 解析字段
-        Ok(FromItem::Table {name: self.next_ident()?, alias})
+        let alias = self.parse_alias()?;
+        Ok(FromItem::Table {name, alias})
+    }
+
+    // 解析别名，AS 可以省略，比如 select a col1 from tbl t
+    fn parse_alias(&mut self) -> LegendDBResult<Option<String>> {
+        if self.next_if_token(Token::Keyword(Keyword::As)).is_some() {
+            return Ok(Some(self.next_ident()?));
+        }
+        Ok(match self.next_if(|t| matches!(t, Token::Identifier(_))) {
+            Some(Token::Identifier(alias)) => Some(alias),
+            _ => None,
+        })
     }
     
     fn parse_having(&mut self) -> LegendDBResult<Option<Expression>> {
         if self.next_if_token(Token::Keyword(Keyword::Having)).is_none() {
             return Ok(None);
         }
-        Ok(Some(self.parser_having_expression()?))
+        Ok(Some(self.parse_condition()?))
     }
     fn parse_group_by(&mut self) -> LegendDBResult<Option<Expression>> {
         if self.next_if_token(Token::Keyword(Keyword::Group)).is_none() {
@@ -497,21 +967,31 @@ impl<'a> Parser<'a> {
         self.next_expect(Token::Keyword(Keyword::By))?;
         Ok(Some(self.parse_expression()?))
     }
-    fn parser_from_join(&mut self) -> LegendDBResult<Option<JoinType>> {
+    // 解析 join 类型，以及是否是 NATURAL JOIN
+    fn parser_from_join(&mut self) -> LegendDBResult<Option<(JoinType, bool)>> {
         // 是否是cross join
         if self.next_if_token(Token::Keyword(Keyword::Cross)).is_some() {
             self.next_expect(Token::Keyword(Keyword::Join))?;
-            return Ok(Some(JoinType::Cross)); // 返回cross join
-        } else if self.next_if_token(Token::Keyword(Keyword::Join)).is_some() {
-            return Ok(Some(JoinType::Inner)); // 返回inner join
+            return Ok(Some((JoinType::Cross, false))); // 返回cross join
+        }
+        let natural = self.next_if_token(Token::Keyword(Keyword::Natural)).is_some();
+        let join_type = if self.next_if_token(Token::Keyword(Keyword::Join)).is_some() {
+            return Ok(Some((JoinType::Inner, natural))); // 返回inner join
         } else if self.next_if_token(Token::Keyword(Keyword::Left)).is_some() {
-            self.next_expect(Token::Keyword(Keyword::Join))?;
-            return Ok(Some(JoinType::Left)); // 返回left join
+            JoinType::Left // 返回left join
         } else if self.next_if_token(Token::Keyword(Keyword::Right)).is_some() {
-            self.next_expect(Token::Keyword(Keyword::Join))?;
-            return Ok(Some(JoinType::Right)); // 返回right join
-        }
-        Ok(None)
+            JoinType::Right // 返回right join
+        } else if self.next_if_token(Token::Keyword(Keyword::Full)).is_some() {
+            JoinType::Full // 返回full join
+        } else if natural {
+            return Err(LegendDBError::Parser("[Parser] Expected JOIN after NATURAL".to_string()));
+        } else {
+            return Ok(None);
+        };
+        // 外连接的 OUTER 可以省略
+        self.next_if_token(Token::Keyword(Keyword::Outer));
+        self.next_expect(Token::Keyword(Keyword::Join))?;
+        Ok(Some((join_type, natural)))
     }
 
     // 解析创建数据库
@@ -532,11 +1012,26 @@ impl<'a> Parser<'a> {
         // 如果 Result 是 Ok(Some(value))，则返回 Some(Ok(value))。
         // 如果 Result 是 Ok(None)，则返回 None。
         // 如果 Result 是 Err(error)，则返回 Some(Err(error))。
-        self.lexer.peek().cloned().transpose()
+        if self.peeked.is_none() {
+            self.peeked = Some(self.lexer.next().transpose());
+            self.peeked_at = (self.lexer.token_position(), self.lexer.token_text());
+        }
+        self.peeked.clone().unwrap_or(Ok(None))
     }
 
     fn custom_next(&mut self) -> LegendDBResult<Token> {
-        self.lexer.next().unwrap_or_else(|| Err(LegendDBError::Parser("[Parser] Unexpected end of input".to_string())))
+        let token = match self.peeked.take() {
+            Some(token) => {
+                self.consumed_at = self.peeked_at;
+                token
+            },
+            None => {
+                let token = self.lexer.next().transpose();
+                self.consumed_at = (self.lexer.token_position(), self.lexer.token_text());
+                token
+            },
+        };
+        token?.ok_or(LegendDBError::Parser("[Parser] Unexpected end of input".to_string()))
     }
 
     fn next_ident(&mut self) -> LegendDBResult<String> {
@@ -574,8 +1069,9 @@ mod tests {
     use crate::sql::parser::parser::Consts;
 use std::collections::BTreeMap;
     use crate::{sql::parser::ast};
-    use crate::sql::parser::ast::{Statement};
-    use crate::custom_error::LegendDBResult;
+    use crate::sql::parser::ast::{AlterTableOperation, Column, Expression, Operation, Statement};
+    use crate::sql::types::{DataType, IntWidth};
+    use crate::custom_error::{LegendDBError, LegendDBResult};
     use super::Parser;
 
     #[test]
@@ -601,6 +1097,12 @@ use std::collections::BTreeMap;
         let stmt2 = Parser::new(sql2).parse()?;
         assert_eq!(stmt1, stmt2);
 
+        // serial 展开为 int auto_increment
+        assert_eq!(
+            Parser::new("create table t (id serial primary key, serial int);").parse()?,
+            Parser::new("create table t (id int primary key auto_increment, serial int);").parse()?
+        );
+
         let sql3 = "
             create            table tbl1 (
             a int default     100,
@@ -664,6 +1166,18 @@ use std::collections::BTreeMap;
         let sql = "select a as col1, b as col2, c from tbl1 limit 10 offset 20;";
         let stmt = Parser::new(sql).parse()?;
         println!("{:?}", stmt);
+        // 别名可以省略 AS
+        assert_eq!(
+            Parser::new("select a col1, b col2, c from tbl1 t join tbl2 u on t.a = u.a;").parse()?,
+            Parser::new("select a as col1, b as col2, c from tbl1 as t join tbl2 as u on t.a = u.a;").parse()?,
+        );
+        assert_eq!(
+            Parser::new("select x from (select a x from tbl1) sub;").parse()?,
+            Parser::new("select x from (select a as x from tbl1) as sub;").parse()?,
+        );
+        assert!(Parser::new("select a col1 col2 from tbl1;").parse().is_err());
+        // LIMIT offset, count 等价于 LIMIT count OFFSET offset
+        assert_eq!(Parser::new("select * from tbl1 limit 20, 10;").parse()?, Parser::new("select * from tbl1 limit 10 offset 20;").parse()?);
         // assert_eq!(
         //     stmt,
         //     Statement::Select  {
@@ -685,15 +1199,16 @@ use std::collections::BTreeMap;
         let mut columns = BTreeMap::new();
         columns.insert("a".to_string(), Consts::Integer(1).into());
         columns.insert("b".to_string(), Consts::Integer(2).into());
-        // let mut where_clause = BTreeMap::new();
-        // where_clause.insert("c".to_string(), Consts::Integer(3).into());
-        // where_clause.insert("d".to_string(), Consts::Integer(4).into());
+        let where_clause = Expression::Operation(Operation::And(
+            Box::new(Expression::Operation(Operation::Equal(Box::new(Expression::Field("c".to_string())), Box::new(Consts::Integer(3).into())))),
+            Box::new(Expression::Operation(Operation::Equal(Box::new(Expression::Field("d".to_string())), Box::new(Consts::Integer(4).into())))),
+        ));
         assert_eq!(
             stmt,
             Statement::Update {
                 table_name: "tbl1".to_string(),
                 columns,
-                where_clause: None,
+                where_clause: Some(where_clause),
             }
         );
         Ok(())
@@ -714,7 +1229,271 @@ use std::collections::BTreeMap;
         println!("{:?}", stmt);
         Ok(())
     }
+
+    #[test]
+    fn test_parser_where_condition() -> LegendDBResult<()> {
+        let field = |name: &str| Box::new(Expression::Field(name.to_string()));
+        let int = |i: i64| Box::new(Expression::Consts(Consts::Integer(i)));
+        let eq = |name: &str, i: i64| Box::new(Expression::Operation(Operation::Equal(field(name), int(i))));
+        // AND 的优先级高于 OR
+        let stmt = Parser::new("delete from t where a = 1 or b = 2 and not c > 3;").parse()?;
+        assert_eq!(stmt, Statement::Delete {
+            table_name: "t".to_string(),
+            where_clause: Some(Expression::Operation(Operation::Or(
+                eq("a", 1),
+                Box::new(Expression::Operation(Operation::And(
+                    eq("b", 2),
+                    Box::new(Expression::Operation(Operation::Not(
+                        Box::new(Expression::Operation(Operation::GreaterThan(field("c"), int(3)))),
+                    ))),
+                ))),
+            ))),
+        });
+        // 括号改变优先级
+        let stmt = Parser::new("delete from t where (a = 1 or b = 2) and c = 3;").parse()?;
+        assert_eq!(stmt, Statement::Delete {
+            table_name: "t".to_string(),
+            where_clause: Some(Expression::Operation(Operation::And(
+                Box::new(Expression::Operation(Operation::Or(eq("a", 1), eq("b", 2)))),
+                eq("c", 3),
+            ))),
+        });
+        assert!(Parser::new("delete from t where (a = 1 or b = 2;").parse().is_err());
+        // 带表名的列
+        let stmt = Parser::new("delete from t where t.a = 1;").parse()?;
+        assert_eq!(stmt, Statement::Delete { table_name: "t".to_string(), where_clause: Some(*eq("t.a", 1)) });
+        assert!(Parser::new("delete from t where t. = 1;").parse().is_err());
+        assert!(Parser::new("delete from t where a = 1 and;").parse().is_err());
+        // 算术运算的优先级高于比较运算，NOT LIKE 和比较运算同级
+        let stmt = Parser::new("delete from t where a + 1 * 2 > 3 and b not like 'x%';").parse()?;
+        assert_eq!(stmt, Statement::Delete {
+            table_name: "t".to_string(),
+            where_clause: Some(Expression::Operation(Operation::And(
+                Box::new(Expression::Operation(Operation::GreaterThan(
+                    Box::new(Expression::Operation(Operation::Add(
+                        field("a"),
+                        Box::new(Expression::Operation(Operation::Multiply(int(1), int(2)))),
+                    ))),
+                    int(3),
+                ))),
+                Box::new(Expression::Operation(Operation::Not(Box::new(Expression::Operation(Operation::Like(
+                    field("b"),
+                    Box::new(Expression::Consts(Consts::String("x%".to_string()))),
+                )))))),
+            ))),
+        });
+        // 比较运算不能连续使用
+        assert!(Parser::new("delete from t where a = 1 = 2;").parse().is_err());
+        assert!(Parser::new("delete from t where a not between 1;").parse().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parser_arithmetic() -> LegendDBResult<()> {
+        let select_expr = |sql: &str| -> LegendDBResult<Expression> {
+            match Parser::new(sql).parse()? {
+                Statement::Select { mut columns, .. } => Ok(columns.remove(0).0),
+                _ => unreachable!(),
+            }
+        };
+        let field = |name: &str| Box::new(Expression::Field(name.to_string()));
+        let int = |i: i64| Box::new(Expression::Consts(Consts::Integer(i)));
+        assert_eq!(
+            select_expr("select a * 2 + 1 from t;")?,
+            Expression::Operation(Operation::Add(
+                Box::new(Expression::Operation(Operation::Multiply(field("a"), int(2)))),
+                int(1),
+            ))
+        );
+        // 减法左结合，乘方右结合，负号的优先级低于乘方
+        assert_eq!(
+            select_expr("select a - b - c from t;")?,
+            Expression::Operation(Operation::Subtract(
+                Box::new(Expression::Operation(Operation::Subtract(field("a"), field("b")))),
+                field("c"),
+            ))
+        );
+        assert_eq!(
+            select_expr("select 2 ^ 3 ^ 2 from t;")?,
+            Expression::Operation(Operation::Exponentiate(
+                int(2),
+                Box::new(Expression::Operation(Operation::Exponentiate(int(3), int(2)))),
+            ))
+        );
+        assert_eq!(
+            select_expr("select -a ^ 2 from t;")?,
+            Expression::Operation(Operation::Negate(Box::new(Expression::Operation(Operation::Exponentiate(field("a"), int(2))))))
+        );
+        // 展示时按优先级加括号
+        assert_eq!(select_expr("select a % 3 * -b + c / 2 from t;")?.to_string(), "a % 3 * -b + c / 2");
+        assert_eq!(select_expr("select a - b - c from t;")?.to_string(), "a - b - c");
+        let expr = Expression::Operation(Operation::Multiply(
+            Box::new(Expression::Operation(Operation::Add(field("a"), field("b")))),
+            Box::new(Expression::Operation(Operation::Subtract(field("c"), int(1)))),
+        ));
+        assert_eq!(expr.to_string(), "(a + b) * (c - 1)");
+        // where 中的比较两边都可以是算术表达式
+        match Parser::new("delete from t where a + b > 10 * c;").parse()? {
+            Statement::Delete { where_clause: Some(where_clause), .. } => assert_eq!(where_clause.to_string(), "a + b > 10 * c"),
+            _ => unreachable!(),
+        }
+        assert!(Parser::new("select a + from t;").parse().is_err());
+        // 类型转换
+        assert_eq!(
+            select_expr("select cast(a + 1 as float) * 2 from t;")?,
+            Expression::Operation(Operation::Multiply(
+                Box::new(Expression::Cast(Box::new(Expression::Operation(Operation::Add(field("a"), int(1)))), DataType::Float)),
+                int(2),
+            ))
+        );
+        assert_eq!(select_expr("select cast(a as date) from t;")?, Expression::Cast(field("a"), DataType::Date));
+        assert!(Parser::new("select cast(a as datee) from t;").parse().is_err());
+        assert!(Parser::new("select cast(a) from t;").parse().is_err());
+
+        // 括号改变运算顺序
+        assert_eq!(
+            select_expr("select (a + b) * c from t;")?,
+            Expression::Operation(Operation::Multiply(
+                Box::new(Expression::Operation(Operation::Add(field("a"), field("b")))),
+                field("c"),
+            ))
+        );
+        assert_eq!(select_expr("select ((a)) from t;")?, *field("a"));
+        assert_eq!(select_expr("select -(a - 1) ^ 2 from t;")?.to_string(), "-(a - 1) ^ 2");
+        match Parser::new("delete from t where (a + b) * 2 > 1 and not (c < 2 or (d = 1));").parse()? {
+            Statement::Delete { where_clause: Some(where_clause), .. } => {
+                assert_eq!(where_clause.to_string(), "(a + b) * 2 > 1 AND NOT (c < 2 OR d = 1)");
+            },
+            _ => unreachable!(),
+        }
+        assert!(Parser::new("select (a + b from t;").parse().is_err());
+        assert!(Parser::new("select a + b) from t;").parse().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parser_check_table() -> LegendDBResult<()> {
+        let stmt = Parser::new("check table t1;").parse()?;
+        assert_eq!(stmt, Statement::CheckTable { table_name: "t1".to_string() });
+        assert!(Parser::new("check t1;").parse().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parser_alter_table() -> LegendDBResult<()> {
+        let stmt = Parser::new("alter table t1 add column c int not null default 10;").parse()?;
+        assert_eq!(
+            stmt,
+            Statement::AlterTable {
+                table_name: "t1".to_string(),
+                operation: AlterTableOperation::AddColumn(Column {
+                    name: "c".to_string(),
+                    data_type: DataType::Integer,
+                    max_length: None,
+                    int_width: Some(IntWidth::INT),
+                    nullable: Some(false),
+                    default: Some(Expression::Consts(Consts::Integer(10))),
+                    is_primary_key: false,
+                    auto_increment: false,
+                    unique: false,
+                    references: None,
+                    check: None,
+                }),
+            }
+        );
+        // column 关键字可以省略
+        let stmt = Parser::new("alter table t1 add d text;").parse()?;
+        assert!(matches!(stmt, Statement::AlterTable { operation: AlterTableOperation::AddColumn(_), .. }));
+        assert!(Parser::new("alter table t1 add;").parse().is_err());
+
+        let stmt = Parser::new("alter table t1 drop column c;").parse()?;
+        assert_eq!(
+            stmt,
+            Statement::AlterTable {
+                table_name: "t1".to_string(),
+                operation: AlterTableOperation::DropColumn("c".to_string()),
+            }
+        );
+        let stmt = Parser::new("alter table t1 modify c float null;").parse()?;
+        assert!(matches!(stmt, Statement::AlterTable { operation: AlterTableOperation::ModifyColumn(Column { nullable: Some(true), .. }), .. }));
+        Ok(())
+    }
+
+    #[test]
+    fn test_parser_drop() -> LegendDBResult<()> {
+        let stmt = Parser::new("drop table t1;").parse()?;
+        assert_eq!(
+            stmt,
+            Statement::DropTable {
+                table_name: "t1".to_string(),
+                if_exists: false,
+            }
+        );
+
+        let stmt = Parser::new("drop table if exists t1;").parse()?;
+        assert_eq!(
+            stmt,
+            Statement::DropTable {
+                table_name: "t1".to_string(),
+                if_exists: true,
+            }
+        );
+
+        let stmt = Parser::new("drop database if exists test;").parse()?;
+        assert_eq!(
+            stmt,
+            Statement::DropDatabase {
+                database_name: "test".to_string(),
+                if_exists: true,
+            }
+        );
+
+        let stmt = Parser::new("drop table if t1;").parse();
+        assert!(stmt.is_err());
+        Ok(())
+    }
     
+    #[test]
+    fn test_parser_use_database() -> LegendDBResult<()> {
+        let sql = "use test;";
+        let stmt = Parser::new(sql).parse()?;
+        assert_eq!(
+            stmt,
+            Statement::UseDatabase {
+                database_name: "test".to_string(),
+            }
+        );
+        // 缺少数据库名
+        assert!(Parser::new("use;").parse().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parser_delete() -> LegendDBResult<()> {
+        let stmt = Parser::new("delete from tbl1;").parse()?;
+        assert_eq!(
+            stmt,
+            Statement::Delete {
+                table_name: "tbl1".to_string(),
+                where_clause: None,
+            }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_parser_set() -> LegendDBResult<()> {
+        let stmt = Parser::new("set safe_updates = 1;").parse()?;
+        assert_eq!(
+            stmt,
+            Statement::Set {
+                name: "safe_updates".to_string(),
+                value: Consts::Integer(1).into(),
+            }
+        );
+        Ok(())
+    }
+
     #[test]
     fn test_select_where() -> LegendDBResult<()> {
         let sql = "select b, sum(c) from t1;";
@@ -722,4 +1501,133 @@ use std::collections::BTreeMap;
         println!("{:?}", stmt);
         Ok(())
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_parser_integer_range() -> LegendDBResult<()> {
+        let stmt = Parser::new("select * from t where a = -9223372036854775808 or a = 9223372036854775807;").parse()?;
+        assert_eq!(stmt.to_sql(), "SELECT * FROM t WHERE a = -9223372036854775808 OR a = 9223372036854775807");
+        // 超出范围的整数给出明确的错误
+        match Parser::new("select * from t where a = 9223372036854775808;").parse() {
+            Err(LegendDBError::Syntax { message: msg, .. }) => assert!(msg.contains("numeric literal 9223372036854775808 is out of range"), "{}", msg),
+            r => panic!("unexpected result {:?}", r),
+        }
+        assert!(Parser::new("insert into t values (-99999999999999999999);").parse().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parser_hex_and_scientific() -> LegendDBResult<()> {
+        let stmt = Parser::new("select 0xFF, 0x7fffffffffffffff, 1e10, 1.5E-3, 2e+2 from t;").parse()?;
+        assert_eq!(stmt.to_sql(), "SELECT 255, 9223372036854775807, 10000000000.0, 0.0015, 200.0 FROM t");
+        match Parser::new("select 0x8000000000000000 from t;").parse() {
+            Err(LegendDBError::Syntax { message: msg, .. }) => assert!(msg.contains("0x8000000000000000 is out of range"), "{}", msg),
+            r => panic!("unexpected result {:?}", r),
+        }
+        assert!(Parser::new("select 1e400 from t;").parse().is_err());
+        assert!(Parser::new("select 0x from t;").parse().is_err());
+        assert!(Parser::new("select 0xFG from t;").parse().is_err());
+        assert!(Parser::new("select 1e from t;").parse().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parser_error_position() {
+        let position = |sql: &str| match Parser::new(sql).parse() {
+            Err(LegendDBError::Syntax { line, column, offset, near, .. }) => (line, column, offset, near),
+            r => panic!("unexpected result {:?}", r),
+        };
+        // 出错的 Token 是最后读取的 Token
+        assert_eq!(position("select a from t\nwhere a = = 1;"), (2, 11, 26, Some("=".to_string())));
+        // b fromm 是列的别名，出错的是后面的 t
+        assert_eq!(position("select a,\n  b\n  fromm t;"), (3, 9, 22, Some("t".to_string())));
+        // 列号按字符计算，偏移按字节计算
+        assert_eq!(position("select '中文', # from t;"), (1, 14, 17, Some("#".to_string())));
+        assert_eq!(position("select 'abc from t;"), (1, 8, 7, Some("'abc from t;".to_string())));
+        assert_eq!(position("select * from t"), (1, 16, 15, None));
+        let err = Parser::new("select * frm t;").parse().unwrap_err();
+        assert_eq!(err.to_string(), "syntax error at line 1, column 10 near 'frm': [Parser] Expected token: Keyword(From), got frm");
+        let err = Parser::new("select * from t;\nselect * from t").parse_batch().unwrap_err();
+        assert_eq!(err.to_string(), "syntax error at line 2, column 16 near end of input: [Parser] Unexpected end of input");
+
+        // 读取了后面的 Token 之后才发现的错误，位置是出错的 Token，而不是最后读取的 Token
+        assert_eq!(position("update t set a = 1,\n  b = 2,\n  a = 3 where b = 1;"), (3, 3, 31, Some("a".to_string())));
+        assert_eq!(position("select a from t\nwhere u = uuid 'xyz' and b = 1;"), (2, 16, 31, Some("'xyz'".to_string())));
+        assert_eq!(position("select a\nfrom t where b < now() - interval '3 parsecs' order by a;"), (2, 35, 43, Some("'3 parsecs'".to_string())));
+        assert_eq!(position("insert into t values (1, x'00'),\n(2, x'0g');"), (2, 5, 37, Some("x'0g'".to_string())));
+        assert_eq!(position("select 1,\n 0x8000000000000000, 2 from t;"), (2, 2, 11, Some("0x8000000000000000".to_string())));
+        assert_eq!(position("select a from t\n where d = date '2024-02-30' or d is null;"), (2, 17, 32, Some("'2024-02-30'".to_string())));
+        assert_eq!(position("create table t (\n  a int primary key,\n  b varchar(0)\n);"), (3, 13, 50, Some("0".to_string())));
+    }
+
+    #[test]
+    fn test_statement_to_sql() -> LegendDBResult<()> {
+        let sqls = [
+            "create table `Order` (id int primary key, `my name` varchar not null default 'a''b', score float default 1.0, ok bool null);",
+            "create table t (a int, b int, constraint pk primary key (a), unique (b, a));",
+            "create table t (a int primary key, b string not null unique default 'x');",
+            "create table t (id int primary key auto_increment, b int);",
+            "create table t (a int primary key check (a > 0 and a < 10), b int, constraint c check (b != a));",
+            "create table t (a int primary key references p on delete set default, b int, foreign key (b) references p (x) on update cascade);",
+            "insert into t (a, b) values (1, 'x\\ny'), (-2, null);",
+            "update t set a = a + 1, b = 'c' where not (a = 1 or b like 'x%');",
+            "delete from t where a in (select b from s where b > 2.5);",
+            "select a as x, count(b), cast(c as string) from t1 a join (select * from t2) b on a.id = b.id right join t3 on t3.id = a.id \
+             where exists (select * from t4) group by a having count(b) > 1 order by a.x desc, b asc limit 10 offset 2;",
+            "select * from t1 cross join t2 left join t3 on t1.a = t3.a;",
+            "select * from t1 full outer join t2 on t1.a = t2.b natural left join t3 join t4 using (id, `my col`);",
+            "select a, b from t order by a + b * 2 desc, 2, count(c) limit 3;",
+            "select count(*) as n, count(a) from t having count(*) > 1;",
+            "select -(a - b) * 2 ^ 3 ^ 2 from t where (a > 1 or b <= 2) and c != 3;",
+            "select a + interval '1 year 2 days 3 hours' from t where b < now() - interval '-30 minutes';",
+            "create table t (d date primary key, t time, ts datetime default timestamp '2024-01-01 08:00:00');",
+            "select cast(ts as date) from t where d >= date '2024-02-29' and t < time '12:00:00';",
+            "drop table if exists t;",
+            "drop database d;",
+            "use d;",
+            "alter table t modify column a int not null;",
+            "alter table t drop column b;",
+            "create table t (a binary primary key, b blob default x'00ff');",
+            "create table t (a char(10) primary key, b varchar(255) not null, c text);",
+            "create table t (a tinyint primary key, b smallint unsigned, c bigint, d int unsigned);",
+            "select * from t where a = x'deadbeef';",
+            "create table t (id uuid primary key default uuid(), uuid int);",
+            "select * from t where id = uuid '123e4567-e89b-42d3-a456-426614174000' or uuid > 1;",
+            "check table t;",
+            "show index recommendations;",
+            "explain select * from t;",
+            "explain update t set a = 1 where b > 2;",
+            "explain analyze select count(*) from _t where _a > 1;",
+            "set safe_updates = on;",
+        ];
+        for sql in sqls {
+            let stmt = Parser::new(sql).parse()?;
+            let canonical = stmt.to_sql();
+            assert_eq!(Parser::new(&format!("{};", canonical)).parse()?, stmt, "{}", canonical);
+        }
+
+        let stmt = Parser::new("select a,`b c` from `Order` t where a=1 and `select`>2.0 order by a;").parse()?;
+        assert_eq!(
+            stmt.to_sql(),
+            "SELECT a, `b c` FROM `Order` AS t WHERE a = 1 AND `select` > 2.0 ORDER BY a ASC"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_statement_fingerprint() -> LegendDBResult<()> {
+        let fingerprint = |sql: &str| Parser::new(sql).parse().map(|stmt| stmt.fingerprint());
+        assert_eq!(
+            fingerprint("select a, count(*) from t where b = 'x' and c in (select c from u where d > -3) group by a limit 10;")?,
+            "SELECT a, count(*) FROM t WHERE b = ? AND c IN (SELECT c FROM u WHERE d > ?) GROUP BY a LIMIT ?"
+        );
+        // 只有常量不同的语句指纹相同
+        assert_eq!(fingerprint("SELECT * FROM t WHERE a - 1 > -2.5;")?, fingerprint("select * from t where a - 100 > 7;")?);
+        assert_eq!(fingerprint("select * from t where a - 1 > -2.5;")?, "SELECT * FROM t WHERE a - ? > ?");
+        assert_eq!(
+            fingerprint("insert into t (a, b) values (1, 'a'), (2, x'00'), (3, null);")?,
+            "INSERT INTO t (a, b) VALUES (?)"
+        );
+        assert_eq!(fingerprint("update t set a = true where b = DATE '2024-01-01';")?, "UPDATE t SET a = ? WHERE b = DATE ?");
+        Ok(())
+    }
+}
@@ -1,4 +1,5 @@
 pub mod planner;
 pub mod node;
+pub mod resolver;
 
 
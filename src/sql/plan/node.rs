@@ -1,9 +1,11 @@
 use std::collections::BTreeMap;
-use crate::sql::engine::engine::Transaction;
-use crate::sql::parser::ast::{Expression, OrderDirection, Statement};
+use std::fmt::{Display, Formatter};
+use crate::sql::engine::engine::{SessionSettings, Transaction};
+use crate::sql::parser::ast::{split_qualified, Expression, JoinType, JoinUsing, OrderDirection, Statement};
 use crate::sql::executor::executor::{Executor, ResultSet};
+use crate::sql::executor::profile::QUERY_STATS_TABLE;
 use crate::sql::plan::planner::Planner;
-use crate::sql::schema::Table;
+use crate::sql::schema::{Column, Table};
 use crate::custom_error::LegendDBResult;
 
 #[derive(Debug, PartialEq)]
@@ -13,16 +15,31 @@ pub enum Node {
     },
     DropTable {
         table_name: String,
+        // 表不存在时不报错
+        if_exists: bool,
     },
     Insert {
         table_name: String,
         columns: Vec<String>,
-        values: Vec<Vec<Expression>>
+        values: Vec<Vec<Expression>>,
+        // 非空列插入NULL时使用默认值，由 sql_mode 控制
+        null_as_default: bool,
+        // 超过列的最大长度的字符串截断、超出范围的整数取边界值，否则报错，由 sql_mode 控制
+        truncate: bool,
     },
 
+    // 检查表数据的完整性
+    CheckTable {
+        table_name: String,
+    },
+    // 根据记录的查询推荐索引
+    IndexRecommendations,
+
     Scan {
         table_name: String,
-        filter: Option<Vec<Expression>>
+        filter: Option<Expression>,
+        // 只需要返回的列，None 表示返回所有列
+        columns: Option<Vec<String>>,
     },
 
     Delete {
@@ -35,11 +52,16 @@ pub enum Node {
         // 扫描复合条件的数据
         source: Box<Node>,
         columns: BTreeMap<String, Expression>,
+        // 超过列的最大长度的字符串截断、超出范围的整数取边界值，否则报错，由 sql_mode 控制
+        truncate: bool,
     },
     // 排序节点
     OrderBy {
         source: Box<Node>,
-        order_by: Vec<(String, OrderDirection)>
+        order_by: Vec<(Expression, OrderDirection)>,
+        // 排序的值相同时，依次按这些表的主键比较，最后按整行比较，保证结果的顺序固定
+        // 每一项是表名和输出列中的表名前缀，None 表示不需要额外比较
+        tiebreak: Option<Vec<(String, Option<String>)>>,
     },
     // Limit 节点
     Limit {
@@ -62,29 +84,179 @@ pub enum Node {
         left: Box<Node>,
         right: Box<Node>,
         predicate: Option<Expression>,
-        outer: bool,
+        // 右连接已经交换了两边，不会出现 Right
+        join_type: JoinType,
+        using: Option<JoinUsing>,
+        // 两边的表名或别名，输出的列名会加上表名 t1.a，嵌套的 join 已经加过表名，为 None
+        left_alias: Option<String>,
+        right_alias: Option<String>,
     },
     // Agg 聚集节点
     Aggregate {
         source: Box<Node>,
         expr: Vec<(Expression, Option<String>)>,
         group_by: Option<Expression>,
+        // having 条件，其中可以直接使用聚合函数
+        having: Option<Expression>,
     },
     Filter {
         source: Box<Node>,
         predicate: Expression,
     },
+    // 统计表的行数，用于没有过滤条件的 select count(*)
+    CountRows {
+        table_name: String,
+        // 输出的列名
+        column: String,
+    },
     CreateDatabase {
         database_name: String,
     },
     DropDatabase {
         database_name: String,
+        // 数据库不存在时不报错
+        if_exists: bool,
     },
     UseDatabase {
         database_name: String,
+    },
+    // 修改表结构
+    AlterTable {
+        table_name: String,
+        operation: AlterTableOperation,
+    },
+    // 展示执行计划
+    Explain {
+        source: Box<Node>,
+    },
+    // 执行查询并展示每个节点的运行统计，统计同时写到 __query_stats 表中
+    ExplainAnalyze {
+        source: Box<Node>,
+        // 执行的 SQL
+        query: String,
+        // 语句的指纹，常量替换为 ?
+        fingerprint: String,
+    },
+}
+
+impl Node {
+    // 按树形展示执行计划，子节点缩进一层
+    fn format(&self, f: &mut Formatter<'_>, depth: usize) -> std::fmt::Result {
+        let indent = if depth == 0 { String::new() } else { format!("{}-> ", "   ".repeat(depth - 1)) };
+        let list = |columns: &[(Expression, Option<String>)]| columns.iter().map(|(expr, alias)| match alias {
+            Some(alias) => format!("{} AS {}", expr, alias),
+            None => expr.to_string(),
+        }).collect::<Vec<_>>().join(", ");
+        match self {
+            Node::Scan { table_name, filter, columns, .. } => {
+                // 目前没有索引，只能全表扫描
+                // 主键 IN 子查询和按主键连接在执行时才知道表结构，计划中仍然展示为全表扫描
+                write!(f, "{}Scan: {} (full table scan)", indent, table_name)?;
+                if let Some(filter) = filter {
+                    write!(f, " filter: {}", filter)?;
+                }
+                if let Some(columns) = columns {
+                    write!(f, " columns: {}", columns.join(", "))?;
+                }
+                writeln!(f)
+            },
+            Node::Filter { source, predicate } => {
+                writeln!(f, "{}Filter: {}", indent, predicate)?;
+                source.format(f, depth + 1)
+            },
+            Node::Projection { source, columns } => {
+                writeln!(f, "{}Projection: {}", indent, list(columns))?;
+                source.format(f, depth + 1)
+            },
+            Node::OrderBy { source, order_by, tiebreak } => {
+                let mut order_by = order_by.iter()
+                    .map(|(expr, direction)| format!("{} {}", expr, if *direction == OrderDirection::Asc { "ASC" } else { "DESC" }))
+                    .collect::<Vec<_>>();
+                if let Some(tables) = tiebreak {
+                    order_by.extend(tables.iter().map(|(table, _)| format!("{} primary key", table)));
+                    order_by.push("row".to_string());
+                }
+                writeln!(f, "{}Order: {}", indent, order_by.join(", "))?;
+                source.format(f, depth + 1)
+            },
+            Node::Limit { source, limit } => {
+                writeln!(f, "{}Limit: {}", indent, limit)?;
+                source.format(f, depth + 1)
+            },
+            Node::Offset { source, offset } => {
+                writeln!(f, "{}Offset: {}", indent, offset)?;
+                source.format(f, depth + 1)
+            },
+            Node::NestedLoopJoin { left, right, predicate, join_type, using, .. } => {
+                let join = match join_type {
+                    JoinType::Inner | JoinType::Cross => "inner",
+                    JoinType::Left | JoinType::Right => "outer",
+                    JoinType::Full => "full outer",
+                };
+                write!(f, "{}Nested Loop Join: {}", indent, join)?;
+                if let Some(predicate) = predicate {
+                    write!(f, " on {}", predicate)?;
+                }
+                match using {
+                    Some(JoinUsing::Columns(columns)) => write!(f, " using ({})", columns.join(", "))?,
+                    Some(JoinUsing::Natural) => write!(f, " natural")?,
+                    None => {},
+                }
+                writeln!(f)?;
+                left.format(f, depth + 1)?;
+                right.format(f, depth + 1)
+            },
+            Node::CountRows { table_name, column } => {
+                writeln!(f, "{}Count Rows: {} (row count statistics) as {}", indent, table_name, column)
+            },
+            Node::Aggregate { source, expr, group_by, having } => {
+                write!(f, "{}Aggregate: {}", indent, list(expr))?;
+                if let Some(group_by) = group_by {
+                    write!(f, " group by: {}", group_by)?;
+                }
+                if let Some(having) = having {
+                    write!(f, " having: {}", having)?;
+                }
+                writeln!(f)?;
+                source.format(f, depth + 1)
+            },
+            Node::Update { table_name, source, columns, .. } => {
+                let set = columns.iter().map(|(col, expr)| format!("{} = {}", col, expr)).collect::<Vec<_>>();
+                writeln!(f, "{}Update: {} set {}", indent, table_name, set.join(", "))?;
+                source.format(f, depth + 1)
+            },
+            Node::Delete { table_name, source } => {
+                writeln!(f, "{}Delete: {}", indent, table_name)?;
+                source.format(f, depth + 1)
+            },
+            Node::Explain { source } | Node::ExplainAnalyze { source, .. } => source.format(f, depth),
+            Node::Insert { table_name, values, .. } => writeln!(f, "{}Insert: {} ({} rows)", indent, table_name, values.len()),
+            Node::CreateTable { schema } => writeln!(f, "{}Create Table: {}", indent, schema.name),
+            Node::DropTable { table_name, .. } => writeln!(f, "{}Drop Table: {}", indent, table_name),
+            Node::AlterTable { table_name, .. } => writeln!(f, "{}Alter Table: {}", indent, table_name),
+            Node::CheckTable { table_name } => writeln!(f, "{}Check Table: {}", indent, table_name),
+            Node::IndexRecommendations => writeln!(f, "{}Index Recommendations: {}", indent, QUERY_STATS_TABLE),
+            Node::CreateDatabase { database_name } => writeln!(f, "{}Create Database: {}", indent, database_name),
+            Node::DropDatabase { database_name, .. } => writeln!(f, "{}Drop Database: {}", indent, database_name),
+            Node::UseDatabase { database_name } => writeln!(f, "{}Use Database: {}", indent, database_name),
+        }
     }
 }
 
+impl Display for Node {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        self.format(f, 0)
+    }
+}
+
+// 修改表结构的操作
+#[derive(Debug, PartialEq)]
+pub enum AlterTableOperation {
+    AddColumn(Column),
+    DropColumn(String),
+    ModifyColumn(Column),
+}
+
 //执行计划定义，底层是不同类型的节点
 #[derive(Debug, PartialEq)]
 pub struct Plan(pub Node);
@@ -95,8 +267,27 @@ impl Plan {
         Planner::new().build(stmt)
     }
 
+    // 根据会话变量构建执行计划
+    pub fn build_with_settings(stmt: Statement, settings: &SessionSettings) -> LegendDBResult<Plan> {
+        Planner::with_settings(settings.clone()).build(stmt)
+    }
+
     pub fn execute<T: Transaction + 'static>(self, txn: &mut T) -> LegendDBResult<ResultSet> {
-        <dyn Executor<T>>::build(self.0).execute(txn)
+        Ok(match <dyn Executor<T>>::build(self.0).execute(txn)? {
+            // join 输出的列带有表名，不重复的列名去掉表名
+            ResultSet::Scan { columns, rows } => {
+                let bare = |col: &String| split_qualified(col).map_or(col.clone(), |(_, name)| name.to_string());
+                let names = columns.iter().map(bare).collect::<Vec<_>>();
+                let columns = columns.into_iter().zip(names.iter()).map(|(col, name)| {
+                    match names.iter().filter(|n| *n == name).count() {
+                        1 => name.clone(),
+                        _ => col,
+                    }
+                }).collect();
+                ResultSet::Scan { columns, rows }
+            },
+            result => result,
+        })
     }
 }
 
@@ -159,6 +350,8 @@ mod tests {
                     Expression::Consts(ast::Consts::String("a".to_string())),
                     Expression::Consts(ast::Consts::Boolean(true)),
                 ]],
+                null_as_default: false,
+                truncate: false,
             })
         );
 
@@ -182,6 +375,8 @@ mod tests {
                         Expression::Consts(ast::Consts::Boolean(false)),
                     ],
                 ],
+                null_as_default: false,
+                truncate: false,
             })
         );
 
@@ -198,9 +393,68 @@ mod tests {
             Plan(Node::Scan {
                 table_name: "tbl1".to_string(),
                 filter: None,
+                columns: None,
             })
         );
 
         Ok(())
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_plan_agg_columns() -> LegendDBResult<()> {
+        // 没有group by时，不能出现非聚合列
+        let stmt = Parser::new("select a, min(b) from t;").parse()?;
+        assert!(Plan::build(stmt).is_err());
+        // 非聚合列不是group by的列
+        let stmt = Parser::new("select a, min(b) from t group by c;").parse()?;
+        assert!(Plan::build(stmt).is_err());
+        // 正常的分组聚合
+        let stmt = Parser::new("select a, min(b) from t group by a;").parse()?;
+        assert!(Plan::build(stmt).is_ok());
+        let stmt = Parser::new("select min(b), max(c) from t;").parse()?;
+        assert!(Plan::build(stmt).is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn test_plan_aliases() -> LegendDBResult<()> {
+        // 查询列别名重复
+        let stmt = Parser::new("select a as x, b as x from t;").parse()?;
+        assert!(Plan::build(stmt).is_err());
+        // from 中的表名重复
+        let stmt = Parser::new("select * from t1 join t1 on a = b;").parse()?;
+        assert!(Plan::build(stmt).is_err());
+        let stmt = Parser::new("select * from t1 join t1 as t2 on a = b;").parse()?;
+        assert!(Plan::build(stmt).is_ok());
+        // having 和 order by 中引用不存在的聚合列
+        let stmt = Parser::new("select b, min(c) as m from t group by b having x > 1;").parse()?;
+        assert!(Plan::build(stmt).is_err());
+        let stmt = Parser::new("select b, min(c) as m from t group by b order by x;").parse()?;
+        assert!(Plan::build(stmt).is_err());
+        let stmt = Parser::new("select b, min(c) as m from t group by b having m > 1 order by m;").parse()?;
+        assert!(Plan::build(stmt).is_ok());
+        // order by 中的别名替换为原始列名
+        let stmt = Parser::new("select a as x from t order by x desc;").parse()?;
+        match Plan::build(stmt)? {
+            Plan(Node::Projection { source, .. }) => match *source {
+                Node::OrderBy { order_by, .. } => assert_eq!(order_by[0].0, Expression::Field("a".into())),
+                node => panic!("unexpected node {:?}", node),
+            },
+            plan => panic!("unexpected plan {:?}", plan),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_plan_use_database() -> LegendDBResult<()> {
+        let stmt = Parser::new("use test;").parse()?;
+        let p = Plan::build(stmt)?;
+        assert_eq!(
+            p,
+            Plan(Node::UseDatabase {
+                database_name: "test".to_string(),
+            })
+        );
+        Ok(())
+    }
+}
@@ -1,14 +1,25 @@
-use crate::sql::parser::ast::{Expression, FromItem, JoinType, Statement};
-use crate::sql::plan::node::{Node, Plan};
-use crate::sql::schema::{Column, Table};
-use crate::sql::types::Value;
+use std::collections::HashSet;
+use crate::sql::parser::ast::{self, collect_fields, evaluate_const, split_qualified, ConstraintKind, Consts, Expression, FromItem, JoinType, Operation, OrderDirection, Statement, TableConstraint};
+use crate::sql::engine::engine::{SessionSettings, SqlMode};
+use crate::sql::plan::node::{AlterTableOperation, Node, Plan};
+use crate::sql::schema::{Column, Constraint, Table};
+use crate::sql::types::{DataType, Value};
 use crate::custom_error::{LegendDBError, LegendDBResult};
 
-pub struct Planner;
+pub struct Planner {
+    // 会话变量，影响执行计划的生成
+    settings: SessionSettings,
+}
 
 impl Planner {
     pub fn new() -> Self {
-        Planner
+        Self::with_settings(SessionSettings::default())
+    }
+
+    pub fn with_settings(settings: SessionSettings) -> Self {
+        Planner {
+            settings,
+        }
     }
     pub fn build(&self, stmt: Statement) -> LegendDBResult<Plan> {
         Ok(Plan(self.build_statement(stmt)?))
@@ -17,25 +28,30 @@ impl Planner {
     pub fn build_statement(&self, stmt: Statement) -> LegendDBResult<Node> {
         Ok(
             match stmt {
-                Statement::CreateTable { name, columns } => {
+                Statement::CreateTable { name, mut columns, mut constraints } => {
+                    // 列级的外键和检查约束转换为表级约束
+                    for column in columns.iter_mut() {
+                        if let Some(fk) = column.references.take() {
+                            constraints.push(TableConstraint { name: None, kind: ConstraintKind::ForeignKey(fk) });
+                        }
+                        if let Some(check) = column.check.take() {
+                            constraints.push(TableConstraint {
+                                name: Some(format!("{}_{}_check", name, column.name)),
+                                kind: ConstraintKind::Check(check),
+                            });
+                        }
+                    }
+                    let mut columns = columns.into_iter().map(build_column).collect::<LegendDBResult<Vec<_>>>()?;
+                    let mut built = Vec::with_capacity(constraints.len());
+                    for constraint in constraints {
+                        let constraint = build_constraint(&name, &mut columns, constraint, &built)?;
+                        built.push(constraint);
+                    }
                     Node::CreateTable {
                         schema: Table {
                             name,
-                            columns: columns.into_iter().map(|c| {
-                                let nullable = c.nullable.unwrap_or(!c.is_primary_key);
-                                let default = match c.default {
-                                    Some(v) => Some(Value::from_expression(v)),
-                                    None if nullable => Some(Value::Null),
-                                    None => None,
-                                };
-                                Column {
-                                    name: c.name,
-                                    data_type: c.data_type,
-                                    nullable,
-                                    default_value: default,
-                                    is_primary_key: c.is_primary_key,
-                                }
-                            }).collect(),
+                            columns,
+                            constraints: built,
                         }
                     }
                 },
@@ -43,11 +59,30 @@ impl Planner {
                     Node::Insert {
                         table_name,
                         columns: columns.unwrap_or_default(),
-                        values
+                        values,
+                        null_as_default: self.settings.sql_mode == SqlMode::MySQL,
+                        truncate: self.settings.sql_mode == SqlMode::MySQL,
                     }
                 },
                 Statement::Select {columns, from, where_clause, group_by, having, order_by, limit, offset } => {
-                    let mut scan_node = self.build_from_item(from, &where_clause)?;
+                    // 校验 from 中的表别名以及查询列的别名
+                    let mut tables = Vec::new();
+                    self.validate_from_aliases(&from, &mut tables)?;
+                    self.validate_select_aliases(&columns)?;
+                    // 解析列名中的表名 t1.a
+                    let single = !matches!(from, FromItem::Join { .. });
+                    let resolve = |expr: Expression| self.resolve_qualified_expr(expr, &tables, single);
+                    let from = self.resolve_qualified_from(from, &tables)?;
+                    let columns = columns.into_iter().map(|(expr, alias)| Ok((resolve(expr)?, alias))).collect::<LegendDBResult<Vec<_>>>()?;
+                    let where_clause = where_clause.map(resolve).transpose()?;
+                    let group_by = group_by.map(resolve).transpose()?;
+                    let mut having = having.map(resolve).transpose()?;
+                    let order_by = order_by.into_iter()
+                        .map(|(expr, direction)| Ok((resolve(expr)?, direction)))
+                        .collect::<LegendDBResult<Vec<_>>>()?;
+                    // 结果的顺序需要固定时，按 from 中的表的主键排序
+                    let mut tiebreak = self.settings.deterministic_order.then(|| key_tables(&from, single));
+                    let mut scan_node = self.build_from_item(from, where_clause)?;
                     // aggregate, group by
                     let mut has_agg = false;
                     if !columns.is_empty() {
@@ -59,15 +94,37 @@ impl Planner {
                             }
                         }
                         if has_agg {
-                            // 构造一个聚合节点
-                            scan_node = Node::Aggregate {
-                                source: Box::new(scan_node),
-                                expr: columns.clone(),
-                                group_by,
+                            // 校验非聚合列必须出现在group by中
+                            self.validate_agg_columns(&columns, &group_by)?;
+                            // having 中的别名需要能够解析
+                            if let Some(having) = &having {
+                                self.validate_having(&columns, having)?;
+                            }
+                            // 聚合的结果中没有主键，按整行排序
+                            if let Some(tables) = &mut tiebreak {
+                                tables.clear();
+                            }
+                            scan_node = match (&scan_node, columns.as_slice()) {
+                                // 单表没有过滤条件的 count(*) 直接使用表的行数统计
+                                (Node::Scan { table_name, filter: None, .. }, [(Expression::Function(func_name, col), alias)])
+                                    if func_name.eq_ignore_ascii_case("count") && col == "*" && group_by.is_none() && having.is_none() && !self.settings.strict_count => {
+                                    Node::CountRows {
+                                        table_name: table_name.clone(),
+                                        column: alias.clone().unwrap_or(func_name.clone()),
+                                    }
+                                },
+                                // 构造一个聚合节点，having 中的聚合函数在聚合时计算
+                                _ => self.build_aggregate(scan_node, columns.clone(), group_by, having.take()),
                             };
                         }
                     }
-                    // having
+                    // order by 中的别名需要能够解析
+                    let order_by = self.resolve_order_by(&columns, order_by, has_agg)?;
+                    // 单表的普通查询只需要解码投影和排序用到的列
+                    if !has_agg && self.settings.optimizer.column_pruning && let Node::Scan { columns: scan_columns, .. } = &mut scan_node {
+                        *scan_columns = self.pruned_columns(&columns, &order_by);
+                    }
+                    // 没有聚合时 having 相当于普通的过滤
                     if let Some(having) = having {
                         scan_node = Node::Filter {
                             source: Box::new(scan_node),
@@ -75,30 +132,25 @@ impl Planner {
                         }
                     };
                     // 排序 order by
-                    if order_by.len() > 0 {
+                    if order_by.len() > 0 || tiebreak.is_some() {
                         scan_node = Node::OrderBy {
                             source: Box::new(scan_node),
                             order_by,
+                            tiebreak,
                         }
                     };
                     // Offset 要在Limit 之前解析
-                    if let Some(offset) = offset {
+                    if let Some(offset) = row_count("OFFSET", offset)? {
                         scan_node = Node::Offset {
                             source: Box::new(scan_node),
-                            offset: match Value::from_expression(offset) {
-                                Value::Integer(offset) => offset as usize,
-                                _ => return Err(LegendDBError::Internal("Offset must be an integer".to_string())),
-                            },
+                            offset,
                         }
                     };
                     // Limit 要在Offset 之后解析
-                    if let Some(limit) = limit {
+                    if let Some(limit) = row_count("LIMIT", limit)? {
                         scan_node = Node::Limit {
                             source: Box::new(scan_node),
-                            limit: match Value::from_expression(limit) {
-                                Value::Integer(limit) => limit as usize,
-                                _ => return Err(LegendDBError::Internal("Limit must be an integer".to_string())),
-                            },
+                            limit,
                         }
                     };
                     
@@ -113,29 +165,32 @@ impl Planner {
                 }
                 // 删除数据
                 Statement::Delete { table_name, where_clause } => {
+                    let tables = [table_name.clone()];
+                    let where_clause = where_clause.map(|expr| self.resolve_qualified_expr(expr, &tables, true)).transpose()?;
                     Node::Delete {
                         table_name: table_name.clone(),
-                        source: Box::new(Node::Scan {
-                            table_name,
-                            filter: where_clause,
-                        }),
+                        source: Box::new(self.build_scan(table_name, where_clause)),
                     }
                 },
                 // 更新数据
                 Statement::Update { table_name, columns, where_clause } => {
+                    let tables = [table_name.clone()];
+                    let where_clause = where_clause.map(|expr| self.resolve_qualified_expr(expr, &tables, true)).transpose()?;
+                    let columns = columns.into_iter()
+                        .map(|(col, expr)| Ok((col, self.resolve_qualified_expr(expr, &tables, true)?)))
+                        .collect::<LegendDBResult<_>>()?;
                     Node::Update {
                         table_name: table_name.clone(),
-                        source: Box::new(Node::Scan {
-                            table_name,
-                            filter: where_clause,
-                        }),
-                        columns
+                        source: Box::new(self.build_scan(table_name, where_clause)),
+                        columns,
+                        truncate: self.settings.sql_mode == SqlMode::MySQL,
                     }
                 },
                 // 删除表
-                Statement::DropTable { table_name } => {
+                Statement::DropTable { table_name, if_exists } => {
                     Node::DropTable {
                         table_name,
+                        if_exists,
                     }
                 },
                 // 创建数据库
@@ -145,9 +200,10 @@ impl Planner {
                     }
                 },
                 // 删除数据库
-                Statement::DropDatabase { database_name } => {
+                Statement::DropDatabase { database_name, if_exists } => {
                     Node::DropDatabase {
                         database_name,
+                        if_exists,
                     }
                 },
                 // 切换数据库
@@ -155,35 +211,531 @@ impl Planner {
                     Node::UseDatabase {
                         database_name,
                     }
+                },
+                // 修改表结构
+                Statement::AlterTable { table_name, operation } => {
+                    let operation = match operation {
+                        ast::AlterTableOperation::AddColumn(column) => {
+                            // 新增的列不能是主键，已有的数据无法确定主键的值
+                            if column.is_primary_key {
+                                return Err(LegendDBError::Internal(format!("cannot add primary key column {} to table {}", column.name, table_name)));
+                            }
+                            AlterTableOperation::AddColumn(build_column(column)?)
+                        },
+                        ast::AlterTableOperation::DropColumn(column_name) => AlterTableOperation::DropColumn(column_name),
+                        ast::AlterTableOperation::ModifyColumn(column) => AlterTableOperation::ModifyColumn(build_column(column)?),
+                    };
+                    Node::AlterTable {
+                        table_name,
+                        operation,
+                    }
+                },
+                // 检查表数据
+                Statement::CheckTable { table_name } => {
+                    Node::CheckTable {
+                        table_name,
+                    }
+                },
+                Statement::ShowIndexRecommendations => Node::IndexRecommendations,
+                // 只生成执行计划，不执行
+                Statement::Explain { statement, analyze: false } => {
+                    Node::Explain {
+                        source: Box::new(self.build_statement(*statement)?),
+                    }
+                },
+                // 执行查询，记录每个节点的运行统计
+                Statement::Explain { statement, analyze: true } => {
+                    Node::ExplainAnalyze {
+                        query: statement.to_sql(),
+                        fingerprint: statement.fingerprint(),
+                        source: Box::new(self.build_statement(*statement)?),
+                    }
+                },
+                // 会话变量由Session处理，不生成执行计划
+                Statement::Set { name, .. } => {
+                    return Err(LegendDBError::Internal(format!("SET {} must be executed by session", name)))
                 }
             }
         )
     }
     
-    pub fn build_from_item(&self, from_item: FromItem, expression: &Option<Vec<Expression>>) -> LegendDBResult<Node> {
+    // 聚合查询中，非聚合的列必须是 group by 的列
+    // select a, min(b) from t;             -> 报错
+    // select a, min(b) from t group by a;  -> 正常
+    fn validate_agg_columns(&self, columns: &[(Expression, Option<String>)], group_by: &Option<Expression>) -> LegendDBResult<()> {
+        for (expr, _) in columns {
+            if let Expression::Field(col) = expr {
+                match group_by {
+                    Some(Expression::Field(group_col)) if group_col == col => {},
+                    Some(_) => return Err(LegendDBError::Internal(format!("column {} must appear in the GROUP BY clause or be used in an aggregate function", col))),
+                    None => return Err(LegendDBError::Internal(format!("column {} must be used in an aggregate function when there is no GROUP BY clause", col))),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // 校验 from 中的表名/别名不能重复
+    // select * from t1 join t1 on a = b;         -> 报错
+    // select * from t1 join t1 as t2 on a = b;   -> 正常
+    fn validate_from_aliases(&self, from_item: &FromItem, names: &mut Vec<String>) -> LegendDBResult<()> {
+        match from_item {
+            FromItem::Table { name: _, alias: Some(name) } | FromItem::Table { name, alias: None } | FromItem::SubQuery { alias: name, .. } => {
+                if names.contains(name) {
+                    return Err(LegendDBError::Internal(format!("table name or alias {} specified more than once", name)));
+                }
+                names.push(name.clone());
+            },
+            FromItem::Join { left, right, .. } => {
+                self.validate_from_aliases(left, names)?;
+                self.validate_from_aliases(right, names)?;
+            }
+        }
+        Ok(())
+    }
+
+    // 列名中的表名必须是 from 中的表名或别名
+    // 单表查询输出的列不带表名，需要去掉表名 t1.a -> a，join 输出的列带有表名，保持不变
+    fn resolve_qualified(&self, name: String, tables: &[String], single: bool) -> LegendDBResult<String> {
+        match split_qualified(&name) {
+            Some((table, _)) if !tables.iter().any(|t| t == table) => {
+                Err(LegendDBError::Internal(format!("unknown table {} in column {}", table, name)))
+            },
+            Some((_, col)) if single => Ok(col.to_string()),
+            _ => Ok(name),
+        }
+    }
+
+    fn resolve_qualified_expr(&self, expr: Expression, tables: &[String], single: bool) -> LegendDBResult<Expression> {
+        expr.transform(&mut |expr| Ok(match expr {
+            Expression::Field(name) => Expression::Field(self.resolve_qualified(name, tables, single)?),
+            Expression::Function(func_name, name) => Expression::Function(func_name, self.resolve_qualified(name, tables, single)?),
+            expr => expr,
+        }))
+    }
+
+    // 解析 join 条件中的表名
+    fn resolve_qualified_from(&self, from_item: FromItem, tables: &[String]) -> LegendDBResult<FromItem> {
+        Ok(match from_item {
+            FromItem::Join { left, right, join_type, predicate, using } => FromItem::Join {
+                left: Box::new(self.resolve_qualified_from(*left, tables)?),
+                right: Box::new(self.resolve_qualified_from(*right, tables)?),
+                join_type,
+                predicate: predicate.map(|expr| self.resolve_qualified_expr(expr, tables, false)).transpose()?,
+                using,
+            },
+            from_item => from_item,
+        })
+    }
+
+    // 校验查询列的别名不能重复
+    fn validate_select_aliases(&self, columns: &[(Expression, Option<String>)]) -> LegendDBResult<()> {
+        let mut aliases = Vec::new();
+        for alias in columns.iter().filter_map(|(_, alias)| alias.as_ref()) {
+            if aliases.contains(&alias) {
+                return Err(LegendDBError::Internal(format!("duplicate alias {} in select list", alias)));
+            }
+            aliases.push(alias);
+        }
+        Ok(())
+    }
+
+    // 聚合查询输出的列名，有别名取别名，没有别名取函数名或列名
+    fn agg_output_columns(&self, columns: &[(Expression, Option<String>)]) -> Vec<String> {
+        columns.iter().filter_map(|(expr, alias)| match (expr, alias) {
+            (_, Some(alias)) => Some(alias.clone()),
+            (Expression::Function(func_name, _), None) => Some(func_name.clone()),
+            (Expression::Field(col), None) => Some(col.clone()),
+            _ => None,
+        }).collect()
+    }
+
+    // 聚合查询中 having 引用的列必须是聚合输出的列
+    fn validate_having(&self, columns: &[(Expression, Option<String>)], having: &Expression) -> LegendDBResult<()> {
+        let output = self.agg_output_columns(columns);
+        let mut fields = Vec::new();
+        collect_fields(having, &mut fields);
+        for field in fields {
+            if !output.contains(&field) {
+                return Err(LegendDBError::Internal(format!("column {} in HAVING clause is not found in select list", field)));
+            }
+        }
+        Ok(())
+    }
+
+    // 解析 order by 中的表达式，整数常量是查询列的序号，从 1 开始
+    // 聚合查询中排序在聚合之后执行，聚合函数替换为对应的输出列，引用的列必须是聚合输出的列
+    // 普通查询中排序在投影之前执行，序号和别名需要替换为原始的表达式
+    // select * 的序号在执行时才知道对应哪一列，保留原样
+    fn resolve_order_by(&self, columns: &[(Expression, Option<String>)], order_by: Vec<(Expression, OrderDirection)>, has_agg: bool) -> LegendDBResult<Vec<(Expression, OrderDirection)>> {
+        let output = self.agg_output_columns(columns);
+        order_by.into_iter().map(|(expr, direction)| {
+            let expr = match expr {
+                Expression::Consts(Consts::Integer(n)) if !columns.is_empty() => {
+                    let (column, alias) = usize::try_from(n).ok()
+                        .and_then(|n| n.checked_sub(1))
+                        .and_then(|i| columns.get(i))
+                        .ok_or(LegendDBError::Internal(format!("ORDER BY position {} is not in select list", n)))?;
+                    match (has_agg, alias) {
+                        (true, Some(alias)) => Expression::Field(alias.clone()),
+                        (true, None) => match column {
+                            Expression::Function(func_name, _) => Expression::Field(func_name.clone()),
+                            column => column.clone(),
+                        },
+                        (false, _) => column.clone(),
+                    }
+                },
+                expr if has_agg => {
+                    let expr = expr.transform(&mut |expr| Ok(match expr {
+                        Expression::Function(func_name, col) => {
+                            let name = columns.iter().find_map(|(column, alias)| match column {
+                                Expression::Function(f, c) if *f == func_name && *c == col => Some(alias.clone().unwrap_or(func_name.clone())),
+                                _ => None,
+                            }).ok_or(LegendDBError::Internal(format!("{}({}) in ORDER BY clause is not found in select list", func_name, col)))?;
+                            Expression::Field(name)
+                        },
+                        expr => expr,
+                    }))?;
+                    let mut fields = Vec::new();
+                    collect_fields(&expr, &mut fields);
+                    if let Some(col) = fields.iter().find(|col| !output.contains(col)) {
+                        return Err(LegendDBError::Internal(format!("column {} in ORDER BY clause is not found in select list", col)));
+                    }
+                    expr
+                },
+                expr => expr.transform(&mut |expr| Ok(match expr {
+                    Expression::Field(name) => columns.iter().find_map(|(column, alias)| match alias {
+                        Some(alias) if *alias == name => Some(column.clone()),
+                        _ => None,
+                    }).unwrap_or(Expression::Field(name)),
+                    expr => expr,
+                }))?,
+            };
+            Ok((expr, direction))
+        }).collect()
+    }
+
+    // 计算扫描时需要解码的列，也就是投影和排序中引用到的列
+    fn pruned_columns(&self, columns: &[(Expression, Option<String>)], order_by: &[(Expression, OrderDirection)]) -> Option<Vec<String>> {
+        if columns.is_empty() {
+            return None;
+        }
+        let mut fields = Vec::new();
+        for (expr, _) in columns {
+            collect_fields(expr, &mut fields);
+        }
+        for (expr, _) in order_by {
+            collect_fields(expr, &mut fields);
+        }
+        let mut seen = HashSet::new();
+        fields.retain(|col| seen.insert(col.clone()));
+        Some(fields)
+    }
+
+    // 单表查询的过滤条件在扫描时执行，join 的过滤条件可能引用多个表的列，在 join 之后执行
+    pub fn build_from_item(&self, from_item: FromItem, filter: Option<Expression>) -> LegendDBResult<Node> {
         Ok(match from_item { 
-            FromItem::Table { name, alias: _ } => {
-                Node::Scan {
-                    table_name: name,
-                    filter: expression.clone(),
+            FromItem::Table { name, alias: _ } => self.build_scan(name, filter),
+            // 子查询单独生成执行计划，输出的列名就是子查询的列名
+            FromItem::SubQuery { query, alias: _ } => {
+                let node = self.build_statement(*query)?;
+                match filter {
+                    Some(predicate) => Node::Filter {
+                        source: Box::new(node),
+                        predicate,
+                    },
+                    None => node,
                 }
             },
-            FromItem::Join { left, right, join_type, predicate} => {
-                let (left, right) = match join_type { 
-                    JoinType::Right => (right, left),
-                    _ => (left, right),
-                };
-                let outer = match join_type { 
-                    JoinType::Inner | JoinType::Cross => false,
-                    _ => true,
+            FromItem::Join { left, right, join_type, predicate, using } => {
+                let (left, right, join_type) = match join_type {
+                    JoinType::Right => (right, left, JoinType::Left),
+                    join_type => (left, right, join_type),
                 };
-                Node::NestedLoopJoin {
-                    left: Box::new(self.build_from_item(*left, expression)?),
-                    right: Box::new(self.build_from_item(*right, expression)?),
+                let (left_alias, right_alias) = (from_item_alias(&left), from_item_alias(&right));
+                let join = Node::NestedLoopJoin {
+                    left: Box::new(self.build_from_item(*left, None)?),
+                    right: Box::new(self.build_from_item(*right, None)?),
                     predicate,
-                    outer,
+                    join_type,
+                    using,
+                    left_alias,
+                    right_alias,
+                };
+                match filter {
+                    Some(predicate) => Node::Filter {
+                        source: Box::new(join),
+                        predicate,
+                    },
+                    None => join,
                 }
             }
         })
     }
-}
\ No newline at end of file
+
+    // 聚合节点，满足条件时把聚合下推到 join 的一边
+    fn build_aggregate(&self, source: Node, expr: Vec<(Expression, Option<String>)>, group_by: Option<Expression>, having: Option<Expression>) -> Node {
+        if self.settings.optimizer.aggregate_pushdown && having.is_none() && let Some(node) = push_down_aggregate(&source, &expr, &group_by) {
+            return node;
+        }
+        Node::Aggregate {
+            source: Box::new(source),
+            expr,
+            group_by,
+            having,
+        }
+    }
+
+    // 扫描单表，过滤条件默认下推到扫描中执行，关闭 enable_predicate_pushdown 时在扫描全表之后过滤
+    fn build_scan(&self, table_name: String, filter: Option<Expression>) -> Node {
+        match filter {
+            Some(predicate) if !self.settings.optimizer.predicate_pushdown => Node::Filter {
+                source: Box::new(Node::Scan { table_name, filter: None, columns: None }),
+                predicate,
+            },
+            filter => Node::Scan { table_name, filter, columns: None },
+        }
+    }
+}
+
+// 聚合下推到 join 的一边
+// select t1.id, sum(t2.v) from t1 join t2 on t1.id = t2.tid group by t1.id
+// -> t2 先按 tid 聚合出 sum(v)，join 之后每个分组再合并预聚合的结果
+// 只处理两个单表的内连接，连接条件是两边的列相等，按连接的列分组，没有 having
+// 计划时不知道表结构，聚合的列必须带表名才能确定属于哪一边，count(*) 跟随其他聚合的列
+// 一个分组只对应一个连接的值，分组中的每一行都连接到同一行预聚合的结果，行数和原来连接到的那一边的行数相同
+// 所以 sum、min、max 按原函数合并，count 合并为 sum 再转换为整数，avg 的值都相同，取 min
+fn push_down_aggregate(source: &Node, expr: &[(Expression, Option<String>)], group_by: &Option<Expression>) -> Option<Node> {
+    let Node::NestedLoopJoin {
+        left, right, predicate: Some(Expression::Operation(Operation::Equal(l, r))),
+        join_type: JoinType::Inner, using: None, left_alias: Some(left_alias), right_alias: Some(right_alias),
+    } = source else {
+        return None;
+    };
+    let (Node::Scan { filter: None, .. }, Node::Scan { filter: None, .. }) = (left.as_ref(), right.as_ref()) else {
+        return None;
+    };
+    let (Expression::Field(l), Expression::Field(r)) = (l.as_ref(), r.as_ref()) else {
+        return None;
+    };
+    // 连接条件两边的列，按 join 的左右排列
+    let aliases = [left_alias, right_alias];
+    let side = |name: &str| split_qualified(name).and_then(|(table, col)| aliases.iter().position(|a| *a == table).map(|i| (i, col.to_string())));
+    let keys = match (side(l)?, side(r)?) {
+        ((0, l), (1, r)) | ((1, r), (0, l)) => [l, r],
+        _ => return None,
+    };
+    let Some(Expression::Field(group_col)) = group_by else {
+        return None;
+    };
+    if !(0..2).any(|i| *group_col == format!("{}.{}", aliases[i], keys[i])) {
+        return None;
+    }
+    // 聚合的列都在同一边
+    let mut agg_side = None;
+    for (e, _) in expr {
+        match e {
+            Expression::Field(col) if col == group_col => {},
+            Expression::Function(func_name, col) if ["count", "sum", "min", "max", "avg"].contains(&func_name.to_lowercase().as_str()) => {
+                if col == "*" {
+                    continue;
+                }
+                let (i, _) = side(col)?;
+                if agg_side.is_some_and(|s| s != i) {
+                    return None;
+                }
+                agg_side = Some(i);
+            },
+            _ => return None,
+        }
+    }
+    let agg_side = agg_side?;
+    let output = expr.iter().map(|(e, alias)| match (e, alias) {
+        (_, Some(alias)) => alias.clone(),
+        (Expression::Function(func_name, _), None) => func_name.clone(),
+        (e, None) => e.to_string(),
+    }).collect::<Vec<_>>();
+    if output.iter().enumerate().any(|(i, name)| output[..i].contains(name)) {
+        return None;
+    }
+    // 预聚合输出连接的列和每个聚合函数的结果，列名是 sum(v)
+    let key = keys[agg_side].clone();
+    let mut pre_expr = vec![(Expression::Field(key.clone()), Some(key.clone()))];
+    let mut final_expr = Vec::with_capacity(expr.len());
+    let mut counts = Vec::new();
+    for ((e, alias), name) in expr.iter().zip(&output) {
+        let Expression::Function(func_name, col) = e else {
+            final_expr.push((e.clone(), alias.clone()));
+            continue;
+        };
+        let col = split_qualified(col).map_or(col.as_str(), |(_, col)| col);
+        let pre_name = format!("{}({})", func_name, col);
+        if !pre_expr.iter().any(|(_, alias)| alias.as_ref() == Some(&pre_name)) {
+            pre_expr.push((Expression::Function(func_name.clone(), col.to_string()), Some(pre_name.clone())));
+        }
+        let merge = match func_name.to_lowercase().as_str() {
+            "count" => {
+                counts.push(name.clone());
+                "sum".to_string()
+            },
+            "avg" => "min".to_string(),
+            _ => func_name.clone(),
+        };
+        final_expr.push((Expression::Function(merge, format!("{}.{}", aliases[agg_side], pre_name)), Some(name.clone())));
+    }
+    let scan = |node: &Node| match node {
+        Node::Scan { table_name, .. } => Node::Scan { table_name: table_name.clone(), filter: None, columns: None },
+        _ => unreachable!(),
+    };
+    let pre_agg = Node::Aggregate {
+        source: Box::new(scan(if agg_side == 0 { left } else { right })),
+        expr: pre_expr,
+        group_by: Some(Expression::Field(key)),
+        having: None,
+    };
+    let (left, right) = match agg_side {
+        0 => (pre_agg, scan(right)),
+        _ => (scan(left), pre_agg),
+    };
+    let node = Node::Aggregate {
+        source: Box::new(Node::NestedLoopJoin {
+            left: Box::new(left),
+            right: Box::new(right),
+            predicate: Some(Expression::Operation(Operation::Equal(Box::new(Expression::Field(l.clone())), Box::new(Expression::Field(r.clone()))))),
+            join_type: JoinType::Inner,
+            using: None,
+            left_alias: Some(left_alias.clone()),
+            right_alias: Some(right_alias.clone()),
+        }),
+        expr: final_expr,
+        group_by: group_by.clone(),
+        having: None,
+    };
+    if counts.is_empty() {
+        return Some(node);
+    }
+    // sum 的结果是浮点数，count 合并之后转换回整数
+    let columns = output.into_iter().map(|name| match counts.contains(&name) {
+        true => (Expression::Cast(Box::new(Expression::Field(name.clone())), DataType::Integer), Some(name)),
+        false => (Expression::Field(name.clone()), Some(name)),
+    }).collect();
+    Some(Node::Projection {
+        source: Box::new(node),
+        columns,
+    })
+}
+
+// join 两边的表名或别名，嵌套的 join 没有名字
+fn from_item_alias(from_item: &FromItem) -> Option<String> {
+    match from_item {
+        FromItem::Table { name, alias } => Some(alias.clone().unwrap_or_else(|| name.clone())),
+        FromItem::SubQuery { alias, .. } => Some(alias.clone()),
+        FromItem::Join { .. } => None,
+    }
+}
+
+// LIMIT 和 OFFSET 的行数，可以是常量表达式，NULL 表示不限制
+fn row_count(clause: &str, expr: Option<Expression>) -> LegendDBResult<Option<usize>> {
+    let Some(expr) = expr else { return Ok(None) };
+    match evaluate_const(&expr) {
+        Ok(Value::Null) => Ok(None),
+        Ok(Value::Integer(n)) if n >= 0 => Ok(Some(n as usize)),
+        Ok(value) => Err(LegendDBError::Internal(format!("{} must be a non-negative integer, got {}", clause, value))),
+        Err(err) => Err(LegendDBError::Internal(format!("{} must be a constant expression, {} is invalid: {}", clause, expr, err))),
+    }
+}
+
+// 表级约束，没有名字时按照 {table}_pkey、{table}_{columns}_key、{table}_{columns}_fkey、{table}_check 生成
+// 多个没有名字的检查约束依次为 {table}_check1、{table}_check2
+// 单列的主键约束等同于在列上声明 primary key，多列的主键暂不支持
+fn build_constraint(table: &str, columns: &mut [Column], constraint: TableConstraint, built: &[Constraint]) -> LegendDBResult<Constraint> {
+    let name = match (&constraint.name, &constraint.kind) {
+        (Some(name), _) => name.clone(),
+        (None, ConstraintKind::PrimaryKey(_)) => format!("{}_pkey", table),
+        (None, ConstraintKind::Unique(cols)) => format!("{}_{}_key", table, cols.join("_")),
+        (None, ConstraintKind::ForeignKey(fk)) => format!("{}_{}_fkey", table, fk.columns.join("_")),
+        (None, ConstraintKind::Check(_)) => {
+            let mut name = format!("{}_check", table);
+            let mut i = 0;
+            while built.iter().any(|c| c.name == name) {
+                i += 1;
+                name = format!("{}_check{}", table, i);
+            }
+            name
+        },
+    };
+    if let ConstraintKind::PrimaryKey(cols) = &constraint.kind {
+        let [col] = cols.as_slice() else {
+            return Err(LegendDBError::Internal(format!("composite primary key ({}) of table {} is not supported", cols.join(", "), table)));
+        };
+        let column = columns.iter_mut().find(|c| &c.name == col)
+            .ok_or(LegendDBError::Internal(format!("table {} has no column {}", table, col)))?;
+        column.is_primary_key = true;
+        column.nullable = false;
+        if column.default_value == Some(Value::Null) {
+            column.default_value = None;
+        }
+    }
+    Ok(Constraint { name, kind: constraint.kind })
+}
+
+// from 中的表以及 join 输出的列中的表名前缀，子查询没有主键，不参与比较
+fn key_tables(from: &FromItem, single: bool) -> Vec<(String, Option<String>)> {
+    match from {
+        FromItem::Table { name, alias } => {
+            let prefix = (!single).then(|| alias.clone().unwrap_or(name.clone()));
+            vec![(name.clone(), prefix)]
+        },
+        FromItem::SubQuery { .. } => Vec::new(),
+        FromItem::Join { left, right, .. } => {
+            let mut tables = key_tables(left, single);
+            tables.extend(key_tables(right, single));
+            tables
+        },
+    }
+}
+
+// 将语法树中的列定义转换为表结构中的列
+fn build_column(c: ast::Column) -> LegendDBResult<Column> {
+    if let Some(fk) = &c.references {
+        return Err(LegendDBError::Internal(format!("column {} {} can only be declared in CREATE TABLE", c.name, fk)));
+    }
+    if let Some(check) = &c.check {
+        return Err(LegendDBError::Internal(format!("column {} CHECK ({}) can only be declared in CREATE TABLE", c.name, check)));
+    }
+    // 自增列由插入时生成整数值，不能再指定默认值
+    if c.auto_increment && c.data_type != DataType::Integer {
+        return Err(LegendDBError::Internal(format!("AUTO_INCREMENT column {} must be INTEGER, got {}", c.name, c.data_type)));
+    }
+    if c.auto_increment && c.default.is_some() {
+        return Err(LegendDBError::Internal(format!("AUTO_INCREMENT column {} cannot have a default value", c.name)));
+    }
+    let nullable = c.nullable.unwrap_or(!c.is_primary_key);
+    // 包含函数调用的默认值保存表达式，插入时再求值，其他的默认值在建表时就计算出来
+    let mut volatile = false;
+    let default = c.default.map(|v| v.transform(&mut |e| {
+        volatile |= matches!(e, Expression::Function(..));
+        Ok(e)
+    })).transpose()?;
+    let (default_value, default_expr) = match default {
+        Some(v) if volatile => {
+            evaluate_const(&v)?;
+            (None, Some(v))
+        },
+        Some(v) => (Some(evaluate_const(&v)?), None),
+        None if nullable => (Some(Value::Null), None),
+        None => (None, None),
+    };
+    Ok(Column {
+        name: c.name,
+        data_type: c.data_type,
+        max_length: c.max_length,
+        int_width: c.int_width,
+        nullable,
+        default_value,
+        default_expr,
+        is_primary_key: c.is_primary_key,
+        unique: c.unique,
+        auto_increment: c.auto_increment,
+    })
+}
@@ -0,0 +1,404 @@
+use crate::custom_error::{LegendDBError, LegendDBResult};
+use crate::sql::engine::engine::Transaction;
+use crate::sql::parser::ast::{split_qualified, Consts, Expression, FromItem, JoinUsing, Operation, Statement};
+use crate::sql::types::DataType;
+
+// 查询中可见的一张表：表名或别名，以及列名和类型，类型未知时为 None
+struct ScopeTable {
+    name: String,
+    columns: Vec<(String, Option<DataType>)>,
+}
+
+// 语义分析，在生成执行计划之前检查列引用和表达式的类型
+// 一次收集所有的错误，避免执行到一半才逐行报错
+pub struct Resolver<'a, T: Transaction> {
+    txn: &'a T,
+    errors: Vec<String>,
+}
+
+impl<'a, T: Transaction> Resolver<'a, T> {
+    pub fn new(txn: &'a T) -> Self {
+        Self { txn, errors: Vec::new() }
+    }
+
+    // 检查语句，表不存在时直接返回，其他的错误合并成一个
+    pub fn resolve(mut self, stmt: &Statement) -> LegendDBResult<()> {
+        self.resolve_statement(stmt)?;
+        if self.errors.is_empty() {
+            return Ok(());
+        }
+        Err(LegendDBError::Semantic(self.errors))
+    }
+
+    fn resolve_statement(&mut self, stmt: &Statement) -> LegendDBResult<Vec<(String, Option<DataType>)>> {
+        match stmt {
+            Statement::Select { columns, from, where_clause, group_by, having, order_by, .. } => {
+                let mut scope = Vec::new();
+                self.resolve_from(from, &mut scope)?;
+                if let Some(expr) = where_clause {
+                    self.check_condition(expr, &scope, &[])?;
+                }
+                let mut output = Vec::new();
+                for (expr, alias) in columns {
+                    let data_type = self.infer(expr, &scope, &[])?;
+                    output.push((alias.clone().unwrap_or_else(|| expr.to_string()), data_type));
+                }
+                // select * 输出所有的列，join 时去掉表名
+                if columns.is_empty() {
+                    output = scope.iter().flat_map(|t| t.columns.clone()).collect();
+                }
+                // group by、having 和 order by 可以使用查询列的别名，没有别名的聚合函数可以用函数名引用
+                let mut names = output.iter().map(|(name, _)| name.clone()).collect::<Vec<_>>();
+                for (expr, alias) in columns {
+                    if let (Expression::Function(func_name, _), None) = (expr, alias) {
+                        names.push(func_name.clone());
+                    }
+                }
+                if let Some(expr) = group_by {
+                    self.infer(expr, &scope, &names)?;
+                }
+                if let Some(expr) = having {
+                    self.check_condition(expr, &scope, &names)?;
+                }
+                // order by 的整数常量是查询列的序号，从 1 开始
+                for (expr, _) in order_by {
+                    match expr {
+                        Expression::Consts(Consts::Integer(n)) => {
+                            if *n < 1 || *n as usize > output.len() {
+                                self.errors.push(format!("ORDER BY position {} is not in select list", n));
+                            }
+                        },
+                        expr => {
+                            self.infer(expr, &scope, &names)?;
+                        },
+                    }
+                }
+                Ok(output)
+            },
+            Statement::Insert { table_name, columns, .. } => {
+                let table = self.txn.get_table_must(table_name.clone())?;
+                for col in columns.iter().flatten() {
+                    if table.get_column_index(col).is_err() {
+                        self.errors.push(format!("unknown column {} in table {}", col, table_name));
+                    }
+                }
+                Ok(Vec::new())
+            },
+            Statement::Update { table_name, columns, where_clause } => {
+                let scope = vec![self.scope_table(table_name, None)?];
+                for (col, expr) in columns {
+                    match self.lookup(col, &scope) {
+                        Some(Some(col_type)) => {
+                            // 和插入一样，更新的值必须和列的类型相同，日期时间类型的列可以写入字符串
+                            if let Some(value_type) = self.infer(expr, &scope, &[])?
+                                && !assignable(&value_type, &col_type) {
+                                self.errors.push(format!("cannot assign {} to column {} of type {}", value_type, col, col_type));
+                            }
+                        },
+                        _ => {
+                            self.infer(expr, &scope, &[])?;
+                        },
+                    }
+                }
+                if let Some(expr) = where_clause {
+                    self.check_condition(expr, &scope, &[])?;
+                }
+                Ok(Vec::new())
+            },
+            Statement::Delete { table_name, where_clause } => {
+                let scope = vec![self.scope_table(table_name, None)?];
+                if let Some(expr) = where_clause {
+                    self.check_condition(expr, &scope, &[])?;
+                }
+                Ok(Vec::new())
+            },
+            Statement::Explain { statement, .. } => self.resolve_statement(statement),
+            _ => Ok(Vec::new()),
+        }
+    }
+
+    fn scope_table(&self, name: &str, alias: Option<&String>) -> LegendDBResult<ScopeTable> {
+        let table = self.txn.get_table_must(name.to_string())?;
+        Ok(ScopeTable {
+            name: alias.cloned().unwrap_or_else(|| name.to_string()),
+            columns: table.columns.into_iter().map(|c| (c.name, Some(c.data_type))).collect(),
+        })
+    }
+
+    fn resolve_from(&mut self, from: &FromItem, scope: &mut Vec<ScopeTable>) -> LegendDBResult<()> {
+        match from {
+            FromItem::Table { name, alias } => scope.push(self.scope_table(name, alias.as_ref())?),
+            FromItem::SubQuery { query, alias } => {
+                let columns = self.resolve_statement(query)?
+                    .into_iter()
+                    .map(|(name, data_type)| (split_qualified(&name).map_or(name.clone(), |(_, col)| col.to_string()), data_type))
+                    .collect();
+                scope.push(ScopeTable { name: alias.clone(), columns });
+            },
+            FromItem::Join { left, right, predicate, using, .. } => {
+                self.resolve_from(left, scope)?;
+                let mid = scope.len();
+                self.resolve_from(right, scope)?;
+                // USING 的列在两边都必须存在
+                if let Some(JoinUsing::Columns(columns)) = using {
+                    for col in columns {
+                        for (side, tables) in [("left", &scope[..mid]), ("right", &scope[mid..])] {
+                            let found = tables.iter().flat_map(|t| t.columns.iter()).filter(|(c, _)| c == col).count();
+                            if found != 1 {
+                                let problem = if found == 0 { "does not exist" } else { "is ambiguous" };
+                                self.errors.push(format!("column {} in USING {} on the {} side of the join", col, problem, side));
+                            }
+                        }
+                    }
+                }
+                if let Some(expr) = predicate {
+                    self.check_condition(expr, scope, &[])?;
+                }
+            },
+        }
+        Ok(())
+    }
+
+    // 查找列的类型，找不到或者有歧义时记录错误并返回 None
+    fn lookup(&mut self, name: &str, scope: &[ScopeTable]) -> Option<Option<DataType>> {
+        // 引号括起来的列名不是合法的标识符，表名是查询中的表时也当作带表名的列
+        let qualified = split_qualified(name)
+            .or_else(|| name.split_once('.').filter(|(table, _)| scope.iter().any(|t| t.name == *table)));
+        let (table, col) = match qualified {
+            Some((table, col)) => (Some(table), col),
+            None => (None, name),
+        };
+        if let Some(table) = table && !scope.iter().any(|t| t.name == table) {
+            self.errors.push(format!("unknown table {} in column {}", table, name));
+            return None;
+        }
+        let matches = scope
+            .iter()
+            .filter(|t| table.is_none_or(|table| t.name == table))
+            .flat_map(|t| t.columns.iter().filter(|(c, _)| c == col))
+            .collect::<Vec<_>>();
+        match matches.as_slice() {
+            [(_, data_type)] => Some(data_type.clone()),
+            [] => {
+                self.errors.push(format!("unknown column {}", name));
+                None
+            },
+            _ => {
+                self.errors.push(format!("column {} is ambiguous", name));
+                None
+            },
+        }
+    }
+
+    // 条件的结果必须是布尔值
+    fn check_condition(&mut self, expr: &Expression, scope: &[ScopeTable], aliases: &[String]) -> LegendDBResult<()> {
+        if let Some(data_type) = self.infer(expr, scope, aliases)?
+            && data_type != DataType::Boolean {
+            self.errors.push(format!("condition {} must be BOOLEAN, got {}", expr, data_type));
+        }
+        Ok(())
+    }
+
+    // 推导表达式的类型，类型无法确定时返回 None，比如 NULL 和聚合函数
+    fn infer(&mut self, expr: &Expression, scope: &[ScopeTable], aliases: &[String]) -> LegendDBResult<Option<DataType>> {
+        Ok(match expr {
+            Expression::Field(name) if aliases.contains(name) => None,
+            Expression::Field(name) => self.lookup(name, scope).flatten(),
+            Expression::Consts(Consts::Null) => None,
+            Expression::Consts(Consts::Boolean(_)) => Some(DataType::Boolean),
+            Expression::Consts(Consts::Integer(_)) => Some(DataType::Integer),
+            Expression::Consts(Consts::Float(_)) => Some(DataType::Float),
+            Expression::Consts(Consts::String(_)) => Some(DataType::String),
+            Expression::Consts(Consts::Interval(_)) => Some(DataType::Interval),
+            Expression::Consts(Consts::Date(_)) => Some(DataType::Date),
+            Expression::Consts(Consts::Time(_)) => Some(DataType::Time),
+            Expression::Consts(Consts::DateTime(_)) => Some(DataType::DateTime),
+            Expression::Consts(Consts::Binary(_)) => Some(DataType::Binary),
+            Expression::Consts(Consts::Uuid(_)) => Some(DataType::Uuid),
+            Expression::Function(name, col) => {
+                if !col.is_empty() && col != "*" {
+                    self.lookup(col, scope);
+                }
+                match name.as_str() {
+                    "count" => Some(DataType::Integer),
+                    "uuid" if col.is_empty() => Some(DataType::Uuid),
+                    _ => None,
+                }
+            },
+            Expression::Cast(expr, data_type) => {
+                self.infer(expr, scope, aliases)?;
+                Some(data_type.clone())
+            },
+            Expression::Subquery(query) => {
+                self.resolve_statement(query)?;
+                None
+            },
+            Expression::Materialized(_) => None,
+            Expression::Operation(op) => self.infer_operation(op, scope, aliases)?,
+        })
+    }
+
+    fn infer_operation(&mut self, op: &Operation, scope: &[ScopeTable], aliases: &[String]) -> LegendDBResult<Option<DataType>> {
+        let symbol = match op {
+            Operation::Equal(..) => "=",
+            Operation::NotEqual(..) => "!=",
+            Operation::GreaterThan(..) => ">",
+            Operation::LessThan(..) => "<",
+            Operation::GreaterThanOrEqual(..) => ">=",
+            Operation::LessThanOrEqual(..) => "<=",
+            Operation::Like(..) => "LIKE",
+            Operation::Add(..) => "+",
+            Operation::Subtract(..) | Operation::Negate(..) => "-",
+            Operation::Multiply(..) => "*",
+            Operation::Divide(..) => "/",
+            Operation::Modulo(..) => "%",
+            Operation::Exponentiate(..) => "^",
+            Operation::And(..) => "AND",
+            Operation::Or(..) => "OR",
+            Operation::Not(..) => "NOT",
+            Operation::In(..) => "IN",
+            Operation::Exists(..) => "EXISTS",
+        };
+        Ok(match op {
+            Operation::Equal(l, r) | Operation::NotEqual(l, r) | Operation::GreaterThan(l, r) | Operation::LessThan(l, r)
+            | Operation::GreaterThanOrEqual(l, r) | Operation::LessThanOrEqual(l, r) => {
+                if let (Some(lt), Some(rt)) = (self.infer(l, scope, aliases)?, self.infer(r, scope, aliases)?)
+                    && !compatible(&lt, &rt) {
+                    self.errors.push(format!("cannot compare {} with {} in {}", lt, rt, Expression::Operation(op.clone())));
+                }
+                Some(DataType::Boolean)
+            },
+            Operation::Like(l, r) => {
+                for expr in [l, r] {
+                    if let Some(data_type) = self.infer(expr, scope, aliases)?
+                        && data_type != DataType::String {
+                        self.errors.push(format!("operator LIKE requires STRING, got {} in {}", data_type, Expression::Operation(op.clone())));
+                    }
+                }
+                Some(DataType::Boolean)
+            },
+            Operation::Add(l, r) | Operation::Subtract(l, r) | Operation::Multiply(l, r) | Operation::Divide(l, r)
+            | Operation::Modulo(l, r) | Operation::Exponentiate(l, r) => {
+                let lt = self.infer(l, scope, aliases)?;
+                let rt = self.infer(r, scope, aliases)?;
+                // 日期时间的运算，另一边是 NULL 时结果也是 NULL
+                if let (Some(l), Some(r)) = (&lt, &rt) && let Some(data_type) = temporal_arithmetic(op, l, r) {
+                    return Ok(Some(data_type));
+                }
+                if [&lt, &rt].into_iter().flatten().any(is_temporal) && (lt.is_none() || rt.is_none()) {
+                    return Ok(None);
+                }
+                // 时间加减时间间隔的结果是时间，时间间隔之间加减、乘以整数的结果是时间间隔
+                if lt == Some(DataType::Interval) || rt == Some(DataType::Interval) {
+                    return Ok(match (op, &lt, &rt) {
+                        (Operation::Add(..) | Operation::Subtract(..), Some(DataType::Interval), Some(DataType::Interval)) => Some(DataType::Interval),
+                        (Operation::Add(..) | Operation::Subtract(..), Some(DataType::String), _)
+                        | (Operation::Add(..), _, Some(DataType::String)) => Some(DataType::String),
+                        (Operation::Multiply(..), Some(DataType::Integer), _)
+                        | (Operation::Multiply(..), _, Some(DataType::Integer)) => Some(DataType::Interval),
+                        (Operation::Add(..) | Operation::Subtract(..) | Operation::Multiply(..), None, _)
+                        | (Operation::Add(..) | Operation::Subtract(..) | Operation::Multiply(..), _, None) => None,
+                        _ => {
+                            let (lt, rt) = (lt.map_or("NULL".to_string(), |t| t.to_string()), rt.map_or("NULL".to_string(), |t| t.to_string()));
+                            self.errors.push(format!("operator {} cannot be applied to {} and {} in {}", symbol, lt, rt, Expression::Operation(op.clone())));
+                            None
+                        },
+                    });
+                }
+                for data_type in [&lt, &rt].into_iter().flatten() {
+                    if !is_numeric(data_type) {
+                        self.errors.push(format!("operator {} cannot be applied to {} in {}", symbol, data_type, Expression::Operation(op.clone())));
+                        return Ok(None);
+                    }
+                }
+                match (lt, rt) {
+                    (Some(DataType::Integer), Some(DataType::Integer)) => Some(DataType::Integer),
+                    (Some(_), Some(_)) => Some(DataType::Float),
+                    _ => None,
+                }
+            },
+            Operation::Negate(expr) => {
+                let data_type = self.infer(expr, scope, aliases)?;
+                if let Some(data_type) = &data_type && !is_numeric(data_type) && *data_type != DataType::Interval {
+                    self.errors.push(format!("operator - cannot be applied to {} in {}", data_type, Expression::Operation(op.clone())));
+                    return Ok(None);
+                }
+                data_type
+            },
+            Operation::And(l, r) | Operation::Or(l, r) => {
+                for expr in [l, r] {
+                    self.check_boolean(symbol, expr, scope, aliases)?;
+                }
+                Some(DataType::Boolean)
+            },
+            Operation::Not(expr) => {
+                self.check_boolean(symbol, expr, scope, aliases)?;
+                Some(DataType::Boolean)
+            },
+            Operation::In(l, r) => {
+                self.infer(l, scope, aliases)?;
+                self.infer(r, scope, aliases)?;
+                Some(DataType::Boolean)
+            },
+            Operation::Exists(expr) => {
+                self.infer(expr, scope, aliases)?;
+                Some(DataType::Boolean)
+            },
+        })
+    }
+
+    fn check_boolean(&mut self, symbol: &str, expr: &Expression, scope: &[ScopeTable], aliases: &[String]) -> LegendDBResult<()> {
+        if let Some(data_type) = self.infer(expr, scope, aliases)?
+            && data_type != DataType::Boolean {
+            self.errors.push(format!("operator {} requires BOOLEAN, got {} in {}", symbol, data_type, expr));
+        }
+        Ok(())
+    }
+}
+
+fn is_numeric(data_type: &DataType) -> bool {
+    matches!(data_type, DataType::Integer | DataType::Float)
+}
+
+fn is_temporal(data_type: &DataType) -> bool {
+    matches!(data_type, DataType::Date | DataType::Time | DataType::DateTime)
+}
+
+// 相同的类型或者都是数字才能比较，日期可以和时间戳比较，日期时间和 UUID 可以和字符串比较
+fn compatible(left: &DataType, right: &DataType) -> bool {
+    left == right
+        || (is_numeric(left) && is_numeric(right))
+        || matches!((left, right), (DataType::Date, DataType::DateTime) | (DataType::DateTime, DataType::Date))
+        || (parses_string(left) && *right == DataType::String)
+        || (*left == DataType::String && parses_string(right))
+}
+
+// 和字符串比较或者写入字符串时，按这个类型解析字符串
+fn parses_string(data_type: &DataType) -> bool {
+    is_temporal(data_type) || *data_type == DataType::Uuid
+}
+
+// 值能否写入指定类型的列，写入时字符串和日期会转换为列的日期时间或者 UUID 类型
+fn assignable(value: &DataType, column: &DataType) -> bool {
+    value == column
+        || (parses_string(column) && *value == DataType::String)
+        || (*value == DataType::Date && *column == DataType::DateTime)
+}
+
+// 日期时间的运算结果的类型，不是日期时间的运算时返回 None
+fn temporal_arithmetic(op: &Operation, left: &DataType, right: &DataType) -> Option<DataType> {
+    let add = matches!(op, Operation::Add(..));
+    let sub = matches!(op, Operation::Subtract(..));
+    match (left, right) {
+        (DataType::Date, DataType::Integer) if add || sub => Some(DataType::Date),
+        (DataType::Integer, DataType::Date) if add => Some(DataType::Date),
+        (DataType::Date, DataType::Time) | (DataType::Time, DataType::Date) if add => Some(DataType::DateTime),
+        (DataType::Date, DataType::Date) if sub => Some(DataType::Integer),
+        (DataType::DateTime, DataType::DateTime) if sub => Some(DataType::Interval),
+        (DataType::Date | DataType::DateTime, DataType::Interval) if add || sub => Some(DataType::DateTime),
+        (DataType::Interval, DataType::Date | DataType::DateTime) if add => Some(DataType::DateTime),
+        (DataType::Time, DataType::Interval) if add || sub => Some(DataType::Time),
+        (DataType::Interval, DataType::Time) if add => Some(DataType::Time),
+        _ => None,
+    }
+}
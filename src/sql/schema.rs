@@ -1,14 +1,24 @@
 use std::fmt::{Display, Formatter};
 use bincode::{Decode, Encode};
 use serde::{Deserialize, Serialize};
-use crate::sql::types::{DataType, Row, Value};
+use crate::sql::parser::ast::{collect_fields, evaluate_const, evaluate_expr, quote_ident, ConstraintKind, Expression};
+use crate::sql::types::{DataType, IntWidth, Row, Value};
 use crate::custom_error::{LegendDBError, LegendDBResult};
 
-#[derive(Serialize, Deserialize, Encode, Decode, Debug, PartialEq)]
+#[derive(Serialize, Deserialize, Encode, Decode, Debug, Clone, PartialEq)]
 
 pub struct Table {
     pub name: String,
     pub columns: Vec<Column>,
+    // 表级约束
+    pub constraints: Vec<Constraint>,
+}
+
+// 表级约束，建表时没有指定名字的约束会自动生成名字
+#[derive(Serialize, Deserialize, Encode, Decode, Debug, Clone, PartialEq)]
+pub struct Constraint {
+    pub name: String,
+    pub kind: ConstraintKind,
 }
 
 impl Table {
@@ -24,6 +34,9 @@ impl Table {
             0 => return Err(LegendDBError::Internal(format!("table {} has no primary key", self.name))),
             _ => return Err(LegendDBError::Internal(format!("table {} has more than one primary key", self.name))),
         }
+        if self.columns.iter().filter(|c| c.auto_increment).count() > 1 {
+            return Err(LegendDBError::Internal(format!("table {} has more than one AUTO_INCREMENT column", self.name)));
+        }
         // 检查列信息
         for column in &self.columns {
             // 检查列名
@@ -31,7 +44,7 @@ impl Table {
                 return Err(LegendDBError::Internal(format!("table {} has empty column name", self.name)));
             }
             // 主键不能为空
-            if column.nullable && column.default_value.is_none() {
+            if column.nullable && column.default_value.is_none() && column.default_expr.is_none() {
                 return Err(LegendDBError::Internal(format!("table {} has nullable column {} without default value", self.name, column.name)));
             }
             // 检查列类型
@@ -44,17 +57,141 @@ impl Table {
                     },
                     None => {}
                 }
+                column.fit(&mut default_value.clone(), false)?;
+            }
+        }
+        // 检查约束
+        for (i, constraint) in self.constraints.iter().enumerate() {
+            if self.constraints[..i].iter().any(|c| c.name == constraint.name) {
+                return Err(LegendDBError::Internal(format!("table {} has duplicate constraint {}", self.name, constraint.name)));
+            }
+            let columns = match &constraint.kind {
+                ConstraintKind::PrimaryKey(columns) | ConstraintKind::Unique(columns) => columns,
+                ConstraintKind::ForeignKey(fk) => &fk.columns,
+                // 检查约束只能引用本表的列，可以引用多次
+                ConstraintKind::Check(expr) => {
+                    let mut fields = Vec::new();
+                    collect_fields(expr, &mut fields);
+                    for field in fields {
+                        self.get_column_index(&field)?;
+                    }
+                    continue;
+                },
+            };
+            for (j, column) in columns.iter().enumerate() {
+                self.get_column_index(column)?;
+                if columns[..j].contains(column) {
+                    return Err(LegendDBError::Internal(format!("constraint {} of table {} has duplicate column {}", constraint.name, self.name, column)));
+                }
             }
         }
         Ok(())
     }
     
+    // 字符串和日期写入日期时间或者 UUID 类型的列时转换为列的类型，比如 '2024-01-01' 可以直接写入 DATE 列
+    // 无法转换的值保持不变，由之后的类型检查报错
+    pub fn coerce_row(&self, row: &mut Row) {
+        for (value, column) in row.iter_mut().zip(&self.columns) {
+            if matches!(column.data_type, DataType::Date | DataType::Time | DataType::DateTime | DataType::Uuid)
+                && matches!(value, Value::String(_) | Value::Date(_))
+                && let Some(coerced) = value.cast(&column.data_type) {
+                *value = coerced;
+            }
+        }
+    }
+
+    // 校验行中的值，非空的列不能为 NULL，值的类型和列的类型一致
+    pub fn validate_row(&self, row: &Row) -> LegendDBResult<()> {
+        for (value, column) in row.iter().zip(&self.columns) {
+            match value.get_type() {
+                None if column.nullable => {},
+                None => {
+                    return Err(LegendDBError::Internal(format!("column {} is null", column.name)));
+                },
+                Some(dt) if dt != column.data_type => {
+                    return Err(LegendDBError::Internal(format!("column {} type is not match", column.name)));
+                },
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    // 检查行中的值是否超过列的长度或范围，truncate 为 true 时截断，否则报错
+    pub fn fit_row(&self, row: &mut Row, truncate: bool) -> LegendDBResult<()> {
+        for (value, column) in row.iter_mut().zip(&self.columns) {
+            column.fit(value, truncate)?;
+        }
+        Ok(())
+    }
+
+    // 校验行是否满足检查约束，条件的结果为 NULL 时也算满足
+    pub fn check_row(&self, row: &Row) -> LegendDBResult<()> {
+        let mut names = None;
+        for constraint in self.constraints.iter() {
+            let ConstraintKind::Check(expr) = &constraint.kind else { continue };
+            let names = names.get_or_insert_with(|| self.columns.iter().map(|c| c.name.clone()).collect::<Vec<_>>());
+            match evaluate_expr(expr, names, row, names, row)? {
+                Value::Boolean(true) | Value::Null => {},
+                Value::Boolean(false) => return Err(LegendDBError::Internal(format!(
+                    "row violates check constraint {} of table {}: {}", constraint.name, self.name, expr
+                ))),
+                value => return Err(LegendDBError::Internal(format!(
+                    "check constraint {} of table {} must be BOOLEAN, got {}", constraint.name, self.name, value
+                ))),
+            }
+        }
+        Ok(())
+    }
+
+    // 唯一约束的名字和列的位置，包括唯一列和表级的 UNIQUE 约束
+    pub fn unique_keys(&self) -> Vec<(String, Vec<usize>)> {
+        let columns = self.columns.iter().enumerate()
+            .filter(|(_, c)| c.unique && !c.is_primary_key)
+            .map(|(i, c)| (c.name.clone(), vec![i]));
+        let constraints = self.constraints.iter().filter_map(|c| match &c.kind {
+            ConstraintKind::Unique(cols) => Some((c.name.clone(), cols.iter().filter_map(|col| self.get_column_index(col).ok()).collect())),
+            _ => None,
+        });
+        columns.chain(constraints).collect()
+    }
+
+    // 违反唯一约束的错误
+    pub fn unique_key_error(&self, name: &str, key: &[Value]) -> LegendDBError {
+        let key = key.iter().map(|v| match v {
+            Value::String(s) => format!("'{}'", s),
+            v => v.to_string(),
+        }).collect::<Vec<_>>().join(", ");
+        LegendDBError::DuplicateKey { table: self.name.clone(), column: name.to_string(), key }
+    }
+
     // 获取主键值
     pub fn get_primary_key(&self, row: &Row) -> LegendDBResult<Value> {
         let position = self.columns.iter().position(|c| c.is_primary_key).expect("table has no primary key");
         Ok(row[position].clone())
     }
     
+    // 获取主键列名
+    pub fn get_primary_key_name(&self) -> LegendDBResult<&str> {
+        self.columns.iter()
+            .find(|c| c.is_primary_key)
+            .map(|c| c.name.as_str())
+            .ok_or(LegendDBError::Internal(format!("table {} has no primary key", self.name)))
+    }
+
+    // 主键重复的错误
+    pub fn duplicate_key_error(&self, key: &Value) -> LegendDBError {
+        let key = match key {
+            Value::String(s) => format!("'{}'", s),
+            v => v.to_string(),
+        };
+        LegendDBError::DuplicateKey {
+            table: self.name.clone(),
+            column: self.get_primary_key_name().unwrap_or_default().to_string(),
+            key,
+        }
+    }
+
     // 获取列索引
     pub fn get_column_index(&self, name: &str) -> LegendDBResult<usize> {
         // 采用下面更优写法
@@ -77,33 +214,101 @@ impl Display for Table {
         let columns_desc = self.columns
             .iter()
             .map(|c| format!("{}", c))
+            .chain(self.constraints.iter().map(|c| format!("CONSTRAINT {} {}", quote_ident(&c.name), c.kind)))
             .collect::<Vec<_>>()
             .join(",\n");
-        write!(f, "CREATE TABLE {} ({})", self.name, columns_desc)
+        // 表名、列名按需加引号，输出的语句可以重新解析
+        write!(f, "CREATE TABLE {} ({})", quote_ident(&self.name), columns_desc)
     }
 }
 
-#[derive(Serialize, Deserialize, Encode, Decode, Debug, PartialEq)]
+#[derive(Serialize, Deserialize, Encode, Decode, Debug, Clone, PartialEq)]
 
 pub struct Column {
     pub name: String,
     pub data_type: DataType,
+    // 字符串的最大长度，按字符计算，None 表示不限制
+    pub max_length: Option<usize>,
+    // 整数的宽度，None 表示按 i64 存放，不额外检查范围
+    pub int_width: Option<IntWidth>,
     pub nullable: bool,
     pub default_value: Option<Value>,
+    // 包含函数调用的默认值，每次插入时求值，比如 default now()
+    pub default_expr: Option<Expression>,
     pub is_primary_key: bool,
+    pub unique: bool,
+    // 插入时没有指定值或者值为 NULL 时，自动生成比已有的值都大的整数
+    pub auto_increment: bool,
+}
+
+impl Column {
+    // 插入时使用的默认值，表达式的结果需要能转换为列的类型
+    pub fn get_default(&self) -> LegendDBResult<Option<Value>> {
+        let Some(expr) = &self.default_expr else {
+            return Ok(self.default_value.clone());
+        };
+        let value = evaluate_const(expr)?;
+        value.cast(&self.data_type).map(Some).ok_or(LegendDBError::Internal(format!(
+            "default value {} of column {} cannot be converted to {}", value, self.name, self.data_type
+        )))
+    }
+
+    // 检查字符串是否超过列的最大长度、整数是否超出列的范围
+    // truncate 为 true 时字符串截断到最大长度，整数取范围的边界值，否则报错
+    pub fn fit(&self, value: &mut Value, truncate: bool) -> LegendDBResult<()> {
+        match (&mut *value, self.max_length, self.int_width) {
+            (Value::String(s), Some(max_length), _) => {
+                let Some((end, _)) = s.char_indices().nth(max_length) else {
+                    return Ok(());
+                };
+                if !truncate {
+                    return Err(LegendDBError::Internal(format!("value too long for column {}, maximum length is {}", self.name, max_length)));
+                }
+                s.truncate(end);
+            },
+            (Value::Integer(i), _, Some(int_width)) => {
+                let (min, max) = int_width.range();
+                if (min..=max).contains(i) {
+                    return Ok(());
+                }
+                if !truncate {
+                    return Err(LegendDBError::Internal(format!("value {} out of range for column {} {}", i, self.name, int_width)));
+                }
+                *i = (*i).clamp(min, max);
+            },
+            _ => {},
+        }
+        Ok(())
+    }
 }
 
 impl Display for Column {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        let mut column_description = format!("{} {:?}", self.name, self.data_type);
+        // Integer 重新解析后就是 INT，其他宽度需要写出来
+        let mut column_description = match self.int_width {
+            Some(int_width) if int_width != IntWidth::INT => format!("{} {}", quote_ident(&self.name), int_width),
+            _ => format!("{} {:?}", quote_ident(&self.name), self.data_type),
+        };
+        if let Some(max_length) = self.max_length {
+            column_description += &format!("({})", max_length);
+        }
         if self.is_primary_key {
             column_description += " PRIMARY KEY";
         }
         if !self.nullable && !self.is_primary_key {
             column_description += " NOT NULL";
         }
+        if self.auto_increment {
+            column_description += " AUTO_INCREMENT";
+        }
+        if self.unique && !self.is_primary_key {
+            column_description += " UNIQUE";
+        }
         if let Some(default_value) = &self.default_value {
-            column_description += &format!(" DEFAULT {}", default_value);
+            column_description += &format!(" DEFAULT {:#}", default_value.to_expression());
+        }
+        if let Some(default_expr) = &self.default_expr {
+            column_description += &format!(" DEFAULT {:#}", default_expr);
         }
         write!(f, "{}", column_description)
     }
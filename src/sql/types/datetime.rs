@@ -0,0 +1,236 @@
+// 时间间隔，以及时间间隔和时间之间的运算
+// 时间使用 UTC 的 yyyy-mm-dd hh:mm:ss 字符串表示，只有日期的 yyyy-mm-dd 也可以参与运算
+// DATE、TIME、TIMESTAMP 类型的值分别存放 1970-01-01 之后的天数、一天中的秒数和 unix 时间戳
+
+use std::cmp::Ordering;
+use std::fmt::{Display, Formatter};
+use bincode::{Decode, Encode};
+use serde::{Deserialize, Serialize};
+use crate::custom_error::{LegendDBError, LegendDBResult};
+
+pub const SECONDS_PER_DAY: i64 = 86400;
+
+// 时间间隔，月份的天数不固定，所以月和秒分开存放
+// 比如 INTERVAL '1 month 2 days' 是 months = 1, seconds = 2 * 86400
+#[derive(Serialize, Deserialize, Encode, Decode, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Interval {
+    pub months: i64,
+    pub seconds: i64,
+}
+
+impl Interval {
+    // 解析 '1 day'、'2 hours 30 minutes'、'-3 months' 这样的字符串，数量和单位成对出现
+    pub fn parse(s: &str) -> Option<Interval> {
+        let mut interval = Interval { months: 0, seconds: 0 };
+        let mut parts = s.split_whitespace();
+        let mut empty = true;
+        while let Some(amount) = parts.next() {
+            let amount: i64 = amount.parse().ok()?;
+            let unit = parts.next()?.to_lowercase();
+            let unit = unit.strip_suffix('s').unwrap_or(&unit);
+            let (months, seconds) = match unit {
+                "year" => (12, 0),
+                "month" => (1, 0),
+                "week" => (0, 7 * SECONDS_PER_DAY),
+                "day" => (0, SECONDS_PER_DAY),
+                "hour" => (0, 3600),
+                "minute" => (0, 60),
+                "second" => (0, 1),
+                _ => return None,
+            };
+            interval.months = interval.months.checked_add(amount.checked_mul(months)?)?;
+            interval.seconds = interval.seconds.checked_add(amount.checked_mul(seconds)?)?;
+            empty = false;
+        }
+        (!empty).then_some(interval)
+    }
+
+    pub fn checked_add(self, other: Interval) -> Option<Interval> {
+        Some(Interval {
+            months: self.months.checked_add(other.months)?,
+            seconds: self.seconds.checked_add(other.seconds)?,
+        })
+    }
+
+    pub fn checked_mul(self, n: i64) -> Option<Interval> {
+        Some(Interval {
+            months: self.months.checked_mul(n)?,
+            seconds: self.seconds.checked_mul(n)?,
+        })
+    }
+
+    pub fn checked_neg(self) -> Option<Interval> {
+        self.checked_mul(-1)
+    }
+}
+
+// 比较时一个月按 30 天计算
+impl PartialOrd for Interval {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        let key = |i: &Interval| i.months as i128 * 30 * SECONDS_PER_DAY as i128 + i.seconds as i128;
+        Some(key(self).cmp(&key(other)))
+    }
+}
+
+// 输出可以重新解析的形式，比如 1 year 2 months 3 days 4 hours
+impl Display for Interval {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let parts = [
+            (self.months / 12, "year"),
+            (self.months % 12, "month"),
+            (self.seconds / SECONDS_PER_DAY, "day"),
+            (self.seconds % SECONDS_PER_DAY / 3600, "hour"),
+            (self.seconds % 3600 / 60, "minute"),
+            (self.seconds % 60, "second"),
+        ];
+        let parts = parts.iter()
+            .filter(|(n, _)| *n != 0)
+            .map(|(n, unit)| format!("{} {}{}", n, unit, if n.abs() == 1 { "" } else { "s" }))
+            .collect::<Vec<_>>();
+        if parts.is_empty() {
+            write!(f, "0 seconds")
+        } else {
+            write!(f, "{}", parts.join(" "))
+        }
+    }
+}
+
+// 1970-01-01 之后的天数转换为公历日期
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+// 公历日期转换为 1970-01-01 之后的天数
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year - era * 400;
+    let mp = if month > 2 { month - 3 } else { month + 9 };
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+// unix 时间戳格式化为 yyyy-mm-dd hh:mm:ss
+pub fn format_timestamp(secs: i64) -> String {
+    let (year, month, day) = civil_from_days(secs.div_euclid(SECONDS_PER_DAY));
+    let rem = secs.rem_euclid(SECONDS_PER_DAY);
+    format!("{:04}-{:02}-{:02} {:02}:{:02}:{:02}", year, month, day, rem / 3600, rem % 3600 / 60, rem % 60)
+}
+
+// 1970-01-01 之后的天数格式化为 yyyy-mm-dd
+pub fn format_date(days: i64) -> String {
+    let (year, month, day) = civil_from_days(days);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+// 一天中的秒数格式化为 hh:mm:ss
+pub fn format_time(secs: i64) -> String {
+    format!("{:02}:{:02}:{:02}", secs / 3600, secs % 3600 / 60, secs % 60)
+}
+
+// 解析 yyyy-mm-dd，返回 1970-01-01 之后的天数
+pub fn parse_date(s: &str) -> Option<i64> {
+    match parse_timestamp(s)? {
+        ((year, month, day), None) => Some(days_from_civil(year, month, day)),
+        _ => None,
+    }
+}
+
+// 解析 hh:mm:ss，返回一天中的秒数
+pub fn parse_time(s: &str) -> Option<i64> {
+    let mut time = s.trim().split(':').map(|p| p.parse::<i64>().ok());
+    let (Some(Some(h)), Some(Some(m)), Some(Some(s)), None) = (time.next(), time.next(), time.next(), time.next()) else {
+        return None;
+    };
+    if !(0..24).contains(&h) || !(0..60).contains(&m) || !(0..60).contains(&s) {
+        return None;
+    }
+    Some(h * 3600 + m * 60 + s)
+}
+
+// 解析 yyyy-mm-dd [hh:mm:ss]，返回 unix 时间戳，只有日期时是当天的零点
+pub fn parse_datetime(s: &str) -> Option<i64> {
+    let ((year, month, day), time) = parse_timestamp(s)?;
+    Some(days_from_civil(year, month, day) * SECONDS_PER_DAY + time.unwrap_or(0))
+}
+
+// 解析 yyyy-mm-dd 或 yyyy-mm-dd hh:mm:ss，返回日期和一天中的秒数，只有日期时秒数为 None
+fn parse_timestamp(s: &str) -> Option<((i64, i64, i64), Option<i64>)> {
+    let (date, time) = match s.trim().split_once(' ') {
+        Some((date, time)) => (date, Some(time.trim())),
+        None => (s.trim(), None),
+    };
+    let mut date = date.split('-').map(|p| p.parse::<i64>().ok());
+    let (Some(Some(year)), Some(Some(month)), Some(Some(day)), None) = (date.next(), date.next(), date.next(), date.next()) else {
+        return None;
+    };
+    if !(0..=9999).contains(&year) || !(1..=12).contains(&month) || day < 1 || day > days_in_month(year, month) {
+        return None;
+    }
+    let time = match time {
+        Some(time) => Some(parse_time(time)?),
+        None => None,
+    };
+    Some(((year, month, day), time))
+}
+
+// 时间加上时间间隔，先加月份，月末的日期超出新月份的天数时取月末，再加秒数
+// 只有日期并且间隔是整天时，结果也只有日期
+pub fn add_interval(timestamp: &str, interval: Interval) -> LegendDBResult<String> {
+    let ((year, month, day), time) = parse_timestamp(timestamp).ok_or(LegendDBError::Internal(format!(
+        "invalid timestamp '{}', expected yyyy-mm-dd [hh:mm:ss]", timestamp
+    )))?;
+    shift(year, month, day, time, interval).ok_or(LegendDBError::Internal(format!(
+        "timestamp out of range in '{}' + INTERVAL '{}'", timestamp, interval
+    )))
+}
+
+// unix 时间戳加上时间间隔，规则和 add_interval 相同，结果的年份超出 0000-9999 时返回 None
+pub fn shift_datetime(secs: i64, interval: Interval) -> Option<i64> {
+    let (year, month, day) = civil_from_days(secs.div_euclid(SECONDS_PER_DAY));
+    let months = (year * 12 + month - 1).checked_add(interval.months)?;
+    let (year, month) = (months.div_euclid(12), months.rem_euclid(12) + 1);
+    if !(0..=9999).contains(&year) {
+        return None;
+    }
+    let day = day.min(days_in_month(year, month));
+    let secs = days_from_civil(year, month, day)
+        .checked_mul(SECONDS_PER_DAY)?
+        .checked_add(secs.rem_euclid(SECONDS_PER_DAY))?
+        .checked_add(interval.seconds)?;
+    // 年份保持四位数
+    if !(0..=9999).contains(&civil_from_days(secs.div_euclid(SECONDS_PER_DAY)).0) {
+        return None;
+    }
+    Some(secs)
+}
+
+// 结果的年份超出 0000-9999 时返回 None
+fn shift(year: i64, month: i64, day: i64, time: Option<i64>, interval: Interval) -> Option<String> {
+    let secs = shift_datetime(days_from_civil(year, month, day) * SECONDS_PER_DAY + time.unwrap_or(0), interval)?;
+    let result = format_timestamp(secs);
+    if time.is_none() && interval.seconds % SECONDS_PER_DAY == 0 {
+        Some(result[..10].to_string())
+    } else {
+        Some(result)
+    }
+}
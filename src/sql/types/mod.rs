@@ -3,9 +3,14 @@ use std::fmt::{Display, Formatter};
 use std::hash::{Hash, Hasher};
 use bincode::{Decode, Encode};
 use serde::{Deserialize, Serialize};
+use crate::custom_error::{LegendDBError, LegendDBResult};
 use crate::sql::parser::ast::{Consts, Expression};
+use crate::sql::types::datetime::Interval;
 
-#[derive(Serialize, Deserialize, Encode, Decode, Debug, PartialEq)]
+pub mod datetime;
+pub mod uuid;
+
+#[derive(Serialize, Deserialize, Encode, Decode, Debug, Clone, PartialEq)]
 pub enum DataType {
     Boolean,
     Integer,
@@ -19,6 +24,8 @@ pub enum DataType {
     Map(Box<DataType>, Box<DataType>),
     Union(Vec<DataType>),
     Null,
+    Interval,
+    Uuid,
 }
 
 #[derive(Serialize, Deserialize, Encode, Decode,Debug, Clone, PartialEq)]
@@ -28,10 +35,18 @@ pub enum Value {
     Integer(i64),
     Float(f64),
     String(String),
-    // Date(String),
-    // Time(String),
-    // DateTime(String),
-    // Binary(Vec<u8>),
+    // 时间间隔只出现在表达式中，不能作为列的类型
+    Interval(Interval),
+    // 1970-01-01 之后的天数
+    Date(i64),
+    // 一天中的秒数
+    Time(i64),
+    // unix 时间戳，精确到秒
+    DateTime(i64),
+    // 二进制数据
+    Binary(#[serde(with = "serde_bytes")] Vec<u8>),
+    // UUID 的 16 个字节
+    Uuid([u8; 16]),
     // Array(Vec<Value>),
     // Map(Vec<(Value, Value)>),
     // Union(Vec<Value>),
@@ -60,6 +75,30 @@ impl Hash for Value {
                 state.write_u8(5);
                 s.hash(state);
             },
+            Value::Interval(i) => {
+                state.write_u8(6);
+                i.hash(state);
+            },
+            Value::Date(d) => {
+                state.write_u8(7);
+                d.hash(state);
+            },
+            Value::Time(t) => {
+                state.write_u8(8);
+                t.hash(state);
+            },
+            Value::DateTime(t) => {
+                state.write_u8(9);
+                t.hash(state);
+            },
+            Value::Binary(b) => {
+                state.write_u8(10);
+                b.hash(state);
+            },
+            Value::Uuid(u) => {
+                state.write_u8(11);
+                u.hash(state);
+            },
         }
     }
 }
@@ -79,6 +118,15 @@ impl PartialOrd for Value {
             (Value::Float(a), Value::Float(b)) => a.partial_cmp(b),
             (Value::Float(a), Value::Integer(b)) => a.partial_cmp(&(*b as f64)),
             (Value::String(a), Value::String(b)) => a.partial_cmp(b),
+            (Value::Interval(a), Value::Interval(b)) => a.partial_cmp(b),
+            (Value::Date(a), Value::Date(b)) => a.partial_cmp(b),
+            (Value::Time(a), Value::Time(b)) => a.partial_cmp(b),
+            (Value::DateTime(a), Value::DateTime(b)) => a.partial_cmp(b),
+            (Value::Binary(a), Value::Binary(b)) => a.partial_cmp(b),
+            (Value::Uuid(a), Value::Uuid(b)) => a.partial_cmp(b),
+            // 日期按当天的零点和时间戳比较
+            (Value::Date(a), Value::DateTime(b)) => (a * datetime::SECONDS_PER_DAY).partial_cmp(b),
+            (Value::DateTime(a), Value::Date(b)) => a.partial_cmp(&(b * datetime::SECONDS_PER_DAY)),
             (_, _) => None,
         }
     }
@@ -93,6 +141,13 @@ impl Display for Value {
             Value::Integer(v) => write!(f, "{}", v),
             Value::Float(v) => write!(f, "{}", v),
             Value::String(v) => write!(f, "{}", v),
+            Value::Interval(v) => write!(f, "{}", v),
+            Value::Date(d) => write!(f, "{}", datetime::format_date(*d)),
+            Value::Time(t) => write!(f, "{}", datetime::format_time(*t)),
+            Value::DateTime(t) => write!(f, "{}", datetime::format_timestamp(*t)),
+            // 和 PostgreSQL 一样以 \x 开头的十六进制
+            Value::Binary(b) => write!(f, "\\x{}", format_hex(b)),
+            Value::Uuid(u) => write!(f, "{}", uuid::format(u)),
         }
     }
 }
@@ -106,10 +161,33 @@ impl Value {
             Expression::Consts(Consts::Integer(i)) => Self::Integer(i),
             Expression::Consts(Consts::Float(f)) => Self::Float(f),
             Expression::Consts(Consts::String(s)) => Self::String(s),
+            Expression::Consts(Consts::Interval(i)) => Self::Interval(i),
+            Expression::Consts(Consts::Date(d)) => Self::Date(d),
+            Expression::Consts(Consts::Time(t)) => Self::Time(t),
+            Expression::Consts(Consts::DateTime(t)) => Self::DateTime(t),
+            Expression::Consts(Consts::Binary(b)) => Self::Binary(b),
+            Expression::Consts(Consts::Uuid(u)) => Self::Uuid(u),
             _ => unreachable!()
         }
     }
     
+    // 转换为常量表达式，用于生成 SQL
+    pub fn to_expression(&self) -> Expression {
+        Expression::Consts(match self {
+            Value::Null => Consts::Null,
+            Value::Boolean(b) => Consts::Boolean(*b),
+            Value::Integer(i) => Consts::Integer(*i),
+            Value::Float(f) => Consts::Float(*f),
+            Value::String(s) => Consts::String(s.clone()),
+            Value::Interval(i) => Consts::Interval(*i),
+            Value::Date(d) => Consts::Date(*d),
+            Value::Time(t) => Consts::Time(*t),
+            Value::DateTime(t) => Consts::DateTime(*t),
+            Value::Binary(b) => Consts::Binary(b.clone()),
+            Value::Uuid(u) => Consts::Uuid(*u),
+        })
+    }
+
     // 获取数据类型
     pub fn get_type(&self) -> Option<DataType> {
         match self {
@@ -118,15 +196,137 @@ impl Value {
             Value::Integer(_) => Some(DataType::Integer),
             Value::Float(_) => Some(DataType::Float),
             Value::String(_) => Some(DataType::String),
-            // Value::Date(_) => Some(DataType::Date),
-            // Value::Time(_) => Some(DataType::Time),
-            // Value::DateTime(_) => Some(DataType::DateTime),
-            // Value::Binary(_) => Some(DataType::Binary),
+            Value::Interval(_) => Some(DataType::Interval),
+            Value::Date(_) => Some(DataType::Date),
+            Value::Time(_) => Some(DataType::Time),
+            Value::DateTime(_) => Some(DataType::DateTime),
+            Value::Binary(_) => Some(DataType::Binary),
+            Value::Uuid(_) => Some(DataType::Uuid),
             // Value::Json(_) => Some(DataType::String),
             // Value::Jsonb(_) => Some(DataType::String),
         }
     }
-    
+
+    // 转换为指定的数据类型，无法转换时返回None
+    // NULL 转换之后还是 NULL
+    pub fn cast(&self, data_type: &DataType) -> Option<Value> {
+        match (self, data_type) {
+            (Value::Null, _) => Some(Value::Null),
+            (v, dt) if v.get_type().as_ref() == Some(dt) => Some(v.clone()),
+            (Value::Boolean(b), DataType::Integer) => Some(Value::Integer(*b as i64)),
+            (Value::Boolean(b), DataType::Float) => Some(Value::Float(*b as i64 as f64)),
+            (Value::Integer(i), DataType::Float) => Some(Value::Float(*i as f64)),
+            (Value::Integer(i), DataType::Boolean) if *i == 0 || *i == 1 => Some(Value::Boolean(*i == 1)),
+            // 浮点数只有没有小数部分时才能转换为整数，避免丢失精度
+            (Value::Float(f), DataType::Integer) if f.fract() == 0.0 && *f >= i64::MIN as f64 && *f <= i64::MAX as f64 => Some(Value::Integer(*f as i64)),
+            (Value::Boolean(_) | Value::Integer(_) | Value::Float(_) | Value::Interval(_)
+            | Value::Date(_) | Value::Time(_) | Value::DateTime(_) | Value::Uuid(_), DataType::String) => Some(Value::String(self.to_string())),
+            (Value::Date(d), DataType::DateTime) => Some(Value::DateTime(d * 86400)),
+            // 字符串按照 yyyy-mm-dd、hh:mm:ss、yyyy-mm-dd hh:mm:ss 的格式解析
+            (Value::String(s), DataType::Date) => datetime::parse_date(s).map(Value::Date),
+            (Value::String(s), DataType::Time) => datetime::parse_time(s).map(Value::Time),
+            (Value::String(s), DataType::DateTime) => datetime::parse_datetime(s).map(Value::DateTime),
+            (Value::String(s), DataType::Uuid) => uuid::parse(s).map(Value::Uuid),
+            (Value::String(s), DataType::Integer) => s.trim().parse().ok().map(Value::Integer),
+            (Value::String(s), DataType::Float) => s.trim().parse().ok().map(Value::Float),
+            (Value::String(s), DataType::Boolean) => match s.trim().to_uppercase().as_str() {
+                "TRUE" => Some(Value::Boolean(true)),
+                "FALSE" => Some(Value::Boolean(false)),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    // CAST 显式类型转换，比隐式转换宽松：浮点数转整数时截断小数部分，数字转布尔时非0为true
+    // 无法转换时报错
+    pub fn cast_explicit(&self, data_type: &DataType) -> LegendDBResult<Value> {
+        if let Some(value) = self.cast(data_type) {
+            return Ok(value);
+        }
+        match (self, data_type) {
+            (Value::Float(f), DataType::Integer) if f.is_finite() && f.trunc() >= i64::MIN as f64 && f.trunc() < i64::MAX as f64 => Ok(Value::Integer(f.trunc() as i64)),
+            (Value::Integer(i), DataType::Boolean) => Ok(Value::Boolean(*i != 0)),
+            (Value::Float(f), DataType::Boolean) => Ok(Value::Boolean(*f != 0.0)),
+            // 时间戳转换为日期时丢弃时间部分
+            (Value::DateTime(t), DataType::Date) => Ok(Value::Date(t.div_euclid(86400))),
+            (Value::DateTime(t), DataType::Time) => Ok(Value::Time(t.rem_euclid(86400))),
+            // 字符串和二进制数据按 UTF-8 编码相互转换
+            (Value::String(s), DataType::Binary) => Ok(Value::Binary(s.as_bytes().to_vec())),
+            (Value::Binary(b), DataType::String) => String::from_utf8(b.clone()).map(Value::String)
+                .map_err(|_| LegendDBError::Internal(format!("can not cast {} to {}: invalid UTF-8", self, data_type))),
+            // UUID 和 16 个字节的二进制数据相互转换
+            (Value::Uuid(u), DataType::Binary) => Ok(Value::Binary(u.to_vec())),
+            (Value::Binary(b), DataType::Uuid) if b.len() == 16 => Ok(Value::Uuid(b.as_slice().try_into().unwrap_or_default())),
+            _ => Err(LegendDBError::Internal(format!("can not cast {:?} to {}", self, data_type))),
+        }
+    }
 }
 
-pub type Row = Vec<Value>;
\ No newline at end of file
+impl Display for DataType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DataType::Boolean => write!(f, "BOOLEAN"),
+            DataType::Integer => write!(f, "INTEGER"),
+            DataType::Float => write!(f, "FLOAT"),
+            DataType::String => write!(f, "STRING"),
+            DataType::Interval => write!(f, "INTERVAL"),
+            DataType::Date => write!(f, "DATE"),
+            DataType::Time => write!(f, "TIME"),
+            DataType::DateTime => write!(f, "TIMESTAMP"),
+            DataType::Binary => write!(f, "BINARY"),
+            DataType::Uuid => write!(f, "UUID"),
+            data_type => write!(f, "{:?}", data_type),
+        }
+    }
+}
+
+// 整数列的宽度，值仍然按 i64 存放，写入时检查是否超出范围
+// BIGINT UNSIGNED 受 i64 的限制，上限是 i64::MAX
+#[derive(Serialize, Deserialize, Encode, Decode, Debug, Clone, Copy, PartialEq)]
+pub struct IntWidth {
+    // 占用的字节数，1、2、4、8
+    pub bytes: u8,
+    pub unsigned: bool,
+}
+
+impl IntWidth {
+    pub const INT: IntWidth = IntWidth { bytes: 4, unsigned: false };
+
+    // 可以存放的最小值和最大值
+    pub fn range(&self) -> (i64, i64) {
+        let shift = 64 - self.bytes as u32 * 8;
+        match self.unsigned {
+            true if shift == 0 => (0, i64::MAX),
+            true => (0, (1 << (64 - shift)) - 1),
+            false => (i64::MIN >> shift, i64::MAX >> shift),
+        }
+    }
+}
+
+impl Display for IntWidth {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = match self.bytes {
+            1 => "TINYINT",
+            2 => "SMALLINT",
+            4 => "INT",
+            _ => "BIGINT",
+        };
+        write!(f, "{}{}", name, if self.unsigned { " UNSIGNED" } else { "" })
+    }
+}
+
+pub type Row = Vec<Value>;
+
+// 二进制数据的十六进制表示，小写
+pub fn format_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// 解析十六进制字符串，长度必须是偶数
+pub fn parse_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) || !s.is_ascii() {
+        return None;
+    }
+    (0..s.len()).step_by(2).map(|i| u8::from_str_radix(&s[i..i + 2], 16).ok()).collect()
+}
\ No newline at end of file
@@ -0,0 +1,74 @@
+// UUID 的生成、解析和展示
+// UUID 类型的值存放 16 个字节，key 中按字节顺序编码，占 16 个字节，比存放 36 个字符的字符串紧凑
+// uuid() 生成随机的第 4 版 UUID，随机数来自标准库的 RandomState，每个进程的种子不同，不能用于密码学的场景
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// 同一个进程内连续生成时保证输入不同
+static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+// 生成随机的第 4 版 UUID
+pub fn generate() -> [u8; 16] {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_nanos());
+    let counter = COUNTER.fetch_add(1, Ordering::Relaxed);
+    let mut bytes = [0; 16];
+    for (i, chunk) in bytes.chunks_mut(8).enumerate() {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u128(nanos);
+        hasher.write_u64(counter);
+        hasher.write_usize(i);
+        chunk.copy_from_slice(&hasher.finish().to_be_bytes());
+    }
+    // 版本号 4，变体 10xx
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    bytes
+}
+
+// 解析 8-4-4-4-12 格式的 UUID，不区分大小写，也可以不带连字符
+pub fn parse(s: &str) -> Option<[u8; 16]> {
+    let s = s.trim();
+    let hex = match s.len() {
+        36 => {
+            let dashes = [8, 13, 18, 23];
+            if s.char_indices().any(|(i, c)| (c == '-') != dashes.contains(&i)) {
+                return None;
+            }
+            s.replace('-', "")
+        },
+        32 => s.to_string(),
+        _ => return None,
+    };
+    super::parse_hex(&hex)?.try_into().ok()
+}
+
+// 展示为小写的 8-4-4-4-12 格式
+pub fn format(bytes: &[u8; 16]) -> String {
+    let hex = super::format_hex(bytes);
+    format!("{}-{}-{}-{}-{}", &hex[..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..])
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sql::types::uuid::{format, generate, parse};
+
+    #[test]
+    fn test_uuid() {
+        let s = "123e4567-e89b-42d3-a456-426614174000";
+        let bytes = parse(s).unwrap();
+        assert_eq!(bytes[0], 0x12);
+        assert_eq!(format(&bytes), s);
+        assert_eq!(parse("123E4567E89B42D3A456426614174000"), Some(bytes));
+        for invalid in ["", "123e4567-e89b-42d3-a456-42661417400", "123e4567e-89b-42d3-a456-426614174000", "123e4567-e89b-42d3-a456-42661417400g"] {
+            assert_eq!(parse(invalid), None, "{}", invalid);
+        }
+        let (a, b) = (generate(), generate());
+        assert_ne!(a, b);
+        assert_eq!(a[6] >> 4, 4);
+        assert_eq!(a[8] >> 6, 2);
+        assert_eq!(parse(&format(&a)), Some(a));
+    }
+}
@@ -1,32 +1,97 @@
 // 磁盘存储引擎
 
 use std::collections::{btree_map, BTreeMap};
-use std::fs::{rename, File, OpenOptions};
+use std::fs::{remove_file, rename, File, OpenOptions};
 use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
-use std::ops::{RangeBounds};
-use std::path::PathBuf;
+use std::ops::{Bound, RangeBounds};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
 use fs4::fs_std::FileExt;
 use btree_map::Range;
 use crate::storage::engine::{Engine, EngineIterator};
-use crate::custom_error::LegendDBResult;
+use crate::sql::types::datetime::format_timestamp;
+use crate::custom_error::{LegendDBError, LegendDBResult};
 
-pub type KeyDir = BTreeMap<Vec<u8>, (u64, u32)>;
+pub type KeyDir = BTreeMap<Vec<u8>, ValueLocation>;
 // 日志文件头大小 key value 都是u32 所以是8个字节
 const LOG_HEADER_SIZE: u32 = 8;
+// 不超过这个长度的 value 直接存放在 keydir 中
+const INLINE_VALUE_SIZE: usize = 16;
+// 扫描时一次最多预读的字节数
+const READAHEAD_SIZE: u64 = 256 * 1024;
+// 预读时两个 value 之间最多跳过的字节数，超过时分开读取
+const READAHEAD_MAX_GAP: u64 = 4 * 1024;
+// 整理时每一步最多复制的字节数
+const COMPACT_CHUNK_SIZE: u64 = 1024 * 1024;
+// 启动恢复时每处理这么多字节汇报一次进度
+const RECOVERY_PROGRESS_INTERVAL: u64 = 16 * 1024 * 1024;
+
+
+// keydir 中 value 的位置，很小的 value 直接存放在 keydir 中，读取时不需要访问磁盘
+#[derive(Debug, Clone, PartialEq)]
+pub enum ValueLocation {
+    // value 在日志文件中的偏移和长度
+    Disk(u64, u32),
+    // value 的内容和长度
+    Inline([u8; INLINE_VALUE_SIZE], u8),
+}
 
+impl ValueLocation {
+    // 写入日志之后 value 的位置，entry_end 是日志条目的结束位置
+    fn new(value: &[u8], entry_end: u64) -> Self {
+        if value.len() <= INLINE_VALUE_SIZE {
+            let mut data = [0; INLINE_VALUE_SIZE];
+            data[..value.len()].copy_from_slice(value);
+            ValueLocation::Inline(data, value.len() as u8)
+        } else {
+            ValueLocation::Disk(entry_end - value.len() as u64, value.len() as u32)
+        }
+    }
+}
 
 #[derive(Debug)]
 pub struct DiskEngine {
     keydir: KeyDir,
-    log: Log
+    log: Log,
+    // 正在进行的整理
+    compaction: Option<Compaction>,
+}
+
+// 整理时按 key 的顺序分批把有效的数据复制到新的日志中，全部复制完之后用新的日志替换旧的日志
+// 整理期间的写入照常写到旧的日志中，已经复制过的 key 同时写到新的日志中，还没有复制的 key 之后会复制最新的值
+#[derive(Debug)]
+struct Compaction {
+    log: Log,
+    keydir: KeyDir,
+    // 最后一个已经复制的 key，None 表示还没有复制过
+    cursor: Option<Vec<u8>>,
+}
+
+impl Compaction {
+    // 是否已经复制过这个 key
+    fn copied(&self, key: &[u8]) -> bool {
+        self.cursor.as_deref().is_some_and(|cursor| key <= cursor)
+    }
 }
 
 impl DiskEngine {
     pub fn new(file_path: PathBuf) -> LegendDBResult<Self> {
+        Self::new_with_progress(file_path, &mut |_, _| {})
+    }
+
+    // 打开时从日志中恢复 keydir，日志很大时比较耗时
+    // progress 的参数是已经处理的字节数和日志的总字节数，处理完成时一定会调用一次
+    pub fn new_with_progress(file_path: PathBuf, progress: &mut dyn FnMut(u64, u64)) -> LegendDBResult<Self> {
         let mut log = Log::new(file_path)?;
+        // 替换日志的重命名是原子的，遗留的临时日志一定是中断的整理，直接删除
+        let compact_path = compact_path(&log.file_path);
+        if compact_path.exists() {
+            remove_file(&compact_path)?;
+            let _ = remove_file(lock_info_path(&compact_path));
+        }
         // 从 log 中去恢复的 keydir
-        let keydir = log.build_keydir()?;
-        Ok(Self { keydir, log })
+        let keydir = log.build_keydir(progress)?;
+        Ok(Self { keydir, log, compaction: None })
     }
 
     pub fn new_compact(file_path: PathBuf) -> LegendDBResult<Self> {
@@ -37,26 +102,45 @@ impl DiskEngine {
 
 
     fn compact(&mut self) -> LegendDBResult<()> {
-        // 新打开一个临时的日志文件
-        let mut new_path = self.log.file_path.clone();
-        new_path.set_extension("compact");
-        let mut new_log = Log::new(new_path)?;
-        let mut new_keydir = KeyDir::new();
-        // 重写数据到临时文件中
-        for (key, (offset, size)) in self.keydir.iter() {
-            // 读取key对应的value
-            let value = self.log.read_entry(*offset, *size)?;
-            // 写入新的log
-            let (new_offset, new_size) = new_log.write_entry(key, Some(&value))?;
-            // 更新keydir
-            new_keydir.insert(key.clone(), (new_offset + new_size as u64 - *size as u64, *size));
+        while !self.compact_chunk(COMPACT_CHUNK_SIZE)? {}
+        Ok(())
+    }
+
+    // 复制下一批数据到新的日志中，至少复制一个 key，复制的字节数达到 max_bytes 时停止
+    // 全部复制完之后替换旧的日志，返回 true
+    fn compact_chunk(&mut self, max_bytes: u64) -> LegendDBResult<bool> {
+        let mut compaction = match self.compaction.take() {
+            Some(compaction) => compaction,
+            None => {
+                // 新打开一个临时的日志文件，清空上次整理中断时遗留的数据
+                let log = Log::new(compact_path(&self.log.file_path))?;
+                log.file.set_len(0)?;
+                Compaction { log, keydir: KeyDir::new(), cursor: None }
+            }
+        };
+        let start = compaction.cursor.clone().map_or(Bound::Unbounded, Bound::Excluded);
+        let mut copied = 0;
+        let mut done = true;
+        for (key, location) in self.keydir.range((start, Bound::Unbounded)) {
+            if copied > 0 && copied >= max_bytes {
+                done = false;
+                break;
+            }
+            // 读取key对应的value，写入新的log
+            let value = self.log.read_location(location)?;
+            let (new_offset, new_size) = compaction.log.write_entry(key, Some(&value))?;
+            compaction.keydir.insert(key.clone(), ValueLocation::new(&value, new_offset + new_size as u64));
+            compaction.cursor = Some(key.clone());
+            copied += new_size as u64;
+        }
+        if !done {
+            self.compaction = Some(compaction);
+            return Ok(false);
         }
         // 将临时文件更改为正式文件
-        rename(new_log.file_path, &self.log.file_path)?;
-        new_log.file_path = self.log.file_path.clone();
-        self.keydir = new_keydir;
-        self.log = new_log;
-        Ok(())
+        self.log.replace_with(compaction.log)?;
+        self.keydir = compaction.keydir;
+        Ok(true)
     }
 }
 
@@ -71,15 +155,18 @@ impl Engine for DiskEngine {
         //100-----------------|----150
         //                    130
         // val size = 20
-        let val_size = value.len() as u32;
-        self.keydir.insert(key, (offset + size as u64 - val_size as u64, val_size));
+        if let Some(compaction) = &mut self.compaction && compaction.copied(&key) {
+            let (offset, size) = compaction.log.write_entry(&key, Some(&value))?;
+            compaction.keydir.insert(key.clone(), ValueLocation::new(&value, offset + size as u64));
+        }
+        self.keydir.insert(key, ValueLocation::new(&value, offset + size as u64));
         Ok(())
     }
 
     fn get(&mut self, key: Vec<u8>) -> LegendDBResult<Option<Vec<u8>>> {
         match self.keydir.get(&key) {
-            Some((offset, size)) => {
-                let value = self.log.read_entry(*offset, *size)?;
+            Some(location) => {
+                let value = self.log.read_location(location)?;
                 Ok(Some(value))
             },
             None => Ok(None),
@@ -88,6 +175,10 @@ impl Engine for DiskEngine {
 
     fn delete(&mut self, key: Vec<u8>) -> LegendDBResult<()> {
         self.log.write_entry(&key, None)?;
+        if let Some(compaction) = &mut self.compaction && compaction.copied(&key) {
+            compaction.log.write_entry(&key, None)?;
+            compaction.keydir.remove(&key);
+        }
         self.keydir.remove(&key);
         Ok(())
     }
@@ -96,23 +187,70 @@ impl Engine for DiskEngine {
         DiskEngineIterator {
             inner: self.keydir.range(range),
             log: &mut self.log,
+            readahead: None,
         }
     }
+
+    fn compact_step(&mut self) -> LegendDBResult<bool> {
+        self.compact_chunk(COMPACT_CHUNK_SIZE)
+    }
     
 }
 
 pub struct DiskEngineIterator<'a> {
-    inner: Range<'a, Vec<u8>, (u64, u32)>,
+    inner: Range<'a, Vec<u8>, ValueLocation>,
     log: &'a mut Log,
+    // 预读的数据，(在日志中的起始位置, 数据)
+    readahead: Option<(u64, Vec<u8>)>,
 }
 
 impl<'a> DiskEngineIterator<'a> {
     
-    fn map(&mut self, item: (&Vec<u8>, &(u64, u32))) -> <Self as Iterator>::Item {
-        let (key, (offset, size)) = item;
-        let value = self.log.read_entry(*offset, *size)?;
+    fn map(&mut self, item: (&Vec<u8>, &ValueLocation), forward: bool) -> <Self as Iterator>::Item {
+        let (key, location) = item;
+        let (offset, size) = match location {
+            ValueLocation::Disk(offset, size) => (*offset, *size as u64),
+            location => return Ok((key.clone(), self.log.read_location(location)?)),
+        };
+        if let Some((start, data)) = &self.readahead
+            && offset >= *start && offset + size <= *start + data.len() as u64 {
+            let begin = (offset - start) as usize;
+            return Ok((key.clone(), data[begin..begin + size as usize].to_vec()));
+        }
+        // 按扫描的方向往后看，日志中位置相近的 value 合并成一次读取
+        let (start, end) = if forward {
+            Self::readahead_range(offset, size, self.inner.clone())
+        } else {
+            Self::readahead_range(offset, size, self.inner.clone().rev())
+        };
+        if end - start == size {
+            return Ok((key.clone(), self.log.read_entry(offset, size as u32)?));
+        }
+        let data = self.log.read_entry(start, (end - start) as u32)?;
+        let begin = (offset - start) as usize;
+        let value = data[begin..begin + size as usize].to_vec();
+        self.readahead = Some((start, data));
         Ok((key.clone(), value))
     }
+
+    // 从当前的 value 开始，依次合并后面在日志中距离不超过 READAHEAD_MAX_GAP 的 value，总长度不超过 READAHEAD_SIZE
+    // 遇到离得远的 value 就停止，所以只有 keydir 的顺序和日志的顺序大致相同时才会预读
+    fn readahead_range<'b>(offset: u64, size: u64, next: impl Iterator<Item = (&'b Vec<u8>, &'b ValueLocation)>) -> (u64, u64) {
+        let (mut start, mut end) = (offset, offset + size);
+        for (_, location) in next {
+            let (offset, size) = match location {
+                ValueLocation::Disk(offset, size) => (*offset, *size as u64),
+                ValueLocation::Inline(..) => continue,
+            };
+            let (new_start, new_end) = (start.min(offset), end.max(offset + size));
+            if offset > end + READAHEAD_MAX_GAP || offset + size + READAHEAD_MAX_GAP < start
+                || new_end - new_start > READAHEAD_SIZE {
+                break;
+            }
+            (start, end) = (new_start, new_end);
+        }
+        (start, end)
+    }
     
 }
 
@@ -122,13 +260,13 @@ impl<'a> Iterator for DiskEngineIterator<'a> {
     type Item = LegendDBResult<(Vec<u8>, Vec<u8>)>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.inner.next().map(|v| self.map(v))
+        self.inner.next().map(|v| self.map(v, true))
     }
 }
 
 impl<'a> DoubleEndedIterator for DiskEngineIterator<'a> {
     fn next_back(&mut self) -> Option<Self::Item> {
-        self.inner.next_back().map(|v| self.map(v))
+        self.inner.next_back().map(|v| self.map(v, false))
     }
 }
 
@@ -150,28 +288,71 @@ impl Log {
             }
         }
 
-        // 打开文件
-        let file = OpenOptions::new()
-            // 文件不存在则创建
-            .create(true)
-            .read(true)
-            .write(true)
-            .open(&file_path)?;
-        //获取文件描述
-        // let file_desc = file.as_raw_fd();
-        // 加独占锁，排他锁 保证同时只有一个服务使用这个文件
-        file.try_lock_exclusive()?;
-        Ok(Self { file_path, file })
-    }
-
-    fn build_keydir(&mut self) -> LegendDBResult<KeyDir> {
+        loop {
+            // 打开文件
+            let file = OpenOptions::new()
+                // 文件不存在则创建
+                .create(true)
+                .read(true)
+                .write(true)
+                .open(&file_path)?;
+            //获取文件描述
+            // let file_desc = file.as_raw_fd();
+            let log = Self { file_path: file_path.clone(), file };
+            if log.lock()? {
+                return Ok(log);
+            }
+        }
+    }
+
+    // 加独占锁，排他锁 保证同时只有一个服务使用这个文件
+    // 加锁失败时从锁信息文件中读取持有锁的进程，方便排查重复启动
+    // 打开和加锁之间日志可能被整理替换掉，这时锁住的是已经删除的旧日志，返回 false 由调用方重新打开
+    fn lock(&self) -> LegendDBResult<bool> {
+        if self.file.try_lock_exclusive().is_err() {
+            return Err(LegendDBError::DatabaseLocked {
+                path: self.file_path.display().to_string(),
+                holder: read_lock_holder(&self.file_path).unwrap_or_else(|| "another process".to_string()),
+            });
+        }
+        if !is_current(&self.file, &self.file_path)? {
+            return Ok(false);
+        }
+        write_lock_holder(&self.file_path)?;
+        Ok(true)
+    }
+
+    // 用整理好的临时日志替换当前的日志，任何时候崩溃，日志文件要么是完整的旧日志，要么是完整的新日志
+    // 1. 临时日志落盘；2. 重命名覆盖旧日志；3. 目录落盘，保证重命名持久化；4. 改用临时日志的句柄，关闭旧日志的句柄
+    // 重命名之前旧日志的句柄一直持有旧日志的锁，重命名之后日志文件就是临时日志，由临时日志的句柄持有锁，
+    // 整个过程中其他进程打开日志都会返回 DatabaseLocked
+    // Windows 上不能覆盖仍然打开着的文件，只能在重命名之前关闭旧日志的句柄，这期间旧日志没有持有锁
+    fn replace_with(&mut self, temp: Log) -> LegendDBResult<()> {
+        temp.file.sync_all()?;
+        #[cfg(windows)]
+        {
+            self.file = temp.file.try_clone()?;
+        }
+        rename(&temp.file_path, &self.file_path)?;
+        sync_dir(&self.file_path)?;
+        let _ = remove_file(lock_info_path(&temp.file_path));
+        self.file = temp.file;
+        write_lock_holder(&self.file_path)
+    }
+
+    fn build_keydir(&mut self, progress: &mut dyn FnMut(u64, u64)) -> LegendDBResult<KeyDir> {
         // 创建一个空的keydir
         let mut keydir = KeyDir::new();
         let mut reader = BufReader::new(&self.file);
         // 获取文件长度
         let file_len = self.file.metadata()?.len();
         let mut offset = 0;
+        let mut reported = 0;
         loop {
+            if offset - reported >= RECOVERY_PROGRESS_INTERVAL {
+                progress(offset, file_len);
+                reported = offset;
+            }
             // 先度前面8个字节，前面8个字节固定，包含key和value key的值，进而读到key和value
             if offset >= file_len {
                 break;
@@ -182,12 +363,19 @@ impl Log {
             if value_size == -1 {
                 keydir.remove(&key);
                 offset += LOG_HEADER_SIZE as u64 + key_size as u64;
+            } else if value_size as usize <= INLINE_VALUE_SIZE {
+                // 很小的 value 紧跟在 key 后面，顺序读出来存放在 keydir 中
+                let mut value = vec![0; value_size as usize];
+                reader.read_exact(&mut value)?;
+                offset += LOG_HEADER_SIZE as u64 + key_size as u64 + value_size as u64;
+                keydir.insert(key, ValueLocation::new(&value, offset));
             } else {
                 // value的长度是offset 加固定的8个字节，再加key的长度
-                keydir.insert(key, (offset + LOG_HEADER_SIZE as u64 + key_size as u64, value_size as u32));
+                keydir.insert(key, ValueLocation::Disk(offset + LOG_HEADER_SIZE as u64 + key_size as u64, value_size as u32));
                 offset += LOG_HEADER_SIZE as u64 + key_size as u64 + value_size as u64;
             }
         }
+        progress(offset, file_len);
         Ok(keydir)
     }
 
@@ -226,6 +414,14 @@ impl Log {
         Ok(buf)
     }
 
+    // 读取 keydir 中记录的 value，直接存放在 keydir 中的 value 不需要读磁盘
+    fn read_location(&mut self, location: &ValueLocation) -> LegendDBResult<Vec<u8>> {
+        match location {
+            ValueLocation::Disk(offset, size) => self.read_entry(*offset, *size),
+            ValueLocation::Inline(data, len) => Ok(data[..*len as usize].to_vec()),
+        }
+    }
+
     fn read_value(buffer_reader: &mut BufReader<&File>, offset: u64) -> LegendDBResult<(Vec<u8>, i32)> {
         buffer_reader.seek(SeekFrom::Start(offset))?;
         let mut key_len = [0; 4];
@@ -244,12 +440,74 @@ impl Log {
     }
 }
 
+// 整理时使用的临时日志文件，比如 sqldb-log.compact
+fn compact_path(file_path: &Path) -> PathBuf {
+    file_path.with_extension("compact")
+}
+
+// 目录落盘，保证目录中文件的重命名持久化，Windows 上不能打开目录，不需要这一步
+fn sync_dir(file_path: &Path) -> LegendDBResult<()> {
+    if cfg!(unix) && let Some(dir) = file_path.parent() {
+        let dir = if dir.as_os_str().is_empty() { Path::new(".") } else { dir };
+        File::open(dir)?.sync_all()?;
+    }
+    Ok(())
+}
+
+// 打开的文件是否还是路径指向的文件，整理替换日志之后，替换之前打开的句柄指向已经删除的旧日志
+// Windows 上不能覆盖仍然打开着的文件，不会出现这种情况
+#[cfg(unix)]
+fn is_current(file: &File, file_path: &Path) -> LegendDBResult<bool> {
+    use std::os::unix::fs::MetadataExt;
+    let (opened, current) = (file.metadata()?, std::fs::metadata(file_path)?);
+    Ok(opened.dev() == current.dev() && opened.ino() == current.ino())
+}
+
+#[cfg(not(unix))]
+fn is_current(_file: &File, _file_path: &Path) -> LegendDBResult<bool> {
+    Ok(true)
+}
+
+// 锁信息文件，记录持有锁的进程号、主机名和加锁的时间，比如 sqldb-log.lock
+// 只在加锁失败时读取，进程退出之后遗留的文件不影响下次加锁
+fn lock_info_path(file_path: &Path) -> PathBuf {
+    let mut name = file_path.as_os_str().to_owned();
+    name.push(".lock");
+    PathBuf::from(name)
+}
+
+fn write_lock_holder(file_path: &Path) -> LegendDBResult<()> {
+    let since = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    std::fs::write(lock_info_path(file_path), format!("{}\n{}\n{}\n", std::process::id(), hostname(), since))?;
+    Ok(())
+}
+
+// 读取持有锁的进程，比如 PID 42 on db-host since 2024-01-01 08:00:00 UTC
+fn read_lock_holder(file_path: &Path) -> Option<String> {
+    let info = std::fs::read_to_string(lock_info_path(file_path)).ok()?;
+    let mut lines = info.lines();
+    let (pid, host, since) = (lines.next()?, lines.next()?, lines.next()?.parse::<i64>().ok()?);
+    Some(format!("PID {} on {} since {} UTC", pid, host, format_timestamp(since)))
+}
+
+fn hostname() -> String {
+    std::env::var("HOSTNAME")
+        .or_else(|_| std::env::var("COMPUTERNAME"))
+        .ok()
+        .or_else(|| std::fs::read_to_string("/etc/hostname").ok().map(|h| h.trim().to_string()))
+        .filter(|h| !h.is_empty())
+        .unwrap_or_else(|| "unknown host".to_string())
+}
+
 #[cfg(test)]
 mod test {
+    use std::fs::File;
     use std::path::PathBuf;
-    use crate::storage::disk::DiskEngine;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use crate::storage::disk::{DiskEngine, Log, ValueLocation};
     use crate::storage::engine::Engine;
-    use crate::custom_error::LegendDBResult;
+    use crate::custom_error::{LegendDBError, LegendDBResult};
 
     #[test]
     fn test_disk_engine_compact() -> LegendDBResult<()> {
@@ -297,4 +555,211 @@ mod test {
 
         Ok(())
     }
+
+    #[test]
+    fn test_inline_small_values() -> LegendDBResult<()> {
+        let p = tempfile::tempdir()?.into_path().join("sqldb-log");
+        let small = b"0123456789abcdef".to_vec();
+        let large = b"0123456789abcdefg".to_vec();
+        let mut eng = DiskEngine::new(p.clone())?;
+        eng.set(b"a".to_vec(), small.clone())?;
+        eng.set(b"b".to_vec(), large.clone())?;
+        eng.set(b"c".to_vec(), vec![])?;
+        assert!(matches!(eng.keydir[&b"a".to_vec()], ValueLocation::Inline(_, 16)));
+        assert!(matches!(eng.keydir[&b"b".to_vec()], ValueLocation::Disk(_, 17)));
+        drop(eng);
+
+        // 恢复和重写之后，小的 value 仍然存放在 keydir 中
+        for open in [DiskEngine::new, DiskEngine::new_compact] {
+            let mut eng = open(p.clone())?;
+            assert!(matches!(eng.keydir[&b"a".to_vec()], ValueLocation::Inline(_, 16)));
+            assert!(matches!(eng.keydir[&b"b".to_vec()], ValueLocation::Disk(_, 17)));
+            assert_eq!(
+                eng.scan(..).collect::<LegendDBResult<Vec<_>>>()?,
+                vec![(b"a".to_vec(), small.clone()), (b"b".to_vec(), large.clone()), (b"c".to_vec(), vec![])]
+            );
+        }
+
+        std::fs::remove_dir_all(p.parent().unwrap())?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_scan_readahead() -> LegendDBResult<()> {
+        let p = tempfile::tempdir()?.into_path().join("sqldb-log");
+        let mut eng = DiskEngine::new(p.clone())?;
+        let value = |i: u8, n: usize| vec![i; n];
+        for i in 0..100u8 {
+            eng.set(vec![i], value(i, 100))?;
+        }
+        // 修改之后的 value 写在日志末尾，和前后的 key 不相邻
+        eng.set(vec![50], value(150, 100))?;
+        eng.set(vec![60], value(160, 300 * 1024))?;
+        let expected = (0..100u8).map(|i| match i {
+            50 => (vec![i], value(150, 100)),
+            60 => (vec![i], value(160, 300 * 1024)),
+            i => (vec![i], value(i, 100)),
+        }).collect::<Vec<_>>();
+
+        // 第一次读取时预读了后面相邻的 value
+        let mut iter = eng.scan(..);
+        iter.next().transpose()?;
+        assert_eq!(iter.readahead.as_ref().map(|(_, data)| data.len()), Some(50 * (8 + 1 + 100) - 8 - 1));
+        drop(iter);
+
+        assert_eq!(eng.scan(..).collect::<LegendDBResult<Vec<_>>>()?, expected);
+        let mut reversed = eng.scan(..).rev().collect::<LegendDBResult<Vec<_>>>()?;
+        reversed.reverse();
+        assert_eq!(reversed, expected);
+
+        std::fs::remove_dir_all(p.parent().unwrap())?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_incremental_compaction() -> LegendDBResult<()> {
+        let p = tempfile::tempdir()?.into_path().join("sqldb-log");
+        let mut eng = DiskEngine::new(p.clone())?;
+        for key in [b"a", b"b", b"c", b"d", b"e"] {
+            eng.set(key.to_vec(), b"old".to_vec())?;
+            eng.set(key.to_vec(), b"value".to_vec())?;
+        }
+        // 每一步复制一个 key，复制了 a 和 b 之后继续写入
+        assert!(!eng.compact_chunk(1)?);
+        assert!(!eng.compact_chunk(1)?);
+        eng.set(b"a".to_vec(), b"new".to_vec())?;
+        eng.delete(b"b".to_vec())?;
+        eng.set(b"aa".to_vec(), b"new".to_vec())?;
+        eng.set(b"c".to_vec(), b"new".to_vec())?;
+        eng.delete(b"d".to_vec())?;
+        assert_eq!(eng.get(b"b".to_vec())?, None);
+        assert_eq!(eng.get(b"c".to_vec())?, Some(b"new".to_vec()));
+        while !eng.compact_chunk(1)? {}
+
+        let expected = vec![
+            (b"a".to_vec(), b"new".to_vec()),
+            (b"aa".to_vec(), b"new".to_vec()),
+            (b"c".to_vec(), b"new".to_vec()),
+            (b"e".to_vec(), b"value".to_vec()),
+        ];
+        assert_eq!(eng.scan(..).collect::<LegendDBResult<Vec<_>>>()?, expected);
+        drop(eng);
+        let mut eng = DiskEngine::new(p.clone())?;
+        assert_eq!(eng.scan(..).collect::<LegendDBResult<Vec<_>>>()?, expected);
+        // 新日志中只有复制的数据和整理期间对已复制的 key 的写入
+        let size = |key: &str, value: Option<&str>| 8 + key.len() as u64 + value.map_or(0, |v| v.len() as u64);
+        assert_eq!(std::fs::metadata(&p)?.len(), size("a", Some("value")) + size("b", Some("value"))
+            + size("a", Some("new")) + size("b", None) + size("aa", Some("new")) + size("c", Some("new")) + size("e", Some("value")));
+
+        std::fs::remove_dir_all(p.parent().unwrap())?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_compaction_swap() -> LegendDBResult<()> {
+        let p = tempfile::tempdir()?.into_path().join("sqldb-log");
+        let compact = p.with_extension("compact");
+        // 中断的整理遗留的临时日志在打开时删除
+        std::fs::write(&compact, b"garbage")?;
+        let mut eng = DiskEngine::new(p.clone())?;
+        assert!(!compact.exists());
+        eng.set(b"a".to_vec(), b"old".to_vec())?;
+        eng.set(b"a".to_vec(), b"value".to_vec())?;
+        eng.compact()?;
+        assert!(!compact.exists());
+        assert!(!compact.with_extension("compact.lock").exists());
+        // 替换之后重新打开的日志仍然持有锁，新的写入追加到替换后的日志中
+        assert!(matches!(DiskEngine::new(p.clone()), Err(LegendDBError::DatabaseLocked { .. })));
+        eng.set(b"b".to_vec(), b"value".to_vec())?;
+        drop(eng);
+        let mut eng = DiskEngine::new(p.clone())?;
+        assert_eq!(
+            eng.scan(..).collect::<LegendDBResult<Vec<_>>>()?,
+            vec![(b"a".to_vec(), b"value".to_vec()), (b"b".to_vec(), b"value".to_vec())]
+        );
+        assert_eq!(std::fs::metadata(&p)?.len(), 2 * (8 + 1 + 5));
+
+        std::fs::remove_dir_all(p.parent().unwrap())?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_reopen_during_compaction() -> LegendDBResult<()> {
+        let p = tempfile::tempdir()?.into_path().join("sqldb-log");
+        let mut eng = DiskEngine::new(p.clone())?;
+        for key in [b"a", b"b", b"c"] {
+            eng.set(key.to_vec(), b"old".to_vec())?;
+            eng.set(key.to_vec(), b"value".to_vec())?;
+        }
+        // 整理的每一步之间和替换之后，其他人都不能打开日志
+        while !eng.compact_chunk(1)? {
+            assert!(matches!(DiskEngine::new(p.clone()), Err(LegendDBError::DatabaseLocked { .. })));
+        }
+        assert!(matches!(DiskEngine::new(p.clone()), Err(LegendDBError::DatabaseLocked { .. })));
+
+        // 替换之前打开、替换之后才加锁的句柄锁住的是已经删除的旧日志，不能当作日志使用
+        let stale = Log { file_path: p.clone(), file: File::open(&p)? };
+        eng.compact()?;
+        assert!(!stale.lock()?);
+        assert!(matches!(Log::new(p.clone()), Err(LegendDBError::DatabaseLocked { .. })));
+        drop(stale);
+
+        // 反复整理的同时另一个线程不停地打开日志，替换日志的过程中也一直持有锁
+        let done = Arc::new(AtomicBool::new(false));
+        let opener = {
+            let (p, done) = (p.clone(), done.clone());
+            std::thread::spawn(move || {
+                while !done.load(Ordering::Relaxed) {
+                    assert!(matches!(Log::new(p.clone()), Err(LegendDBError::DatabaseLocked { .. })), "log opened during compaction");
+                }
+            })
+        };
+        for i in 0..200 {
+            eng.set(b"a".to_vec(), format!("value{}", i).into_bytes())?;
+            eng.compact()?;
+        }
+        done.store(true, Ordering::Relaxed);
+        opener.join().unwrap();
+        assert_eq!(eng.get(b"a".to_vec())?, Some(b"value199".to_vec()));
+
+        std::fs::remove_dir_all(p.parent().unwrap())?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_lock_holder() -> LegendDBResult<()> {
+        let p = tempfile::tempdir()?.into_path().join("sqldb-log");
+        let eng = DiskEngine::new(p.clone())?;
+        // 同一个文件只能被打开一次，错误中带有持有锁的进程
+        match DiskEngine::new(p.clone()) {
+            Err(LegendDBError::DatabaseLocked { path, holder }) => {
+                assert_eq!(path, p.display().to_string());
+                assert!(holder.starts_with(&format!("PID {} on ", std::process::id())), "{}", holder);
+            },
+            _ => unreachable!(),
+        }
+        // 释放之后可以重新打开
+        drop(eng);
+        DiskEngine::new(p.clone())?;
+
+        std::fs::remove_dir_all(p.parent().unwrap())?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_recovery_progress() -> LegendDBResult<()> {
+        let p = tempfile::tempdir()?.into_path().join("sqldb-log");
+        let mut eng = DiskEngine::new(p.clone())?;
+        eng.set(b"key1".to_vec(), b"value".to_vec())?;
+        eng.delete(b"key1".to_vec())?;
+        drop(eng);
+
+        let mut reports = Vec::new();
+        DiskEngine::new_with_progress(p.clone(), &mut |done, total| reports.push((done, total)))?;
+        let len = std::fs::metadata(&p)?.len();
+        assert_eq!(reports, vec![(len, len)]);
+
+        std::fs::remove_dir_all(p.parent().unwrap())?;
+        Ok(())
+    }
 }
@@ -11,6 +11,11 @@ pub trait Engine {
 
     fn get(&mut self, key: Vec<u8>) ->LegendDBResult<Option<Vec<u8>>>;
 
+    // 批量读取多个key，结果和key的顺序一致
+    fn multi_get(&mut self, keys: Vec<Vec<u8>>) -> LegendDBResult<Vec<Option<Vec<u8>>>> {
+        keys.into_iter().map(|key| self.get(key)).collect()
+    }
+
     // 删除key,如果key不存在的话则忽略
     fn delete(&mut self, key: Vec<u8>) -> LegendDBResult<()>;
 
@@ -19,19 +24,38 @@ pub trait Engine {
 
     // 前缀扫描
     fn scan_prefix(&mut self, prefix: Vec<u8>) -> Self::EngineIterator<'_> {
-        // start aaa
-        // end aaab
-        // let _start = (1..9).start_bound(); 这就是一个范围
-        let start = Bound::Included(prefix.clone());
-        let mut prefix_bound = prefix.clone();
-        if let Some(last) = prefix_bound.iter_mut().last() {
-            *last += 1;
-        };
-        let end = Bound::Excluded(prefix_bound);
-        self.scan((start, end))
+        self.scan(prefix_range(prefix))
+    }
+
+    // 整理存储空间，每次调用只做一小部分工作，返回 true 表示整理完成
+    // 两次调用之间可以正常读写，不需要整理的存储引擎直接返回 true
+    fn compact_step(&mut self) -> LegendDBResult<bool> {
+        Ok(true)
     }
 }
 
+// 前缀对应的扫描范围
+pub fn prefix_range(prefix: Vec<u8>) -> (Bound<Vec<u8>>, Bound<Vec<u8>>) {
+    // start aaa
+    // end aaab
+    // let _start = (1..9).start_bound(); 这就是一个范围
+    let start = Bound::Included(prefix.clone());
+    // 末尾是255的字节无法加1，需要去掉之后再对前一个字节加1
+    // 全部是255时没有上界
+    let mut prefix_bound = prefix;
+    while prefix_bound.last() == Some(&255) {
+        prefix_bound.pop();
+    }
+    let end = match prefix_bound.last_mut() {
+        Some(last) => {
+            *last += 1;
+            Bound::Excluded(prefix_bound)
+        },
+        None => Bound::Unbounded,
+    };
+    (start, end)
+}
+
 pub trait EngineIterator: DoubleEndedIterator<Item = LegendDBResult<(Vec<u8>, Vec<u8>)>> {}
 
 mod tests {
@@ -66,6 +90,9 @@ mod tests {
 
         eng.set(b"cc".to_vec(), vec![5, 6, 7, 8])?;
         assert_eq!(eng.get(b"cc".to_vec())?, Some(vec![5, 6, 7, 8]));
+
+        // 批量读取，结果和 key 的顺序一致
+        assert_eq!(eng.multi_get(vec![b"cc".to_vec(), b"aa".to_vec(), b"".to_vec()])?, vec![Some(vec![5, 6, 7, 8]), None, Some(vec![])]);
         Ok(())
     }
 
@@ -120,6 +147,19 @@ mod tests {
         // let (key2, _) = iter.next().transpose()?.unwrap();
         // assert_eq!(key2, b"canehe".to_vec());
 
+        // 前缀末尾是 255 时上界进位到前一个字节，全部是 255 时没有上界
+        eng.set(vec![b'c', 255], b"value7".to_vec())?;
+        eng.set(vec![b'c', 255, 0], b"value8".to_vec())?;
+        eng.set(vec![b'd'], b"value9".to_vec())?;
+        eng.set(vec![255, 255, 1], b"value10".to_vec())?;
+        for (prefix, expected) in [
+            (vec![b'c', 255], vec![vec![b'c', 255], vec![b'c', 255, 0]]),
+            (vec![255], vec![vec![255, 255, 1]]),
+            (vec![255, 255], vec![vec![255, 255, 1]]),
+        ] {
+            let keys = eng.scan_prefix(prefix).map(|r| r.map(|(key, _)| key)).collect::<LegendDBResult<Vec<_>>>()?;
+            assert_eq!(keys, expected);
+        }
         Ok(())
     }
 
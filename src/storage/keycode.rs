@@ -105,8 +105,10 @@ impl<'a> Serializer for &'a mut KeyCodeSerializer{
         todo!()
     }
 
+    // 定长的字节数组按原样编码，比如 UUID 的 16 个字节
     fn serialize_u8(self, v: u8) -> LegendDBResult<Self::Ok> {
-        todo!()
+        self.output.push(v);
+        Ok(())
     }
 
     fn serialize_u16(self, v: u16) -> LegendDBResult<Self::Ok> {
@@ -138,9 +140,10 @@ impl<'a> Serializer for &'a mut KeyCodeSerializer{
         todo!()
     }
 
+    // 字符串和字节数组使用相同的编码，需要带上结尾标记
+    // 否则表 t1 的前缀会匹配到表 t10 的数据
     fn serialize_str(self, v: &str) -> LegendDBResult<Self::Ok> {
-        self.output.extend(v.as_bytes());
-        Ok(())
+        self.serialize_bytes(v.as_bytes())
     }
 
     //原始值           编码后
@@ -407,7 +410,7 @@ impl<'de, 'a> Deserializer<'de> for & mut KeyCodeDeserializer<'de>  {
     where
         V: Visitor<'de>
     {
-        todo!()
+        visitor.visit_u8(self.take_bytes(1)[0])
     }
 
     fn deserialize_u16<V>(self, visitor: V) -> LegendDBResult<V::Value>
@@ -475,7 +478,8 @@ impl<'de, 'a> Deserializer<'de> for & mut KeyCodeDeserializer<'de>  {
     where
         V: Visitor<'de>
     {
-        todo!()
+        let bytes = self.next_bytes()?;
+        visitor.visit_string(String::from_utf8(bytes)?)
     }
 
     fn deserialize_bytes<V>(mut self, visitor: V) -> LegendDBResult<V::Value>
@@ -579,8 +583,9 @@ impl<'de, 'a> Deserializer<'de> for & mut KeyCodeDeserializer<'de>  {
 
 #[cfg(test)]
 mod tests {
-    use crate::storage::keycode::serializer;
+    use crate::storage::keycode::{deserializer, serializer};
     use crate::storage::mvcc::MvccKey;
+    use crate::custom_error::LegendDBResult;
 
     #[test]
     fn test_encode() {
@@ -598,4 +603,30 @@ mod tests {
         println!("{:?}", v);
         ser_cmp(k, v);
     }
+
+    #[test]
+    fn test_encode_str_prefix() {
+        use crate::sql::engine::kv::{KeyPrefix, TransactionKey};
+        use crate::sql::types::Value;
+        let prefix = KeyPrefix::Row("t1".to_string()).encode().unwrap();
+        let k1 = TransactionKey::RowKey("t1".to_string(), Value::Integer(1)).encode().unwrap();
+        let k10 = TransactionKey::RowKey("t10".to_string(), Value::Integer(1)).encode().unwrap();
+        assert!(k1.starts_with(&prefix));
+        assert!(!k10.starts_with(&prefix));
+    }
+
+    #[test]
+    fn test_encode_uuid() -> LegendDBResult<()> {
+        use crate::sql::types::Value;
+        // UUID 编码为变体的序号加上原样的 16 个字节，保持字节的顺序
+        let a = Value::Uuid([0; 16]);
+        let mut b = [0; 16];
+        b[15] = 1;
+        let b = Value::Uuid(b);
+        let (ka, kb) = (serializer(&a)?, serializer(&b)?);
+        assert_eq!(ka.len(), 17);
+        assert!(ka < kb);
+        assert_eq!(deserializer::<Value>(&kb)?, b);
+        Ok(())
+    }
 }
\ No newline at end of file
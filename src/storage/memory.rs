@@ -1,6 +1,10 @@
 use std::collections::btree_map::Range;
 use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, BufWriter, Read, Write};
 use std::ops::{RangeBounds};
+use std::path::PathBuf;
+use fs4::fs_std::FileExt;
 use crate::storage::engine::{Engine, EngineIterator};
 use crate::custom_error::LegendDBResult;
 
@@ -8,20 +12,45 @@ use crate::custom_error::LegendDBResult;
 #[derive(Debug)]
 pub struct MemoryEngine {
     data: BTreeMap<Vec<u8>, Vec<u8>>,
+    // 可选的预写日志，用于重启后恢复内存中的数据
+    wal: Option<Wal>,
 }
 
 impl MemoryEngine {
     pub fn new() -> Self {
         MemoryEngine {
             data: BTreeMap::new(),
+            wal: None,
         }
     }
+
+    // 开启预写日志的内存引擎，启动时从日志中恢复数据
+    pub fn new_with_wal(file_path: PathBuf) -> LegendDBResult<Self> {
+        let mut wal = Wal::new(file_path)?;
+        let data = wal.replay()?;
+        Ok(MemoryEngine {
+            data,
+            wal: Some(wal),
+        })
+    }
+
+    // 使用当前内存中的数据重写预写日志，清理掉已经被覆盖和删除的记录
+    pub fn checkpoint(&mut self) -> LegendDBResult<()> {
+        if let Some(wal) = self.wal.as_mut() {
+            wal.rewrite(&self.data)?;
+        }
+        Ok(())
+    }
 }
 
 impl Engine for MemoryEngine {
     type EngineIterator<'a> = MemoryEngineIterator<'a>;
 
     fn set(&mut self, key: Vec<u8>, value: Vec<u8>) -> LegendDBResult<()> {
+        // 先写日志，再修改内存
+        if let Some(wal) = self.wal.as_mut() {
+            wal.write_entry(&key, Some(&value))?;
+        }
         self.data.insert(key, value);
         Ok(())
     }
@@ -31,6 +60,9 @@ impl Engine for MemoryEngine {
     }
 
     fn delete(&mut self, key: Vec<u8>) -> LegendDBResult<()> {
+        if let Some(wal) = self.wal.as_mut() {
+            wal.write_entry(&key, None)?;
+        }
         self.data.remove(&key);
         Ok(())
     }
@@ -74,4 +106,132 @@ impl<'a> MemoryEngineIterator<'a> {
 }
 
 
-impl<'a> EngineIterator for MemoryEngineIterator<'a> {}
\ No newline at end of file
+impl<'a> EngineIterator for MemoryEngineIterator<'a> {}
+
+// 内存引擎的预写日志，格式和磁盘引擎的日志一致
+// +-------------+-------------+----------------+----------------+
+// | key len(4)    val len(4)     key(varint)       val(varint)  |
+// +-------------+-------------+----------------+----------------+
+// val len 为 -1 表示删除
+#[derive(Debug)]
+struct Wal {
+    file_path: PathBuf,
+    file: File,
+}
+
+impl Wal {
+    fn new(file_path: PathBuf) -> LegendDBResult<Self> {
+        if let Some(dir) = file_path.parent()
+            && !dir.exists()
+        {
+            std::fs::create_dir_all(dir)?;
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .read(true)
+            .append(true)
+            .open(&file_path)?;
+        // 加独占锁，保证同时只有一个服务使用这个文件
+        file.try_lock_exclusive()?;
+        Ok(Self { file_path, file })
+    }
+
+    // 重放日志，恢复内存数据
+    fn replay(&mut self) -> LegendDBResult<BTreeMap<Vec<u8>, Vec<u8>>> {
+        let mut data = BTreeMap::new();
+        let file_len = self.file.metadata()?.len();
+        let mut reader = BufReader::new(&self.file);
+        let mut offset = 0;
+        let mut len_buf = [0; 4];
+        while offset < file_len {
+            reader.read_exact(&mut len_buf)?;
+            let key_size = u32::from_be_bytes(len_buf);
+            reader.read_exact(&mut len_buf)?;
+            let value_size = i32::from_be_bytes(len_buf);
+            let mut key = vec![0; key_size as usize];
+            reader.read_exact(&mut key)?;
+            offset += 8 + key_size as u64;
+            if value_size == -1 {
+                data.remove(&key);
+            } else {
+                let mut value = vec![0; value_size as usize];
+                reader.read_exact(&mut value)?;
+                offset += value_size as u64;
+                data.insert(key, value);
+            }
+        }
+        Ok(data)
+    }
+
+    fn write_entry(&mut self, key: &[u8], value: Option<&Vec<u8>>) -> LegendDBResult<()> {
+        let entry_size = 8 + key.len() + value.map_or(0, |v| v.len());
+        let mut writer = BufWriter::with_capacity(entry_size, &mut self.file);
+        writer.write_all(&(key.len() as u32).to_be_bytes())?;
+        writer.write_all(&value.map_or(-1, |v| v.len() as i32).to_be_bytes())?;
+        writer.write_all(key)?;
+        if let Some(value) = value {
+            writer.write_all(value)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    // 将内存中的数据写入临时文件，再替换掉原来的日志
+    fn rewrite(&mut self, data: &BTreeMap<Vec<u8>, Vec<u8>>) -> LegendDBResult<()> {
+        let mut new_path = self.file_path.clone();
+        new_path.set_extension("checkpoint");
+        let mut new_wal = Wal::new(new_path)?;
+        new_wal.file.set_len(0)?;
+        for (key, value) in data.iter() {
+            new_wal.write_entry(key, Some(value))?;
+        }
+        new_wal.file.sync_all()?;
+        std::fs::rename(&new_wal.file_path, &self.file_path)?;
+        new_wal.file_path = self.file_path.clone();
+        *self = new_wal;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::storage::engine::Engine;
+    use crate::storage::memory::MemoryEngine;
+    use crate::custom_error::LegendDBResult;
+
+    #[test]
+    fn test_memory_engine_wal() -> LegendDBResult<()> {
+        let p = tempfile::tempdir()?.into_path().join("memory-wal");
+        let mut eng = MemoryEngine::new_with_wal(p.clone())?;
+        eng.set(b"key1".to_vec(), b"value1".to_vec())?;
+        eng.set(b"key2".to_vec(), b"value2".to_vec())?;
+        eng.set(b"key2".to_vec(), b"value3".to_vec())?;
+        eng.delete(b"key1".to_vec())?;
+        eng.set(b"key3".to_vec(), vec![])?;
+        drop(eng);
+
+        // 重启之后从日志中恢复
+        let mut eng2 = MemoryEngine::new_with_wal(p.clone())?;
+        let v = eng2.scan(..).collect::<LegendDBResult<Vec<_>>>()?;
+        assert_eq!(
+            v,
+            vec![
+                (b"key2".to_vec(), b"value3".to_vec()),
+                (b"key3".to_vec(), vec![]),
+            ]
+        );
+
+        // checkpoint 之后数据不变
+        eng2.checkpoint()?;
+        eng2.set(b"key4".to_vec(), b"value4".to_vec())?;
+        drop(eng2);
+        let mut eng3 = MemoryEngine::new_with_wal(p.clone())?;
+        assert_eq!(eng3.get(b"key2".to_vec())?, Some(b"value3".to_vec()));
+        assert_eq!(eng3.get(b"key4".to_vec())?, Some(b"value4".to_vec()));
+        assert_eq!(eng3.get(b"key1".to_vec())?, None);
+
+        std::fs::remove_dir_all(p.parent().unwrap())?;
+        Ok(())
+    }
+}
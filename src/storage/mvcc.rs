@@ -1,8 +1,9 @@
-use std::collections::{BTreeMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ops::Bound;
 use std::sync::{Arc, Mutex, MutexGuard};
 use bincode::{config, Decode, Encode};
 use serde::{Deserialize, Serialize};
-use crate::storage::engine::Engine;
+use crate::storage::engine::{prefix_range, Engine};
 use crate::storage::keycode::{deserializer, serializer};
 use crate::custom_error::{LegendDBError, LegendDBResult};
 
@@ -10,12 +11,15 @@ use crate::custom_error::{LegendDBError, LegendDBResult};
 pub struct Mvcc<E: Engine> {
     // 多线程运行，所以用Arc对象
     engine: Arc<Mutex<E>>,
+    // 已经被删除的key，扫描时跳过
+    tombstones: Arc<Mutex<Tombstones>>,
 }
 
 impl<E: Engine> Clone for Mvcc<E>  {
     fn clone(&self) -> Self {
         Self {
             engine: self.engine.clone(),
+            tombstones: self.tombstones.clone(),
         }
     }
 }
@@ -23,18 +27,63 @@ impl<E: Engine> Clone for Mvcc<E>  {
 impl<E: Engine> Mvcc<E>  {
     pub fn new(engine: E) -> Self {
         Self {
-            engine: Arc::new(Mutex::new(engine))
+            engine: Arc::new(Mutex::new(engine)),
+            tombstones: Arc::new(Mutex::new(Tombstones::default())),
         }
     }
 
     pub fn begin(&self) -> LegendDBResult<MvccTransaction<E>> {
-        MvccTransaction::begin(self.engine.clone())
+        MvccTransaction::begin(self.engine.clone(), self.tombstones.clone())
+    }
+
+    // 启动时恢复，进程异常退出时遗留的活跃事务都没有提交，需要全部回滚
+    // 同时清理已提交事务遗留的 TxnWrite 记录
+    // 只能在没有其他事务运行时调用，返回回滚的事务数量
+    pub fn recover(&self) -> LegendDBResult<usize> {
+        let active_versions = {
+            let mut engine = self.engine.lock()?;
+            MvccTransaction::get_active_txns(&mut engine)?
+        };
+        for version in active_versions.iter() {
+            let txn = MvccTransaction {
+                engine: self.engine.clone(),
+                tombstones: self.tombstones.clone(),
+                state: MvccTransactionStat {
+                    version: *version,
+                    active_versions: HashSet::new(),
+                },
+            };
+            txn.rollback()?;
+        }
+        // 提交时先删除活跃事务记录，再清理 TxnWrite，如果中间进程退出，会遗留已提交事务的 TxnWrite
+        // 回滚之后剩下的 TxnWrite 都属于已提交的事务，直接删除
+        // 编码后的第一个字节是枚举的序号，就是所有 TxnWrite 的公共前缀
+        let mut prefix = MvccKeyPrefix::TxnWrite(0).encode()?;
+        prefix.truncate(1);
+        let mut engine = self.engine.lock()?;
+        let leftovers = engine.scan_prefix(prefix).map(|r| r.map(|(key, _)| key)).collect::<LegendDBResult<Vec<_>>>()?;
+        for key in leftovers {
+            engine.delete(key)?;
+        }
+        Ok(active_versions.len())
+    }
+
+    // 整理存储空间，每一步只短暂持有存储引擎的锁，期间其他事务可以正常读写
+    pub fn compact(&self) -> LegendDBResult<()> {
+        while !self.compact_step()? {}
+        Ok(())
+    }
+
+    // 整理的一步，返回 true 表示整理完成
+    pub fn compact_step(&self) -> LegendDBResult<bool> {
+        self.engine.lock()?.compact_step()
     }
 }
 
 #[derive(Debug, Clone)]
 pub struct MvccTransaction<E: Engine> {
     engine: Arc<Mutex<E>>,
+    tombstones: Arc<Mutex<Tombstones>>,
     state: MvccTransactionStat,
 }
 
@@ -48,8 +97,9 @@ pub struct MvccTransactionStat {
 
 impl MvccTransactionStat {
     pub fn is_visible(&self, version: Version) -> bool {
+        // 事务开始时还活跃的事务，它们的修改对当前事务不可见
         if self.active_versions.contains(&version) {
-            true
+            false
         } else {
             version <= self.version
         }
@@ -58,6 +108,24 @@ impl MvccTransactionStat {
 
 pub type Version = u64;
 
+// 删除标记 None 编码后的值
+const TOMBSTONE: &[u8] = &[0];
+
+// 删除标记索引，记录最新版本是已提交删除标记的key
+// 扫描时如果删除标记对当前事务可见，直接跳过这个key的所有版本，不用再逐个解码
+// 索引只在内存中，由扫描过程逐步建立，key被重新写入时移除
+#[derive(Debug, Default)]
+pub struct Tombstones {
+    keys: HashMap<Vec<u8>, Version>,
+}
+
+impl Tombstones {
+    // key 对当前事务是否已经被删除
+    fn is_deleted(&self, key: &[u8], state: &MvccTransactionStat) -> bool {
+        self.keys.get(key).is_some_and(|version| state.is_visible(*version))
+    }
+}
+
 // 事务号枚举
 #[derive(Debug, Encode, Decode, Serialize, Deserialize)]
 pub enum MvccKey {
@@ -117,7 +185,7 @@ impl MvccKeyPrefix {
 impl<E: Engine> MvccTransaction<E> {
 
     // 开启事务
-    pub fn begin(eng: Arc<Mutex<E>>) -> LegendDBResult<Self> {
+    pub fn begin(eng: Arc<Mutex<E>>, tombstones: Arc<Mutex<Tombstones>>) -> LegendDBResult<Self> {
         // 获取存储引擎
         let mut engine = eng.lock()?;
         // 获取最新的事务号
@@ -138,6 +206,7 @@ impl<E: Engine> MvccTransaction<E> {
         engine.set(MvccKey::TxnActive(next_version).encode()?, vec![])?;
         Ok(Self {
             engine: eng.clone(),
+            tombstones,
             state: MvccTransactionStat {
                 version: next_version,
                 active_versions,
@@ -147,6 +216,9 @@ impl<E: Engine> MvccTransaction<E> {
     
     pub fn commit(&self) -> LegendDBResult<()> {
         let mut engine = self.engine.lock()?;
+        // 先从活跃事务列表中删除当前事务，这一步就是提交点
+        // 如果在清理 Txn Write 的过程中进程退出，恢复时不会把已经提交的事务回滚掉
+        engine.delete(MvccKey::TxnActive(self.state.version).encode()?)?;
         // vec![]和 Vec::new()在创建空数组时几乎没有区别，但宏的方式会可能会有一些编译时开销
         // let mut delete_keys = vec![];
         let mut delete_keys = Vec::new();
@@ -160,8 +232,7 @@ impl<E: Engine> MvccTransaction<E> {
         for key in delete_keys.into_iter() {
             engine.delete(key)?;
         }
-        // 从活跃事务列表中删除当前事务
-        engine.delete(MvccKey::TxnActive(self.state.version).encode()?)
+        Ok(())
     }
     // 回滚事务基本上跟提交事务差不多，还会多一步，将事务存储的数据删除
     pub fn rollback(&self) -> LegendDBResult<()> {
@@ -193,6 +264,11 @@ impl<E: Engine> MvccTransaction<E> {
         engine.delete(MvccKey::TxnActive(self.state.version).encode()?)
     }
     
+    // 当前事务的版本号
+    pub fn version(&self) -> Version {
+        self.state.version
+    }
+
     pub fn set(&self, key: Vec<u8>, value: Vec<u8>) -> LegendDBResult<()> {
         self.write_inner(key, Some(value))
     }
@@ -229,7 +305,13 @@ impl<E: Engine> MvccTransaction<E> {
                 MvccKey::Version(_, version) => {
                     // 检测这个 version 是否是可见的
                     if !self.state.is_visible(version) {
-                        return Err(LegendDBError::WriteMvccConflict);
+                        let conflict_active = engine.get(MvccKey::TxnActive(version).encode()?)?.is_some();
+                        return Err(LegendDBError::WriteMvccConflict {
+                            key: key.escape_ascii().to_string(),
+                            version: self.state.version,
+                            conflict_version: version,
+                            conflict_active,
+                        });
                     }
                 }
                 _ => {
@@ -240,6 +322,8 @@ impl<E: Engine> MvccTransaction<E> {
                 }
             }
         }
+        // key 被重新写入，不能再跳过
+        self.tombstones.lock()?.keys.remove(&key);
         // 记录这个version写入了哪些key， 用于回滚事务
         engine.set(
             MvccKey::TxnWrite(self.state.version, key.clone()).encode()?,
@@ -251,12 +335,35 @@ impl<E: Engine> MvccTransaction<E> {
         Ok(())
     }
     
-    pub(crate) fn get(&self, key: Vec<u8>) -> LegendDBResult<Option<Vec<u8>>> {
+    pub fn get(&self, key: Vec<u8>) -> LegendDBResult<Option<Vec<u8>>> {
         let mut engine = self.engine.lock()?;
+        self.get_visible(&mut engine, key, self.state.version)
+    }
+
+    // 读取当前事务开始前的快照数据，不包含当前事务自身的写入
+    pub fn get_snapshot(&self, key: Vec<u8>) -> LegendDBResult<Option<Vec<u8>>> {
+        let mut engine = self.engine.lock()?;
+        self.get_visible(&mut engine, key, self.state.version - 1)
+    }
+
+    // 批量读取，只获取一次锁，结果和key的顺序一致
+    pub fn multi_get(&self, keys: Vec<Vec<u8>>) -> LegendDBResult<Vec<Option<Vec<u8>>>> {
+        let mut engine = self.engine.lock()?;
+        keys.into_iter().map(|key| self.get_visible(&mut engine, key, self.state.version)).collect()
+    }
+
+    // 批量读取事务开始前的快照数据
+    pub fn multi_get_snapshot(&self, keys: Vec<Vec<u8>>) -> LegendDBResult<Vec<Option<Vec<u8>>>> {
+        let mut engine = self.engine.lock()?;
+        keys.into_iter().map(|key| self.get_visible(&mut engine, key, self.state.version - 1)).collect()
+    }
+
+    // 读取版本号不超过 max_version 的最新可见版本
+    fn get_visible(&self, engine: &mut MutexGuard<E>, key: Vec<u8>, max_version: Version) -> LegendDBResult<Option<Vec<u8>>> {
         // 假如当前的version是9
         // 可见版本就小于等于9，就需要扫描0到9的数据
         let from = MvccKey::Version(key.clone(), 0).encode()?;
-        let to = MvccKey::Version(key.clone(), self.state.version).encode()?;
+        let to = MvccKey::Version(key.clone(), max_version).encode()?;
         // rev反转，肯定是从最新事务号开始找
         let mut iter = engine.scan(from..=to).rev();
         // 从最新的版本开始读取，找到一个最新可见的版本
@@ -276,7 +383,7 @@ impl<E: Engine> MvccTransaction<E> {
         }
         Ok(None)
     }
-    
+
     pub fn scan_prefix(&mut self, prefix: Vec<u8>) -> LegendDBResult<Vec<ScanResult>> {
         let mut engine = self.engine.lock()?;
         let mut enc_prefix = MvccKeyPrefix::Version(prefix).encode()?;
@@ -286,32 +393,50 @@ impl<E: Engine> MvccTransaction<E> {
         // 97 98        -> 97 98 0 0         -> 97 98
         // 去掉最后的 [0, 0] 后缀
         enc_prefix.truncate(enc_prefix.len() - 2);
-        let mut iter = engine.scan_prefix(enc_prefix);
+        let (mut start, end) = prefix_range(enc_prefix);
+        let mut tombstones = self.tombstones.lock()?;
         let mut results = BTreeMap::new();
-        while let Some((key, value)) = iter.next().transpose()? {
-            match MvccKey::decode(&key)? {
-                MvccKey::Version(raw_key, version) => {
-                    if self.state.is_visible(version) {
-                        match bincode::decode_from_slice(&value, config::standard())? {
-                            (Some(raw_value), _) => {
+        // 当前正在处理的key，以及它最新的版本号和是否是删除标记
+        let mut current: Option<(Vec<u8>, Version, bool)> = None;
+        'scan: loop {
+            let mut iter = engine.scan((start.clone(), end.clone()));
+            while let Some((key, value)) = iter.next().transpose()? {
+                match MvccKey::decode(&key)? {
+                    MvccKey::Version(raw_key, version) => {
+                        if current.as_ref().is_none_or(|(k, _, _)| *k != raw_key) {
+                            if let Some((k, v, true)) = current.take() {
+                                self.record_tombstone(&mut tombstones, k, v);
+                            }
+                            // 删除标记可见，跳过这个key剩下的所有版本
+                            if tombstones.is_deleted(&raw_key, &self.state) {
+                                results.remove(&raw_key);
+                                start = Bound::Excluded(MvccKey::Version(raw_key, u64::MAX).encode()?);
+                                continue 'scan;
+                            }
+                        }
+                        let is_tombstone = value == TOMBSTONE;
+                        current = Some((raw_key.clone(), version, is_tombstone));
+                        if self.state.is_visible(version) {
+                            if is_tombstone {
+                                // 删除标记，说明这个key在可见版本中已经被删除
+                                results.remove(&raw_key);
+                            } else if let (Some(raw_value), _) = bincode::decode_from_slice(&value, config::standard())? {
                                 results.insert(raw_key, raw_value);
-                            },
-                            (None, _) => {
-                                return Err(LegendDBError::Internal(format!(
-                                    "unexpected value {:?}",
-                                    String::from_utf8(value)
-                                )))
-                            },
-                        };
+                            }
+                        }
+                    }
+                    _ => {
+                        return Err(LegendDBError::Internal(format!(
+                            "unexpected key {:?}",
+                            String::from_utf8(key)
+                        )))
                     }
-                }
-                _ => {
-                    return Err(LegendDBError::Internal(format!(
-                        "unexpected key {:?}",
-                        String::from_utf8(key)
-                    )))
                 }
             }
+            break;
+        }
+        if let Some((k, v, true)) = current {
+            self.record_tombstone(&mut tombstones, k, v);
         }
 
         Ok(results
@@ -320,6 +445,14 @@ impl<E: Engine> MvccTransaction<E> {
             .collect())
     }
 
+    // 最新版本是删除标记时加入索引，只记录其他事务已经提交的删除
+    // 当前事务自己的删除可能被回滚
+    fn record_tombstone(&self, tombstones: &mut Tombstones, key: Vec<u8>, version: Version) {
+        if version != self.state.version && self.state.is_visible(version) {
+            tombstones.keys.insert(key, version);
+        }
+    }
+
     // 获取当前活跃事务列表
     pub fn get_active_txns(engine: &mut MutexGuard<E>) -> LegendDBResult<HashSet<Version>> {
         let mut active_txns = HashSet::new();
@@ -351,7 +484,7 @@ mod tests {
     use crate::storage::engine::Engine;
     use crate::storage::memory::MemoryEngine;
     use crate::storage::mvcc::Mvcc;
-    use crate::custom_error::{LegendDBResult};
+    use crate::custom_error::{LegendDBError, LegendDBResult};
 
     // 1. Get
     fn get(eng: impl Engine) -> LegendDBResult<()> {
@@ -418,6 +551,33 @@ mod tests {
         Ok(())
     }
 
+    // 批量读取
+    fn multi_get(eng: impl Engine) -> LegendDBResult<()> {
+        let mvcc = Mvcc::new(eng);
+        let tx = mvcc.begin()?;
+        tx.set(b"key1".to_vec(), b"val1".to_vec())?;
+        tx.set(b"key2".to_vec(), b"val2".to_vec())?;
+        tx.commit()?;
+
+        let tx1 = mvcc.begin()?;
+        tx1.set(b"key1".to_vec(), b"val3".to_vec())?;
+        tx1.delete(b"key2".to_vec())?;
+        let keys = vec![b"key2".to_vec(), b"key3".to_vec(), b"key1".to_vec()];
+        assert_eq!(tx1.multi_get(keys.clone())?, vec![None, None, Some(b"val3".to_vec())]);
+        // 快照读取不包含当前事务的写入
+        assert_eq!(tx1.multi_get_snapshot(keys)?, vec![Some(b"val2".to_vec()), None, Some(b"val1".to_vec())]);
+        Ok(())
+    }
+    #[test]
+    fn test_multi_get() -> LegendDBResult<()> {
+        multi_get(MemoryEngine::new())?;
+
+        let p = tempfile::tempdir()?.into_path().join("sqldb-log");
+        multi_get(DiskEngine::new(p.clone())?)?;
+        std::fs::remove_dir_all(p.parent().unwrap())?;
+        Ok(())
+    }
+
     // 3. scan prefix
     fn scan_prefix(eng: impl Engine) -> LegendDBResult<()> {
         let mvcc = Mvcc::new(eng);
@@ -490,6 +650,107 @@ mod tests {
         Ok(())
     }
 
+    // 扫描跳过已删除的key
+    fn scan_tombstones(eng: impl Engine) -> LegendDBResult<()> {
+        let mvcc = Mvcc::new(eng);
+        let tx = mvcc.begin()?;
+        for i in 0..10u8 {
+            tx.set(vec![b'a', i], vec![i])?;
+        }
+        tx.commit()?;
+
+        // 开始早于删除的事务
+        let mut tx1 = mvcc.begin()?;
+
+        let mut tx2 = mvcc.begin()?;
+        for i in 1..10u8 {
+            tx2.delete(vec![b'a', i])?;
+        }
+        // 当前事务自己的删除不会进入索引
+        assert_eq!(tx2.scan_prefix(b"a".to_vec())?.len(), 1);
+        assert!(mvcc.tombstones.lock()?.keys.is_empty());
+        tx2.commit()?;
+
+        let mut tx3 = mvcc.begin()?;
+        assert_eq!(tx3.scan_prefix(b"a".to_vec())?.len(), 1);
+        assert_eq!(mvcc.tombstones.lock()?.keys.len(), 9);
+        // 再次扫描时跳过已删除的key
+        assert_eq!(
+            tx3.scan_prefix(b"a".to_vec())?,
+            vec![super::ScanResult { key: vec![b'a', 0], value: vec![0] }]
+        );
+        // 删除之前开始的事务仍然能看到全部数据
+        assert_eq!(tx1.scan_prefix(b"a".to_vec())?.len(), 10);
+        tx3.commit()?;
+
+        // 重新写入之后不再跳过
+        let tx4 = mvcc.begin()?;
+        tx4.set(vec![b'a', 5], vec![50])?;
+        tx4.commit()?;
+        assert_eq!(mvcc.tombstones.lock()?.keys.len(), 8);
+        let mut tx5 = mvcc.begin()?;
+        assert_eq!(
+            tx5.scan_prefix(b"a".to_vec())?,
+            vec![
+                super::ScanResult { key: vec![b'a', 0], value: vec![0] },
+                super::ScanResult { key: vec![b'a', 5], value: vec![50] },
+            ]
+        );
+        Ok(())
+    }
+
+    // 扫描时最新可见的版本是删除标记的 key 不出现在结果中
+    fn scan_deleted(eng: impl Engine) -> LegendDBResult<()> {
+        let mvcc = Mvcc::new(eng);
+        let tx = mvcc.begin()?;
+        tx.set(b"a1".to_vec(), b"v1".to_vec())?;
+        tx.set(b"a2".to_vec(), b"v1".to_vec())?;
+        tx.set(b"a3".to_vec(), b"v1".to_vec())?;
+        tx.commit()?;
+        let tx = mvcc.begin()?;
+        tx.delete(b"a2".to_vec())?;
+        tx.commit()?;
+
+        let result = |key: &[u8], value: &[u8]| super::ScanResult { key: key.to_vec(), value: value.to_vec() };
+        // 其他事务提交的删除
+        let mut tx1 = mvcc.begin()?;
+        assert_eq!(tx1.scan_prefix(b"a".to_vec())?, vec![result(b"a1", b"v1"), result(b"a3", b"v1")]);
+        // 当前事务自己的删除，删除之后重新写入
+        tx1.delete(b"a1".to_vec())?;
+        assert_eq!(tx1.scan_prefix(b"a".to_vec())?, vec![result(b"a3", b"v1")]);
+        tx1.set(b"a1".to_vec(), b"v2".to_vec())?;
+        assert_eq!(tx1.scan_prefix(b"a".to_vec())?, vec![result(b"a1", b"v2"), result(b"a3", b"v1")]);
+        tx1.commit()?;
+        // 删除之后其他事务重新写入
+        let tx = mvcc.begin()?;
+        tx.set(b"a2".to_vec(), b"v2".to_vec())?;
+        tx.commit()?;
+        let mut tx2 = mvcc.begin()?;
+        assert_eq!(
+            tx2.scan_prefix(b"a".to_vec())?,
+            vec![result(b"a1", b"v2"), result(b"a2", b"v2"), result(b"a3", b"v1")]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_scan_deleted() -> LegendDBResult<()> {
+        scan_deleted(MemoryEngine::new())?;
+        let p = tempfile::tempdir()?.into_path().join("sqldb-log");
+        scan_deleted(DiskEngine::new(p.clone())?)?;
+        std::fs::remove_dir_all(p.parent().unwrap())?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_scan_tombstones() -> LegendDBResult<()> {
+        scan_tombstones(MemoryEngine::new())?;
+        let p = tempfile::tempdir()?.into_path().join("sqldb-log");
+        scan_tombstones(DiskEngine::new(p.clone())?)?;
+        std::fs::remove_dir_all(p.parent().unwrap())?;
+        Ok(())
+    }
+
     // 4. scan isolation
     fn scan_isolation(eng: impl Engine) -> LegendDBResult<()> {
         let mvcc = Mvcc::new(eng);
@@ -630,6 +891,32 @@ mod tests {
         tx1.set(b"key1".to_vec(), b"val1-2".to_vec())?;
 
         tx1.commit()?;
+
+        let tx2 = mvcc.begin()?;
+        let tx3 = mvcc.begin()?;
+        tx2.set(b"key2".to_vec(), b"val2-1".to_vec())?;
+        // 冲突的事务还没有提交
+        match tx3.set(b"key2".to_vec(), b"val2-2".to_vec()) {
+            Err(LegendDBError::WriteMvccConflict { key, version, conflict_version, conflict_active }) => {
+                assert_eq!(key, "key2");
+                assert_eq!(version, tx3.version());
+                assert_eq!(conflict_version, tx2.version());
+                assert!(conflict_active);
+            },
+            r => panic!("expected write conflict, got {:?}", r),
+        }
+        tx2.commit()?;
+        // 冲突的事务已经提交
+        match tx3.set(b"key2".to_vec(), b"val2-2".to_vec()) {
+            Err(e @ LegendDBError::WriteMvccConflict { conflict_active: false, .. }) => {
+                assert_eq!(
+                    e.to_string(),
+                    format!("write mvcc conflict on key key2: transaction {} conflicts with committed transaction {}", tx3.version(), tx2.version())
+                );
+            },
+            r => panic!("expected write conflict, got {:?}", r),
+        }
+        tx3.rollback()?;
         Ok(())
     }
 
@@ -860,4 +1147,217 @@ mod tests {
         std::fs::remove_dir_all(p.parent().unwrap())?;
         Ok(())
     }
+
+    // 可见性：事务开始时还活跃的事务即使之后提交了也不可见，之后开始的事务不可见
+    #[test]
+    fn test_is_visible() -> LegendDBResult<()> {
+        let state = super::MvccTransactionStat { version: 5, active_versions: [2, 4].into_iter().collect() };
+        assert!(state.is_visible(1));
+        assert!(state.is_visible(3));
+        assert!(state.is_visible(5));
+        assert!(!state.is_visible(2));
+        assert!(!state.is_visible(4));
+        assert!(!state.is_visible(6));
+
+        let mvcc = Mvcc::new(MemoryEngine::new());
+        let tx1 = mvcc.begin()?;
+        let mut tx2 = mvcc.begin()?;
+        tx1.set(b"key1".to_vec(), b"val1".to_vec())?;
+        tx1.commit()?;
+        // tx2 开始时 tx1 还是活跃的，tx1 提交之后 tx2 仍然看不到它的修改
+        assert_eq!(tx2.get(b"key1".to_vec())?, None);
+        assert_eq!(tx2.scan_prefix(b"key".to_vec())?, vec![]);
+        assert_eq!(mvcc.begin()?.get(b"key1".to_vec())?, Some(b"val1".to_vec()));
+        Ok(())
+    }
+
+    // 删除次数用完之后删除失败的存储引擎，模拟提交过程中进程退出
+    struct FailingDeletes {
+        engine: MemoryEngine,
+        deletes_left: std::sync::Arc<std::sync::atomic::AtomicUsize>,
+    }
+
+    impl Engine for FailingDeletes {
+        type EngineIterator<'a> = <MemoryEngine as Engine>::EngineIterator<'a>;
+
+        fn set(&mut self, key: Vec<u8>, value: Vec<u8>) -> LegendDBResult<()> {
+            self.engine.set(key, value)
+        }
+
+        fn get(&mut self, key: Vec<u8>) -> LegendDBResult<Option<Vec<u8>>> {
+            self.engine.get(key)
+        }
+
+        fn delete(&mut self, key: Vec<u8>) -> LegendDBResult<()> {
+            let left = self.deletes_left.load(std::sync::atomic::Ordering::SeqCst);
+            if left == 0 {
+                return Err(LegendDBError::Internal("injected delete failure".to_string()));
+            }
+            self.deletes_left.store(left - 1, std::sync::atomic::Ordering::SeqCst);
+            self.engine.delete(key)
+        }
+
+        fn scan(&mut self, range: impl std::ops::RangeBounds<Vec<u8>>) -> Self::EngineIterator<'_> {
+            self.engine.scan(range)
+        }
+    }
+
+    // 提交时先删除活跃事务记录，清理 TxnWrite 的过程中失败时，事务已经提交，恢复时不会被回滚
+    #[test]
+    fn test_commit_point() -> LegendDBResult<()> {
+        let deletes_left = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(usize::MAX));
+        let mvcc = Mvcc::new(FailingDeletes { engine: MemoryEngine::new(), deletes_left: deletes_left.clone() });
+        let tx = mvcc.begin()?;
+        tx.set(b"key1".to_vec(), b"val1".to_vec())?;
+        tx.set(b"key2".to_vec(), b"val2".to_vec())?;
+        // 只有删除活跃事务记录能成功
+        deletes_left.store(1, std::sync::atomic::Ordering::SeqCst);
+        assert!(tx.commit().is_err());
+        deletes_left.store(usize::MAX, std::sync::atomic::Ordering::SeqCst);
+
+        assert_eq!(mvcc.recover()?, 0);
+        let tx1 = mvcc.begin()?;
+        assert_eq!(tx1.get(b"key1".to_vec())?, Some(b"val1".to_vec()));
+        assert_eq!(tx1.get(b"key2".to_vec())?, Some(b"val2".to_vec()));
+        Ok(())
+    }
+
+    // 10. recover
+    #[test]
+    fn test_recover() -> LegendDBResult<()> {
+        let p = tempfile::tempdir()?.into_path().join("sqldb-log");
+        {
+            let mvcc = Mvcc::new(DiskEngine::new(p.clone())?);
+            let tx = mvcc.begin()?;
+            tx.set(b"key1".to_vec(), b"val1".to_vec())?;
+            tx.commit()?;
+
+            // 模拟进程退出时没有提交的事务
+            let tx1 = mvcc.begin()?;
+            tx1.set(b"key1".to_vec(), b"val1-1".to_vec())?;
+            tx1.set(b"key2".to_vec(), b"val2-1".to_vec())?;
+        }
+
+        let mvcc = Mvcc::new(DiskEngine::new(p.clone())?);
+        assert_eq!(mvcc.recover()?, 1);
+        let tx2 = mvcc.begin()?;
+        assert_eq!(tx2.get(b"key1".to_vec())?, Some(b"val1".to_vec()));
+        assert_eq!(tx2.get(b"key2".to_vec())?, None);
+        // 遗留的事务回滚后，不会再产生写冲突
+        tx2.set(b"key1".to_vec(), b"val1-2".to_vec())?;
+        tx2.commit()?;
+        assert_eq!(mvcc.recover()?, 0);
+
+        // 模拟提交时删除活跃事务记录之后、清理 TxnWrite 之前进程退出
+        let tx3 = mvcc.begin()?;
+        tx3.set(b"key3".to_vec(), b"val3".to_vec())?;
+        mvcc.engine.lock()?.delete(super::MvccKey::TxnActive(tx3.state.version).encode()?)?;
+        assert_eq!(mvcc.recover()?, 0);
+        let mut prefix = super::MvccKeyPrefix::TxnWrite(0).encode()?;
+        prefix.truncate(1);
+        assert_eq!(mvcc.engine.lock()?.scan_prefix(prefix).count(), 0);
+        assert_eq!(mvcc.begin()?.get(b"key3".to_vec())?, Some(b"val3".to_vec()));
+
+        std::fs::remove_dir_all(p.parent().unwrap())?;
+        Ok(())
+    }
+
+    // 恢复时回滚多个没有提交的事务，包括删除操作
+    #[test]
+    fn test_recover_deletes() -> LegendDBResult<()> {
+        let p = tempfile::tempdir()?.into_path().join("sqldb-log");
+        {
+            let mvcc = Mvcc::new(DiskEngine::new(p.clone())?);
+            let tx = mvcc.begin()?;
+            tx.set(b"key1".to_vec(), b"val1".to_vec())?;
+            tx.set(b"key2".to_vec(), b"val2".to_vec())?;
+            tx.commit()?;
+
+            let tx1 = mvcc.begin()?;
+            tx1.delete(b"key1".to_vec())?;
+            let tx2 = mvcc.begin()?;
+            tx2.delete(b"key2".to_vec())?;
+            tx2.set(b"key3".to_vec(), b"val3".to_vec())?;
+        }
+
+        let mvcc = Mvcc::new(DiskEngine::new(p.clone())?);
+        assert_eq!(mvcc.recover()?, 2);
+        let mut tx = mvcc.begin()?;
+        assert_eq!(
+            tx.scan_prefix(b"key".to_vec())?,
+            vec![
+                super::ScanResult { key: b"key1".to_vec(), value: b"val1".to_vec() },
+                super::ScanResult { key: b"key2".to_vec(), value: b"val2".to_vec() },
+            ]
+        );
+        tx.delete(b"key1".to_vec())?;
+        tx.commit()?;
+
+        std::fs::remove_dir_all(p.parent().unwrap())?;
+        Ok(())
+    }
+
+    // 整理分多步进行，两步之间执行事务，数据和可见性都不受影响
+    #[test]
+    fn test_compact_between_transactions() -> LegendDBResult<()> {
+        let p = tempfile::tempdir()?.into_path().join("sqldb-log");
+        let key = |i: usize| format!("key{:03}", i).into_bytes();
+        let value = |tag: &str| tag.repeat(8 * 1024).into_bytes();
+        let steps = {
+            let mvcc = Mvcc::new(DiskEngine::new(p.clone())?);
+            let tx = mvcc.begin()?;
+            for i in 0..300 {
+                tx.set(key(i), value("a"))?;
+            }
+            tx.commit()?;
+            // 整理开始之前的快照
+            let old = mvcc.begin()?;
+            // 没有提交的事务，整理完成之后回滚
+            let pending = mvcc.begin()?;
+            pending.set(key(299), value("p"))?;
+
+            let mut steps = 0;
+            while !mvcc.compact_step()? {
+                steps += 1;
+                // 已经复制和还没有复制的 key 都会被修改
+                let tx = mvcc.begin()?;
+                tx.set(key(0), value(&steps.to_string()))?;
+                tx.set(key(150 + steps), value("b"))?;
+                tx.delete(key(250 + steps))?;
+                tx.commit()?;
+
+                assert_eq!(old.get(key(0))?, Some(value("a")));
+                assert_eq!(old.get(key(250 + steps))?, Some(value("a")));
+                let tx = mvcc.begin()?;
+                assert_eq!(tx.get(key(0))?, Some(value(&steps.to_string())));
+                assert_eq!(tx.get(key(250 + steps))?, None);
+                assert_eq!(tx.get(key(299))?, Some(value("a")));
+                tx.commit()?;
+            }
+            // 数据超过一步复制的大小，整理至少分成了两步
+            assert!(steps > 0);
+            pending.rollback()?;
+
+            let mut tx = mvcc.begin()?;
+            assert_eq!(tx.scan_prefix(b"key".to_vec())?.len(), 300 - steps);
+            assert_eq!(tx.get(key(0))?, Some(value(&steps.to_string())));
+            assert_eq!(tx.get(key(151))?, Some(value("b")));
+            assert_eq!(tx.get(key(299))?, Some(value("a")));
+            assert_eq!(old.get(key(151))?, Some(value("a")));
+            old.commit()?;
+            tx.commit()?;
+            steps
+        };
+
+        // 重新打开时读到的是整理之后的日志
+        let mvcc = Mvcc::new(DiskEngine::new(p.clone())?);
+        assert_eq!(mvcc.recover()?, 0);
+        let mut tx = mvcc.begin()?;
+        assert_eq!(tx.scan_prefix(b"key".to_vec())?.len(), 300 - steps);
+        assert_eq!(tx.get(key(0))?, Some(value(&steps.to_string())));
+        assert_eq!(tx.get(key(299))?, Some(value("a")));
+
+        std::fs::remove_dir_all(p.parent().unwrap())?;
+        Ok(())
+    }
 }